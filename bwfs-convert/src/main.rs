@@ -0,0 +1,166 @@
+use clap::Parser;
+use bwfs::{Config, BWFS};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// bwfs-convert - Re-encode a BWFS filesystem with another number of bits per pixel
+#[derive(Parser, Debug)]
+#[command(name = "bwfs-convert")]
+#[command(about = "Copy a BWFS filesystem into a new storage path with a different bits_per_pixel", long_about = None)]
+struct Args {
+    /// Path to configuration file of the source filesystem
+    #[arg(short = 'c', long = "config")]
+    config: String,
+
+    /// Bits stored in each pixel of the new filesystem (1, 2, 4 or 8)
+    #[arg(long = "bits", value_name = "N")]
+    bits: u8,
+
+    /// Storage path of the new filesystem (must not hold one already)
+    #[arg(short = 'd', long = "dest")]
+    dest: String,
+
+    /// Where to write the configuration of the new filesystem (defaults to
+    /// `<config>-<N>bit.<ext>` next to the source configuration)
+    #[arg(short = 'o', long = "output-config")]
+    output_config: Option<String>,
+
+    /// Convert even if the source filesystem appears to be mounted
+    #[arg(long = "force")]
+    force: bool,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    println!("bwfs-convert - Re-encoding Black and White FileSystem");
+    println!("=====================================================");
+
+    // Load configuration
+    println!("Loading configuration from: {}", args.config);
+    let config = Config::from_file(&args.config)?;
+    config.validate()?;
+
+    if !BWFS::has_metadata(&config.storage_path) {
+        anyhow::bail!("No filesystem metadata in storage path. Did you run mkfs.bwfs?");
+    }
+
+    // Un montaje activo puede tener cambios que aún no llegaron al disco
+    if bwfs::storage::is_mounted(Path::new(&config.storage_path)) {
+        if !args.force {
+            anyhow::bail!(
+                "Filesystem at {} is mounted; unmount it first or pass --force",
+                config.storage_path
+            );
+        }
+        println!("Warning: filesystem appears to be mounted, continuing (--force)");
+    }
+
+    if Path::new(&args.dest).exists() && Path::new(&args.dest).canonicalize()? == Path::new(&config.storage_path).canonicalize()? {
+        anyhow::bail!("The destination must differ from the source storage path");
+    }
+
+    let dest_config = Config {
+        storage_path: args.dest.clone(),
+        bits_per_pixel: args.bits,
+        ..config.clone()
+    };
+    dest_config.validate()?;
+    let output_config = args
+        .output_config
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_output_config(&args.config, args.bits));
+
+    let source = BWFS::load(config.clone())?;
+    println!(
+        "Converting {} ({} bit(s) per pixel, {} bytes per block) into {} ({} bit(s), {} bytes per block)",
+        config.storage_path,
+        config.bits_per_pixel,
+        config.bytes_per_block(),
+        dest_config.storage_path,
+        dest_config.bits_per_pixel,
+        dest_config.bytes_per_block()
+    );
+
+    // Si falla, no dejar a medias un directorio creado aquí
+    let created_dest = !Path::new(&args.dest).exists();
+    let (_, copied) = match bwfs::convert::convert(&source, dest_config.clone()) {
+        Ok(result) => result,
+        Err(e) => {
+            if created_dest {
+                let _ = std::fs::remove_dir_all(&args.dest);
+            }
+            return Err(e);
+        }
+    };
+
+    let text = std::fs::read_to_string(&args.config)
+        .with_context(|| format!("cannot read {}", args.config))?;
+    std::fs::write(&output_config, converted_config(&args.config, &text, &dest_config)?)
+        .with_context(|| format!("cannot write {:?}", output_config))?;
+
+    println!("\n✓ Copied {} entries", copied);
+    println!("✓ Configuration of the new filesystem written to {:?}", output_config);
+    Ok(())
+}
+
+/// `config-2bit.ini` for `config.ini` converted to 2 bits per pixel
+fn default_output_config(config: &str, bits: u8) -> PathBuf {
+    let path = Path::new(config);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("config");
+    let name = match path.extension().and_then(|s| s.to_str()) {
+        Some(ext) => format!("{}-{}bit.{}", stem, bits, ext),
+        None => format!("{}-{}bit", stem, bits),
+    };
+    path.with_file_name(name)
+}
+
+/// The source configuration file pointed at the new filesystem. An INI
+/// file keeps its comments and layout: only `storage_path` and
+/// `bits_per_pixel` change. TOML and JSON are written from `dest`.
+fn converted_config(path: &str, text: &str, dest: &Config) -> Result<String> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("toml") => return Ok(toml::to_string_pretty(dest)?),
+        Some("json") => return Ok(serde_json::to_string_pretty(dest)? + "\n"),
+        _ => {}
+    }
+
+    let mut lines = Vec::new();
+    let mut section = String::new();
+    let mut bits_written = false;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            // bits_per_pixel faltaba en [filesystem]: va al final de la sección
+            if section == "filesystem" && !bits_written {
+                lines.push(format!("bits_per_pixel = {}", dest.bits_per_pixel));
+                bits_written = true;
+            }
+            section = trimmed.trim_matches(|c| c == '[' || c == ']').trim().to_string();
+        }
+        let key = trimmed.split_once('=').map(|(key, _)| key.trim());
+        match key {
+            Some("storage_path") if section == "filesystem" => {
+                lines.push(format!("storage_path = {}", dest.storage_path));
+            }
+            Some("bits_per_pixel") if section == "filesystem" => {
+                lines.push(format!("bits_per_pixel = {}", dest.bits_per_pixel));
+                bits_written = true;
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    if !bits_written {
+        if section != "filesystem" {
+            lines.push("[filesystem]".to_string());
+        }
+        lines.push(format!("bits_per_pixel = {}", dest.bits_per_pixel));
+    }
+    Ok(lines.join("\n") + "\n")
+}
@@ -0,0 +1,114 @@
+use clap::Parser;
+use bwfs::fs::CopyProgress;
+use bwfs::{Config, BWFS};
+use anyhow::Result;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// bwfs-copy - Copy the raw block images of a BWFS filesystem
+#[derive(Parser, Debug)]
+#[command(name = "bwfs-copy")]
+#[command(about = "Copy the block images and metadata of a BWFS filesystem to another path", long_about = None)]
+struct Args {
+    /// Path to configuration file
+    #[arg(short = 'c', long = "config")]
+    config: String,
+    
+    /// Destination directory for the copied block set
+    #[arg(short = 'd', long = "dest")]
+    dest: String,
+    
+    /// Only copy blocks marked as allocated in the block bitmap
+    #[arg(short = 'a', long = "allocated-only")]
+    allocated_only: bool,
+    
+    /// Show bytes, files, throughput and ETA while copying (only on a terminal)
+    #[arg(long = "progress")]
+    progress: bool,
+}
+
+/// Minimum time between two progress redraws
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Renders a single, periodically rewritten progress line on stderr
+struct ProgressLine {
+    start: Instant,
+    last_draw: Option<Instant>,
+}
+
+impl ProgressLine {
+    fn new() -> Self {
+        Self { start: Instant::now(), last_draw: None }
+    }
+    
+    fn update(&mut self, p: &CopyProgress) {
+        let now = Instant::now();
+        let finished = p.files_done == p.files_total;
+        if !finished && self.last_draw.is_some_and(|t| now - t < PROGRESS_INTERVAL) {
+            return;
+        }
+        self.last_draw = Some(now);
+        
+        let elapsed = (now - self.start).as_secs_f64();
+        let rate = if elapsed > 0.0 { p.bytes_done as f64 / elapsed } else { 0.0 };
+        let eta = if rate > 0.0 {
+            format!("{:.0}s", p.bytes_total.saturating_sub(p.bytes_done) as f64 / rate)
+        } else {
+            "--".to_string()
+        };
+        
+        let mut err = std::io::stderr();
+        let _ = write!(
+            err,
+            "\r{:.1}/{:.1} MB  {}/{} files  {:.1} MB/s  ETA {}   ",
+            p.bytes_done as f64 / 1_000_000.0,
+            p.bytes_total as f64 / 1_000_000.0,
+            p.files_done,
+            p.files_total,
+            rate / 1_000_000.0,
+            eta
+        );
+        if finished {
+            let _ = writeln!(err);
+        }
+        let _ = err.flush();
+    }
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    
+    let args = Args::parse();
+    
+    println!("bwfs-copy - Copying Black and White FileSystem blocks");
+    println!("=====================================================");
+    
+    // Load configuration
+    println!("Loading configuration from: {}", args.config);
+    let config = Config::from_file(&args.config)?;
+    config.validate()?;
+    
+    if !BWFS::has_metadata(&config.storage_path) {
+        anyhow::bail!("No filesystem metadata in storage path. Did you run mkfs.bwfs?");
+    }
+    
+    println!("Source: {}", config.storage_path);
+    println!("Destination: {}", args.dest);
+    if args.allocated_only {
+        println!("Copying allocated blocks only");
+    }
+    
+    let fs = BWFS::load(config)?;
+    let copied = if args.progress && std::io::stderr().is_terminal() {
+        let mut line = ProgressLine::new();
+        fs.export_blocks_with_progress(Path::new(&args.dest), args.allocated_only, |p| line.update(p))?
+    } else {
+        fs.export_blocks(Path::new(&args.dest), args.allocated_only)?
+    };
+    
+    println!("\n✓ Copied {} blocks (checksums verified) and metadata", copied);
+    println!("Point storage_path at {} to use the copy.", args.dest);
+    
+    Ok(())
+}
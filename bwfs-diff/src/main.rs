@@ -0,0 +1,58 @@
+use clap::Parser;
+use bwfs::{Config, BWFS};
+use bwfs::diff::{self, ChangeKind};
+use anyhow::Result;
+
+/// bwfs-diff - Compare two BWFS filesystems
+#[derive(Parser, Debug)]
+#[command(name = "bwfs-diff")]
+#[command(about = "Report added, removed and modified files between two BWFS filesystems", long_about = None)]
+struct Args {
+    /// Configuration file of the first (old) filesystem
+    #[arg(short = 'a', long = "config-a")]
+    config_a: String,
+    
+    /// Configuration file of the second (new) filesystem
+    #[arg(short = 'b', long = "config-b")]
+    config_b: String,
+    
+    /// Also report files whose only difference is the modification time
+    #[arg(long = "mtime")]
+    mtime: bool,
+}
+
+fn load(path: &str) -> Result<BWFS> {
+    let config = Config::from_file(path)?;
+    config.validate()?;
+    BWFS::load(config)
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    
+    let args = Args::parse();
+    
+    let fs_a = load(&args.config_a)?;
+    let fs_b = load(&args.config_b)?;
+    
+    let changes = diff::diff(&fs_a, &fs_b, args.mtime)?;
+    
+    for change in &changes {
+        match change.kind {
+            ChangeKind::Added => println!("A  {}", change.path),
+            ChangeKind::Removed => println!("D  {}", change.path),
+            ChangeKind::Modified => {
+                println!("M  {} ({})", change.path, change.reasons.join(", "))
+            }
+        }
+    }
+    
+    if changes.is_empty() {
+        println!("No differences");
+    } else {
+        println!("\n{} difference(s)", changes.len());
+        std::process::exit(1);
+    }
+    
+    Ok(())
+}
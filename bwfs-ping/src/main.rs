@@ -0,0 +1,79 @@
+use clap::Parser;
+use bwfs::network::NetworkClient;
+use bwfs::Config;
+use anyhow::Result;
+use std::time::Duration;
+
+/// bwfs-ping - Check that the distributed nodes of a BWFS config answer
+#[derive(Parser, Debug)]
+#[command(name = "bwfs-ping")]
+#[command(about = "Ping every node in the [network] section of a BWFS config and report its round-trip time", long_about = None)]
+struct Args {
+    /// Path to configuration file
+    #[arg(short = 'c', long = "config")]
+    config: String,
+
+    /// Seconds to wait for each node before reporting it unreachable
+    #[arg(short = 't', long = "timeout", default_value_t = 5.0)]
+    timeout: f64,
+
+    /// Pings sent to each node; the best round trip is reported
+    #[arg(short = 'n', long = "count", default_value_t = 1)]
+    count: u32,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+    if !args.timeout.is_finite() || args.timeout <= 0.0 || args.count == 0 {
+        anyhow::bail!("--timeout must be positive and --count at least 1");
+    }
+
+    let config = Config::from_file(&args.config)?;
+    if config.distributed_nodes.is_empty() {
+        println!("No distributed nodes configured in {}", args.config);
+        return Ok(());
+    }
+
+    let client = NetworkClient::new(config.distributed_nodes, config.fingerprint)
+        .with_ping_timeout(Duration::from_secs_f64(args.timeout));
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    let mut unreachable = 0;
+    for (node_idx, addr) in client.nodes().iter().enumerate() {
+        let mut best: Option<Duration> = None;
+        let mut last_error = None;
+        for _ in 0..args.count {
+            match runtime.block_on(client.ping(node_idx)) {
+                Ok(rtt) => best = Some(best.map_or(rtt, |b| b.min(rtt))),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match (best, last_error) {
+            (Some(rtt), None) => {
+                println!("✓ node{} {:<24} {:.2} ms", node_idx + 1, addr, rtt.as_secs_f64() * 1000.0);
+            }
+            (Some(rtt), Some(e)) => {
+                println!(
+                    "! node{} {:<24} {:.2} ms, but some pings failed: {:#}",
+                    node_idx + 1, addr, rtt.as_secs_f64() * 1000.0, e
+                );
+            }
+            (None, e) => {
+                unreachable += 1;
+                println!(
+                    "✗ node{} {:<24} unreachable: {:#}",
+                    node_idx + 1, addr, e.unwrap_or_else(|| anyhow::anyhow!("no answer"))
+                );
+            }
+        }
+    }
+
+    if unreachable > 0 {
+        println!("\n{} of {} node(s) unreachable", unreachable, client.nodes().len());
+        std::process::exit(1);
+    }
+    println!("\n✓ All {} node(s) reachable", client.nodes().len());
+    Ok(())
+}
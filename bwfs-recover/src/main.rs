@@ -0,0 +1,103 @@
+use clap::Parser;
+use bwfs::Config;
+use anyhow::Result;
+use std::path::Path;
+
+/// bwfs-recover - Rewrite the superblock of a BWFS filesystem whose block 0
+/// was overwritten
+#[derive(Parser, Debug)]
+#[command(name = "bwfs-recover")]
+#[command(about = "Rewrite the fingerprint of a BWFS (Black and White FileSystem) whose superblock was overwritten", long_about = None)]
+struct Args {
+    /// Path to configuration file
+    #[arg(short = 'c', long = "config")]
+    config: String,
+
+    /// Proceed even if the filesystem appears to be mounted
+    #[arg(long = "force")]
+    force: bool,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    println!("bwfs-recover - Recovering Black and White FileSystem superblock");
+    println!("===============================================================");
+
+    // Load configuration
+    println!("Loading configuration from: {}", args.config);
+    let config = Config::from_file(&args.config)?;
+    config.validate()?;
+
+    let storage_path = Path::new(&config.storage_path);
+    if !storage_path.is_dir() {
+        anyhow::bail!(
+            "Storage path {} does not exist or is not a directory",
+            config.storage_path
+        );
+    }
+
+    if bwfs::storage::is_mounted(storage_path) {
+        if !args.force {
+            anyhow::bail!(
+                "Filesystem at {} is mounted; unmount it first or pass --force",
+                config.storage_path
+            );
+        }
+        println!("Warning: filesystem appears to be mounted, continuing (--force)");
+    }
+
+    let storage = bwfs::storage::BlockStorage::new(
+        &config.storage_path,
+        config.block_width,
+        config.block_height,
+        config.total_blocks,
+        config.fingerprint.clone(),
+    )?
+    .with_bits_per_pixel(config.bits_per_pixel)?
+    .with_encoding(config.encoding)
+    .with_pixel_threshold(config.pixel_threshold)
+    .with_png_compression(config.png_compression);
+
+    // Un bloque 0 ilegible cuenta como fingerprint que no coincide
+    if storage.verify_fingerprint().unwrap_or(false) {
+        println!("\n✓ Block 0 already starts with fingerprint '{}'; nothing to do.", config.fingerprint);
+        return Ok(());
+    }
+
+    println!();
+    println!("!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
+    println!("WARNING: this bypasses the fingerprint check that keeps mount.bwfs from");
+    println!("using the wrong storage directory. Block 0 is rewritten with the");
+    println!("fingerprint from {} without checking that", args.config);
+    println!("{} really holds that filesystem.", config.storage_path);
+    println!("!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
+    println!();
+
+    let recovery = storage.rewrite_superblock(config.total_inodes)?;
+    println!("✓ Fingerprint '{}' written to block 0", config.fingerprint);
+
+    if !recovery.geometry {
+        println!("Warning: geometry not recorded in superblock; --auto mount unavailable");
+    }
+    if recovery.merkle_root {
+        println!("✓ Merkle root kept");
+    } else if config.integrity_tree {
+        println!("Warning: Merkle root lost; it is stored again at the next checkpoint after mounting");
+    }
+    if recovery.metadata_record {
+        println!("✓ Metadata area record kept");
+    } else if config.metadata_blocks > 0 {
+        if config.metadata_cache {
+            println!("Warning: metadata area record lost; the next mount loads the metadata cache file");
+        } else {
+            println!("Warning: metadata area record lost and metadata_cache = false; the filesystem cannot be loaded");
+        }
+    }
+
+    println!("Run bwfs_verify -c {} --check before mounting.", args.config);
+
+    Ok(())
+}
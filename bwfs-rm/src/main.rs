@@ -0,0 +1,63 @@
+use clap::Parser;
+use bwfs::{Config, BWFS};
+use anyhow::Result;
+
+/// bwfs-rm - Recursively remove paths from an unmounted BWFS filesystem
+#[derive(Parser, Debug)]
+#[command(name = "bwfs-rm")]
+#[command(about = "Recursively remove files and directories from an unmounted BWFS filesystem", long_about = None)]
+struct Args {
+    /// Path to configuration file
+    #[arg(short = 'c', long = "config")]
+    config: String,
+
+    /// Paths to remove, absolute from the filesystem root (e.g. /docs/old)
+    #[arg(value_name = "PATH", required = true)]
+    paths: Vec<String>,
+
+    /// Remove even if the filesystem appears to be mounted
+    #[arg(long = "force")]
+    force: bool,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    println!("bwfs-rm - Removing from Black and White FileSystem");
+    println!("==================================================");
+
+    // Load configuration
+    println!("Loading configuration from: {}", args.config);
+    let config = Config::from_file(&args.config)?;
+    config.validate()?;
+
+    if !BWFS::has_metadata(&config.storage_path) {
+        anyhow::bail!("No filesystem metadata in storage path. Did you run mkfs.bwfs?");
+    }
+
+    // Guardar la metadata pisaría el estado del montaje activo
+    if bwfs::storage::is_mounted(std::path::Path::new(&config.storage_path)) {
+        if !args.force {
+            anyhow::bail!(
+                "Filesystem at {} is mounted; unmount it first or pass --force",
+                config.storage_path
+            );
+        }
+        println!("Warning: filesystem appears to be mounted, continuing (--force)");
+    }
+
+    let fs = BWFS::load(config)?;
+
+    let mut total = 0;
+    for path in &args.paths {
+        let removed = fs.remove_tree(path)?;
+        println!("Removed {} ({} entries)", path, removed);
+        total += removed;
+    }
+
+    println!("\n✓ Removed {} entries in total", total);
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use clap::Parser;
+use bwfs::{Config, BWFS};
+use anyhow::Result;
+
+/// bwfs-stat - Report space usage of an unmounted BWFS filesystem
+#[derive(Parser, Debug)]
+#[command(name = "bwfs-stat")]
+#[command(about = "Report block and inode usage of a BWFS filesystem without reading its data blocks", long_about = None)]
+struct Args {
+    /// Path to configuration file
+    #[arg(short = 'c', long = "config")]
+    config: String,
+}
+
+/// Format a byte count with a binary unit (e.g. "1.5 MiB")
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    let config = Config::from_file(&args.config)?;
+    config.validate()?;
+
+    if !BWFS::has_metadata(&config.storage_path) {
+        anyhow::bail!("No filesystem metadata in storage path. Did you run mkfs.bwfs?");
+    }
+
+    let fs = BWFS::load(config)?;
+    let report = fs.usage_report();
+    let block_bytes = |blocks: u64| human_size(blocks * report.block_size as u64);
+    let percent = |part: u64, total: u64| {
+        if total == 0 {
+            0.0
+        } else {
+            part as f64 * 100.0 / total as f64
+        }
+    };
+
+    println!("Block size:   {} bytes", report.block_size);
+    println!(
+        "Blocks:       {} total, {} used ({:.1}%), {} free",
+        report.total_blocks,
+        report.used_blocks,
+        percent(report.used_blocks, report.total_blocks),
+        report.free_blocks
+    );
+    println!(
+        "Space:        {} total, {} used, {} free",
+        block_bytes(report.total_blocks),
+        block_bytes(report.used_blocks),
+        block_bytes(report.free_blocks)
+    );
+    println!(
+        "Inodes:       {} total, {} used ({:.1}%)",
+        report.total_inodes,
+        report.used_inodes,
+        percent(report.used_inodes, report.total_inodes)
+    );
+    println!(
+        "Entries:      {} file(s), {} dir(s), {} symlink(s), {} special",
+        report.regular_files, report.directories, report.symlinks, report.special_files
+    );
+
+    if !report.largest_files.is_empty() {
+        println!("\nLargest files by blocks:");
+        println!("{:>8}  {:>10}  PATH", "BLOCKS", "SIZE");
+        for (path, blocks, size) in &report.largest_files {
+            println!("{:>8}  {:>10}  {}", blocks, human_size(*size), path);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use clap::Parser;
+use bwfs::{Config, BWFS};
+use anyhow::Result;
+use std::path::PathBuf;
+
+/// bwfs-tar - Export or import a BWFS filesystem as a tar archive
+#[derive(Parser, Debug)]
+#[command(name = "bwfs-tar")]
+#[command(about = "Write the files of an unmounted BWFS filesystem to a tar archive, or fill an empty one from a tar archive", long_about = None)]
+struct Args {
+    /// Path to configuration file
+    #[arg(short = 'c', long = "config")]
+    config: String,
+
+    /// Write every file, directory and symlink to this tar archive
+    #[arg(long = "export", value_name = "TAR", conflicts_with = "import", required_unless_present = "import")]
+    export: Option<PathBuf>,
+
+    /// Populate the (empty) filesystem from this tar archive
+    #[arg(long = "import", value_name = "TAR")]
+    import: Option<PathBuf>,
+
+    /// Import even if the filesystem appears to be mounted
+    #[arg(long = "force")]
+    force: bool,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    let config = Config::from_file(&args.config)?;
+    config.validate()?;
+
+    if !BWFS::has_metadata(&config.storage_path) {
+        anyhow::bail!("No filesystem metadata in storage path. Did you run mkfs.bwfs?");
+    }
+
+    if args.import.is_some() && bwfs::storage::is_mounted(std::path::Path::new(&config.storage_path)) {
+        if !args.force {
+            anyhow::bail!(
+                "Filesystem at {} is mounted; unmount it first or pass --force",
+                config.storage_path
+            );
+        }
+        println!("Warning: filesystem appears to be mounted, continuing (--force)");
+    }
+
+    let fs = BWFS::load(config)?;
+
+    if let Some(tar_path) = &args.export {
+        let count = fs.export_tar(tar_path)?;
+        println!("✓ Exported {} entries to {}", count, tar_path.display());
+    } else if let Some(tar_path) = &args.import {
+        let count = fs.import_tar(tar_path)?;
+        println!("✓ Imported {} entries from {}", count, tar_path.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use clap::Parser;
+use bwfs::{Config, BWFS};
+use anyhow::Result;
+use std::path::Path;
+
+/// bwfs-tune - Change parameters of an existing BWFS filesystem
+#[derive(Parser, Debug)]
+#[command(name = "bwfs-tune")]
+#[command(about = "Change parameters of an existing BWFS (Black and White FileSystem)", long_about = None)]
+struct Args {
+    /// Path to configuration file
+    #[arg(short = 'c', long = "config")]
+    config: String,
+
+    /// New fingerprint to write to the superblock
+    #[arg(long = "fingerprint", value_name = "NEW")]
+    fingerprint: Option<String>,
+
+    /// Move file data that points at the reserved superblock (block 0) to
+    /// fresh blocks
+    #[arg(long = "relocate-reserved")]
+    relocate_reserved: bool,
+
+    /// Proceed even if the filesystem appears to be mounted
+    #[arg(long = "force")]
+    force: bool,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    println!("bwfs-tune - Tuning Black and White FileSystem");
+    println!("=============================================");
+
+    // Load configuration
+    println!("Loading configuration from: {}", args.config);
+    let config = Config::from_file(&args.config)?;
+    config.validate()?;
+
+    let storage_path = Path::new(&config.storage_path);
+    if !BWFS::has_metadata(&config.storage_path) {
+        anyhow::bail!("No filesystem metadata in storage path. Did you run mkfs.bwfs?");
+    }
+
+    if bwfs::storage::is_mounted(storage_path) {
+        if !args.force {
+            anyhow::bail!(
+                "Filesystem at {} is mounted; unmount it first or pass --force",
+                config.storage_path
+            );
+        }
+        println!("Warning: filesystem appears to be mounted, continuing (--force)");
+    }
+
+    if args.fingerprint.is_none() && !args.relocate_reserved {
+        println!("Nothing to change.");
+        return Ok(());
+    }
+
+    let mut fs = BWFS::load(config)?;
+
+    if args.relocate_reserved {
+        let relocated = fs.relocate_reserved_blocks()?;
+        println!("\n✓ Relocated {} block pointer(s) away from block 0", relocated);
+    }
+
+    if let Some(fingerprint) = args.fingerprint {
+        fs.set_fingerprint(&fingerprint)?;
+
+        println!("\n✓ Fingerprint changed to '{}'", fingerprint.trim());
+        println!("Update 'fingerprint' in {} before mounting again.", args.config);
+    }
+
+    Ok(())
+}
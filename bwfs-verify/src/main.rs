@@ -0,0 +1,113 @@
+use clap::Parser;
+use bwfs::{Config, BWFS};
+use bwfs::hash;
+use anyhow::Result;
+
+/// bwfs-verify - Check the integrity of an unmounted BWFS filesystem
+#[derive(Parser, Debug)]
+#[command(name = "bwfs-verify")]
+#[command(about = "Check block integrity and print the content digest of a BWFS filesystem", long_about = None)]
+struct Args {
+    /// Path to configuration file
+    #[arg(short = 'c', long = "config")]
+    config: String,
+
+    /// Print a SHA-256 of the directory tree and file contents, independent
+    /// of block layout (equal for filesystems with the same content)
+    #[arg(long = "digest")]
+    digest: bool,
+
+    /// Check that inodes, directories and the block bitmap agree (e.g.
+    /// after a crash) instead of verifying block contents
+    #[arg(long = "check")]
+    check: bool,
+
+    /// Compare the block bitmap with the blocks the inodes use and look for
+    /// directory entries pointing at missing inodes
+    #[arg(long = "fsck")]
+    fsck: bool,
+
+    /// With --fsck, free leaked blocks, mark used blocks as allocated and
+    /// drop dangling directory entries
+    #[arg(long = "repair", requires = "fsck")]
+    repair: bool,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    let config = Config::from_file(&args.config)?;
+    config.validate()?;
+
+    if !BWFS::has_metadata(&config.storage_path) {
+        anyhow::bail!("No filesystem metadata in storage path. Did you run mkfs.bwfs?");
+    }
+
+    let integrity_tree = config.integrity_tree;
+    let fs = BWFS::load(config)?;
+
+    if args.digest {
+        println!("{}", hash::to_hex(&fs.content_digest()?));
+        return Ok(());
+    }
+
+    if args.check {
+        let problems = fs.check_consistency();
+        if problems.is_empty() {
+            println!("✓ Metadata is consistent");
+            return Ok(());
+        }
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        println!("\n{} problem(s)", problems.len());
+        std::process::exit(1);
+    }
+
+    if args.fsck {
+        let report = fs.fsck(args.repair)?;
+        for block_num in &report.leaked_blocks {
+            println!("Block {} is allocated but not used by any inode", block_num);
+        }
+        for block_num in &report.unmarked_blocks {
+            println!("Block {} is used but marked free", block_num);
+        }
+        for block_num in &report.doubly_allocated_blocks {
+            println!("Block {} is referenced by more than one inode pointer", block_num);
+        }
+        for (dir_ino, name, ino) in &report.dangling_entries {
+            println!("Entry '{}' in directory {} points to missing inode {}", name, dir_ino, ino);
+        }
+        if report.is_clean() {
+            println!("✓ Block bitmap and directories are consistent");
+            return Ok(());
+        }
+        if report.repaired {
+            // Los bloques doblemente asignados solo se informan
+            println!("
+✓ Repaired; doubly allocated blocks need manual attention");
+            return Ok(());
+        }
+        std::process::exit(1);
+    }
+
+    if !integrity_tree {
+        println!("integrity_tree is disabled; nothing to verify (use --digest for a content hash)");
+        return Ok(());
+    }
+
+    let divergent = fs.verify_tree()?;
+    if divergent.is_empty() {
+        println!("✓ All blocks match the Merkle tree");
+    } else {
+        for block_num in &divergent {
+            println!("Block {} does not match its Merkle leaf", block_num);
+        }
+        println!("\n{} divergent block(s)", divergent.len());
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Capacity the cache starts with (or its ceiling, if lower)
+const INITIAL_CAPACITY_BYTES: usize = 4 * 1024 * 1024;
+
+/// Lookups between two capacity adjustments
+const ADJUST_WINDOW: u64 = 256;
+
+/// Grow when a window that evicted blocks hit less often than this
+const GROW_BELOW_HIT_RATE: f64 = 0.9;
+
+/// Shrink when less than this fraction of system memory is available
+const PRESSURE_AVAILABLE_RATIO: f64 = 0.1;
+
+/// Snapshot of the block cache counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    /// Bytes of block data currently cached
+    pub bytes: usize,
+    /// Current adaptive capacity in bytes
+    pub capacity_bytes: usize,
+    /// Configured ceiling (`cache_max_bytes`)
+    pub max_bytes: usize,
+    /// Number of cached blocks
+    pub entries: usize,
+    /// Bytes held by pinned blocks (included in `bytes`)
+    pub pinned_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// LRU cache of decoded blocks, bounded in bytes.
+///
+/// The capacity starts small and doubles (up to `max_bytes`) while the
+/// cache keeps evicting and missing; it halves when the system runs low on
+/// memory and resets on `trim`. Pinned blocks are never evicted.
+pub struct BlockCache {
+    max_bytes: usize,
+    capacity: usize,
+    bytes: usize,
+
+    /// block -> (data, last use)
+    entries: HashMap<u32, (Vec<u8>, u64)>,
+
+    /// last use -> block, oldest first (pinned blocks are not listed)
+    order: BTreeMap<u64, u32>,
+
+    /// Blocks kept resident regardless of LRU pressure
+    pinned: HashSet<u32>,
+
+    tick: u64,
+
+    hits: u64,
+    misses: u64,
+
+    /// Counters of the current adjustment window
+    window_lookups: u64,
+    window_hits: u64,
+    window_evictions: u64,
+}
+
+impl BlockCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            capacity: max_bytes.min(INITIAL_CAPACITY_BYTES),
+            bytes: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            pinned: HashSet::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+            window_lookups: 0,
+            window_hits: 0,
+            window_evictions: 0,
+        }
+    }
+
+    /// Cached contents of a block, marking it most recently used
+    pub fn get(&mut self, block_num: u32) -> Option<Vec<u8>> {
+        self.window_lookups += 1;
+        self.tick += 1;
+        let tick = self.tick;
+
+        let found = match self.entries.get_mut(&block_num) {
+            Some((data, last_use)) => {
+                if !self.pinned.contains(&block_num) {
+                    self.order.remove(last_use);
+                    self.order.insert(tick, block_num);
+                }
+                *last_use = tick;
+                Some(data.clone())
+            }
+            None => None,
+        };
+
+        if found.is_some() {
+            self.hits += 1;
+            self.window_hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        if self.window_lookups >= ADJUST_WINDOW {
+            self.adjust();
+        }
+        found
+    }
+
+    /// Cache a block, evicting least recently used ones to stay under the
+    /// current capacity. Blocks larger than the capacity are not cached,
+    /// unless pinned: those are always kept.
+    pub fn insert(&mut self, block_num: u32, data: Vec<u8>) {
+        self.remove(block_num);
+        let pinned = self.pinned.contains(&block_num);
+        if !pinned {
+            if data.len() > self.capacity {
+                return;
+            }
+            self.evict_to(self.capacity - data.len());
+        }
+
+        self.tick += 1;
+        self.bytes += data.len();
+        if !pinned {
+            self.order.insert(self.tick, block_num);
+        }
+        self.entries.insert(block_num, (data, self.tick));
+    }
+
+    /// Keep a block resident once cached, exempt from eviction
+    pub fn pin(&mut self, block_num: u32) {
+        if self.pinned.insert(block_num) {
+            if let Some((_, last_use)) = self.entries.get(&block_num) {
+                self.order.remove(last_use);
+            }
+        }
+    }
+
+    /// Make a pinned block evictable again
+    pub fn unpin(&mut self, block_num: u32) {
+        if self.pinned.remove(&block_num) {
+            if let Some((_, last_use)) = self.entries.get(&block_num) {
+                self.order.insert(*last_use, block_num);
+            }
+        }
+    }
+
+    /// Drop a block (it was rewritten)
+    pub fn remove(&mut self, block_num: u32) {
+        if let Some((data, last_use)) = self.entries.remove(&block_num) {
+            self.order.remove(&last_use);
+            self.bytes -= data.len();
+        }
+    }
+
+    /// Lower the current capacity to at most `bytes`, evicting as needed.
+    /// The capacity may grow back toward `max_bytes` later.
+    pub fn shrink_to(&mut self, bytes: usize) {
+        self.capacity = self.capacity.min(bytes);
+        self.evict_to(self.capacity);
+    }
+
+    /// Drop every cached block and return to the initial capacity. Pins
+    /// are kept, so pinned blocks return on their next read.
+    pub fn trim(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.bytes = 0;
+        self.capacity = self.max_bytes.min(INITIAL_CAPACITY_BYTES);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            bytes: self.bytes,
+            capacity_bytes: self.capacity,
+            max_bytes: self.max_bytes,
+            entries: self.entries.len(),
+            pinned_bytes: self
+                .pinned
+                .iter()
+                .filter_map(|block_num| self.entries.get(block_num))
+                .map(|(data, _)| data.len())
+                .sum(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+
+    /// Evict least recently used blocks until at most `limit` bytes remain
+    /// (or only pinned blocks are left)
+    fn evict_to(&mut self, limit: usize) {
+        while self.bytes > limit {
+            let Some((_, block_num)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((data, _)) = self.entries.remove(&block_num) {
+                self.bytes -= data.len();
+                self.window_evictions += 1;
+            }
+        }
+    }
+
+    /// End of a window: shrink under memory pressure, otherwise grow if the
+    /// cache was too small for the working set
+    fn adjust(&mut self) {
+        let hit_rate = self.window_hits as f64 / self.window_lookups as f64;
+
+        if under_memory_pressure() {
+            let floor = self.max_bytes.min(INITIAL_CAPACITY_BYTES);
+            self.capacity = (self.capacity / 2).max(floor);
+            self.evict_to(self.capacity);
+            log::debug!("Block cache shrunk to {} bytes (memory pressure)", self.capacity);
+        } else if self.window_evictions > 0
+            && hit_rate < GROW_BELOW_HIT_RATE
+            && self.capacity < self.max_bytes
+        {
+            self.capacity = self.capacity.saturating_mul(2).min(self.max_bytes);
+            log::debug!(
+                "Block cache grown to {} bytes (hit rate {:.0}%)",
+                self.capacity,
+                hit_rate * 100.0
+            );
+        }
+
+        self.window_lookups = 0;
+        self.window_hits = 0;
+        self.window_evictions = 0;
+    }
+}
+
+/// Whether MemAvailable has dropped below `PRESSURE_AVAILABLE_RATIO` of
+/// MemTotal. Systems without /proc/meminfo never report pressure.
+fn under_memory_pressure() -> bool {
+    let Ok(meminfo) = std::fs::read_to_string("/proc/meminfo") else {
+        return false;
+    };
+    let field = |name: &str| -> Option<f64> {
+        meminfo
+            .lines()
+            .find(|line| line.starts_with(name))?
+            .split_whitespace()
+            .nth(1)?
+            .parse()
+            .ok()
+    };
+    match (field("MemTotal:"), field("MemAvailable:")) {
+        (Some(total), Some(available)) if total > 0.0 => available / total < PRESSURE_AVAILABLE_RATIO,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_evicts_to_stay_under_the_byte_ceiling() {
+        let ceiling = 8 * 512;
+        let mut cache = BlockCache::new(ceiling);
+
+        // Un recorrido que no cabe: fallos y desalojos ventana tras ventana
+        for round in 0..4 * ADJUST_WINDOW as u32 {
+            let block_num = round % 20;
+            if cache.get(block_num).is_none() {
+                cache.insert(block_num, vec![block_num as u8; 512]);
+            }
+            let stats = cache.stats();
+            assert!(stats.bytes <= ceiling, "{} cached bytes over a {} ceiling", stats.bytes, ceiling);
+            assert!(stats.capacity_bytes <= ceiling);
+        }
+        assert_eq!(cache.stats().entries, 8);
+
+        // Los más recientes siguen; los más viejos se desalojaron
+        let last = (4 * ADJUST_WINDOW as u32 - 1) % 20;
+        assert_eq!(cache.get(last), Some(vec![last as u8; 512]));
+        assert_eq!(cache.get((last + 1) % 20), None);
+
+        // Un bloque mayor que la capacidad no se guarda
+        cache.insert(99, vec![0; ceiling + 1]);
+        assert!(cache.get(99).is_none());
+        assert!(cache.stats().bytes <= ceiling);
+    }
+}
@@ -1,7 +1,29 @@
 use serde::{Deserialize, Serialize};
 
+/// Default ceiling of the block cache (64 MiB)
+const DEFAULT_CACHE_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Default permissions of entries created without an explicit mode
+const DEFAULT_FILE_MODE: u16 = 0o644;
+const DEFAULT_DIR_MODE: u16 = 0o755;
+
+/// Default inode numbering: root is 1 and allocation starts right after
+const DEFAULT_ROOT_INO: u64 = 1;
+const DEFAULT_FIRST_INO: u64 = 2;
+
+/// Default size limit of files stored inline in their inode
+const DEFAULT_INLINE_DATA_MAX_BYTES: u64 = 60;
+
+/// Keys `from_toml`/`from_json` require, like `from_ini` does
+const REQUIRED_KEYS: [&str; 3] = ["name", "total_blocks", "storage_path"];
+
 /// Configuration for BWFS filesystem
+///
+/// Loaded from INI (`from_ini`), TOML (`from_toml`) or JSON (`from_json`);
+/// in the last two every field is a top-level key named like the field and
+/// missing ones take the `Default` value.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     /// Filesystem name
     pub name: String,
@@ -27,9 +49,436 @@ pub struct Config {
     
     /// TCP port for network communication
     pub tcp_port: u16,
+    
+    /// IP address the block server binds to (loopback by default; use
+    /// `0.0.0.0` to serve peers on other hosts)
+    pub bind_address: String,
+    
+    /// Maximum length of a file name in bytes (reported by statfs)
+    pub max_name_len: u32,
+    
+    /// Maintain a Merkle tree over all data blocks (root kept in the superblock)
+    pub integrity_tree: bool,
+    
+    /// Verify every block read against its Merkle leaf (needs `integrity_tree`)
+    pub paranoid: bool,
+    
+    /// Times a block read is retried after a transient I/O failure
+    pub read_retries: u32,
+    
+    /// Delay before the first read retry in milliseconds (doubles on each retry)
+    pub retry_backoff_ms: u64,
+    
+    /// zlib effort used when encoding block PNGs
+    pub png_compression: PngCompression,
+    
+    /// Encoding of the persisted filesystem metadata
+    pub metadata_format: MetadataFormat,
+    
+    /// When metadata changes are checkpointed to disk
+    pub metadata_sync: MetadataSync,
+    
+    /// Period of the background checkpoint under `MetadataSync::Interval`
+    /// (0 disables the thread: only fsync, release and unmount checkpoint)
+    pub metadata_sync_interval_ms: u64,
+    
+    /// Closes within this many milliseconds of the last close-triggered
+    /// save defer their save to a background flush (0 saves on every close)
+    pub save_debounce_ms: u64,
+    
+    /// Ceiling of the in-memory block cache in bytes (0 disables it)
+    pub cache_max_bytes: usize,
+    
+    /// Soft limit on the estimated memory of metadata plus block cache
+    /// (0 = unlimited)
+    pub max_memory_bytes: usize,
+    
+    /// Blocks kept in the cache regardless of LRU pressure, in addition to
+    /// the superblock (always pinned)
+    pub cache_pinned_blocks: Vec<u32>,
+    
+    /// Permission bits for files created without an explicit mode
+    pub default_file_mode: u16,
+    
+    /// Permission bits for directories created without an explicit mode
+    /// (including the root directory made by mkfs)
+    pub default_dir_mode: u16,
+    
+    /// Inode number of the root directory made by mkfs. The kernel always
+    /// sees the root as inode 1; the mount translates between the two.
+    pub root_ino: u64,
+    
+    /// First inode number handed out to new files and directories
+    pub first_ino: u64,
+    
+    /// Bits stored in each pixel of a data block (1, 2, 4 or 8). Above 1
+    /// pixels become gray levels; the superblock always uses 1 bit.
+    pub bits_per_pixel: u8,
+    
+    /// Grayscale or RGB data blocks (RGB stores `bits_per_pixel` bits in
+    /// each channel). Recorded in the superblock by mkfs.
+    pub encoding: Encoding,
+    
+    /// 1-bit samples brighter than this read as 1. Writes always use
+    /// 0/255; only reads are affected.
+    pub pixel_threshold: u8,
+    
+    /// Files and symlinks up to this many bytes keep their data in the
+    /// inode instead of a block (0 disables inline data; capped at the
+    /// size of a data block)
+    pub inline_data_max_bytes: u64,
+    
+    /// Blocks per copy of the metadata area that mkfs reserves after the
+    /// superblock (two copies, written alternately). Checkpoints stored
+    /// there make the images self-contained; 0 keeps the metadata only in
+    /// the metadata file. Fixed at mkfs time.
+    pub metadata_blocks: u32,
+    
+    /// Also write `metadata.json`/`metadata.bin` as a cache that loads
+    /// faster than the metadata area. Always written without an area.
+    pub metadata_cache: bool,
+    
+    /// When reads update atime; the mount options `--noatime`, `--relatime`
+    /// and `--strictatime` override it
+    pub atime_mode: AtimePolicy,
+    
+    /// zlib-compress data blocks before packing them into pixels (blocks
+    /// that do not shrink stay raw; both kinds are read either way)
+    pub block_compression: bool,
+    
+    /// Record data block writes in `journal.log` so a crash before the next
+    /// checkpoint rolls them forward on load instead of losing them. An
+    /// existing journal is replayed even when this is off.
+    pub journal: bool,
+    
+    /// When journal records are synced to disk
+    pub journal_sync: JournalSync,
+    
+    /// Least time between two journal syncs under `JournalSync::Interval`
+    /// (0 syncs at every commit)
+    pub journal_sync_interval_ms: u64,
+}
+
+/// When reads update an inode's access time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AtimePolicy {
+    /// Update atime on every read
+    Strict,
+    /// Update atime only if it is not newer than mtime/ctime, or is older than 24h
+    #[default]
+    Relatime,
+    /// Never update atime on read
+    Noatime,
+}
+
+impl AtimePolicy {
+    /// Parse an INI value (strict/relatime/noatime)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "strict" | "strictatime" => Some(Self::Strict),
+            "relatime" => Some(Self::Relatime),
+            "noatime" => Some(Self::Noatime),
+            _ => None,
+        }
+    }
+}
+
+/// Color model of the data block images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// One gray sample per pixel
+    #[default]
+    Grayscale,
+    /// Three samples (R, G, B) per pixel: three times the data per image
+    Rgb,
+}
+
+impl Encoding {
+    /// Parse an INI value (grayscale/rgb)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "grayscale" | "gray" => Some(Self::Grayscale),
+            "rgb" => Some(Self::Rgb),
+            _ => None,
+        }
+    }
+    
+    /// Samples stored per pixel
+    pub fn channels(self) -> usize {
+        match self {
+            Self::Grayscale => 1,
+            Self::Rgb => 3,
+        }
+    }
+}
+
+/// zlib compression level for block PNGs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PngCompression {
+    /// Fastest encoding, larger files (write-heavy workloads)
+    Fast,
+    /// The encoder's default trade-off
+    #[default]
+    Default,
+    /// Smallest files, slowest encoding (archival)
+    Best,
+}
+
+impl PngCompression {
+    /// Parse an INI value (fast/default/best)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fast" => Some(Self::Fast),
+            "default" => Some(Self::Default),
+            "best" => Some(Self::Best),
+            _ => None,
+        }
+    }
+}
+
+/// On-disk encoding of the filesystem metadata
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataFormat {
+    /// Human-readable `metadata.json`
+    #[default]
+    Json,
+    /// Compact bincode-encoded `metadata.bin`
+    Binary,
+}
+
+impl MetadataFormat {
+    /// Parse an INI value (json/binary)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "binary" | "bin" => Some(Self::Binary),
+            _ => None,
+        }
+    }
+    
+    /// Name of the metadata file in the storage directory
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Json => "metadata.json",
+            Self::Binary => "metadata.bin",
+        }
+    }
+    
+    /// The other format, checked on load when this one's file is missing
+    pub fn other(self) -> Self {
+        match self {
+            Self::Json => Self::Binary,
+            Self::Binary => Self::Json,
+        }
+    }
+}
+
+/// When metadata changes reach the on-disk checkpoint.
+///
+/// Every change lives in memory until the next checkpoint, so this policy
+/// bounds how much metadata a crash can lose. With `journal` enabled, data
+/// writes made since the last checkpoint are rolled forward on load; other
+/// changes (creates, renames, truncates...) still depend on the checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataSync {
+    /// Checkpoint after every metadata change (nothing lost, slowest)
+    Always,
+    /// Checkpoint in the background every `metadata_sync_interval_ms`
+    /// (loses at most one interval of changes)
+    Interval,
+    /// Checkpoint only on fsync, release and unmount
+    #[default]
+    Never,
+}
+
+impl MetadataSync {
+    /// Parse an INI value (always/interval/never)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "always" => Some(Self::Always),
+            "interval" => Some(Self::Interval),
+            "never" => Some(Self::Never),
+            _ => None,
+        }
+    }
+}
+
+/// When journal records reach the disk.
+///
+/// Appended records sit in the page cache until synced: they survive the
+/// mount process dying but not a power loss or kernel crash. Every
+/// checkpoint syncs what it keeps of the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalSync {
+    /// Sync every record before its block is written (one fsync per block,
+    /// slowest; a crash loses no recorded write)
+    Always,
+    /// Sync at a commit if `journal_sync_interval_ms` passed since the last
+    /// sync (with 0, at every commit). A crash loses the operations
+    /// committed since the last sync.
+    #[default]
+    Interval,
+    /// Never sync; only checkpoints make the journal durable
+    Never,
+}
+
+impl JournalSync {
+    /// Parse an INI value (always/interval/never)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "always" => Some(Self::Always),
+            "interval" => Some(Self::Interval),
+            "never" => Some(Self::Never),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a list of blocks and inclusive ranges ("1, 4-7")
+fn parse_block_list(value: &str) -> Option<Vec<u32>> {
+    let mut blocks = Vec::new();
+    for part in value.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let start: u32 = start.trim().parse().ok()?;
+                let end: u32 = end.trim().parse().ok()?;
+                if start > end {
+                    return None;
+                }
+                blocks.extend(start..=end);
+            }
+            None => blocks.push(part.parse().ok()?),
+        }
+    }
+    Some(blocks)
+}
+
+/// Parse an octal permission value ("644", "0644" or "0o644")
+fn parse_mode(value: &str) -> Option<u16> {
+    let value = value.trim();
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    u16::from_str_radix(digits, 8).ok().filter(|mode| *mode <= 0o7777)
+}
+
+/// Parse an INI boolean (true/false, yes/no, on/off, 1/0)
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+impl Default for Config {
+    /// The values `from_ini` uses for missing keys. `name`, `total_blocks`
+    /// and `storage_path` have no real default and are left empty.
+    fn default() -> Self {
+        Config {
+            name: String::new(),
+            block_width: 1000,
+            block_height: 1000,
+            total_blocks: 0,
+            total_inodes: 1024,
+            storage_path: String::new(),
+            fingerprint: "BWFS".to_string(),
+            distributed_nodes: Vec::new(),
+            tcp_port: 9000,
+            bind_address: crate::network::DEFAULT_BIND_ADDRESS.to_string(),
+            max_name_len: 255,
+            integrity_tree: false,
+            paranoid: false,
+            read_retries: 0,
+            retry_backoff_ms: 50,
+            png_compression: PngCompression::default(),
+            metadata_format: MetadataFormat::default(),
+            metadata_sync: MetadataSync::default(),
+            metadata_sync_interval_ms: 5000,
+            save_debounce_ms: 0,
+            cache_max_bytes: DEFAULT_CACHE_MAX_BYTES,
+            max_memory_bytes: 0,
+            cache_pinned_blocks: Vec::new(),
+            default_file_mode: DEFAULT_FILE_MODE,
+            default_dir_mode: DEFAULT_DIR_MODE,
+            root_ino: DEFAULT_ROOT_INO,
+            first_ino: DEFAULT_FIRST_INO,
+            bits_per_pixel: 1,
+            encoding: Encoding::default(),
+            pixel_threshold: crate::storage::DEFAULT_PIXEL_THRESHOLD,
+            inline_data_max_bytes: DEFAULT_INLINE_DATA_MAX_BYTES,
+            metadata_blocks: 0,
+            metadata_cache: true,
+            atime_mode: AtimePolicy::default(),
+            block_compression: false,
+            journal: false,
+            journal_sync: JournalSync::default(),
+            journal_sync_interval_ms: 0,
+        }
+    }
 }
 
 impl Config {
+    /// Load a configuration file, choosing the format by extension:
+    /// `.toml` and `.json` are read with `from_toml`/`from_json`, anything
+    /// else as INI
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let extension = std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("toml") => Self::from_toml(path),
+            Some("json") => Self::from_json(path),
+            _ => Self::from_ini(path),
+        }
+    }
+    
+    /// Load configuration from a TOML file with one top-level key per
+    /// field, e.g. `distributed_nodes = ["10.0.0.1:9000", "10.0.0.2:9000"]`
+    pub fn from_toml(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load TOML: {}", e))?;
+        let table: toml::Table = toml::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Failed to parse TOML: {}", e))?;
+        Self::check_required_keys(|key| table.contains_key(key))?;
+        let mut config: Config = table
+            .try_into()
+            .map_err(|e| anyhow::anyhow!("Invalid configuration in {}: {}", path, e))?;
+        config.normalize()?;
+        
+        Ok(config)
+    }
+    
+    /// Load configuration from a JSON object with one key per field
+    pub fn from_json(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load JSON: {}", e))?;
+        let value: serde_json::Value = serde_json::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Failed to parse JSON: {}", e))?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| anyhow::anyhow!("{} must contain a JSON object", path))?;
+        Self::check_required_keys(|key| object.contains_key(key))?;
+        let mut config: Config = serde_json::from_value(value)
+            .map_err(|e| anyhow::anyhow!("Invalid configuration in {}: {}", path, e))?;
+        config.normalize()?;
+        
+        Ok(config)
+    }
+    
+    /// Same error as `from_ini` for a missing mandatory key
+    fn check_required_keys(has_key: impl Fn(&str) -> bool) -> anyhow::Result<()> {
+        match REQUIRED_KEYS.iter().find(|key| !has_key(key)) {
+            Some(key) => anyhow::bail!("Missing '{}' field", key),
+            None => Ok(()),
+        }
+    }
+    
     /// Load configuration from INI file
     pub fn from_ini(path: &str) -> anyhow::Result<Self> {
         use configparser::ini::Ini;
@@ -65,15 +514,182 @@ pub fn from_ini(path: &str) -> anyhow::Result<Self> {
             .and_then(|s| s.parse().ok())
             .unwrap_or(9000);
         
-        // Parse distributed nodes if present
-        let mut distributed_nodes = Vec::new();
-        for i in 1..10 {
-            if let Some(node) = ini.get("network", &format!("node{}", i)) {
-                distributed_nodes.push(node);
+        let bind_address = ini.get("filesystem", "bind_address")
+            .map(|s| s.trim().to_string())
+            .unwrap_or_else(|| crate::network::DEFAULT_BIND_ADDRESS.to_string());
+        
+        let max_name_len = ini.get("filesystem", "max_name_len")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(255);
+        
+        let integrity_tree = ini.get("filesystem", "integrity_tree")
+            .and_then(|s| parse_bool(&s))
+            .unwrap_or(false);
+        
+        let paranoid = ini.get("filesystem", "paranoid")
+            .and_then(|s| parse_bool(&s))
+            .unwrap_or(false);
+        
+        let read_retries = ini.get("filesystem", "read_retries")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        
+        let retry_backoff_ms = ini.get("filesystem", "retry_backoff_ms")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(50);
+        
+        let png_compression = match ini.get("filesystem", "png_compression") {
+            Some(s) => PngCompression::parse(&s).ok_or_else(|| {
+                anyhow::anyhow!("Invalid png_compression '{}' (expected fast, default or best)", s)
+            })?,
+            None => PngCompression::default(),
+        };
+        
+        let metadata_format = match ini.get("filesystem", "metadata_format") {
+            Some(s) => MetadataFormat::parse(&s).ok_or_else(|| {
+                anyhow::anyhow!("Invalid metadata_format '{}' (expected json or binary)", s)
+            })?,
+            None => MetadataFormat::default(),
+        };
+        
+        let metadata_sync = match ini.get("filesystem", "metadata_sync") {
+            Some(s) => MetadataSync::parse(&s).ok_or_else(|| {
+                anyhow::anyhow!("Invalid metadata_sync '{}' (expected always, interval or never)", s)
+            })?,
+            None => MetadataSync::default(),
+        };
+        
+        let metadata_sync_interval_ms = ini.get("filesystem", "metadata_sync_interval_ms")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(5000);
+        
+        let save_debounce_ms = ini.get("filesystem", "save_debounce_ms")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        
+        let cache_max_bytes = ini.get("filesystem", "cache_max_bytes")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_CACHE_MAX_BYTES);
+        
+        let max_memory_bytes = ini.get("filesystem", "max_memory_bytes")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        
+        let cache_pinned_blocks = match ini.get("filesystem", "cache_pinned_blocks") {
+            Some(s) => parse_block_list(&s).ok_or_else(|| {
+                anyhow::anyhow!("Invalid cache_pinned_blocks '{}' (expected e.g. 1, 4-7)", s)
+            })?,
+            None => Vec::new(),
+        };
+        
+        let default_file_mode = match ini.get("filesystem", "default_file_mode") {
+            Some(s) => parse_mode(&s).ok_or_else(|| {
+                anyhow::anyhow!("Invalid default_file_mode '{}' (expected octal, e.g. 644)", s)
+            })?,
+            None => DEFAULT_FILE_MODE,
+        };
+        
+        let default_dir_mode = match ini.get("filesystem", "default_dir_mode") {
+            Some(s) => parse_mode(&s).ok_or_else(|| {
+                anyhow::anyhow!("Invalid default_dir_mode '{}' (expected octal, e.g. 755)", s)
+            })?,
+            None => DEFAULT_DIR_MODE,
+        };
+        
+        let root_ino = match ini.get("filesystem", "root_ino") {
+            Some(s) => s.trim().parse().map_err(|_| anyhow::anyhow!("Invalid root_ino '{}'", s))?,
+            None => DEFAULT_ROOT_INO,
+        };
+        
+        let first_ino = match ini.get("filesystem", "first_ino") {
+            Some(s) => s.trim().parse().map_err(|_| anyhow::anyhow!("Invalid first_ino '{}'", s))?,
+            None => DEFAULT_FIRST_INO,
+        };
+        
+        let bits_per_pixel = match ini.get("filesystem", "bits_per_pixel") {
+            Some(s) => s.trim().parse().map_err(|_| anyhow::anyhow!("Invalid bits_per_pixel '{}'", s))?,
+            None => 1,
+        };
+        
+        let encoding = match ini.get("filesystem", "encoding") {
+            Some(s) => Encoding::parse(&s).ok_or_else(|| {
+                anyhow::anyhow!("Invalid encoding '{}' (expected grayscale or rgb)", s)
+            })?,
+            None => Encoding::default(),
+        };
+        
+        let pixel_threshold = match ini.get("filesystem", "pixel_threshold") {
+            Some(s) => s.trim().parse().map_err(|_| {
+                anyhow::anyhow!("Invalid pixel_threshold '{}' (expected 0-254)", s)
+            })?,
+            None => crate::storage::DEFAULT_PIXEL_THRESHOLD,
+        };
+        
+        let inline_data_max_bytes = ini.get("filesystem", "inline_data_max_bytes")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_INLINE_DATA_MAX_BYTES);
+        
+        let metadata_blocks = ini.get("filesystem", "metadata_blocks")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        
+        let metadata_cache = ini.get("filesystem", "metadata_cache")
+            .and_then(|s| parse_bool(&s))
+            .unwrap_or(true);
+        
+        let block_compression = ini.get("filesystem", "block_compression")
+            .and_then(|s| parse_bool(&s))
+            .unwrap_or(false);
+        
+        let journal = ini.get("filesystem", "journal")
+            .and_then(|s| parse_bool(&s))
+            .unwrap_or(false);
+        
+        let journal_sync = match ini.get("filesystem", "journal_sync") {
+            Some(s) => JournalSync::parse(&s).ok_or_else(|| {
+                anyhow::anyhow!("Invalid journal_sync '{}' (expected always, interval or never)", s)
+            })?,
+            None => JournalSync::default(),
+        };
+        
+        let journal_sync_interval_ms = ini.get("filesystem", "journal_sync_interval_ms")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        
+        let atime_mode = match ini.get("filesystem", "atime_mode") {
+            Some(s) => AtimePolicy::parse(&s).ok_or_else(|| {
+                anyhow::anyhow!("Invalid atime_mode '{}' (expected strict, relatime or noatime)", s)
+            })?,
+            None => AtimePolicy::default(),
+        };
+        
+        // Parse distributed nodes if present: every nodeN key, by number
+        let mut numbered_nodes: Vec<(u32, String)> = ini
+            .get_map_ref()
+            .get("network")
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                let number = key.strip_prefix("node")?.parse().ok()?;
+                Some((number, value.clone()?))
+            })
+            .collect();
+        numbered_nodes.sort_by_key(|(number, _)| *number);
+        for pair in numbered_nodes.windows(2) {
+            if pair[1].0 > pair[0].0 + 1 {
+                log::warn!(
+                    "[network] jumps from node{} to node{}; the missing nodes are skipped",
+                    pair[0].0,
+                    pair[1].0
+                );
             }
         }
+        if let Some((first, _)) = numbered_nodes.first().filter(|(first, _)| *first > 1) {
+            log::warn!("[network] starts at node{} instead of node1", first);
+        }
+        let distributed_nodes = numbered_nodes.into_iter().map(|(_, node)| node).collect();
         
-        Ok(Config {
+        let mut config = Config {
             name,
             block_width,
             block_height,
@@ -83,7 +699,228 @@ pub fn from_ini(path: &str) -> anyhow::Result<Self> {
             fingerprint,
             distributed_nodes,
             tcp_port,
-        })
+            bind_address,
+            max_name_len,
+            integrity_tree,
+            paranoid,
+            read_retries,
+            retry_backoff_ms,
+            png_compression,
+            metadata_format,
+            metadata_sync,
+            metadata_sync_interval_ms,
+            save_debounce_ms,
+            cache_max_bytes,
+            max_memory_bytes,
+            cache_pinned_blocks,
+            default_file_mode,
+            default_dir_mode,
+            root_ino,
+            first_ino,
+            bits_per_pixel,
+            encoding,
+            pixel_threshold,
+            inline_data_max_bytes,
+            metadata_blocks,
+            metadata_cache,
+            atime_mode,
+            block_compression,
+            journal,
+            journal_sync,
+            journal_sync_interval_ms,
+        };
+        config.normalize()?;
+        
+        Ok(config)
+    }
+    
+    /// Build a configuration from the geometry recorded in the superblock
+    /// at `storage_path`; every other setting takes its default value
+    pub fn from_superblock(storage_path: &str) -> anyhow::Result<Self> {
+        let geometry = crate::storage::BlockStorage::read_geometry(storage_path)?;
+        
+        let mut config = Config {
+            block_width: geometry.block_width,
+            block_height: geometry.block_height,
+            total_blocks: geometry.total_blocks,
+            total_inodes: geometry.total_inodes,
+            storage_path: storage_path.to_string(),
+            fingerprint: geometry.fingerprint,
+            bits_per_pixel: geometry.bits_per_pixel,
+            encoding: geometry.encoding,
+            ..Config::default()
+        };
+        config.normalize()?;
+        
+        Ok(config)
+    }
+    
+    /// Take the geometry and fingerprint from the superblock, keeping the
+    /// rest of this configuration. Mismatching values are logged.
+    pub fn apply_superblock(&mut self) -> anyhow::Result<()> {
+        let geometry = crate::storage::BlockStorage::read_geometry(&self.storage_path)?;
+        let recorded = (
+            geometry.block_width,
+            geometry.block_height,
+            geometry.total_blocks,
+            geometry.bits_per_pixel,
+            geometry.encoding,
+        );
+        let configured = (
+            self.block_width,
+            self.block_height,
+            self.total_blocks,
+            self.bits_per_pixel,
+            self.encoding,
+        );
+        if recorded != configured {
+            log::warn!(
+                "Configured geometry {:?} differs from superblock {:?}; using the superblock",
+                configured,
+                recorded
+            );
+        }
+        if geometry.fingerprint != self.fingerprint {
+            log::warn!(
+                "Configured fingerprint '{}' differs from superblock '{}'; using the superblock",
+                self.fingerprint,
+                geometry.fingerprint
+            );
+        }
+        
+        self.block_width = geometry.block_width;
+        self.block_height = geometry.block_height;
+        self.total_blocks = geometry.total_blocks;
+        self.total_inodes = geometry.total_inodes;
+        self.fingerprint = geometry.fingerprint;
+        self.bits_per_pixel = geometry.bits_per_pixel;
+        self.encoding = geometry.encoding;
+        self.normalize()
+    }
+    
+    /// Bytes of data stored in one data block image
+    pub fn bytes_per_block(&self) -> usize {
+        self.block_width as usize
+            * self.block_height as usize
+            * self.bits_per_pixel as usize
+            * self.encoding.channels()
+            / 8
+    }
+    
+    /// Bytes stored in the superblock, which is always 1 bit per pixel
+    pub fn superblock_bytes(&self) -> usize {
+        (self.block_width as usize * self.block_height as usize) / 8
+    }
+    
+    /// Clean up a freshly loaded configuration.
+    ///
+    /// Trims whitespace from string fields, drops empty and duplicate
+    /// distributed nodes (keeping the first occurrence), and rejects value
+    /// combinations that would only fail later at runtime.
+    pub fn normalize(&mut self) -> anyhow::Result<()> {
+        self.name = self.name.trim().to_string();
+        self.storage_path = self.storage_path.trim().to_string();
+        self.fingerprint = self.fingerprint.trim().to_string();
+        
+        let mut nodes: Vec<String> = Vec::with_capacity(self.distributed_nodes.len());
+        for node in &self.distributed_nodes {
+            let node = node.trim();
+            if node.is_empty() {
+                continue;
+            }
+            if nodes.iter().any(|n| n == node) {
+                log::warn!("Ignoring duplicate distributed node '{}'", node);
+                continue;
+            }
+            nodes.push(node.to_string());
+        }
+        self.distributed_nodes = nodes;
+        
+        if self.storage_path.is_empty() {
+            anyhow::bail!("storage_path must not be empty");
+        }
+        
+        if self.fingerprint.is_empty() {
+            self.fingerprint = "BWFS".to_string();
+        }
+        
+        if self.block_width == 0 || self.block_height == 0 {
+            anyhow::bail!("Block dimensions must be greater than 0");
+        }
+        
+        if !crate::storage::SUPPORTED_BITS_PER_PIXEL.contains(&self.bits_per_pixel) {
+            anyhow::bail!("bits_per_pixel must be 1, 2, 4 or 8 (got {})", self.bits_per_pixel);
+        }
+        
+        // Con 255 ni siquiera el blanco puro leería como 1
+        if self.pixel_threshold == u8::MAX {
+            anyhow::bail!("pixel_threshold must be below 255");
+        }
+        
+        let superblock_bytes = self.superblock_bytes();
+        if self.fingerprint.len() > superblock_bytes {
+            anyhow::bail!(
+                "Fingerprint ({} bytes) does not fit in a {}-byte superblock",
+                self.fingerprint.len(),
+                superblock_bytes
+            );
+        }
+        
+        if let Some(block_num) = self.cache_pinned_blocks.iter().find(|&&b| b >= self.total_blocks) {
+            anyhow::bail!(
+                "cache_pinned_blocks lists block {} but the filesystem has {} blocks",
+                block_num,
+                self.total_blocks
+            );
+        }
+        
+        if self.root_ino == 0 || self.first_ino == 0 {
+            anyhow::bail!("root_ino and first_ino must be greater than 0");
+        }
+        
+        // Los inodos asignables van de first_ino a first_ino + total_inodes - 1
+        let last_ino = self.first_ino.saturating_add((self.total_inodes as u64).saturating_sub(1));
+        if (self.first_ino..=last_ino).contains(&self.root_ino) {
+            anyhow::bail!(
+                "root_ino {} falls inside the allocation range {}..={} (first_ino + total_inodes)",
+                self.root_ino,
+                self.first_ino,
+                last_ino
+            );
+        }
+        
+        if self.paranoid && !self.integrity_tree {
+            anyhow::bail!("paranoid needs integrity_tree = true (reads are checked against its hashes)");
+        }
+        
+        let metadata_bytes = crate::storage::METADATA_SUPERBLOCK_BYTES;
+        if self.metadata_blocks > 0 && superblock_bytes < metadata_bytes {
+            anyhow::bail!(
+                "metadata_blocks needs a superblock of at least {} bytes (have {})",
+                metadata_bytes,
+                superblock_bytes
+            );
+        }
+        
+        // Superblock y dos copias del área, y al menos un bloque de datos
+        if self.metadata_blocks > 0 && 1 + 2 * self.metadata_blocks as u64 >= self.total_blocks as u64 {
+            anyhow::bail!(
+                "metadata_blocks = {} reserves two copies, leaving no data blocks out of {}",
+                self.metadata_blocks,
+                self.total_blocks
+            );
+        }
+        
+        let merkle_bytes = crate::storage::MERKLE_SUPERBLOCK_BYTES;
+        if self.integrity_tree && superblock_bytes < merkle_bytes {
+            anyhow::bail!(
+                "integrity_tree needs a superblock of at least {} bytes (have {})",
+                merkle_bytes,
+                superblock_bytes
+            );
+        }
+        
+        Ok(())
     }
     
     /// Validate configuration values
@@ -92,6 +929,10 @@ pub fn validate(&self) -> anyhow::Result<()> {
             anyhow::bail!("Block dimensions must not exceed 1000x1000 pixels");
         }
         
+        if self.block_width == 0 || self.block_height == 0 {
+            anyhow::bail!("Block dimensions must be greater than 0");
+        }
+        
         if self.total_blocks == 0 {
             anyhow::bail!("Total blocks must be greater than 0");
         }
@@ -100,6 +941,81 @@ pub fn validate(&self) -> anyhow::Result<()> {
             anyhow::bail!("Total inodes must be greater than 0");
         }
         
+        if self.max_name_len == 0 || self.max_name_len > 255 {
+            anyhow::bail!("max_name_len must be between 1 and 255");
+        }
+        
+        if self.bind_address.trim().parse::<std::net::IpAddr>().is_err() {
+            anyhow::bail!("bind_address '{}' is not an IP address", self.bind_address);
+        }
+        
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_config, write_superblock, TempDir};
+
+    #[test]
+    fn normalize_trims_strings_and_dedups_nodes() {
+        let dir = TempDir::new("normalize");
+        let mut config = Config {
+            name: "  padded \t".to_string(),
+            fingerprint: " BWFS_TEST\n".to_string(),
+            distributed_nodes: vec![
+                "10.0.0.1:9000".to_string(),
+                " 10.0.0.2:9000 ".to_string(),
+                "10.0.0.1:9000 ".to_string(),
+                "  ".to_string(),
+            ],
+            ..test_config(dir.path())
+        };
+        config.storage_path = format!(" {} ", config.storage_path);
+        config.normalize().unwrap();
+
+        assert_eq!(config.name, "padded");
+        assert_eq!(config.fingerprint, "BWFS_TEST");
+        assert_eq!(config.storage_path, dir.path().to_string_lossy());
+        assert_eq!(config.distributed_nodes, ["10.0.0.1:9000", "10.0.0.2:9000"]);
+    }
+
+    #[test]
+    fn loading_an_ini_file_normalizes_it() {
+        let dir = TempDir::new("normalize-ini");
+        let path = dir.path().join("config.ini");
+        std::fs::write(
+            &path,
+            "[filesystem]\nname = test\ntotal_blocks = 64\nstorage_path = ./data\n\
+             [network]\nnode1 = 10.0.0.1:9000\nnode2 = 10.0.0.1:9000\nnode3 = 10.0.0.3:9000\n",
+        )
+        .unwrap();
+
+        let config = Config::from_ini(&path.to_string_lossy()).unwrap();
+        assert_eq!(config.distributed_nodes, ["10.0.0.1:9000", "10.0.0.3:9000"]);
+    }
+
+    #[test]
+    fn superblock_geometry_configures_a_mount() {
+        let dir = TempDir::new("auto-mount");
+        let created = Config { total_blocks: 48, total_inodes: 24, ..test_config(dir.path()) };
+        assert!(Config::from_superblock(&created.storage_path).is_err());
+        write_superblock(&created);
+        let fs = crate::BWFS::new(created.clone()).unwrap();
+        let ino = fs.create_with_size_hint(fs.root_ino(), "f", 0o644, 0).unwrap();
+        fs.write_data(ino, 0, &[9; 700]).unwrap();
+        fs.save().unwrap();
+        drop(fs);
+
+        // Solo la ruta: el resto sale del bloque 0, no de config.ini
+        let auto = Config::from_superblock(&created.storage_path).unwrap();
+        assert_eq!(
+            (auto.block_width, auto.block_height, auto.total_blocks, auto.total_inodes),
+            (64, 64, 48, 24)
+        );
+        assert_eq!(auto.fingerprint, created.fingerprint);
+        let fs = crate::BWFS::load(auto).unwrap();
+        assert_eq!(fs.read_file(ino).unwrap(), vec![9; 700]);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::config::Config;
+use crate::fs::BWFS;
+use crate::inode::{FileType, INode, DIRECT_BLOCKS, POINTER_BYTES};
+use crate::storage::BlockStorage;
+use anyhow::{Context, Result};
+use fuser::TimeOrNow;
+use std::collections::{BTreeSet, HashMap};
+
+fn errno_error(what: &str, errno: libc::c_int) -> anyhow::Error {
+    anyhow::anyhow!("{}: {}", what, std::io::Error::from_raw_os_error(errno))
+}
+
+/// Create a filesystem from `dest` and copy every entry of `source` into
+/// it, re-encoded with the block geometry of `dest` (typically another
+/// `bits_per_pixel`).
+///
+/// The destination storage path must not hold a filesystem. Before any
+/// data is copied the blocks and inodes the tree needs with the new block
+/// size are checked against the fresh filesystem; if they do not fit this
+/// fails before any entry is copied. Modes, owners, times, extended
+/// attributes, device numbers, hard links and holes are kept. Metadata is
+/// saved at the end. The superblock of `dest` records its fingerprint and
+/// geometry, as `mkfs.bwfs` does, so it can be mounted with `--auto`. Returns the new filesystem and the number of entries
+/// copied.
+pub fn convert(source: &BWFS, dest: Config) -> Result<(BWFS, usize)> {
+    if BWFS::has_metadata(&dest.storage_path) {
+        anyhow::bail!("{} already holds a filesystem", dest.storage_path);
+    }
+    let block_size = dest.bytes_per_block() as u64;
+    let inline_max = dest.inline_data_max_bytes.min(block_size);
+
+    // Superblock con la geometría nueva antes de crear el filesystem, como mkfs
+    let storage = BlockStorage::new(
+        &dest.storage_path,
+        dest.block_width,
+        dest.block_height,
+        dest.total_blocks,
+        dest.fingerprint.clone(),
+    )?
+    .with_bits_per_pixel(dest.bits_per_pixel)?
+    .with_encoding(dest.encoding)
+    .with_pixel_threshold(dest.pixel_threshold)
+    .with_png_compression(dest.png_compression);
+    storage.write_fingerprint()?;
+    storage.write_geometry(dest.total_inodes)?;
+    drop(storage);
+    let dest = BWFS::new(dest)?;
+
+    // Capacidad: bloques de datos e indirectos con el nuevo tamaño de bloque
+    let entries = source.walk();
+    let mut counted = BTreeSet::new();
+    let mut blocks_needed = 0;
+    for (path, inode) in &entries {
+        if !counted.insert(inode.ino) {
+            continue;
+        }
+        blocks_needed += match inode.file_type {
+            FileType::RegularFile if inode.size > inline_max => {
+                let ranges = data_ranges(source, inode).map_err(|errno| errno_error(path, errno))?;
+                blocks_for(&ranges, block_size)
+            }
+            FileType::Symlink if inode.size > inline_max => blocks_for(&[(0, inode.size)], block_size),
+            _ => 0,
+        };
+    }
+    let usage = dest.usage_report();
+    if blocks_needed > usage.free_blocks {
+        anyhow::bail!(
+            "the data needs {} blocks of {} bytes but the destination has {} free",
+            blocks_needed, block_size, usage.free_blocks
+        );
+    }
+    let free_inodes = usage.total_inodes.saturating_sub(usage.used_inodes);
+    if counted.len() as u64 > free_inodes {
+        anyhow::bail!(
+            "the tree has {} inodes but the destination has {} free",
+            counted.len(), free_inodes
+        );
+    }
+
+    // Ruta -> inodo nuevo, y inodo original -> nuevo para los hard links
+    let mut paths: HashMap<&str, u64> = HashMap::from([("", dest.root_ino())]);
+    let mut inos: HashMap<u64, u64> = HashMap::new();
+    // Los directorios se fechan al final: crear sus hijos cambia mtime
+    let mut dir_times = Vec::new();
+    let mut count = 0;
+
+    for (path, inode) in &entries {
+        let (parent_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let &parent = paths
+            .get(parent_path)
+            .ok_or_else(|| anyhow::anyhow!("{}: parent not copied", path))?;
+        let fail = |errno| errno_error(path, errno);
+
+        if let Some(&ino) = inos.get(&inode.ino) {
+            dest.link_inode(ino, parent, name).map_err(fail)?;
+            count += 1;
+            continue;
+        }
+
+        let ino = match inode.file_type {
+            FileType::Directory => dest.create_dir(parent, name, inode.mode, inode.uid, inode.gid).map_err(fail)?.ino,
+            FileType::RegularFile => {
+                let ino = dest.create_with_size_hint(parent, name, inode.mode, 0)?;
+                copy_file_data(source, &dest, inode, ino).map_err(fail)?;
+                dest.set_mode_and_owner(ino, None, Some(inode.uid), Some(inode.gid)).map_err(fail)?;
+                ino
+            }
+            FileType::Symlink => {
+                let target = source.read_symlink(inode.ino).map_err(fail)?;
+                dest.create_symlink(parent, name, &target, inode.uid, inode.gid).map_err(fail)?.ino
+            }
+            file_type => dest
+                .create_node(parent, name, file_type, inode.mode, inode.rdev, inode.uid, inode.gid)
+                .map_err(fail)?
+                .ino,
+        };
+        for (xattr, value) in &inode.xattrs {
+            dest.set_xattr(ino, xattr, value, 0).map_err(fail)?;
+        }
+        if inode.is_dir() {
+            dir_times.push((ino, inode.atime, inode.mtime));
+        } else {
+            dest.set_times(ino, Some(TimeOrNow::SpecificTime(inode.atime)), Some(TimeOrNow::SpecificTime(inode.mtime)))
+                .map_err(fail)?;
+        }
+        paths.insert(path, ino);
+        inos.insert(inode.ino, ino);
+        count += 1;
+    }
+
+    if let Some(root_inode) = source.inode(source.root_ino()) {
+        let dest_root = dest.root_ino();
+        dest.set_mode_and_owner(dest_root, Some(root_inode.mode as u32), Some(root_inode.uid), Some(root_inode.gid))
+            .map_err(|errno| errno_error("/", errno))?;
+        for (xattr, value) in &root_inode.xattrs {
+            dest.set_xattr(dest_root, xattr, value, 0).map_err(|errno| errno_error("/", errno))?;
+        }
+        dir_times.push((dest_root, root_inode.atime, root_inode.mtime));
+    }
+    for (ino, atime, mtime) in dir_times {
+        dest.set_times(ino, Some(TimeOrNow::SpecificTime(atime)), Some(TimeOrNow::SpecificTime(mtime)))
+            .map_err(|errno| errno_error("directory times", errno))?;
+    }
+    dest.save().context("cannot save the converted filesystem")?;
+    Ok((dest, count))
+}
+
+/// Byte ranges of regular file `inode` that hold data, in order
+fn data_ranges(fs: &BWFS, inode: &INode) -> std::result::Result<Vec<(u64, u64)>, libc::c_int> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    while offset < inode.size {
+        let start = match fs.seek_data_hole(inode.ino, offset, libc::SEEK_DATA) {
+            Ok(start) => start,
+            Err(libc::ENXIO) => break,
+            Err(errno) => return Err(errno),
+        };
+        let end = fs.seek_data_hole(inode.ino, start, libc::SEEK_HOLE)?;
+        ranges.push((start, end));
+        offset = end;
+    }
+    Ok(ranges)
+}
+
+/// Blocks of `block_size` bytes that store the byte `ranges` of a file:
+/// the data blocks plus the indirect blocks that map them
+fn blocks_for(ranges: &[(u64, u64)], block_size: u64) -> u64 {
+    let indices: BTreeSet<u64> = ranges
+        .iter()
+        .filter(|(start, end)| start < end)
+        .flat_map(|&(start, end)| start / block_size..end.div_ceil(block_size))
+        .collect();
+    let per_block = block_size / POINTER_BYTES as u64;
+    let direct = DIRECT_BLOCKS as u64;
+    let single = indices.iter().any(|index| (direct..direct + per_block).contains(index));
+    let level2: BTreeSet<u64> = indices
+        .iter()
+        .filter(|&&index| index >= direct + per_block)
+        .map(|index| (index - direct - per_block) / per_block)
+        .collect();
+    let double = if level2.is_empty() { 0 } else { 1 + level2.len() as u64 };
+    indices.len() as u64 + u64::from(single) + double
+}
+
+/// Copy the contents of regular file `inode` of `source` into `dest_ino`,
+/// leaving its holes unallocated
+fn copy_file_data(source: &BWFS, dest: &BWFS, inode: &INode, dest_ino: u64) -> std::result::Result<(), libc::c_int> {
+    if inode.size == 0 {
+        return Ok(());
+    }
+    let data = source.read_file(inode.ino).map_err(|_| libc::EIO)?;
+    for (start, end) in data_ranges(source, inode)? {
+        dest.write_data(dest_ino, start, &data[start as usize..end as usize])?;
+    }
+    dest.set_file_size(dest_ino, inode.size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_config, TempDir};
+
+    #[test]
+    fn converting_to_two_bits_keeps_the_contents() {
+        let src_dir = TempDir::new("convert-src");
+        let dest_dir = TempDir::new("convert-dest");
+        let source = BWFS::new(test_config(src_dir.path())).unwrap();
+        let root = source.root_ino();
+
+        let docs = source.create_dir(root, "docs", 0o750, 10, 20).unwrap().ino;
+        let big: Vec<u8> = (0..20 * 512u32).map(|i| (i * 7 % 253) as u8).collect();
+        let big_ino = source.create_with_size_hint(docs, "big", 0o600, 0).unwrap();
+        source.write_data(big_ino, 0, &big).unwrap();
+        source.set_xattr(big_ino, "user.tag", b"v", 0).unwrap();
+        source.link_inode(big_ino, root, "big-link").unwrap();
+        let sparse = source.create_with_size_hint(root, "sparse", 0o644, 0).unwrap();
+        source.write_data(sparse, 3000, b"tail").unwrap();
+        source.create_symlink(root, "to-big", b"docs/big", 0, 0).unwrap();
+        let small = source.create_with_size_hint(root, "small", 0o644, 0).unwrap();
+        source.write_data(small, 0, b"hello").unwrap();
+
+        let dest_config = Config { bits_per_pixel: 2, ..test_config(dest_dir.path()) };
+        let (dest, copied) = convert(&source, dest_config.clone()).unwrap();
+        assert_eq!(copied, 6);
+        drop(dest);
+
+        let recorded = Config::from_superblock(&dest_config.storage_path).unwrap();
+        assert_eq!(recorded.bits_per_pixel, 2);
+        assert_eq!(recorded.total_blocks, dest_config.total_blocks);
+
+        // El destino se monta de nuevo con su propio superblock
+        let dest = BWFS::load(dest_config).unwrap();
+        assert_eq!(dest.storage_handle().lock().unwrap().bytes_per_block(), 1024);
+        let by_path: HashMap<String, INode> = dest.walk().into_iter().collect();
+        let dest_big = &by_path["/docs/big"];
+        assert_eq!(dest.read_file(dest_big.ino).unwrap(), big);
+        assert_eq!(by_path["/big-link"].ino, dest_big.ino);
+        assert_eq!(dest_big.nlink, 2);
+        assert_eq!(dest_big.mode, 0o600);
+        assert_eq!(dest_big.xattrs["user.tag"], b"v");
+        assert_eq!((by_path["/docs"].mode, by_path["/docs"].uid, by_path["/docs"].gid), (0o750, 10, 20));
+
+        let dest_sparse = &by_path["/sparse"];
+        let mut expected = vec![0u8; 3000];
+        expected.extend_from_slice(b"tail");
+        assert_eq!(dest.read_file(dest_sparse.ino).unwrap(), expected);
+        assert_eq!(dest_sparse.allocated_blocks, 1);
+        assert_eq!(dest.read_symlink(by_path["/to-big"].ino).unwrap(), b"docs/big");
+        assert_eq!(dest.read_file(by_path["/small"].ino).unwrap(), b"hello");
+        assert_eq!(dest.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn conversion_that_does_not_fit_is_refused() {
+        let src_dir = TempDir::new("convert-full-src");
+        let dest_dir = TempDir::new("convert-full-dest");
+        let source = BWFS::new(test_config(src_dir.path())).unwrap();
+        let ino = source.create_with_size_hint(source.root_ino(), "f", 0o644, 0).unwrap();
+        source.write_data(ino, 0, &[1u8; 30 * 512]).unwrap();
+
+        let dest_config = Config { total_blocks: 16, ..test_config(dest_dir.path()) };
+        let Err(error) = convert(&source, dest_config) else {
+            panic!("30 blocks of data converted into a 16 block filesystem");
+        };
+        assert!(error.to_string().contains("blocks"), "{}", error);
+    }
+}
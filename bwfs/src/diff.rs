@@ -0,0 +1,128 @@
+use crate::fs::BWFS;
+use crate::hash;
+use crate::inode::INode;
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Kind of difference found for a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// Present only in the second filesystem
+    Added,
+    /// Present only in the first filesystem
+    Removed,
+    /// Present in both but different
+    Modified,
+}
+
+/// One difference between two filesystems
+#[derive(Debug, Clone)]
+pub struct DiffEntry {
+    pub path: String,
+    pub kind: ChangeKind,
+    /// What differs for `Modified` entries ("type", "size", "mode", "mtime", "content")
+    pub reasons: Vec<&'static str>,
+}
+
+/// Compare the directory trees of two filesystems.
+///
+/// Entries are matched by path. Files are compared by type, size and mode,
+/// then by a SHA-256 of their contents; mtime is only compared when
+/// `compare_mtime` is set, since independently created trees never share it.
+/// Results are sorted by path.
+pub fn diff(a: &BWFS, b: &BWFS, compare_mtime: bool) -> Result<Vec<DiffEntry>> {
+    let tree_a: BTreeMap<String, INode> = a.walk().into_iter().collect();
+    let tree_b: BTreeMap<String, INode> = b.walk().into_iter().collect();
+
+    let mut changes = Vec::new();
+
+    for (path, inode_a) in &tree_a {
+        let Some(inode_b) = tree_b.get(path) else {
+            changes.push(DiffEntry {
+                path: path.clone(),
+                kind: ChangeKind::Removed,
+                reasons: Vec::new(),
+            });
+            continue;
+        };
+
+        let mut reasons = Vec::new();
+        if inode_a.file_type != inode_b.file_type {
+            reasons.push("type");
+        } else {
+            if inode_a.size != inode_b.size {
+                reasons.push("size");
+            }
+            if inode_a.mode != inode_b.mode {
+                reasons.push("mode");
+            }
+            if inode_a.rdev != inode_b.rdev {
+                reasons.push("rdev");
+            }
+            if compare_mtime && inode_a.mtime != inode_b.mtime {
+                reasons.push("mtime");
+            }
+            if !inode_a.is_dir() && inode_a.size == inode_b.size {
+                let hash_a = hash::sha256(&a.read_file(inode_a.ino)?);
+                let hash_b = hash::sha256(&b.read_file(inode_b.ino)?);
+                if hash_a != hash_b {
+                    reasons.push("content");
+                }
+            }
+        }
+
+        if !reasons.is_empty() {
+            changes.push(DiffEntry {
+                path: path.clone(),
+                kind: ChangeKind::Modified,
+                reasons,
+            });
+        }
+    }
+
+    for path in tree_b.keys() {
+        if !tree_a.contains_key(path) {
+            changes.push(DiffEntry {
+                path: path.clone(),
+                kind: ChangeKind::Added,
+                reasons: Vec::new(),
+            });
+        }
+    }
+
+    changes.sort_by(|x, y| x.path.cmp(&y.path));
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_config, TempDir};
+
+    /// A filesystem holding /docs/a and /b
+    fn populated(dir: &TempDir) -> BWFS {
+        let fs = BWFS::new(test_config(dir.path())).unwrap();
+        let docs = fs.create_dir(fs.root_ino(), "docs", 0o755, 0, 0).unwrap().ino;
+        for (parent, name) in [(docs, "a"), (fs.root_ino(), "b")] {
+            let ino = fs.create_with_size_hint(parent, name, 0o644, 0).unwrap();
+            fs.write_data(ino, 0, &[name.as_bytes()[0]; 900]).unwrap();
+        }
+        fs
+    }
+
+    #[test]
+    fn diff_reports_only_the_added_file() {
+        let (dir_a, dir_b) = (TempDir::new("diff-a"), TempDir::new("diff-b"));
+        let a = populated(&dir_a);
+        let b = populated(&dir_b);
+        assert!(diff(&a, &b, false).unwrap().is_empty());
+
+        let docs = b.walk().into_iter().find(|(path, _)| path == "/docs").unwrap().1.ino;
+        let ino = b.create_with_size_hint(docs, "new", 0o644, 0).unwrap();
+        b.write_data(ino, 0, b"new").unwrap();
+
+        let changes = diff(&a, &b, false).unwrap();
+        assert_eq!(changes.len(), 1, "{:?}", changes);
+        assert_eq!((changes[0].path.as_str(), changes[0].kind), ("/docs/new", ChangeKind::Added));
+    }
+}
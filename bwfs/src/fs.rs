@@ -1,17 +1,141 @@
-use crate::inode::{DirEntry, FileType, INode};
-use crate::storage::{Bitmap, BlockStorage};
-use crate::config::Config;
+use crate::inode::{DirEntry, FileType, INode, DIRECT_BLOCKS};
+use crate::storage::{Bitmap, BlockStorage, MetadataArea};
+use crate::config::{AtimePolicy, Config, MetadataFormat, MetadataSync};
+use crate::hash::Digest;
+use crate::merkle::MerkleTree;
+use crate::journal::{Journal, JournalRecord};
 use fuser::{
     FileAttr, FileType as FuseFileType, Filesystem, KernelConfig, ReplyAttr, ReplyData,
-    ReplyDirectory, ReplyEntry, ReplyOpen, ReplyWrite, Request, ReplyCreate, ReplyEmpty, ReplyStatfs,
+    Notifier, ReplyDirectory, ReplyEntry, ReplyOpen, ReplyWrite, Request, ReplyCreate, ReplyEmpty,
+    ReplyIoctl, ReplyLseek, ReplyStatfs, ReplyXattr, TimeOrNow,
 };
 use std::collections::{HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use anyhow::Result;
 
 const TTL: Duration = Duration::from_secs(1);
 
+/// Inode number the kernel always gives the root of a FUSE mount
+const FUSE_ROOT_INO: u64 = 1;
+
+/// Under relatime, atime is refreshed at least once per this window
+const RELATIME_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Todas las trazas llevan el id de la instancia para poder distinguir
+// varios BWFS montados en el mismo proceso.
+macro_rules! log_enter {
+    ($fs:expr, $func:expr) => {
+        log::info!("➡️ [{}] ENTER {}", $fs.instance_id, $func);
+    };
+}
+
+macro_rules! log_exit {
+    ($fs:expr, $func:expr) => {
+        log::info!("⬅️ [{}] EXIT {}", $fs.instance_id, $func);
+    };
+}
+
+macro_rules! log_point {
+    ($fs:expr, $msg:expr) => {{
+        log::info!("📌 [{}] {}", $fs.instance_id, $msg);
+    }};
+}
+
+/// FUSE file type for a BWFS file type
+fn fuse_file_type(file_type: FileType) -> FuseFileType {
+    match file_type {
+        FileType::RegularFile => FuseFileType::RegularFile,
+        FileType::Directory => FuseFileType::Directory,
+        FileType::Symlink => FuseFileType::Symlink,
+        FileType::CharDevice => FuseFileType::CharDevice,
+        FileType::BlockDevice => FuseFileType::BlockDevice,
+        FileType::NamedPipe => FuseFileType::NamedPipe,
+        FileType::Socket => FuseFileType::Socket,
+    }
+}
+
+/// Operations between two checks of `max_memory_bytes`
+const MEMORY_CHECK_INTERVAL: u64 = 256;
+
+/// errno for a failed storage operation: ENOSPC when the device is full,
+/// EIO otherwise
+pub(crate) fn storage_errno(error: &anyhow::Error) -> libc::c_int {
+    let no_space = error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .and_then(|e| e.raw_os_error())
+            == Some(libc::ENOSPC)
+    });
+    if no_space {
+        libc::ENOSPC
+    } else {
+        libc::EIO
+    }
+}
+
+/// Longest extended attribute name and value accepted (as in Linux)
+const XATTR_NAME_MAX: usize = 255;
+const XATTR_SIZE_MAX: usize = 65536;
+
+/// Answer a getxattr/listxattr request: a `size` of 0 asks only for the
+/// length, a buffer too small for `data` gets ERANGE
+fn reply_xattr(reply: ReplyXattr, size: u32, data: &[u8]) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if data.len() > size as usize {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(data);
+    }
+}
+
+/// Check that `inode` can hold file data: EISDIR for a directory, EINVAL
+/// for a symlink or special file. Used by every data operation so they
+/// all map the wrong type the same way.
+fn require_file(inode: &INode) -> std::result::Result<(), libc::c_int> {
+    match inode.file_type {
+        FileType::RegularFile => Ok(()),
+        FileType::Directory => Err(libc::EISDIR),
+        _ => Err(libc::EINVAL),
+    }
+}
+
+/// Check that `inode` is a directory, ENOTDIR otherwise
+fn require_dir(inode: &INode) -> std::result::Result<(), libc::c_int> {
+    if inode.is_dir() {
+        Ok(())
+    } else {
+        Err(libc::ENOTDIR)
+    }
+}
+
+/// Drop one name's link to `inode`. A link count that is already 0 means
+/// the metadata is corrupt (or the name was removed twice); it is logged
+/// and left at 0 instead of wrapping around.
+fn drop_link(inode: &mut INode) {
+    if inode.nlink == 0 {
+        log::warn!("inode {} already has nlink 0; not decrementing", inode.ino);
+    }
+    inode.nlink = inode.nlink.saturating_sub(1);
+}
+
+/// Drop the link a removed subdirectory's ".." held on `parent`. A
+/// directory always keeps 2 links ("." and its own name), so a count of 2
+/// or less here is corrupt and is left alone.
+fn drop_subdir_link(parent: &mut INode) {
+    if parent.nlink <= 2 {
+        log::warn!(
+            "directory {} has nlink {} with a subdirectory being removed; not decrementing",
+            parent.ino, parent.nlink
+        );
+        return;
+    }
+    parent.nlink -= 1;
+}
+
 /// Filesystem metadata for persistence
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 struct FilesystemMetadata {
@@ -20,6 +144,383 @@ struct FilesystemMetadata {
     block_bitmap: Bitmap,
     inode_bitmap: Bitmap,
     next_ino: u64,
+    #[serde(default)]
+    next_generation: u64,
+    // Sin skip_serializing_if: bincode necesita todos los campos siempre
+    #[serde(default)]
+    merkle_tree: Option<MerkleTree>,
+    /// Checkpoint sequence, compared with the metadata area's on load
+    #[serde(default)]
+    seq: u64,
+}
+
+/// Magic prefix of `metadata.bin`
+const METADATA_BIN_MAGIC: &[u8; 8] = b"BWFSMETA";
+
+impl FilesystemMetadata {
+    /// Serialize in the given on-disk format
+    fn encode(&self, format: MetadataFormat) -> Result<Vec<u8>> {
+        match format {
+            MetadataFormat::Json => Ok(serde_json::to_vec_pretty(self)?),
+            MetadataFormat::Binary => {
+                let mut data = METADATA_BIN_MAGIC.to_vec();
+                bincode::serialize_into(&mut data, self)?;
+                Ok(data)
+            }
+        }
+    }
+
+    /// Parse metadata in either format, told apart by the binary magic
+    fn decode(data: &[u8]) -> Result<Self> {
+        match data.strip_prefix(METADATA_BIN_MAGIC.as_slice()) {
+            Some(payload) => Ok(bincode::deserialize(payload)?),
+            None => Ok(serde_json::from_slice(data)?),
+        }
+    }
+}
+
+/// State kept for every open file or directory handle
+struct OpenHandle {
+    ino: u64,
+    /// Entries captured by `opendir`; `readdir` serves offsets from this
+    /// snapshot so concurrent creates/unlinks don't shift or repeat entries
+    dir_entries: Option<Vec<DirEntry>>,
+    /// When the handle was opened (reported by `open_handles`)
+    opened_at: Instant,
+}
+
+impl OpenHandle {
+    fn file(ino: u64) -> Self {
+        Self { ino, dir_entries: None, opened_at: Instant::now() }
+    }
+}
+
+/// ioctl on any file or directory of a mount that returns `open_handles()`
+/// as JSON (`_IOR('B', 1, [u8; LIST_HANDLES_MAX_BYTES])`)
+pub const IOCTL_LIST_HANDLES: u32 =
+    (2 << 30) | ((LIST_HANDLES_MAX_BYTES as u32) << 16) | ((b'B' as u32) << 8) | 1;
+
+/// ioctl on an empty regular file that turns it into a copy-on-write clone
+/// of the file whose inode number is passed as a little-endian u64
+/// (`_IOW('B', 2, u64)`)
+pub const IOCTL_CLONE_FROM: u32 = (1 << 30) | (8 << 16) | ((b'B' as u32) << 8) | 2;
+
+/// Size of the `IOCTL_LIST_HANDLES` reply buffer; entries that don't fit
+/// are left out
+pub const LIST_HANDLES_MAX_BYTES: usize = 8192;
+
+/// One open file or directory handle of a mounted filesystem
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct OpenHandleInfo {
+    pub fh: u64,
+    pub ino: u64,
+    /// First path found for the inode; `None` if it was unlinked while open
+    pub path: Option<String>,
+    /// How long the handle has been open
+    pub open_for: Duration,
+}
+
+/// Metadata snapshot waiting to be written by `Checkpoint::write`
+struct Checkpoint {
+    metadata: FilesystemMetadata,
+    pending_blocks: Vec<(u32, std::path::PathBuf)>,
+    storage_dir: std::path::PathBuf,
+    storage: Arc<Mutex<BlockStorage>>,
+    dirty: Arc<Mutex<bool>>,
+    written: Arc<Mutex<u64>>,
+    generation: u64,
+    format: MetadataFormat,
+    /// Also write the checkpoint to the storage's metadata area
+    to_area: bool,
+    /// Write the metadata file even when the area holds the checkpoint
+    metadata_cache: bool,
+    journal: Arc<Journal>,
+    /// Journal position when the snapshot was taken: the records before it
+    /// are covered by this checkpoint
+    journal_position: u64,
+}
+
+impl Checkpoint {
+    /// Persist the snapshot following the write barrier described in
+    /// `BWFS::save`. Takes no filesystem lock except the short ones needed
+    /// to hand back unsynced blocks on failure.
+    fn write(self) -> Result<()> {
+        use std::fs;
+        use std::io::Write;
+
+        // 1) Barrera: datos antes que metadata
+        if let Err((failed, e)) =
+            crate::storage::sync_block_files(&self.pending_blocks, &self.storage_dir)
+        {
+            self.storage.lock().unwrap().requeue_unsynced(&failed);
+            *self.dirty.lock().unwrap() = true;
+            return Err(e);
+        }
+
+        let metadata_bytes = self.metadata.encode(self.format)?;
+        // El área guarda siempre el formato binario, el más compacto
+        let area_bytes = if self.to_area {
+            Some(self.metadata.encode(MetadataFormat::Binary)?)
+        } else {
+            None
+        };
+
+        // Un checkpoint más nuevo ya escrito no debe ser pisado por este
+        let mut written = self.written.lock().unwrap();
+        if *written > self.generation {
+            return Ok(());
+        }
+
+        let metadata_path = self.storage_dir.join(self.format.file_name());
+        let tmp_path = self.storage_dir.join(format!("{}.tmp", self.format.file_name()));
+        let stale_path = self.storage_dir.join(self.format.other().file_name());
+
+        let result = (|| -> Result<()> {
+            // 2) Área de metadata en las imágenes: la copia autoritativa
+            if let Some(area_bytes) = &area_bytes {
+                let stored = self
+                    .storage
+                    .lock()
+                    .unwrap()
+                    .write_metadata_payload(area_bytes, self.generation);
+                match stored {
+                    Ok(()) if !self.metadata_cache => {
+                        // Sin caché, un archivo viejo solo ocuparía espacio
+                        for path in [&metadata_path, &stale_path] {
+                            if path.exists() {
+                                fs::remove_file(path)?;
+                            }
+                        }
+                        return crate::storage::sync_dir(&self.storage_dir);
+                    }
+                    Ok(()) => {}
+                    // El archivo, con una secuencia más nueva, cubre el fallo
+                    Err(e) if self.metadata_cache => {
+                        log::error!("Metadata area not updated, only the metadata file has this checkpoint: {:#}", e);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            // 3) Escribir a un temporal y hacerlo durable
+            let mut tmp = fs::File::create(&tmp_path)?;
+            tmp.write_all(&metadata_bytes)?;
+            tmp.sync_all()?;
+            drop(tmp);
+
+            // 4) Reemplazo atómico
+            fs::rename(&tmp_path, &metadata_path)?;
+
+            // Tras cambiar de formato, el archivo del otro quedaría obsoleto
+            if stale_path.exists() {
+                fs::remove_file(&stale_path)?;
+            }
+            crate::storage::sync_dir(&self.storage_dir)
+        })();
+
+        match result {
+            Ok(()) => {
+                *written = self.generation;
+                log::info!("BWFS checkpoint -> metadata actualizada en {:?}", metadata_path);
+                // El checkpoint ya está en disco: un error aquí solo deja
+                // registros que la repetición vuelve a aplicar sin efecto
+                if let Err(e) = self.journal.discard_through(self.journal_position) {
+                    log::error!("Cannot truncate the journal after a checkpoint: {:#}", e);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                *self.dirty.lock().unwrap() = true;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Shared handles to the state a checkpoint snapshots, so checkpoints can
+/// also be taken from a background thread (see `MetadataSync::Interval`)
+#[derive(Clone)]
+struct CheckpointSource {
+    storage: Arc<Mutex<BlockStorage>>,
+    inodes: Arc<Mutex<HashMap<u64, INode>>>,
+    directories: Arc<Mutex<HashMap<u64, Vec<DirEntry>>>>,
+    block_bitmap: Arc<Mutex<Bitmap>>,
+    inode_bitmap: Arc<Mutex<Bitmap>>,
+    next_ino: Arc<Mutex<u64>>,
+    next_generation: Arc<Mutex<u64>>,
+    dirty: Arc<Mutex<bool>>,
+    checkpoint_seq: Arc<Mutex<u64>>,
+    checkpoint_written: Arc<Mutex<u64>>,
+    storage_dir: std::path::PathBuf,
+    format: MetadataFormat,
+    metadata_cache: bool,
+    journal: Arc<Journal>,
+}
+
+impl CheckpointSource {
+    /// Take a consistent snapshot of the metadata under the locks
+    fn prepare(&self) -> Result<Checkpoint> {
+        // La raíz de Merkle se escribe en el superblock antes de la barrera
+        let (merkle_tree, to_area) = {
+            let storage = self.storage.lock().unwrap();
+            if let Some(root) = storage.merkle_root() {
+                if storage.stored_merkle_root()? != Some(root) {
+                    storage.write_merkle_root(&root)?;
+                }
+            }
+            (storage.merkle_tree(), storage.metadata_area().is_some())
+        };
+
+        // Todos los locks a la vez (en el orden habitual) para que inodos,
+        // directorios y bitmaps sean coherentes entre sí
+        let (mut metadata, journal_position) = {
+            let inodes = self.inodes.lock().unwrap();
+            let directories = self.directories.lock().unwrap();
+            let block_bitmap = self.block_bitmap.lock().unwrap();
+            let next_ino = self.next_ino.lock().unwrap();
+            let inode_bitmap = self.inode_bitmap.lock().unwrap();
+            let next_generation = self.next_generation.lock().unwrap();
+            let metadata = FilesystemMetadata {
+                inodes: inodes.clone(),
+                directories: directories.clone(),
+                block_bitmap: block_bitmap.clone(),
+                inode_bitmap: inode_bitmap.clone(),
+                next_ino: *next_ino,
+                next_generation: *next_generation,
+                merkle_tree,
+                seq: 0,
+            };
+            // Las operaciones del journal se confirman con el lock de inodos
+            // tomado: todo lo anterior a esta posición está en el snapshot
+            (metadata, self.journal.position())
+        };
+
+        // Cualquier bloque referenciado por el snapshot ya fue escrito (las
+        // escrituras tienen el lock de inodos), así que está en esta lista
+        let pending_blocks = self.storage.lock().unwrap().take_unsynced();
+
+        let generation = {
+            let mut seq = self.checkpoint_seq.lock().unwrap();
+            *seq += 1;
+            *seq
+        };
+        metadata.seq = generation;
+
+        Ok(Checkpoint {
+            metadata,
+            pending_blocks,
+            storage_dir: self.storage_dir.clone(),
+            storage: Arc::clone(&self.storage),
+            dirty: Arc::clone(&self.dirty),
+            written: Arc::clone(&self.checkpoint_written),
+            generation,
+            format: self.format,
+            to_area,
+            metadata_cache: self.metadata_cache,
+            journal: Arc::clone(&self.journal),
+            journal_position,
+        })
+    }
+
+    /// Write a checkpoint if metadata is dirty. The flag is cleared before
+    /// the snapshot and restored if the checkpoint fails.
+    fn sync_if_dirty(&self) -> Result<()> {
+        if !std::mem::replace(&mut *self.dirty.lock().unwrap(), false) {
+            return Ok(());
+        }
+        let result = self.prepare().and_then(Checkpoint::write);
+        if result.is_err() {
+            *self.dirty.lock().unwrap() = true;
+        }
+        result
+    }
+}
+
+/// The background checkpoint thread and the channel that stops it
+struct SyncThread {
+    stop: mpsc::Sender<()>,
+    handle: std::thread::JoinHandle<()>,
+}
+
+/// Progress of a bulk copy, passed to the caller's callback after each file
+#[derive(Debug, Clone, Default)]
+pub struct CopyProgress {
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// Estimated memory held by a mounted filesystem
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryUsage {
+    /// Inode table and directory entries
+    pub metadata_bytes: usize,
+    /// Decoded blocks in the block cache
+    pub cache_bytes: usize,
+    pub total_bytes: usize,
+}
+
+/// Result of `BWFS::fsck`: the block bitmap compared with the blocks the
+/// inodes actually use, plus directory entries whose inode is gone
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    /// Blocks marked used that no inode references
+    pub leaked_blocks: Vec<u32>,
+    /// Blocks an inode references but the bitmap marks free
+    pub unmarked_blocks: Vec<u32>,
+    /// Blocks referenced more often than their clone reference count allows
+    pub doubly_allocated_blocks: Vec<u32>,
+    /// (directory, name, missing inode) of every dangling entry
+    pub dangling_entries: Vec<(u64, String, u64)>,
+    /// Whether the problems above were fixed
+    pub repaired: bool,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.leaked_blocks.is_empty()
+            && self.unmarked_blocks.is_empty()
+            && self.doubly_allocated_blocks.is_empty()
+            && self.dangling_entries.is_empty()
+    }
+}
+
+/// Number of files listed in `UsageReport::largest_files`
+const USAGE_REPORT_LARGEST: usize = 10;
+
+/// Space usage of a filesystem, from `BWFS::usage_report`
+#[derive(Debug, Clone, Default)]
+pub struct UsageReport {
+    pub block_size: usize,
+    pub total_blocks: u64,
+    /// Blocks marked used in the bitmap, including the superblock and the
+    /// metadata area
+    pub used_blocks: u64,
+    pub free_blocks: u64,
+    pub total_inodes: u64,
+    /// Inode numbers marked used in the inode bitmap (the root is not counted)
+    pub used_inodes: u64,
+    pub regular_files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    /// Devices, FIFOs and sockets
+    pub special_files: u64,
+    /// (path, blocks, size) of the files with the most blocks, largest
+    /// first; a hard-linked file is listed once under its first path
+    pub largest_files: Vec<(String, u64, u64)>,
+}
+
+/// Totals reported by `statfs`, from `BWFS::fs_stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsStats {
+    pub block_size: u32,
+    pub total_blocks: u64,
+    pub free_blocks: u64,
+    pub total_inodes: u64,
+    pub free_inodes: u64,
+    /// Longest entry name accepted (`namelen`)
+    pub max_name_len: u32,
 }
 
 /// Main BWFS filesystem structure
@@ -33,8 +534,8 @@ pub struct BWFS {
     /// Directory entries (ino -> Vec<DirEntry>)
     directories: Arc<Mutex<HashMap<u64, Vec<DirEntry>>>>,
 
-    /// Open file handles (handle -> ino)
-    open_files: Arc<Mutex<HashMap<u64, u64>>>,
+    /// Open file and directory handles (handle -> per-handle state)
+    open_files: Arc<Mutex<HashMap<u64, OpenHandle>>>,
 
     /// Next available file handle
     next_fh: Arc<Mutex<u64>>,
@@ -45,522 +546,3940 @@ pub struct BWFS {
     /// INode bitmap
     inode_bitmap: Arc<Mutex<Bitmap>>,
 
+    /// Reference counts of data blocks shared by clones (only blocks with
+    /// more than one reference are listed). Rebuilt from the inode table on
+    /// load. Locked after inodes/directories and never while holding the
+    /// block bitmap.
+    block_refs: Arc<Mutex<HashMap<u32, u32>>>,
+
     /// Configuration
     config: Config,
 
     /// Next available inode number
     next_ino: Arc<Mutex<u64>>,
 
+    /// Inode number of the root directory (the kernel always calls it 1)
+    root_ino: u64,
+
+    /// Generation handed to the next inode created
+    next_generation: Arc<Mutex<u64>>,
+
     /// Global dirty flag: true if metadata (inodes/dirs/bitmaps) has pending changes
     dirty: Arc<Mutex<bool>>,
+
+    /// Identifier used to tag this instance's log lines
+    instance_id: String,
+
+    /// When `read` updates atime
+    atime_policy: AtimePolicy,
+
+    /// Reply with the precise POSIX errno where handlers otherwise approximate
+    strict_posix: bool,
+
+    /// Generation of the last checkpoint started
+    checkpoint_seq: Arc<Mutex<u64>>,
+
+    /// Generation of the last checkpoint written to disk (held while writing)
+    checkpoint_written: Arc<Mutex<u64>>,
+
+    /// Kernel notification channel, set once the FUSE session exists
+    notifier: Arc<Mutex<Option<Notifier>>>,
+
+    /// Background checkpoint thread (`MetadataSync::Interval` and
+    /// `save_debounce_ms`), stopped and joined on unmount or drop
+    sync_thread: Mutex<Option<SyncThread>>,
+
+    /// When `release`/`releasedir` last saved (for `save_debounce_ms`)
+    last_close_save: Mutex<Option<Instant>>,
+
+    /// Operations counted toward the next `max_memory_bytes` check
+    memory_check_ops: AtomicU64,
+
+    /// The last checkpoint failed with ENOSPC: block allocations are refused
+    /// until a checkpoint succeeds again
+    metadata_no_space: AtomicBool,
+
+    /// Write-ahead record of data block writes (see `Config::journal`)
+    journal: Arc<Journal>,
 }
 
 impl BWFS {
-    /// Create a new BWFS instance
-    pub fn new(config: Config) -> Result<Self> {
-        let storage = BlockStorage::new(
+    /// Open the block storage described by the configuration
+    fn open_storage(config: &Config) -> Result<BlockStorage> {
+        Ok(BlockStorage::new(
             &config.storage_path,
             config.block_width,
             config.block_height,
             config.total_blocks,
             config.fingerprint.clone(),
-        )?;
+        )?
+        .with_bits_per_pixel(config.bits_per_pixel)?
+        .with_encoding(config.encoding)
+        .with_pixel_threshold(config.pixel_threshold)
+        .with_read_retry(config.read_retries, config.retry_backoff_ms)
+        .with_png_compression(config.png_compression)
+        .with_block_compression(config.block_compression)
+        .with_cache(config.cache_max_bytes)
+        // El superblock se lee constantemente: siempre fijo en la caché
+        .with_pinned_blocks(&[0])
+        .with_pinned_blocks(&config.cache_pinned_blocks)
+        .with_paranoid(config.paranoid))
+    }
+
+    /// Create a new BWFS instance
+    pub fn new(config: Config) -> Result<Self> {
+        let mut storage = Self::open_storage(&config)?;
+
+        // Área de metadata justo tras el superblock (antes del árbol de
+        // Merkle, que la deja fuera)
+        let metadata_area = (config.metadata_blocks > 0).then_some(MetadataArea {
+            first_block: 1,
+            blocks_per_copy: config.metadata_blocks,
+        });
+        if metadata_area.is_some_and(|area| area.blocks().end >= config.total_blocks) {
+            anyhow::bail!("metadata_blocks = {} leaves no data blocks", config.metadata_blocks);
+        }
+        storage.set_metadata_area(metadata_area);
+
+        if config.integrity_tree {
+            let tree = storage.build_merkle_tree()?;
+            storage.enable_merkle(tree);
+        }
 
         // Bitmap de bloques: todos libres al inicio.
         // Reservamos explícitamente el bloque 0 para el superblock/fingerprint.
         let mut block_bitmap = Bitmap::new(config.total_blocks as usize);
         block_bitmap.set(0); // 🔒 bloque 0 reservado (superblock)
+        for block_num in metadata_area.iter().flat_map(|area| area.blocks()) {
+            block_bitmap.set(block_num as usize);
+        }
 
+        // La raíz queda fuera del rango asignable, así que no ocupa bit
         let inode_bitmap = Bitmap::new(config.total_inodes as usize);
 
         let mut inodes = HashMap::new();
         let mut directories = HashMap::new();
 
-        // Create root inode (ino = 1)
-        let root_inode = INode::new(1, FileType::Directory, 0o755, 0, 0);
-        inodes.insert(1, root_inode);
+        // Create root inode (ino = root_ino, 1 by default)
+        let root_ino = config.root_ino;
+        let root_inode = INode::new(root_ino, FileType::Directory, config.default_dir_mode, 0, 0);
+        inodes.insert(root_ino, root_inode);
 
         // Create root directory entries (. and ..)
         directories.insert(
-            1,
+            root_ino,
             vec![
-                DirEntry::new(1, ".".to_string(), FileType::Directory),
-                DirEntry::new(1, "..".to_string(), FileType::Directory),
+                DirEntry::new(root_ino, ".".to_string(), FileType::Directory),
+                DirEntry::new(root_ino, "..".to_string(), FileType::Directory),
             ],
         );
 
-        Ok(Self {
+        // Un journal de un sistema anterior en la misma ruta no aplica
+        let journal = Journal::create(std::path::Path::new(&config.storage_path), config.journal)?
+            .with_sync(config.journal_sync, Duration::from_millis(config.journal_sync_interval_ms));
+
+        let fs = Self {
             storage: Arc::new(Mutex::new(storage)),
             inodes: Arc::new(Mutex::new(inodes)),
             directories: Arc::new(Mutex::new(directories)),
             open_files: Arc::new(Mutex::new(HashMap::new())),
             next_fh: Arc::new(Mutex::new(1)),
             block_bitmap: Arc::new(Mutex::new(block_bitmap)),
+            block_refs: Arc::new(Mutex::new(HashMap::new())),
             inode_bitmap: Arc::new(Mutex::new(inode_bitmap)),
+            instance_id: Self::make_instance_id(&config),
+            atime_policy: config.atime_mode,
+            strict_posix: false,
+            checkpoint_seq: Arc::new(Mutex::new(0)),
+            checkpoint_written: Arc::new(Mutex::new(0)),
+            notifier: Arc::new(Mutex::new(None)),
+            sync_thread: Mutex::new(None),
+            last_close_save: Mutex::new(None),
+            memory_check_ops: AtomicU64::new(0),
+            metadata_no_space: AtomicBool::new(false),
+            journal: Arc::new(journal),
+            next_ino: Arc::new(Mutex::new(config.first_ino)),
+            root_ino,
             config,
-            next_ino: Arc::new(Mutex::new(2)),
+            next_generation: Arc::new(Mutex::new(1)),
             dirty: Arc::new(Mutex::new(false)),
-        })
+        };
+        fs.start_background_sync();
+        Ok(fs)
     }
 
     /// Load existing filesystem
+    ///
+    /// Metadata is read from `metadata.json` or `metadata.bin` (the
+    /// configured format is tried first; the encoding is detected from the
+    /// content). If neither exists, a complete `.tmp` left by an interrupted
+    /// checkpoint is used instead. When there is no metadata at all but data
+    /// block images exist, loading fails rather than presenting an empty
+    /// filesystem on top of them; a fresh filesystem is only created when
+    /// the storage holds no data blocks. Data writes recorded in the journal
+    /// since that checkpoint are then rolled forward (`replay_journal`).
     pub fn load(config: Config) -> Result<Self> {
         use std::fs;
         use std::path::PathBuf;
 
-        let storage = BlockStorage::new(
-            &config.storage_path,
-            config.block_width,
-            config.block_height,
-            config.total_blocks,
-            config.fingerprint.clone(),
-        )?;
-
-        // Try to load metadata from metadata.json
-        let metadata_path = PathBuf::from(&config.storage_path).join("metadata.json");
-
-        if metadata_path.exists() {
-            // Load from metadata file
-            let metadata_str = fs::read_to_string(&metadata_path)?;
-            let metadata: FilesystemMetadata = serde_json::from_str(&metadata_str)?;
-
-            let inodes = metadata.inodes.into_iter().collect();
-            let directories = metadata.directories.into_iter().collect();
-            let next_ino = metadata.next_ino;
-
-            // Aseguramos que el bloque 0 SIEMPRE quede reservado,
-            // aunque una versión vieja del FS no lo tuviera marcado.
-            let mut bb = metadata.block_bitmap.clone();
-            bb.set(0); // 🔒 bloque 0 reservado (superblock)
-
-            Ok(Self {
-                storage: Arc::new(Mutex::new(storage)),
-                inodes: Arc::new(Mutex::new(inodes)),
-                directories: Arc::new(Mutex::new(directories)),
-                open_files: Arc::new(Mutex::new(HashMap::new())),
-                next_fh: Arc::new(Mutex::new(1)),
-                block_bitmap: Arc::new(Mutex::new(bb)),
-                inode_bitmap: Arc::new(Mutex::new(metadata.inode_bitmap)),
-                config,
-                next_ino: Arc::new(Mutex::new(next_ino)),
-                dirty: Arc::new(Mutex::new(false)),
+        let mut storage = Self::open_storage(&config)?;
+
+        let storage_dir = PathBuf::from(&config.storage_path);
+        let formats = [config.metadata_format, config.metadata_format.other()];
+        let metadata_path = formats
+            .iter()
+            .map(|format| storage_dir.join(format.file_name()))
+            .find(|path| path.exists());
+        let recovered = || {
+            formats.iter().find_map(|format| {
+                let tmp_path = storage_dir.join(format!("{}.tmp", format.file_name()));
+                Self::read_metadata_tmp(&tmp_path).map(|metadata| (tmp_path, metadata))
             })
-        } else {
-            // Create new filesystem
-            Self::new(config)
-        }
-    }
-
-    /// Save filesystem state to disk
-    pub fn save(&self) -> Result<()> {
-        use std::fs;
-        use std::path::PathBuf;
+        };
 
-        log::info!("BWFS::save() -> escribiendo metadata.json en disco");
+        // El área de metadata es autoritativa; el archivo es una caché que
+        // solo se usa si es al menos igual de reciente
+        let metadata_record = storage.read_metadata_record()?;
+        if let Some(record) = &metadata_record {
+            if config.metadata_blocks != 0 && config.metadata_blocks != record.area.blocks_per_copy {
+                log::warn!(
+                    "config says metadata_blocks = {} but mkfs reserved {}; using the superblock's value",
+                    config.metadata_blocks,
+                    record.area.blocks_per_copy
+                );
+            }
+            storage.set_metadata_area(Some(record.area));
+        }
 
-        let metadata = FilesystemMetadata {
-            inodes: self.inodes.lock().unwrap().clone(),
-            directories: self.directories.lock().unwrap().clone(),
-            block_bitmap: self.block_bitmap.lock().unwrap().clone(),
-            inode_bitmap: self.inode_bitmap.lock().unwrap().clone(),
-            next_ino: *self.next_ino.lock().unwrap(),
+        let read_cache = || -> Result<Option<FilesystemMetadata>> {
+            if let Some(metadata_path) = &metadata_path {
+                return FilesystemMetadata::decode(&fs::read(metadata_path)?).map(Some);
+            }
+            // El temporal se hace durable antes del rename: si el rename no
+            // llegó a ocurrir, es el checkpoint más reciente
+            Ok(recovered().map(|(tmp_path, metadata)| {
+                log::warn!(
+                    "metadata missing in {:?}; recovered metadata from {:?}",
+                    storage_dir, tmp_path
+                );
+                metadata
+            }))
         };
 
-        let metadata_path = PathBuf::from(&self.config.storage_path).join("metadata.json");
-        let metadata_str = serde_json::to_string_pretty(&metadata)?;
-        fs::write(&metadata_path, metadata_str)?;
-
-        log::info!(
-            "BWFS::save() -> metadata.json actualizado en {:?}",
-            metadata_path
-        );
+        let metadata: FilesystemMetadata = match metadata_record.as_ref().filter(|record| record.len > 0) {
+            Some(record) => {
+                let cached = read_cache().unwrap_or_else(|e| {
+                    log::warn!("Ignoring unreadable metadata file: {:#}", e);
+                    None
+                });
+                match cached {
+                    Some(metadata) if metadata.seq >= record.seq => metadata,
+                    cached => {
+                        let from_area = storage
+                            .read_metadata_payload(record)
+                            .and_then(|payload| FilesystemMetadata::decode(&payload));
+                        match (from_area, cached) {
+                            (Ok(metadata), cached) => {
+                                if cached.is_some() {
+                                    log::warn!("metadata file is older than the metadata area; loading from the block images");
+                                }
+                                metadata
+                            }
+                            (Err(e), Some(metadata)) => {
+                                log::error!("metadata area is unreadable ({:#}); falling back to the older metadata file", e);
+                                metadata
+                            }
+                            (Err(e), None) => {
+                                return Err(e.context("metadata area is unreadable and there is no metadata file"));
+                            }
+                        }
+                    }
+                }
+            }
+            None => match read_cache()? {
+                Some(metadata) => metadata,
+                None => {
+                    let data_blocks = (1..config.total_blocks)
+                        .filter(|&block_num| storage.block_exists(block_num))
+                        .count();
+                    if data_blocks > 0 {
+                        anyhow::bail!(
+                            "metadata is missing in {:?} but {} data block images exist; \
+                             refusing to start an empty filesystem over them \
+                             (restore metadata.json/metadata.bin or re-run mkfs.bwfs)",
+                            storage_dir,
+                            data_blocks
+                        );
+                    }
+                    // Create new filesystem
+                    return Self::new(config);
+                }
+            },
+        };
+        let last_seq = metadata.seq.max(metadata_record.map_or(0, |record| record.seq));
+
+        Self::check_geometry(&config, &metadata, &storage)?;
+
+        let mut inodes: HashMap<u64, INode> = metadata.inodes.into_iter().collect();
+        let mut directories = metadata.directories.into_iter().collect();
+        let mut bb = metadata.block_bitmap.clone();
+        // Antes de las reparaciones: los bloques recuperados cuentan para
+        // los inodos huérfanos y los bloques compartidos
+        let replayed = Self::replay_journal(&storage_dir, &mut inodes, &mut bb, &storage)?;
+        let journal = Journal::open(&storage_dir, config.journal)?
+            .with_sync(config.journal_sync, Duration::from_millis(config.journal_sync_interval_ms));
+        let repaired = Self::repair_duplicate_entries(&inodes, &mut directories);
+        for (ino, block_idx) in Self::reserved_block_refs(&inodes, &storage) {
+            log::error!(
+                "Inode {} maps block index {} to the reserved superblock (block 0); \
+                 writes to it corrupt the superblock. Run `bwfs_tune --relocate-reserved` to move it",
+                ino, block_idx
+            );
+        }
+        let block_refs = Self::count_shared_blocks(&inodes, &storage);
+        // Metadata antigua no tiene el contador; así además nunca se desvía
+        for inode in inodes.values_mut() {
+            inode.allocated_blocks = Self::owned_blocks(inode, &storage).len() as u32;
+        }
+        // La raíz se fija en mkfs; la metadata no la guarda aparte
+        let root_ino = Self::find_root(&directories).unwrap_or(FUSE_ROOT_INO);
+        if root_ino != config.root_ino {
+            log::warn!(
+                "Configured root_ino {} differs from the filesystem's root {}; using {}",
+                config.root_ino,
+                root_ino,
+                root_ino
+            );
+        }
+        let next_ino = metadata.next_ino;
+        // Metadata antigua sin contador: los inodos existentes tienen generación 0
+        let next_generation = metadata.next_generation.max(1);
+
+        // Aseguramos que el bloque 0 SIEMPRE quede reservado,
+        // aunque una versión vieja del FS no lo tuviera marcado.
+        bb.set(0); // 🔒 bloque 0 reservado (superblock)
+
+        // Versiones anteriores no liberaban el bit al borrar ni lo indexaban
+        // desde first_ino: el bitmap de inodos se reconstruye desde la tabla
+        let mut inode_bitmap = Bitmap::new(config.total_inodes as usize);
+        for slot in inodes.keys().filter_map(|&ino| Self::ino_slot(&config, ino)) {
+            inode_bitmap.set(slot);
+        }
+        let inode_bitmap_drift = inode_bitmap.as_bytes() != metadata.inode_bitmap.as_bytes();
+        if inode_bitmap_drift {
+            log::warn!(
+                "inode bitmap marked {} inodes in use but {} exist; rebuilt from the inode table",
+                metadata.inode_bitmap.count_set(),
+                inode_bitmap.count_set()
+            );
+        }
 
-        Ok(())
-    }
+        if config.integrity_tree {
+            let tree = match metadata.merkle_tree {
+                Some(tree) if tree.len() == config.total_blocks as usize => tree,
+                // Sin árbol persistido (o de otro tamaño): reconstruir desde disco
+                _ => storage.build_merkle_tree()?,
+            };
+            storage.enable_merkle(tree);
+        }
 
-    /// Marca el filesystem como "sucio" (con cambios pendientes de persistir)
-    fn mark_dirty(&self) {
-        let mut dirty = self.dirty.lock().unwrap();
-        *dirty = true;
-        log::info!("📌 mark_dirty(): filesystem marcado como DIRTY");
+        let fs = Self {
+            storage: Arc::new(Mutex::new(storage)),
+            inodes: Arc::new(Mutex::new(inodes)),
+            directories: Arc::new(Mutex::new(directories)),
+            open_files: Arc::new(Mutex::new(HashMap::new())),
+            next_fh: Arc::new(Mutex::new(1)),
+            block_bitmap: Arc::new(Mutex::new(bb)),
+            block_refs: Arc::new(Mutex::new(block_refs)),
+            inode_bitmap: Arc::new(Mutex::new(inode_bitmap)),
+            instance_id: Self::make_instance_id(&config),
+            atime_policy: config.atime_mode,
+            strict_posix: false,
+            checkpoint_seq: Arc::new(Mutex::new(last_seq)),
+            checkpoint_written: Arc::new(Mutex::new(0)),
+            notifier: Arc::new(Mutex::new(None)),
+            sync_thread: Mutex::new(None),
+            last_close_save: Mutex::new(None),
+            memory_check_ops: AtomicU64::new(0),
+            metadata_no_space: AtomicBool::new(false),
+            journal: Arc::new(journal),
+            config,
+            next_ino: Arc::new(Mutex::new(next_ino)),
+            root_ino,
+            next_generation: Arc::new(Mutex::new(next_generation)),
+            // Las reparaciones se persisten en el próximo checkpoint
+            dirty: Arc::new(Mutex::new(
+                repaired > 0 || inode_bitmap_drift || replayed > 0,
+            )),
+        };
+        fs.start_background_sync();
+        Ok(fs)
     }
 
-    /// Si hay cambios pendientes, llama a `save()` y limpia la bandera.
-    fn sync_if_dirty(&self) -> Result<()> {
-        {
-            let dirty = self.dirty.lock().unwrap();
-            if !*dirty {
-                log::info!("📌 sync_if_dirty(): metadata CLEAN, nada que sincronizar");
-                return Ok(());
+    /// Refuse to load when `total_blocks`/`total_inodes` disagree with the
+    /// bitmaps in the metadata or with the block images on disk (e.g.
+    /// config.ini edited after mkfs): out-of-range blocks would otherwise
+    /// be silently lost or overwritten.
+    fn check_geometry(config: &Config, metadata: &FilesystemMetadata, storage: &BlockStorage) -> Result<()> {
+        if metadata.block_bitmap.len() != config.total_blocks as usize {
+            anyhow::bail!(
+                "config says total_blocks = {} but the filesystem metadata tracks {} blocks; \
+                 restore the value used by mkfs.bwfs",
+                config.total_blocks,
+                metadata.block_bitmap.len()
+            );
+        }
+        if metadata.inode_bitmap.len() != config.total_inodes as usize {
+            anyhow::bail!(
+                "config says total_inodes = {} but the filesystem metadata tracks {} inodes; \
+                 restore the value used by mkfs.bwfs",
+                config.total_inodes,
+                metadata.inode_bitmap.len()
+            );
+        }
+        if let Some(highest) = storage.highest_block_file()? {
+            if highest >= config.total_blocks {
+                anyhow::bail!(
+                    "storage holds block_{:08}.png but total_blocks = {}; \
+                     the configuration does not match this filesystem",
+                    highest,
+                    config.total_blocks
+                );
             }
         }
-
-        log::info!("📌 sync_if_dirty(): metadata DIRTY, llamando a save() ...");
-        self.save()?;
-        let mut dirty = self.dirty.lock().unwrap();
-        *dirty = false;
-        log::info!("📌 sync_if_dirty(): metadata sincronizada, bandera limpia");
         Ok(())
     }
 
-    /// Convert INode to FUSE FileAttr
-    fn inode_to_attr(&self, inode: &INode) -> FileAttr {
-        let kind = match inode.file_type {
-            FileType::RegularFile => FuseFileType::RegularFile,
-            FileType::Directory => FuseFileType::Directory,
-            FileType::Symlink => FuseFileType::Symlink,
-        };
+    /// Roll the journal left by a crash forward onto the loaded metadata.
+    ///
+    /// Only committed operations are applied, and of those only the blocks
+    /// whose image on disk matches the CRC in their record: each is mapped
+    /// at its file index, marked in the bitmap and taken away from any
+    /// inode the older checkpoint still gives it to. A file grows to its
+    /// committed size only when every block of the operation is on disk.
+    /// Incomplete operations are discarded (their new blocks stay free).
+    /// Replaying again changes nothing. Returns how many blocks and sizes
+    /// were updated.
+    fn replay_journal(
+        storage_dir: &std::path::Path,
+        inodes: &mut HashMap<u64, INode>,
+        block_bitmap: &mut Bitmap,
+        storage: &BlockStorage,
+    ) -> Result<usize> {
+        let records = Journal::read_records(storage_dir)?;
+        if records.is_empty() {
+            return Ok(0);
+        }
+        let committed: HashMap<u64, (u64, u64)> = records
+            .iter()
+            .filter_map(|record| match *record {
+                JournalRecord::Commit { op, ino, size } => Some((op, (ino, size))),
+                JournalRecord::Block { .. } => None,
+            })
+            .collect();
+
+        // Registros de operaciones confirmadas con el bloque ya en disco. Un
+        // bloque que no llegó solo falta si nada posterior reescribió esa
+        // posición del archivo
+        let metadata_blocks = storage.metadata_area().map(|area| area.blocks());
+        let mut landed = Vec::new();
+        let mut incomplete_ops = std::collections::HashSet::new();
+        let mut rewritten = std::collections::HashSet::new();
+        for record in records.iter().rev() {
+            let JournalRecord::Block { op, ino, index, block, crc } = *record else {
+                continue;
+            };
+            if !committed.contains_key(&op) {
+                continue;
+            }
+            let on_disk = block != 0
+                && (block as usize) < block_bitmap.len()
+                && !metadata_blocks.as_ref().is_some_and(|blocks| blocks.contains(&block))
+                && storage.read_block(block).is_ok_and(|data| crc32fast::hash(&data) == crc);
+            if on_disk {
+                landed.push((ino, index, block));
+            } else if !rewritten.contains(&(ino, index)) {
+                incomplete_ops.insert(op);
+            }
+            rewritten.insert((ino, index));
+        }
+        landed.reverse();
+
+        // Dueños de cada bloque según el checkpoint
+        let mut owners: HashMap<u32, Vec<(u64, u32)>> = HashMap::new();
+        let mut pointer_blocks = std::collections::HashSet::new();
+        for inode in inodes.values() {
+            match inode.mapped_blocks(storage).and_then(|mapped| Ok((mapped, inode.pointer_blocks(storage)?))) {
+                Ok((mapped, pointers)) => {
+                    for (index, block_num) in mapped {
+                        owners.entry(block_num).or_default().push((inode.ino, index));
+                    }
+                    pointer_blocks.extend(pointers);
+                }
+                Err(e) => log::error!("journal replay: cannot read the indirect blocks of inode {}: {}", inode.ino, e),
+            }
+        }
 
-        FileAttr {
-            ino: inode.ino,
-            size: inode.size,
-            blocks: (inode.size + 511) / 512,
-            atime: inode.atime,
-            mtime: inode.mtime,
-            ctime: inode.ctime,
-            crtime: inode.ctime,
-            kind,
-            perm: inode.mode,
-            nlink: inode.nlink,
-            uid: inode.uid,
-            gid: inode.gid,
-            rdev: 0,
-            blksize: 4096,
-            flags: 0,
+        // Reservar todos antes de mapear: los bloques indirectos nuevos no
+        // deben caer sobre un bloque que el journal todavía va a usar
+        let mut reserved = Vec::new();
+        for &(_, _, block_num) in &landed {
+            if !block_bitmap.is_set(block_num as usize) {
+                block_bitmap.set(block_num as usize);
+                reserved.push(block_num);
+            }
         }
-    }
 
-    /// Allocate a new inode number
-    fn allocate_ino(&self) -> u64 {
-        let mut next_ino = self.next_ino.lock().unwrap();
-        let ino = *next_ino;
-        *next_ino += 1;
+        let mut changed = 0;
+        for &(ino, index, block_num) in &landed {
+            if owners.get(&block_num).is_some_and(|list| list.contains(&(ino, index))) {
+                continue;
+            }
+            let usable = inodes
+                .get(&ino)
+                .is_some_and(|inode| inode.file_type == FileType::RegularFile && (index == 0 || !inode.is_inline()));
+            if !usable || pointer_blocks.contains(&block_num) {
+                log::warn!("journal replay: block {} not recovered for inode {} index {}", block_num, ino, index);
+                continue;
+            }
 
-        let mut bitmap = self.inode_bitmap.lock().unwrap();
-        bitmap.set(ino as usize);
+            // El contenido de esos dueños ya fue reemplazado en disco
+            for (other_ino, other_index) in owners.remove(&block_num).unwrap_or_default() {
+                if let Some(other) = inodes.get_mut(&other_ino) {
+                    other.set_block_number_with_storage(other_index, u32::MAX, storage, &mut || None)?;
+                    log::warn!(
+                        "journal replay: block {} was reused; unmapped from inode {} index {}",
+                        block_num, other_ino, other_index
+                    );
+                }
+            }
 
-        ino
-    }
+            let inode = inodes.get_mut(&ino).unwrap();
+            let previous = inode.get_block_number_with_storage(index, storage)?;
+            let mapped = inode.set_block_number_with_storage(index, block_num, storage, &mut || {
+                block_bitmap.allocate().map(|b| b as u32)
+            })?;
+            if !mapped {
+                continue;
+            }
+            // El primer bloque de un archivo inline recibe sus datos al crecer
+            inode.inline_data.clear();
+
+            if let Some(old) = previous {
+                if let Some(list) = owners.get_mut(&old) {
+                    list.retain(|&owner| owner != (ino, index));
+                    if list.is_empty() {
+                        owners.remove(&old);
+                        block_bitmap.clear(old as usize);
+                    }
+                }
+            }
+            owners.entry(block_num).or_default().push((ino, index));
+            changed += 1;
+        }
 
-    /// Allocate a new file handle
-    fn allocate_fh(&self) -> u64 {
-        let mut next_fh = self.next_fh.lock().unwrap();
-        let fh = *next_fh;
-        *next_fh += 1;
-        fh
-    }
+        for block_num in reserved {
+            if !owners.contains_key(&block_num) {
+                block_bitmap.clear(block_num as usize);
+            }
+        }
 
-    /// Allocate a new block (nunca retorna el bloque 0 porque está reservado en el bitmap)
-    fn allocate_block(&self) -> Option<u32> {
-        let mut bitmap = self.block_bitmap.lock().unwrap();
-        bitmap.allocate().map(|idx| idx as u32)
-    }
+        for (op, &(ino, size)) in &committed {
+            if incomplete_ops.contains(op) {
+                log::warn!("journal replay: operation {} on inode {} is only partly on disk; size not extended", op, ino);
+                continue;
+            }
+            if let Some(inode) = inodes.get_mut(&ino) {
+                if inode.file_type == FileType::RegularFile && !inode.is_inline() && size > inode.size {
+                    inode.size = size;
+                    changed += 1;
+                }
+            }
+        }
 
-    /// Free a block
-    fn free_block(&self, block_num: u32) {
-        let mut bitmap = self.block_bitmap.lock().unwrap();
-        // Nunca deberíamos liberar el bloque 0; por seguridad lo evitamos
-        if block_num != 0 {
-            bitmap.deallocate(block_num as usize);
+        let discarded = records
+            .iter()
+            .filter_map(|record| match *record {
+                JournalRecord::Block { op, .. } if op != 0 && !committed.contains_key(&op) => Some(op),
+                _ => None,
+            })
+            .collect::<std::collections::HashSet<_>>()
+            .len();
+        if changed > 0 || discarded > 0 {
+            log::warn!(
+                "journal replay: {} change(s) rolled forward from {:?}, {} incomplete operation(s) discarded",
+                changed, storage_dir, discarded
+            );
         }
+        Ok(changed)
     }
-}
-
-macro_rules! log_enter {
-    ($func:expr) => {
-        log::info!("➡️ ENTER {}", $func);
-    };
-}
 
-macro_rules! log_exit {
-    ($func:expr) => {
-        log::info!("⬅️ EXIT {}", $func);
-    };
-}
+    /// The root directory: the only one whose `..` points to itself
+    fn find_root(directories: &HashMap<u64, Vec<DirEntry>>) -> Option<u64> {
+        directories
+            .iter()
+            .find(|(&ino, entries)| entries.iter().any(|e| e.name == ".." && e.ino == ino))
+            .map(|(&ino, _)| ino)
+    }
 
-macro_rules! log_point {
-    ($msg:expr) => {{
-        log::info!("📌 {}", $msg);
-    }};
-}
+    /// Every block an inode owns: its mapped data blocks plus the indirect
+    /// blocks holding their pointers. An unreadable indirect block is logged
+    /// and the blocks under it are skipped.
+    fn owned_blocks(inode: &INode, storage: &BlockStorage) -> Vec<u32> {
+        let mapped = inode.mapped_blocks(storage).and_then(|mapped| {
+            let mut blocks: Vec<u32> = mapped.into_iter().map(|(_, block_num)| block_num).collect();
+            blocks.extend(inode.pointer_blocks(storage)?);
+            Ok(blocks)
+        });
+        mapped.unwrap_or_else(|e| {
+            log::error!("Cannot read the indirect blocks of inode {}: {}", inode.ino, e);
+            (0..DIRECT_BLOCKS).filter_map(|i| inode.get_block_number(i)).collect()
+        })
+    }
 
-impl Filesystem for BWFS {
-    fn init(&mut self, _req: &Request, _config: &mut KernelConfig) -> Result<(), libc::c_int> {
-        log_enter!("init()");
-        log_point!("Initializing FS");
-        log_exit!("init()");
-        Ok(())
+    /// Reference count of every block used by more than one inode pointer
+    fn count_shared_blocks(inodes: &HashMap<u64, INode>, storage: &BlockStorage) -> HashMap<u32, u32> {
+        let mut counts: HashMap<u32, u32> = HashMap::new();
+        for inode in inodes.values() {
+            for block_num in Self::owned_blocks(inode, storage) {
+                *counts.entry(block_num).or_insert(0) += 1;
+            }
+        }
+        counts.retain(|_, count| *count > 1);
+        counts
     }
 
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &std::ffi::OsStr, reply: ReplyEntry) {
-        let name = name.to_string_lossy().to_string();
-        log_enter!("lookup()");
-        log_point!(format!("lookup: parent={}, name={}", parent, name.clone()));
+    /// (ino, block index) of every inode pointer to the reserved block 0,
+    /// left behind by images created before block 0 was reserved. Indirect
+    /// pointers are included; an inode whose indirect blocks are unreadable
+    /// is checked through its direct pointers only.
+    fn reserved_block_refs(inodes: &HashMap<u64, INode>, storage: &BlockStorage) -> Vec<(u64, u32)> {
+        let mut refs: Vec<(u64, u32)> = inodes
+            .values()
+            .flat_map(|inode| {
+                let mapped = inode.mapped_blocks(storage).unwrap_or_else(|e| {
+                    log::error!("Cannot read the indirect blocks of inode {}: {}", inode.ino, e);
+                    (0..DIRECT_BLOCKS)
+                        .filter_map(|i| inode.get_block_number(i).map(|b| (i, b)))
+                        .collect()
+                });
+                mapped
+                    .into_iter()
+                    .filter(|&(_, block_num)| block_num == 0)
+                    .map(|(i, _)| (inode.ino, i))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        refs.sort_unstable();
+        refs
+    }
 
-        let directories = self.directories.lock().unwrap();
-        let inodes = self.inodes.lock().unwrap();
+    /// Drop duplicate names within each directory, left behind by the old
+    /// rename double-insert.
+    ///
+    /// Of the entries sharing a name, the one whose inode exists and has
+    /// the highest generation wins and takes the place of the first
+    /// occurrence. Each conflict is logged. Returns the number of entries
+    /// removed.
+    fn repair_duplicate_entries(
+        inodes: &HashMap<u64, INode>,
+        directories: &mut HashMap<u64, Vec<DirEntry>>,
+    ) -> usize {
+        let rank = |entry: &DirEntry| inodes.get(&entry.ino).map(|inode| inode.generation);
+        let mut removed = 0;
+
+        for (dir_ino, entries) in directories.iter_mut() {
+            let mut kept: Vec<DirEntry> = Vec::with_capacity(entries.len());
+            for entry in entries.drain(..) {
+                let Some(existing) = kept.iter_mut().find(|e| e.name == entry.name) else {
+                    kept.push(entry);
+                    continue;
+                };
 
-        if let Some(entries) = directories.get(&parent) {
-            if let Some(entry) = entries.iter().find(|e| e.name == name) {
-                log_point!("lookup match found");
-                if let Some(inode) = inodes.get(&entry.ino) {
-                    let attr = self.inode_to_attr(inode);
-                    reply.entry(&TTL, &attr, 0);
-                    log_exit!("lookup()");
-                    return;
-                }
+                // None (inodo inexistente) pierde siempre frente a Some
+                let (winner, loser) = if rank(&entry) > rank(existing) {
+                    (entry, existing.clone())
+                } else {
+                    (existing.clone(), entry)
+                };
+                log::warn!(
+                    "Directory {} has duplicate entry '{}': keeping ino {}, dropping ino {}",
+                    dir_ino, winner.name, winner.ino, loser.ino
+                );
+                *existing = winner;
+                removed += 1;
             }
+            *entries = kept;
         }
 
-        log_point!("lookup: NOENT");
-        reply.error(libc::ENOENT);
-        log_exit!("lookup()");
+        removed
     }
 
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        log_enter!("getattr()");
-        log_point!(format!("getattr ino={}", ino));
+    /// Parse a leftover metadata `.tmp`; `None` if absent or incomplete
+    fn read_metadata_tmp(path: &std::path::Path) -> Option<FilesystemMetadata> {
+        let data = std::fs::read(path).ok()?;
+        match FilesystemMetadata::decode(&data) {
+            Ok(metadata) => Some(metadata),
+            Err(e) => {
+                log::warn!("Ignoring unreadable {:?}: {}", path, e);
+                None
+            }
+        }
+    }
 
-        let inodes = self.inodes.lock().unwrap();
+    /// Whether `storage_path` holds persisted metadata in either format,
+    /// counting a complete `.tmp` that `load` would recover, or a
+    /// checkpoint in the metadata area of the block images
+    pub fn has_metadata(storage_path: &str) -> bool {
+        let dir = std::path::Path::new(storage_path);
+        [MetadataFormat::Json, MetadataFormat::Binary].iter().any(|format| {
+            dir.join(format.file_name()).exists()
+                || Self::read_metadata_tmp(&dir.join(format!("{}.tmp", format.file_name()))).is_some()
+        }) || BlockStorage::has_metadata_record(storage_path)
+    }
 
-        if let Some(inode) = inodes.get(&ino) {
-            let attr = self.inode_to_attr(inode);
-            reply.attr(&TTL, &attr);
+    /// Identifier of this instance: the filesystem name, or the storage
+    /// path when no name is configured.
+    fn make_instance_id(config: &Config) -> String {
+        let name = config.name.trim();
+        if name.is_empty() {
+            config.storage_path.clone()
         } else {
-            log_point!("getattr: NOENT");
-            reply.error(libc::ENOENT);
+            name.to_string()
         }
-        log_exit!("getattr()");
     }
 
-    fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
-        log_enter!("open()");
-        log_point!(format!("open ino={} flags={}", ino, flags));
-
-        let inodes = self.inodes.lock().unwrap();
+    /// Identifier used to tag this instance's log lines
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
 
-        if inodes.contains_key(&ino) {
-            let fh = self.allocate_fh();
-            let mut open_files = self.open_files.lock().unwrap();
-            open_files.insert(fh, ino);
+    /// Inode number of the root directory
+    pub fn root_ino(&self) -> u64 {
+        self.root_ino
+    }
 
-            log_point!(format!("open: fh={} assigned", fh));
+    /// Configuration the filesystem was created or loaded with
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
 
-            reply.opened(fh, 0);
-        } else {
-            log_point!("open: NOENT");
-            reply.error(libc::ENOENT);
-        }
-        log_exit!("open()");
+    /// Shared handle to the block storage, e.g. for an embedded
+    /// `NetworkServer`. Holders must take the lock for every block access,
+    /// which serializes them with the filesystem's own reads and writes.
+    pub fn storage_handle(&self) -> Arc<Mutex<BlockStorage>> {
+        Arc::clone(&self.storage)
     }
 
-    fn read(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        size: u32,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        reply: ReplyData,
-    ) {
-        log_point!(format!(
-            "read: ino={}, offset={}, size={}",
-            ino, offset, size
-        ));
+    /// Slot for the session's `Notifier`. The mount stores it here after
+    /// creating the FUSE session so out-of-band changes can invalidate the
+    /// kernel's caches.
+    pub fn notifier_handle(&self) -> Arc<Mutex<Option<Notifier>>> {
+        Arc::clone(&self.notifier)
+    }
 
-        let inodes = self.inodes.lock().unwrap();
-        let storage = self.storage.lock().unwrap();
+    /// Drop the kernel's cached attributes and data for `ino`.
+    ///
+    /// Only for changes made outside a FUSE request (tools, background
+    /// repair, remote writes): notifying from inside a handler for the same
+    /// inode can deadlock the kernel.
+    pub fn invalidate_inode(&self, ino: u64) {
+        if let Some(notifier) = self.notifier.lock().unwrap().as_ref() {
+            // ENOENT solo significa que el kernel no lo tenía en caché
+            if let Err(e) = notifier.inval_inode(self.kernel_ino(ino), 0, 0) {
+                log_point!(self, format!("invalidate_inode(): ino={} -> {}", ino, e));
+            }
+        }
+    }
 
-        if let Some(inode) = inodes.get(&ino) {
-            if !inode.is_file() {
-                log_point!("read -> EISDIR");
-                reply.error(libc::EISDIR);
-                return;
+    /// Drop the kernel's cached lookup of `name` in `parent` (see
+    /// `invalidate_inode` for when this may be called)
+    pub fn invalidate_entry(&self, parent: u64, name: &str) {
+        if let Some(notifier) = self.notifier.lock().unwrap().as_ref() {
+            if let Err(e) = notifier.inval_entry(self.kernel_ino(parent), std::ffi::OsStr::new(name)) {
+                log_point!(self, format!(
+                    "invalidate_entry(): parent={}, name='{}' -> {}",
+                    parent, name, e
+                ));
             }
+        }
+    }
 
-            let mut data = Vec::new();
-            let block_size = storage.bytes_per_block();
-            log_point!(format!("read -> block_size={}", block_size));
+    /// Choose when reads update atime (defaults to `atime_mode` from the
+    /// configuration)
+    pub fn set_atime_policy(&mut self, policy: AtimePolicy) {
+        self.atime_policy = policy;
+    }
 
-            let start_block = (offset as usize) / block_size;
-            let end_block = ((offset as usize + size as usize) + block_size - 1) / block_size;
+    /// Enable strict POSIX error codes (`EISDIR`, `EINVAL`) in the handlers
+    /// that otherwise fall back to `ENOENT` or silently accept the operation.
+    /// `rename` always reports them.
+    pub fn set_strict_posix(&mut self, strict: bool) {
+        self.strict_posix = strict;
+    }
 
-            log_point!(format!(
-                "read -> start_block={} end_block={}",
-                start_block, end_block
-            ));
+    /// Update an inode's atime after a read, according to the atime policy.
+    /// Only marks the filesystem dirty when atime actually changed.
+    fn touch_atime(&self, ino: u64) {
+        let updated = {
+            let mut inodes = self.inodes.lock().unwrap();
+            let Some(inode) = inodes.get_mut(&ino) else {
+                return;
+            };
 
-            for block_idx in start_block..end_block {
-                if let Some(block_num) = inode.get_block_number(block_idx as u32) {
-                    log_point!(format!(
-                        "read -> block {} mapped to physical {}",
-                        block_idx, block_num
-                    ));
-                    if let Ok(block_data) = storage.read_block(block_num) {
-                        data.extend_from_slice(&block_data);
-                    } else {
-                        log_point!(format!("read -> error reading block {}", block_num));
-                    }
-                } else {
-                    log_point!(format!("read -> block {} not allocated", block_idx));
+            let now = SystemTime::now();
+            let update = match self.atime_policy {
+                AtimePolicy::Strict => true,
+                AtimePolicy::Noatime => false,
+                AtimePolicy::Relatime => {
+                    inode.atime <= inode.mtime
+                        || inode.atime <= inode.ctime
+                        || now
+                            .duration_since(inode.atime)
+                            .map(|age| age >= RELATIME_WINDOW)
+                            .unwrap_or(false)
                 }
+            };
+
+            if update {
+                inode.atime = now;
             }
+            update
+        };
 
-            let start_offset = (offset as usize) % block_size;
-            let end_offset = (start_offset + size as usize).min(data.len());
+        if updated {
+            log_point!(self, format!("touch_atime(): ino={} atime updated", ino));
+            self.mark_dirty();
+        }
+    }
 
-            log_point!(format!(
-                "read -> slicing data from {} to {} (data.len={})",
-                start_offset,
-                end_offset,
-                data.len()
-            ));
+    /// Save filesystem state to disk
+    ///
+    /// Persistence ordering (write barrier): metadata may only reference blocks
+    /// whose data is already durable. `save()` therefore:
+    ///   1. fsyncs every data block written since the last save,
+    ///   2. with a metadata area, writes the metadata to its inactive copy
+    ///      and then switches the superblock record to it,
+    ///   3. writes the metadata to `metadata.json.tmp` (or `metadata.bin.tmp`)
+    ///      and fsyncs it (skipped with an area and `metadata_cache = false`),
+    ///   4. atomically renames it into place and fsyncs the directory.
+    ///
+    /// A crash at any point leaves either the old or the new metadata in place,
+    /// and both only point at blocks whose PNGs are complete on disk. The
+    /// area is authoritative: `load` only prefers the file when its
+    /// checkpoint is at least as recent.
+    ///
+    /// The locks are only held while taking the snapshot; serialization and
+    /// disk I/O happen afterwards (see `checkpoint_background`).
+    pub fn save(&self) -> Result<()> {
+        log::info!("[{}] BWFS::save() -> escribiendo metadata en disco", self.instance_id);
+        self.prepare_checkpoint()?.write()
+    }
 
-            if start_offset < data.len() {
-                reply.data(&data[start_offset..end_offset]);
-            } else {
-                reply.data(&[]);
+    /// Start a checkpoint whose serialization and disk writes run on a
+    /// separate thread. Only the snapshot is taken on the caller's thread,
+    /// so other operations are blocked for the copy, not the write.
+    pub fn checkpoint_background(&self) -> Result<std::thread::JoinHandle<Result<()>>> {
+        let checkpoint = self.prepare_checkpoint()?;
+        let instance_id = self.instance_id.clone();
+        Ok(std::thread::spawn(move || {
+            let result = checkpoint.write();
+            if let Err(e) = &result {
+                log::error!("[{}] background checkpoint failed: {}", instance_id, e);
             }
-        } else {
-            log_point!("read -> ENOENT");
-            reply.error(libc::ENOENT);
+            result
+        }))
+    }
+
+    /// Take a consistent snapshot of the metadata under the locks
+    fn prepare_checkpoint(&self) -> Result<Checkpoint> {
+        self.checkpoint_source().prepare()
+    }
+
+    /// Handles to everything a checkpoint snapshots
+    fn checkpoint_source(&self) -> CheckpointSource {
+        CheckpointSource {
+            storage: Arc::clone(&self.storage),
+            inodes: Arc::clone(&self.inodes),
+            directories: Arc::clone(&self.directories),
+            block_bitmap: Arc::clone(&self.block_bitmap),
+            inode_bitmap: Arc::clone(&self.inode_bitmap),
+            next_ino: Arc::clone(&self.next_ino),
+            next_generation: Arc::clone(&self.next_generation),
+            dirty: Arc::clone(&self.dirty),
+            checkpoint_seq: Arc::clone(&self.checkpoint_seq),
+            checkpoint_written: Arc::clone(&self.checkpoint_written),
+            storage_dir: std::path::PathBuf::from(&self.config.storage_path),
+            format: self.config.metadata_format,
+            metadata_cache: self.config.metadata_cache,
+            journal: Arc::clone(&self.journal),
         }
     }
 
-    fn write(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        data: &[u8],
-        _write_flags: u32,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        reply: ReplyWrite,
-    ) {
-        log_point!(format!(
-            "ENTER write(): ino={}, offset={}, size={}",
-            ino,
-            offset,
-            data.len()
-        ));
+    /// Change the filesystem fingerprint.
+    ///
+    /// Block 0 is rewritten with the new value (geometry and Merkle root are
+    /// preserved) and synced, and the in-memory configuration is updated.
+    /// `config.ini` is not touched; callers must update it themselves.
+    pub fn set_fingerprint(&mut self, fingerprint: &str) -> Result<()> {
+        let fingerprint = fingerprint.trim().to_string();
+        {
+            let mut storage = self.storage.lock().unwrap();
+            storage.set_fingerprint(fingerprint.clone())?;
+            storage.sync_blocks()?;
+        }
+        log::info!(
+            "[{}] set_fingerprint(): '{}' -> '{}'",
+            self.instance_id, self.config.fingerprint, fingerprint
+        );
+        self.config.fingerprint = fingerprint;
+        Ok(())
+    }
 
-        // --------------------------------------------
-        // BLOQUE DE LOCK → se libera al salir
-        // --------------------------------------------
-        let write_result = {
+    /// Make the block storage fail on demand according to `plan` (`None`
+    /// removes it), to exercise error paths deterministically in tests
+    #[cfg(test)]
+    pub(crate) fn inject_faults(&self, plan: Option<crate::storage::FaultPlan>) {
+        self.storage.lock().unwrap().set_faults(plan);
+    }
+
+    /// Move file data that points at the reserved block 0 to fresh blocks.
+    ///
+    /// Each reference gets a newly allocated block holding a copy of block
+    /// 0's current contents, and the inode pointer is updated. Block 0 stays
+    /// reserved. Returns the number of pointers relocated.
+    pub fn relocate_reserved_blocks(&self) -> Result<usize> {
+        let mut relocated = 0;
+        let result = (|| -> Result<()> {
             let mut inodes = self.inodes.lock().unwrap();
             let storage = self.storage.lock().unwrap();
-
-            // Obtener el inode
-            let inode = match inodes.get_mut(&ino) {
-                Some(inode) => inode,
-                None => {
-                    log_point!("write() -> ENOENT");
-                    reply.error(libc::ENOENT);
-                    return;
+            let refs = Self::reserved_block_refs(&inodes, &storage);
+            if refs.is_empty() {
+                return Ok(());
+            }
+            let data = storage.read_block(0)?;
+
+            for (ino, block_idx) in refs {
+                let block_num = self
+                    .allocate_block()
+                    .ok_or_else(|| anyhow::anyhow!("No free block to relocate inode {}", ino))?;
+                if let Err(e) = storage.write_block(block_num, &data) {
+                    self.free_block(block_num);
+                    return Err(e);
                 }
-            };
-
-            if !inode.is_file() {
-                log_point!("write() -> EISDIR");
-                reply.error(libc::EISDIR);
-                return;
+                // El puntero ya existe: nunca hace falta un bloque indirecto nuevo
+                if let Err(e) = inodes
+                    .get_mut(&ino)
+                    .unwrap()
+                    .set_block_number_with_storage(block_idx, block_num, &storage, &mut || None)
+                {
+                    self.free_block(block_num);
+                    return Err(e);
+                }
+                log::warn!(
+                    "[{}] relocated inode {} block index {} from block 0 to block {}",
+                    self.instance_id, ino, block_idx, block_num
+                );
+                relocated += 1;
             }
+            Ok(())
+        })();
 
-            let block_size = storage.bytes_per_block();
-            log_point!(format!("write() -> block_size={}", block_size));
+        // Lo ya reubicado se persiste aunque una reubicación posterior falle
+        if relocated > 0 {
+            // Bloque 0 siempre reservado, aunque el bitmap antiguo no lo marcara
+            self.block_bitmap.lock().unwrap().set(0);
+            self.mark_dirty();
+            self.sync_if_dirty()?;
+        }
+        result.map(|()| relocated)
+    }
 
-            let start_block = (offset as usize) / block_size;
-            let blocks_needed = ((offset as usize + data.len()) + block_size - 1) / block_size;
+    /// Estimate the memory held by metadata and the block cache
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let inode_bytes: usize = self
+            .inodes
+            .lock()
+            .unwrap()
+            .values()
+            .map(|inode| {
+                std::mem::size_of::<u64>()
+                    + std::mem::size_of::<INode>()
+                    + inode.inline_data.len()
+                    + inode.xattrs.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>()
+            })
+            .sum();
+        let directory_bytes: usize = self
+            .directories
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entries| {
+                std::mem::size_of::<(u64, Vec<DirEntry>)>()
+                    + entries
+                        .iter()
+                        .map(|e| std::mem::size_of::<DirEntry>() + e.name.len())
+                        .sum::<usize>()
+            })
+            .sum();
+        let cache_bytes = self.cache_stats().map(|stats| stats.bytes).unwrap_or(0);
+
+        let metadata_bytes = inode_bytes + directory_bytes;
+        MemoryUsage {
+            metadata_bytes,
+            cache_bytes,
+            total_bytes: metadata_bytes + cache_bytes,
+        }
+    }
 
-            log_point!(format!(
-                "write() -> start_block={} blocks_needed={}",
-                start_block, blocks_needed
-            ));
+    /// Every `MEMORY_CHECK_INTERVAL` calls, shrink the block cache if the
+    /// estimated usage exceeds `max_memory_bytes`. Metadata is always kept
+    /// in memory, so only the cache can give memory back.
+    fn enforce_memory_limit(&self) {
+        let limit = self.config.max_memory_bytes;
+        if limit == 0 {
+            return;
+        }
+        let ops = self.memory_check_ops.fetch_add(1, Ordering::Relaxed);
+        if !ops.is_multiple_of(MEMORY_CHECK_INTERVAL) {
+            return;
+        }
 
-            // --------------------------------------------
-            // Asignar bloques faltantes (usa allocate_block → safe)
-            // --------------------------------------------
-            for block_idx in start_block..blocks_needed {
-                if inode.get_block_number(block_idx as u32).is_none() {
-                    // Intentar asignar bloque
-                    if let Some(new_block) = self.allocate_block() {
-                        log_point!(format!(
-                            "write() -> allocating PHYSICAL block {}",
-                            new_block
-                        ));
+        let usage = self.memory_usage();
+        if usage.total_bytes <= limit {
+            return;
+        }
+        let cache_budget = limit.saturating_sub(usage.metadata_bytes);
+        log::warn!(
+            "[{}] estimated memory {} bytes exceeds max_memory_bytes {}; shrinking block cache to {} bytes",
+            self.instance_id, usage.total_bytes, limit, cache_budget
+        );
+        self.storage.lock().unwrap().shrink_cache(cache_budget);
+        if usage.metadata_bytes > limit {
+            log::warn!(
+                "[{}] metadata alone ({} bytes) exceeds max_memory_bytes",
+                self.instance_id, usage.metadata_bytes
+            );
+        }
+    }
 
-                        inode.set_block_number(block_idx as u32, new_block);
+    /// List the open file and directory handles, sorted by handle
+    pub fn open_handles(&self) -> Vec<OpenHandleInfo> {
+        let handles: Vec<(u64, u64, Instant)> = self
+            .open_files
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&fh, handle)| (fh, handle.ino, handle.opened_at))
+            .collect();
+
+        let mut paths: HashMap<u64, String> = HashMap::from([(self.root_ino, "/".to_string())]);
+        for (path, inode) in self.walk() {
+            paths.entry(inode.ino).or_insert(path);
+        }
 
-                        let _ = storage.init_block(new_block);
-                    } else {
-                        log_point!("write() -> ENOSPC");
-                        reply.error(libc::ENOSPC);
-                        return;
-                    }
-                }
-            }
+        let mut result: Vec<OpenHandleInfo> = handles
+            .into_iter()
+            .map(|(fh, ino, opened_at)| OpenHandleInfo {
+                fh,
+                ino,
+                path: paths.get(&ino).cloned(),
+                open_for: opened_at.elapsed(),
+            })
+            .collect();
+        result.sort_by_key(|handle| handle.fh);
+        result
+    }
 
-            // --------------------------------------------
-            // Escribir datos
-            // --------------------------------------------
-            let mut written = 0;
+    /// Counters of the block cache (`None` if `cache_max_bytes` is 0)
+    pub fn cache_stats(&self) -> Option<crate::cache::CacheStats> {
+        self.storage.lock().unwrap().cache_stats()
+    }
+
+    /// Release the memory held by the block cache
+    pub fn trim_cache(&self) {
+        self.storage.lock().unwrap().trim_cache();
+    }
 
-            for block_idx in start_block..blocks_needed {
-                let block_num = inode.get_block_number(block_idx as u32).unwrap();
+    /// Root of the Merkle tree over the data blocks (integrity mode only)
+    pub fn merkle_root(&self) -> Option<Digest> {
+        self.storage.lock().unwrap().merkle_root()
+    }
 
-                log_point!(format!("write() -> writing to block {}", block_num));
+    /// Recompute every block hash from disk and compare it against the tree.
+    ///
+    /// Returns the blocks whose contents diverge from the recorded hashes;
+    /// an empty list means the data matches the tree. Fails if integrity
+    /// mode is not enabled.
+    pub fn verify_tree(&self) -> Result<Vec<u32>> {
+        let storage = self.storage.lock().unwrap();
+        let divergent = storage.verify_merkle()?;
 
-                let block_offset = if block_idx == start_block {
-                    (offset as usize) % block_size
-                } else {
-                    0
-                };
+        for block_num in &divergent {
+            log::warn!(
+                "[{}] verify_tree(): block {} does not match its Merkle leaf",
+                self.instance_id,
+                block_num
+            );
+        }
+        if let (Some(root), Some(stored)) = (storage.merkle_root(), storage.stored_merkle_root()?) {
+            if root != stored {
+                log::warn!(
+                    "[{}] verify_tree(): superblock Merkle root is out of date",
+                    self.instance_id
+                );
+            }
+        }
 
-                let write_size = (block_size - block_offset).min(data.len() - written);
+        Ok(divergent)
+    }
+
+    /// Copy the raw block images plus the metadata file to `dest`.
+    ///
+    /// The PNGs are copied byte for byte (no re-encoding) and each copy is
+    /// verified against the source with a CRC32. With `allocated_only`, only
+    /// blocks marked used in the block bitmap are copied. Returns the number
+    /// of block images copied.
+    pub fn export_blocks(&self, dest: &std::path::Path, allocated_only: bool) -> Result<usize> {
+        self.export_blocks_with_progress(dest, allocated_only, |_| {})
+    }
 
-                let mut block_data =
-                    storage.read_block(block_num).unwrap_or_else(|_| vec![0; block_size]);
+    /// Like `export_blocks`, calling `progress` once after each block image
+    /// is copied
+    pub fn export_blocks_with_progress(
+        &self,
+        dest: &std::path::Path,
+        allocated_only: bool,
+        mut progress: impl FnMut(&CopyProgress),
+    ) -> Result<usize> {
+        use std::fs;
+        use std::path::PathBuf;
 
-                block_data[block_offset..block_offset + write_size]
-                    .copy_from_slice(&data[written..written + write_size]);
+        let source_dir = PathBuf::from(&self.config.storage_path);
+        if fs::canonicalize(&source_dir)? == fs::canonicalize(dest).unwrap_or(dest.to_path_buf()) {
+            anyhow::bail!("Destination must differ from the storage path");
+        }
+        fs::create_dir_all(dest)?;
 
-                if let Err(e) = storage.write_block(block_num, &block_data) {
-                    log_point!(format!("write() -> error writing block: {}", e));
-                    reply.error(libc::EIO);
-                    return;
-                }
+        // Metadata consistente con los bloques que vamos a copiar
+        self.save()?;
 
-                written += write_size;
+        let block_bitmap = self.block_bitmap.lock().unwrap().clone();
+        let storage = self.storage.lock().unwrap();
 
-                log_point!(format!(
-                    "write() -> wrote {} bytes into block {}",
-                    write_size, block_num
-                ));
+        // Se listan antes de copiar para conocer el total (ETA)
+        let mut sources = Vec::new();
+        let mut state = CopyProgress::default();
+        for block_num in 0..self.config.total_blocks {
+            if allocated_only && !block_bitmap.is_set(block_num as usize) {
+                continue;
+            }
+            let src = storage.get_block_path(block_num);
+            if let Ok(meta) = fs::metadata(&src) {
+                state.files_total += 1;
+                state.bytes_total += meta.len();
+                sources.push((block_num, src));
             }
+        }
 
-            // --------------------------------------------
-            // Actualizar metadata del inode
-            // --------------------------------------------
-            let new_size = (offset as u64 + data.len() as u64).max(inode.size);
-            log_point!(format!("write() -> new inode size={}", new_size));
+        let mut copied = 0;
+        for (block_num, src) in sources {
+            let dst = dest.join(src.file_name().unwrap());
 
-            inode.size = new_size;
-            inode.mtime = SystemTime::now();
+            let data = fs::read(&src)?;
+            fs::write(&dst, &data)?;
 
-            // Resultado a devolver luego fuera del lock
-            data.len() as u32
-        }; // <-- aquí se LIBERAN TODOS LOS LOCKS (inodes + storage)
+            let copy = fs::read(&dst)?;
+            if crc32fast::hash(&data) != crc32fast::hash(&copy) {
+                anyhow::bail!("Checksum mismatch after copying block {}", block_num);
+            }
 
-        // Marcar metadata como sucia; se sincronizará en fsync()/release()
-        self.mark_dirty();
+            log_point!(self, format!("export_blocks(): copied block {}", block_num));
+            copied += 1;
+
+            state.files_done += 1;
+            state.bytes_done += data.len() as u64;
+            progress(&state);
+        }
+
+        let metadata_file = self.config.metadata_format.file_name();
+        fs::copy(source_dir.join(metadata_file), dest.join(metadata_file))?;
+        crate::storage::sync_dir(dest)?;
 
-        log_point!("write() -> EXIT OK (lazy metadata, fsync/release will persist)");
-        reply.written(write_result);
+        Ok(copied)
+    }
+
+    /// Marca el filesystem como "sucio" (con cambios pendientes de persistir)
+    fn mark_dirty(&self) {
+        let mut dirty = self.dirty.lock().unwrap();
+        *dirty = true;
+        drop(dirty);
+        log_point!(self, "mark_dirty(): filesystem marcado como DIRTY");
+        self.enforce_memory_limit();
+
+        // Los llamadores ya soltaron sus locks, así que se puede guardar aquí.
+        // Con el disco lleno se reintenta en cada cambio: un borrado puede
+        // haber liberado el espacio que faltaba.
+        if self.config.metadata_sync == MetadataSync::Always
+            || self.metadata_no_space.load(Ordering::Relaxed)
+        {
+            if let Err(e) = self.sync_if_dirty() {
+                log::error!("Failed to checkpoint metadata: {}", e);
+            }
+        }
+    }
+
+    /// Start the background checkpoint thread if the configuration asks
+    /// for one. A single thread covers the periodic checkpoint and the
+    /// close debounce; an interval of 0 leaves it off.
+    fn start_background_sync(&self) {
+        let mut periods = Vec::new();
+        if self.config.metadata_sync == MetadataSync::Interval && self.config.metadata_sync_interval_ms > 0 {
+            periods.push(self.config.metadata_sync_interval_ms);
+        }
+        if self.config.save_debounce_ms > 0 {
+            periods.push(self.config.save_debounce_ms);
+        }
+        let Some(&period) = periods.iter().min() else {
+            return;
+        };
+        log_point!(self, format!("start_background_sync(): checkpointing dirty metadata every {} ms", period));
+
+        let interval = Duration::from_millis(period);
+        let source = self.checkpoint_source();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = std::thread::spawn(move || {
+            // Esperar en el canal en lugar de dormir: parar no espera un
+            // intervalo completo (cerrar el emisor también despierta)
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if let Err(e) = source.sync_if_dirty() {
+                    log::error!("Periodic metadata checkpoint failed: {}", e);
+                }
+            }
+        });
+        *self.sync_thread.lock().unwrap() = Some(SyncThread { stop, handle });
+    }
+
+    /// Stop the background checkpoint thread and wait for it, so no
+    /// checkpoint is still being written when this returns
+    fn stop_background_sync(&self) {
+        let Some(thread) = self.sync_thread.lock().unwrap().take() else {
+            return;
+        };
+        let _ = thread.stop.send(());
+        if thread.handle.join().is_err() {
+            log::error!("Background metadata checkpoint thread panicked");
+        }
+    }
+
+    /// Save on close, unless `save_debounce_ms` says a save happened too
+    /// recently: then the background checkpoint thread writes it shortly.
+    fn sync_on_close(&self) -> Result<()> {
+        let window = Duration::from_millis(self.config.save_debounce_ms);
+        if !window.is_zero() {
+            let mut last = self.last_close_save.lock().unwrap();
+            if last.is_some_and(|at| at.elapsed() < window) {
+                log_point!(self, "sync_on_close(): guardado diferido (debounce)");
+                return Ok(());
+            }
+            *last = Some(Instant::now());
+        }
+        self.sync_if_dirty()
+    }
+
+    /// Si hay cambios pendientes, llama a `save()` y limpia la bandera.
+    fn sync_if_dirty(&self) -> Result<()> {
+        {
+            let dirty = self.dirty.lock().unwrap();
+            if !*dirty {
+                log_point!(self, "sync_if_dirty(): metadata CLEAN, nada que sincronizar");
+                return Ok(());
+            }
+        }
+
+        log_point!(self, "sync_if_dirty(): metadata DIRTY, llamando a save() ...");
+        // Se limpia ANTES del snapshot: un cambio posterior vuelve a ensuciar
+        // y no se pierde. Si el guardado falla, se restaura.
+        *self.dirty.lock().unwrap() = false;
+        if let Err(e) = self.save() {
+            *self.dirty.lock().unwrap() = true;
+            if storage_errno(&e) == libc::ENOSPC
+                && !self.metadata_no_space.swap(true, Ordering::Relaxed)
+            {
+                log::error!(
+                    "[{}] storage path is full; metadata stays in memory and new block \
+                     allocations fail with ENOSPC until a checkpoint succeeds",
+                    self.instance_id
+                );
+            }
+            return Err(e);
+        }
+        if self.metadata_no_space.swap(false, Ordering::Relaxed) {
+            log::warn!("[{}] metadata checkpoint succeeded again; allocations resumed", self.instance_id);
+        }
+        log_point!(self, "sync_if_dirty(): metadata sincronizada, bandera limpia");
+        Ok(())
+    }
+
+    /// Inode number the kernel uses for `ino`. FUSE fixes the root at 1, so
+    /// with a custom `root_ino` the two numbers trade places.
+    fn kernel_ino(&self, ino: u64) -> u64 {
+        if ino == self.root_ino {
+            FUSE_ROOT_INO
+        } else if ino == FUSE_ROOT_INO {
+            self.root_ino
+        } else {
+            ino
+        }
+    }
+
+    /// Inode number behind a kernel inode number (inverse of `kernel_ino`)
+    fn internal_ino(&self, ino: u64) -> u64 {
+        // El intercambio es su propio inverso
+        self.kernel_ino(ino)
+    }
+
+    /// Convert INode to FUSE FileAttr
+    fn inode_to_attr(&self, inode: &INode) -> FileAttr {
+        let kind = fuse_file_type(inode.file_type);
+
+        FileAttr {
+            ino: self.kernel_ino(inode.ino),
+            size: inode.size,
+            // Unidades de 512 bytes de lo realmente asignado: los huecos no cuentan
+            blocks: (inode.allocated_blocks as u64 * self.config.bytes_per_block() as u64).div_ceil(512),
+            atime: inode.atime,
+            mtime: inode.mtime,
+            ctime: inode.ctime,
+            crtime: inode.ctime,
+            kind,
+            perm: inode.mode,
+            nlink: inode.nlink,
+            uid: inode.uid,
+            gid: inode.gid,
+            rdev: inode.rdev,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    /// Validate a directory entry name against the configured `max_name_len`.
+    /// Returns the errno to reply with if the name is not acceptable.
+    fn validate_name(&self, name: &str) -> Result<(), libc::c_int> {
+        if name.is_empty() || name.contains('/') {
+            return Err(libc::EINVAL);
+        }
+        if name.len() > self.config.max_name_len as usize {
+            return Err(libc::ENAMETOOLONG);
+        }
+        Ok(())
+    }
+
+    /// Apply `chmod`/`chown`: permission bits from `mode` (the file type
+    /// bits are ignored), and the owner and group that are `Some`. ctime is
+    /// updated whenever anything is set.
+    pub fn set_mode_and_owner(
+        &self,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> std::result::Result<(), libc::c_int> {
+        log_point!(self, format!(
+            "set_mode_and_owner(): ino={}, mode={:?}, uid={:?}, gid={:?}",
+            ino, mode, uid, gid
+        ));
+        if mode.is_none() && uid.is_none() && gid.is_none() {
+            return Ok(());
+        }
+
+        {
+            let mut inodes = self.inodes.lock().unwrap();
+            let inode = inodes.get_mut(&ino).ok_or(libc::ENOENT)?;
+            if let Some(mode) = mode {
+                inode.mode = (mode & 0o7777) as u16;
+            }
+            if let Some(uid) = uid {
+                inode.uid = uid;
+            }
+            if let Some(gid) = gid {
+                inode.gid = gid;
+            }
+            inode.ctime = SystemTime::now();
+        }
+
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Set extended attribute `name`. `flags` follows setxattr(2):
+    /// `XATTR_CREATE` fails with EEXIST if it exists, `XATTR_REPLACE` with
+    /// ENODATA if it does not. ctime is updated.
+    pub fn set_xattr(
+        &self,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: i32,
+    ) -> std::result::Result<(), libc::c_int> {
+        log_point!(self, format!("set_xattr(): ino={}, name='{}', {} bytes", ino, name, value.len()));
+        if name.is_empty() || name.len() > XATTR_NAME_MAX {
+            return Err(libc::ERANGE);
+        }
+        if value.len() > XATTR_SIZE_MAX {
+            return Err(libc::E2BIG);
+        }
+
+        {
+            let mut inodes = self.inodes.lock().unwrap();
+            let inode = inodes.get_mut(&ino).ok_or(libc::ENOENT)?;
+            let exists = inode.xattrs.contains_key(name);
+            if flags & libc::XATTR_CREATE != 0 && exists {
+                return Err(libc::EEXIST);
+            }
+            if flags & libc::XATTR_REPLACE != 0 && !exists {
+                return Err(libc::ENODATA);
+            }
+            inode.xattrs.insert(name.to_string(), value.to_vec());
+            inode.ctime = SystemTime::now();
+        }
+
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Value of extended attribute `name` (ENODATA if it is not set)
+    pub fn get_xattr(&self, ino: u64, name: &str) -> std::result::Result<Vec<u8>, libc::c_int> {
+        let inodes = self.inodes.lock().unwrap();
+        let inode = inodes.get(&ino).ok_or(libc::ENOENT)?;
+        inode.xattrs.get(name).cloned().ok_or(libc::ENODATA)
+    }
+
+    /// Names of the extended attributes of `ino` as listxattr(2) returns
+    /// them: each followed by a NUL, in name order
+    pub fn list_xattrs(&self, ino: u64) -> std::result::Result<Vec<u8>, libc::c_int> {
+        let inodes = self.inodes.lock().unwrap();
+        let inode = inodes.get(&ino).ok_or(libc::ENOENT)?;
+        let mut names: Vec<&String> = inode.xattrs.keys().collect();
+        names.sort();
+        let mut list = Vec::new();
+        for name in names {
+            list.extend_from_slice(name.as_bytes());
+            list.push(0);
+        }
+        Ok(list)
+    }
+
+    /// Remove extended attribute `name` (ENODATA if it is not set)
+    pub fn remove_xattr(&self, ino: u64, name: &str) -> std::result::Result<(), libc::c_int> {
+        log_point!(self, format!("remove_xattr(): ino={}, name='{}'", ino, name));
+        {
+            let mut inodes = self.inodes.lock().unwrap();
+            let inode = inodes.get_mut(&ino).ok_or(libc::ENOENT)?;
+            inode.xattrs.remove(name).ok_or(libc::ENODATA)?;
+            inode.ctime = SystemTime::now();
+        }
+
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Set access and/or modification time, as `utimensat` does: `Now` takes
+    /// the current time, `None` (UTIME_OMIT) keeps the existing value. ctime
+    /// is updated whenever either time is set.
+    pub fn set_times(
+        &self,
+        ino: u64,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+    ) -> std::result::Result<(), libc::c_int> {
+        log_point!(self, format!("set_times(): ino={}, atime={:?}, mtime={:?}", ino, atime, mtime));
+        if atime.is_none() && mtime.is_none() {
+            return Ok(());
+        }
+
+        {
+            let mut inodes = self.inodes.lock().unwrap();
+            let inode = inodes.get_mut(&ino).ok_or(libc::ENOENT)?;
+
+            let now = SystemTime::now();
+            let resolve = |time: TimeOrNow| match time {
+                TimeOrNow::SpecificTime(time) => time,
+                TimeOrNow::Now => now,
+            };
+            if let Some(atime) = atime {
+                inode.atime = resolve(atime);
+            }
+            if let Some(mtime) = mtime {
+                inode.mtime = resolve(mtime);
+            }
+            inode.ctime = now;
+        }
+
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Create an empty regular file `name` in `parent` and reserve blocks
+    /// for `expected_size` bytes up front.
+    ///
+    /// The blocks are taken as one contiguous run when possible, otherwise
+    /// one by one; if the filesystem cannot hold them the file is created
+    /// without a reservation. Reserved blocks are zeroed and stay allocated
+    /// beyond the file size until written or truncated. Returns the new
+    /// inode number.
+    pub fn create_with_size_hint(
+        &self,
+        parent: u64,
+        name: &str,
+        mode: u16,
+        expected_size: u64,
+    ) -> Result<u64> {
+        self.validate_name(name)
+            .map_err(|errno| anyhow::anyhow!("Invalid name '{}' (errno {})", name, errno))?;
+
+        let ino = {
+            let mut inodes = self.inodes.lock().unwrap();
+            let mut directories = self.directories.lock().unwrap();
+            let storage = self.storage.lock().unwrap();
+
+            if !inodes.get(&parent).map(|i| i.is_dir()).unwrap_or(false) {
+                anyhow::bail!("Parent inode {} is not a directory", parent);
+            }
+            if directories
+                .get(&parent)
+                .is_some_and(|entries| entries.iter().any(|e| e.name == name))
+            {
+                anyhow::bail!("'{}' already exists in inode {}", name, parent);
+            }
+
+            let ino = self
+                .allocate_ino()
+                .ok_or_else(|| anyhow::anyhow!("No free inodes left for '{}'", name))?;
+            let mut inode = INode::new(ino, FileType::RegularFile, mode, 0, 0);
+            inode.generation = self.allocate_generation();
+
+            // Un archivo que cabrá inline no necesita bloques
+            let block_size = storage.bytes_per_block() as u64;
+            let count = if expected_size <= self.inline_limit(&storage) {
+                0
+            } else {
+                u32::try_from(expected_size.div_ceil(block_size)).unwrap_or(u32::MAX)
+            };
+            if let Err(e) = self.reserve_blocks(&mut inode, &storage, count) {
+                log::warn!(
+                    "[{}] create_with_size_hint(): no reservation for '{}': {}",
+                    self.instance_id, name, e
+                );
+            }
+
+            directories
+                .entry(parent)
+                .or_default()
+                .push(DirEntry::for_inode(name.to_string(), &inode));
+            inodes.insert(ino, inode);
+            ino
+        };
+
+        self.mark_dirty();
+        Ok(ino)
+    }
+
+    /// Create a symbolic link `name` in `parent` pointing at `target`. The
+    /// target is stored as the link's data, like the contents of a file,
+    /// and its length is the link's size. Returns the new inode.
+    pub fn create_symlink(
+        &self,
+        parent: u64,
+        name: &str,
+        target: &[u8],
+        uid: u32,
+        gid: u32,
+    ) -> std::result::Result<INode, libc::c_int> {
+        log_point!(self, format!("create_symlink(): parent={}, name='{}'", parent, name));
+        self.validate_name(name)?;
+        if target.is_empty() {
+            return Err(libc::ENOENT);
+        }
+        if target.len() > libc::PATH_MAX as usize {
+            return Err(libc::ENAMETOOLONG);
+        }
+
+        let inode = {
+            let mut inodes = self.inodes.lock().unwrap();
+            let mut directories = self.directories.lock().unwrap();
+            let storage = self.storage.lock().unwrap();
+
+            if !inodes.get(&parent).is_some_and(|i| i.is_dir()) {
+                return Err(libc::ENOTDIR);
+            }
+            if directories
+                .get(&parent)
+                .is_some_and(|entries| entries.iter().any(|e| e.name == name))
+            {
+                return Err(libc::EEXIST);
+            }
+
+            let ino = self.allocate_ino().ok_or(libc::ENOSPC)?;
+            let mut inode = INode::new(ino, FileType::Symlink, 0o777, uid, gid);
+            inode.generation = self.allocate_generation();
+            inode.size = target.len() as u64;
+
+            // Un destino corto se guarda en el propio inodo
+            if target.len() as u64 <= self.inline_limit(&storage) {
+                inode.inline_data = target.to_vec();
+            } else {
+                let mut allocated = Vec::new();
+                for (block_idx, chunk) in target.chunks(storage.bytes_per_block()).enumerate() {
+                    let stored = match self.allocate_block() {
+                        Some(block_num) => {
+                            allocated.push((block_idx as u32, block_num));
+                            self.map_file_block(&mut inode, &storage, block_idx as u32, block_num)
+                                .and_then(|_| storage.write_block(block_num, chunk).map_err(|e| storage_errno(&e)))
+                        }
+                        None => Err(libc::ENOSPC),
+                    };
+                    if let Err(errno) = stored {
+                        self.undo_allocations(&mut inode, &storage, &allocated);
+                        self.free_ino(ino);
+                        return Err(errno);
+                    }
+                }
+            }
+
+            directories
+                .entry(parent)
+                .or_default()
+                .push(DirEntry::for_inode(name.to_string(), &inode));
+            inodes.insert(ino, inode.clone());
+            inode
+        };
+
+        self.mark_dirty();
+        Ok(inode)
+    }
+
+    /// Create a special file (device, FIFO or socket), or an empty regular
+    /// file, named `name` in `parent`. `rdev` is kept for devices and
+    /// ignored for the other types. Returns the new inode.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_node(
+        &self,
+        parent: u64,
+        name: &str,
+        file_type: FileType,
+        mode: u16,
+        rdev: u32,
+        uid: u32,
+        gid: u32,
+    ) -> std::result::Result<INode, libc::c_int> {
+        log_point!(self, format!(
+            "create_node(): parent={}, name='{}', type={:?}, rdev={:#x}",
+            parent, name, file_type, rdev
+        ));
+        self.validate_name(name)?;
+        if matches!(file_type, FileType::Directory | FileType::Symlink) {
+            return Err(libc::EINVAL);
+        }
+
+        let inode = {
+            let mut inodes = self.inodes.lock().unwrap();
+            let mut directories = self.directories.lock().unwrap();
+
+            if !inodes.get(&parent).is_some_and(|i| i.is_dir()) {
+                return Err(libc::ENOTDIR);
+            }
+            if directories
+                .get(&parent)
+                .is_some_and(|entries| entries.iter().any(|e| e.name == name))
+            {
+                return Err(libc::EEXIST);
+            }
+
+            let ino = self.allocate_ino().ok_or(libc::ENOSPC)?;
+            let mut inode = INode::new(ino, file_type, mode & 0o7777, uid, gid);
+            inode.generation = self.allocate_generation();
+            if inode.is_device() {
+                inode.rdev = rdev;
+            }
+
+            directories
+                .entry(parent)
+                .or_default()
+                .push(DirEntry::for_inode(name.to_string(), &inode));
+            inodes.insert(ino, inode.clone());
+            inode
+        };
+
+        self.mark_dirty();
+        Ok(inode)
+    }
+
+    /// Create an empty directory `name` in `parent`, with its `.` and `..`
+    /// entries. Returns the new inode.
+    pub fn create_dir(
+        &self,
+        parent: u64,
+        name: &str,
+        mode: u16,
+        uid: u32,
+        gid: u32,
+    ) -> std::result::Result<INode, libc::c_int> {
+        log_point!(self, format!("create_dir(): parent={}, name='{}'", parent, name));
+        self.validate_name(name)?;
+
+        let inode = {
+            let mut inodes = self.inodes.lock().unwrap();
+            let mut directories = self.directories.lock().unwrap();
+
+            if !inodes.get(&parent).is_some_and(|i| i.is_dir()) {
+                return Err(libc::ENOTDIR);
+            }
+            if directories
+                .get(&parent)
+                .is_some_and(|entries| entries.iter().any(|e| e.name == name))
+            {
+                return Err(libc::EEXIST);
+            }
+
+            let ino = self.allocate_ino().ok_or(libc::ENOSPC)?;
+            let mut inode = INode::new(ino, FileType::Directory, mode & 0o7777, uid, gid);
+            inode.nlink = 2;
+            inode.generation = self.allocate_generation();
+
+            directories.insert(
+                ino,
+                vec![
+                    DirEntry::new(ino, ".".to_string(), FileType::Directory),
+                    DirEntry::new(parent, "..".to_string(), FileType::Directory),
+                ],
+            );
+            directories
+                .entry(parent)
+                .or_default()
+                .push(DirEntry::for_inode(name.to_string(), &inode));
+            if let Some(parent_inode) = inodes.get_mut(&parent) {
+                parent_inode.nlink += 1;
+            }
+            inodes.insert(ino, inode.clone());
+            inode
+        };
+
+        self.mark_dirty();
+        Ok(inode)
+    }
+
+    /// Move entry `name` of `parent` to `newname` in `newparent`, replacing
+    /// an existing destination the way POSIX `rename` does
+    pub fn rename_entry(
+        &self,
+        parent: u64,
+        name: &str,
+        newparent: u64,
+        newname: &str,
+    ) -> std::result::Result<(), libc::c_int> {
+        if let Err(errno) = self.validate_name(newname) {
+            log_point!(self, format!("rename() -> invalid name '{}' (errno {})", newname, errno));
+            return Err(errno);
+        }
+
+        let mut exit_code: Option<i32> = None; // None = OK; Some(errno) = error
+
+        {
+            log_point!(self, "rename() -> locking inodes and directories");
+            let mut inodes = self.inodes.lock().unwrap();
+            let mut directories = self.directories.lock().unwrap();
+            log_point!(self, "rename() -> locks acquired");
+
+            // Un destino existente se reemplaza (o se rechaza) antes de mover
+            let mut same_inode = false;
+            match self.rename_target(&mut inodes, &mut directories, parent, name, newparent, newname) {
+                Ok(proceed) => same_inode = !proceed,
+                Err(errno) => {
+                    log_point!(self, format!("rename(): target check failed (errno {})", errno));
+                    exit_code = Some(errno);
+                }
+            }
+
+            // ----------------------------------------------------------
+            // Buscar entrada en el parent original
+            // ----------------------------------------------------------
+            let entry_info = directories
+                .get_mut(&parent)
+                .filter(|_| exit_code.is_none() && !same_inode)
+                .and_then(|entries| {
+                    entries
+                        .iter()
+                        .position(|e| e.name == name)
+                        .map(|pos| (pos, entries))
+                });
+
+            if let Some((pos, parent_entries)) = entry_info {
+                log_point!(self, format!(
+                    "rename(): found '{}' at pos {} in parent {}",
+                    name, pos, parent
+                ));
+
+                // ----------------------------------------------------------
+                // Quitar la entrada del directorio original
+                // ----------------------------------------------------------
+                let mut entry = parent_entries.remove(pos);
+                log_point!(self, format!(
+                    "rename(): removed old entry '{}' (ino={}) from parent {}",
+                    name, entry.ino, parent
+                ));
+
+                // ----------------------------------------------------------
+                // Actualizar nombre
+                // ----------------------------------------------------------
+                entry.name = newname.to_string();
+                // Refrescar el tipo desde el inodo: readdir confía en el DirEntry
+                if let Some(inode) = inodes.get(&entry.ino) {
+                    entry.file_type = inode.file_type;
+                }
+                log_point!(self, format!(
+                    "rename(): updated name '{}' -> '{}'",
+                    name, newname
+                ));
+
+                // ----------------------------------------------------------
+                // Insertar en el nuevo parent
+                // ----------------------------------------------------------
+                let (moved_ino, moved_is_dir) = (entry.ino, entry.file_type == FileType::Directory);
+                directories
+                    .entry(newparent)
+                    .or_default()
+                    .push(entry);
+
+                log_point!(self, format!(
+                    "rename(): inserted updated entry into newparent {}",
+                    newparent
+                ));
+
+                // ----------------------------------------------------------
+                // Directorio movido a otro padre: ".." y nlink de los padres
+                // ----------------------------------------------------------
+                if moved_is_dir && parent != newparent {
+                    if let Some(dotdot) = directories
+                        .get_mut(&moved_ino)
+                        .and_then(|entries| entries.iter_mut().find(|e| e.name == ".."))
+                    {
+                        dotdot.ino = newparent;
+                    }
+                    if let Some(old_parent) = inodes.get_mut(&parent) {
+                        drop_subdir_link(old_parent);
+                    }
+                    if let Some(new_parent) = inodes.get_mut(&newparent) {
+                        new_parent.nlink += 1;
+                    }
+                    log_point!(self, format!(
+                        "rename(): directory {} now under {} ('..' and parent nlink updated)",
+                        moved_ino, newparent
+                    ));
+                }
+            } else if exit_code.is_none() && !same_inode {
+                log_point!(self, format!(
+                    "rename(): entry '{}' not found in parent {}",
+                    name, parent
+                ));
+                exit_code = Some(libc::ENOENT);
+            }
+
+            // Locks salen aquí
+        }
+
+        match exit_code {
+            None => {
+                // Directory tree cambió → metadata sucia
+                self.mark_dirty();
+                Ok(())
+            }
+            Some(errno) => Err(errno),
+        }
+    }
+
+    /// Add a hard link `newname` in `newparent` to the existing inode `ino`
+    /// and return the inode with its new link count. Directories cannot be
+    /// linked (EPERM).
+    pub fn link_inode(
+        &self,
+        ino: u64,
+        newparent: u64,
+        newname: &str,
+    ) -> std::result::Result<INode, libc::c_int> {
+        self.validate_name(newname)?;
+
+        let inode = {
+            let mut inodes = self.inodes.lock().unwrap();
+            let mut directories = self.directories.lock().unwrap();
+
+            match (inodes.get(&newparent), inodes.get(&ino)) {
+                (None, _) | (_, None) => return Err(libc::ENOENT),
+                (Some(parent), _) if !parent.is_dir() => return Err(libc::ENOTDIR),
+                // No se permiten hard links a directorios
+                (_, Some(inode)) if inode.is_dir() => return Err(libc::EPERM),
+                (_, Some(inode)) if inode.nlink == u32::MAX => return Err(libc::EMLINK),
+                _ => {}
+            }
+            if directories
+                .get(&newparent)
+                .is_some_and(|entries| entries.iter().any(|e| e.name == newname))
+            {
+                return Err(libc::EEXIST);
+            }
+
+            let inode = inodes.get_mut(&ino).unwrap();
+            inode.nlink += 1;
+            inode.ctime = SystemTime::now();
+            directories
+                .entry(newparent)
+                .or_default()
+                .push(DirEntry::for_inode(newname.to_string(), inode));
+            log_point!(self, format!(
+                "link_inode(): '{}' in parent {} -> ino={} (nlink={})",
+                newname, newparent, ino, inode.nlink
+            ));
+            inode.clone()
+        };
+
+        self.mark_dirty();
+        Ok(inode)
+    }
+
+    /// Target of a symbolic link
+    pub fn read_symlink(&self, ino: u64) -> std::result::Result<Vec<u8>, libc::c_int> {
+        let inodes = self.inodes.lock().unwrap();
+        let storage = self.storage.lock().unwrap();
+
+        let inode = inodes.get(&ino).ok_or(libc::ENOENT)?;
+        if inode.file_type != FileType::Symlink {
+            return Err(libc::EINVAL);
+        }
+        self.read_range(inode, &storage, 0, inode.size as usize)
+    }
+
+    /// Give an empty inode `count` zeroed blocks, contiguous if possible,
+    /// mapping indices past the direct blocks through indirect blocks.
+    /// On failure nothing stays allocated.
+    fn reserve_blocks(&self, inode: &mut INode, storage: &BlockStorage, count: u32) -> Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
+
+        if self.metadata_no_space.load(Ordering::Relaxed) {
+            anyhow::bail!("storage path is full");
+        }
+        let contiguous = self.block_bitmap.lock().unwrap().allocate_contiguous(count as usize);
+        let blocks: Vec<u32> = match contiguous {
+            Some(start) => (start as u32..start as u32 + count).collect(),
+            None => {
+                log_point!(self, format!("reserve_blocks(): no run of {} blocks, allocating one by one", count));
+                let mut blocks = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    match self.allocate_block() {
+                        Some(block_num) => blocks.push(block_num),
+                        None => {
+                            blocks.iter().for_each(|&b| self.free_block(b));
+                            anyhow::bail!("not enough free blocks for {} blocks", count);
+                        }
+                    }
+                }
+                blocks
+            }
+        };
+
+        let allocated: Vec<(u32, u32)> = (0..count).zip(blocks).collect();
+        let zeros = vec![0u8; storage.bytes_per_block()];
+        for (done, &(block_idx, block_num)) in allocated.iter().enumerate() {
+            // Pasados los bloques directos se mapea por los indirectos, como
+            // en write_data
+            let result = match self.map_file_block(inode, storage, block_idx, block_num) {
+                Ok(()) => storage.write_block(block_num, &zeros).map_err(|e| (done + 1, e)),
+                Err(errno) => Err((done, anyhow::anyhow!("cannot map block index {} (errno {})", block_idx, errno))),
+            };
+            if let Err((mapped, e)) = result {
+                self.undo_allocations(inode, storage, &allocated[..mapped]);
+                for &(_, block_num) in &allocated[mapped..] {
+                    self.free_block(block_num);
+                }
+                // El inodo es nuevo: sus bloques de punteros tampoco quedan
+                if let Ok(pointers) = inode.pointer_blocks(storage) {
+                    pointers.into_iter().for_each(|block_num| self.free_block(block_num));
+                    inode.indirect_block = u32::MAX;
+                    inode.double_indirect_block = u32::MAX;
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Inode bitmap slot of `ino`: bit `i` tracks inode `first_ino + i`.
+    /// The root and anything outside the allocation range have no slot.
+    fn ino_slot(config: &Config, ino: u64) -> Option<usize> {
+        ino.checked_sub(config.first_ino)
+            .filter(|&slot| slot < config.total_inodes as u64)
+            .map(|slot| slot as usize)
+    }
+
+    /// Allocate a new inode number, reusing the lowest free one. `None`
+    /// when every inode in the allocation range is in use.
+    fn allocate_ino(&self) -> Option<u64> {
+        let mut next_ino = self.next_ino.lock().unwrap();
+        let mut bitmap = self.inode_bitmap.lock().unwrap();
+
+        // Con el bitmap lleno tampoco queda nada por encima de next_ino:
+        // el rango asignable es exactamente el del bitmap
+        let ino = self.config.first_ino + bitmap.allocate()? as u64;
+        // next_ino queda como marca del número más alto entregado
+        *next_ino = (*next_ino).max(ino + 1);
+
+        Some(ino)
+    }
+
+    /// Release an inode number in the inode bitmap once its inode is removed
+    fn free_ino(&self, ino: u64) {
+        if let Some(slot) = Self::ino_slot(&self.config, ino) {
+            self.inode_bitmap.lock().unwrap().clear(slot);
+        }
+    }
+
+    /// Allocate the generation number for a newly created inode
+    fn allocate_generation(&self) -> u64 {
+        let mut next_generation = self.next_generation.lock().unwrap();
+        let generation = *next_generation;
+        *next_generation += 1;
+        generation
+    }
+
+    /// Allocate a new file handle
+    fn allocate_fh(&self) -> u64 {
+        let mut next_fh = self.next_fh.lock().unwrap();
+        let fh = *next_fh;
+        *next_fh += 1;
+        fh
+    }
+
+    /// Allocate a new block (nunca retorna el bloque 0 porque está reservado en el bitmap)
+    fn allocate_block(&self) -> Option<u32> {
+        // Sin espacio para la metadata, no se aceptan bloques nuevos
+        if self.metadata_no_space.load(Ordering::Relaxed) {
+            return None;
+        }
+        let mut bitmap = self.block_bitmap.lock().unwrap();
+        bitmap.allocate().map(|idx| idx as u32)
+    }
+
+    /// Drop one reference to a block, freeing it once no inode uses it
+    fn free_block(&self, block_num: u32) {
+        {
+            let mut refs = self.block_refs.lock().unwrap();
+            if let Some(count) = refs.get_mut(&block_num) {
+                *count -= 1;
+                if *count <= 1 {
+                    refs.remove(&block_num);
+                }
+                return;
+            }
+        }
+
+        let mut bitmap = self.block_bitmap.lock().unwrap();
+        // Nunca deberíamos liberar el bloque 0; por seguridad lo evitamos
+        if block_num != 0 {
+            bitmap.deallocate(block_num as usize);
+        }
+    }
+
+    /// Store `data` as block `block_idx` of a file. A block shared with a
+    /// clone is copied on write: the data goes to a fresh block, the inode
+    /// is repointed and the shared block loses one reference.
+    fn write_file_block(
+        &self,
+        inode: &mut INode,
+        block_idx: u32,
+        storage: &BlockStorage,
+        data: &[u8],
+    ) -> std::result::Result<(), libc::c_int> {
+        let block_num = self.file_block(inode, storage, block_idx)?.ok_or(libc::EIO)?;
+        if !self.block_refs.lock().unwrap().contains_key(&block_num) {
+            self.journal_block(inode.ino, block_idx, block_num, data)?;
+            return storage.write_block(block_num, data).map_err(|e| storage_errno(&e));
+        }
+
+        let new_block = self.allocate_block().ok_or(libc::ENOSPC)?;
+        if let Err(errno) = self.journal_block(inode.ino, block_idx, new_block, data) {
+            self.free_block(new_block);
+            return Err(errno);
+        }
+        if let Err(e) = storage.write_block(new_block, data) {
+            self.free_block(new_block);
+            return Err(storage_errno(&e));
+        }
+        if let Err(errno) = self.map_file_block(inode, storage, block_idx, new_block) {
+            self.free_block(new_block);
+            return Err(errno);
+        }
+        self.free_block(block_num);
+        log_point!(self, format!(
+            "write_file_block(): ino={} index {} copied on write from block {} to {}",
+            inode.ino, block_idx, block_num, new_block
+        ));
+        Ok(())
+    }
+
+    /// Record a block write in the journal before it happens; EIO if the
+    /// record cannot be appended (the block is then not written)
+    fn journal_block(&self, ino: u64, block_idx: u32, block_num: u32, data: &[u8]) -> std::result::Result<(), libc::c_int> {
+        self.journal.record_block(ino, block_idx, block_num, data).map_err(|e| {
+            log::error!("[{}] cannot append to the journal: {:#}", self.instance_id, e);
+            libc::EIO
+        })
+    }
+
+    /// Block number of file block `block_idx`, following indirect blocks
+    fn file_block(
+        &self,
+        inode: &INode,
+        storage: &BlockStorage,
+        block_idx: u32,
+    ) -> std::result::Result<Option<u32>, libc::c_int> {
+        inode.get_block_number_with_storage(block_idx, storage).map_err(|e| {
+            log_point!(self, format!(
+                "file_block(): ino={} index {}: cannot read indirect block -> {}",
+                inode.ino, block_idx, e
+            ));
+            storage_errno(&e)
+        })
+    }
+
+    /// Point file block `block_idx` at `block_num` (`u32::MAX` clears it),
+    /// allocating indirect blocks as needed. EFBIG past the largest file.
+    fn map_file_block(
+        &self,
+        inode: &mut INode,
+        storage: &BlockStorage,
+        block_idx: u32,
+        block_num: u32,
+    ) -> std::result::Result<(), libc::c_int> {
+        match inode.set_block_number_with_storage(block_idx, block_num, storage, &mut || self.allocate_block()) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(libc::EFBIG),
+            Err(e) => {
+                log_point!(self, format!(
+                    "map_file_block(): ino={} index {} -> {}",
+                    inode.ino, block_idx, e
+                ));
+                Err(storage_errno(&e))
+            }
+        }
+    }
+
+    /// Make the empty regular file `dest_ino` share all data blocks of
+    /// `src_ino` (copy-on-write: see `write_file_block`)
+    pub fn clone_blocks_into(&self, src_ino: u64, dest_ino: u64) -> std::result::Result<(), libc::c_int> {
+        log_point!(self, format!("clone_blocks_into(): src={} dest={}", src_ino, dest_ino));
+        if src_ino == dest_ino {
+            return Err(libc::EINVAL);
+        }
+
+        {
+            let mut inodes = self.inodes.lock().unwrap();
+            let storage = self.storage.lock().unwrap();
+            let src = inodes.get(&src_ino).ok_or(libc::ENOENT)?;
+            if !src.is_file() {
+                return Err(libc::EINVAL);
+            }
+            let size = src.size;
+            let inline_data = src.inline_data.clone();
+            let blocks = src.mapped_blocks(&storage).map_err(|e| storage_errno(&e))?;
+
+            let dest = inodes.get_mut(&dest_ino).ok_or(libc::ENOENT)?;
+            if !dest.is_file() {
+                return Err(libc::EINVAL);
+            }
+            let dest_blocks = dest.mapped_blocks(&storage).map_err(|e| storage_errno(&e))?;
+            if dest.size != 0 || !dest_blocks.is_empty() {
+                // Solo se clona sobre un archivo vacío
+                return Err(libc::EINVAL);
+            }
+
+            // Los bloques de datos se comparten; los indirectos son propios
+            for &(block_idx, block_num) in &blocks {
+                if let Err(errno) = self.map_file_block(dest, &storage, block_idx, block_num) {
+                    dest.direct_blocks = [u32::MAX; DIRECT_BLOCKS as usize];
+                    for block_num in dest.trim_pointer_blocks(0, &storage).unwrap_or_default() {
+                        self.free_block(block_num);
+                    }
+                    dest.allocated_blocks = 0;
+                    return Err(errno);
+                }
+            }
+
+            let mut refs = self.block_refs.lock().unwrap();
+            for &(_, block_num) in &blocks {
+                *refs.entry(block_num).or_insert(1) += 1;
+            }
+            dest.size = size;
+            dest.inline_data = inline_data;
+            let now = SystemTime::now();
+            dest.mtime = now;
+            dest.ctime = now;
+        }
+
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Create `name` in `parent` as a copy-on-write clone of `src_ino`:
+    /// both files share their data blocks until either one is written.
+    /// Returns the new inode number.
+    pub fn clone_file(&self, src_ino: u64, parent: u64, name: &str) -> Result<u64> {
+        let mode = self
+            .inodes
+            .lock()
+            .unwrap()
+            .get(&src_ino)
+            .map(|inode| inode.mode)
+            .ok_or_else(|| anyhow::anyhow!("Inode {} not found", src_ino))?;
+
+        let ino = self.create_with_size_hint(parent, name, mode, 0)?;
+        if let Err(errno) = self.clone_blocks_into(src_ino, ino) {
+            anyhow::bail!("Failed to clone inode {} (errno {})", src_ino, errno);
+        }
+        Ok(ino)
+    }
+
+    /// Roll back blocks allocated to an inode by a failed operation
+    fn undo_allocations(&self, inode: &mut INode, storage: &BlockStorage, allocated: &[(u32, u32)]) {
+        for &(block_idx, block_num) in allocated {
+            // Limpiar un puntero nunca asigna; si el bloque indirecto no se
+            // puede leer, el puntero queda y el bloque no se libera
+            if self.map_file_block(inode, storage, block_idx, u32::MAX).is_ok() {
+                self.free_block(block_num);
+            }
+        }
+    }
+
+    /// Zero the bytes past `size` in the block holding it, so a file that
+    /// grows over its old end reads zeros there instead of stale data
+    fn zero_file_tail(
+        &self,
+        inode: &mut INode,
+        storage: &BlockStorage,
+        size: u64,
+    ) -> std::result::Result<(), libc::c_int> {
+        let block_size = storage.bytes_per_block() as u64;
+        let tail = size % block_size;
+        if tail == 0 {
+            return Ok(());
+        }
+        let block_idx = (size / block_size) as u32;
+        if let Some(block_num) = self.file_block(inode, storage, block_idx)? {
+            let mut data = storage.read_block(block_num).map_err(|_| libc::EIO)?;
+            data[tail as usize..].fill(0);
+            self.write_file_block(inode, block_idx, storage, &data)?;
+        }
+        Ok(())
+    }
+
+    /// Largest file kept inline: `inline_data_max_bytes`, capped at one
+    /// block so the data always fits in the first block when it spills
+    fn inline_limit(&self, storage: &BlockStorage) -> u64 {
+        self.config.inline_data_max_bytes.min(storage.bytes_per_block() as u64)
+    }
+
+    /// Move inline data into the file's first block, before the file grows
+    /// past the inline limit
+    fn spill_inline(&self, inode: &mut INode, storage: &BlockStorage) -> std::result::Result<(), libc::c_int> {
+        if !inode.is_inline() {
+            return Ok(());
+        }
+        let block_num = self.allocate_block().ok_or(libc::ENOSPC)?;
+        if let Err(errno) = self.map_file_block(inode, storage, 0, block_num) {
+            self.free_block(block_num);
+            return Err(errno);
+        }
+        let mut data = vec![0u8; storage.bytes_per_block()];
+        data[..inode.inline_data.len()].copy_from_slice(&inode.inline_data);
+        if let Err(errno) = self.journal_block(inode.ino, 0, block_num, &data) {
+            self.undo_allocations(inode, storage, &[(0, block_num)]);
+            return Err(errno);
+        }
+        if let Err(e) = storage.write_block(block_num, &data) {
+            self.undo_allocations(inode, storage, &[(0, block_num)]);
+            return Err(storage_errno(&e));
+        }
+        inode.inline_data.clear();
+        log_point!(self, format!("spill_inline(): ino={} moved to block {}", inode.ino, block_num));
+        Ok(())
+    }
+
+    /// Move the data of a file that shrank under the inline limit back
+    /// into the inode and free its blocks
+    fn collapse_to_inline(&self, inode: &mut INode, storage: &BlockStorage) -> std::result::Result<(), libc::c_int> {
+        let data = self.read_range(inode, storage, 0, inode.size as usize)?;
+        let mapped = inode.mapped_blocks(storage).map_err(|e| storage_errno(&e))?;
+        for (block_idx, block_num) in mapped {
+            self.map_file_block(inode, storage, block_idx, u32::MAX)?;
+            self.free_block(block_num);
+        }
+        for block_num in inode.trim_pointer_blocks(0, storage).map_err(|e| storage_errno(&e))? {
+            self.free_block(block_num);
+        }
+        inode.inline_data = data;
+        log_point!(self, format!("collapse_to_inline(): ino={} now inline ({} bytes)", inode.ino, inode.size));
+        Ok(())
+    }
+
+    /// Free every data and indirect block referenced by an inode
+    fn free_inode_blocks(&self, inode: &INode) {
+        let blocks = Self::owned_blocks(inode, &self.storage.lock().unwrap());
+        for block_num in blocks {
+            self.free_block(block_num);
+            log_point!(self, format!("free_inode_blocks(): freed block {}", block_num));
+        }
+    }
+
+    /// POSIX checks for `rename`, run with the inode and directory locks
+    /// held. Reports type mismatches and non-empty targets with the precise
+    /// errno and, when the rename is allowed, unlinks an existing target so
+    /// the moved entry can take its name (freeing it if that was its last
+    /// link). Returns `false` when source and target are already the same
+    /// inode, in which case rename does nothing.
+    fn rename_target(
+        &self,
+        inodes: &mut HashMap<u64, INode>,
+        directories: &mut HashMap<u64, Vec<DirEntry>>,
+        parent: u64,
+        name: &str,
+        newparent: u64,
+        newname: &str,
+    ) -> Result<bool, libc::c_int> {
+        if name == "." || name == ".." || newname == "." || newname == ".." {
+            return Err(libc::EINVAL);
+        }
+
+        match inodes.get(&newparent) {
+            None => return Err(libc::ENOENT),
+            Some(inode) if !inode.is_dir() => return Err(libc::ENOTDIR),
+            Some(_) => {}
+        }
+
+        let source = directories
+            .get(&parent)
+            .and_then(|entries| entries.iter().find(|e| e.name == name))
+            .cloned()
+            .ok_or(libc::ENOENT)?;
+        let source_is_dir = source.file_type == FileType::Directory;
+
+        // Un directorio no puede moverse dentro de su propio subárbol
+        if source_is_dir {
+            let mut ino = newparent;
+            while ino != self.root_ino {
+                if ino == source.ino {
+                    return Err(libc::EINVAL);
+                }
+                match directories
+                    .get(&ino)
+                    .and_then(|entries| entries.iter().find(|e| e.name == ".."))
+                {
+                    Some(dotdot) if dotdot.ino != ino => ino = dotdot.ino,
+                    _ => break,
+                }
+            }
+        }
+
+        let Some(target) = directories
+            .get(&newparent)
+            .and_then(|entries| entries.iter().find(|e| e.name == newname))
+            .cloned()
+        else {
+            return Ok(true);
+        };
+        if target.ino == source.ino {
+            return Ok(false);
+        }
+
+        let target_is_dir = target.file_type == FileType::Directory;
+        if source_is_dir && !target_is_dir {
+            return Err(libc::ENOTDIR);
+        }
+        if !source_is_dir && target_is_dir {
+            return Err(libc::EISDIR);
+        }
+        if target_is_dir
+            && directories
+                .get(&target.ino)
+                .map(|children| children.len() > 2)
+                .unwrap_or(false)
+        {
+            return Err(libc::ENOTEMPTY);
+        }
+
+        // El destino existe y es compatible: se reemplaza
+        if let Some(entries) = directories.get_mut(&newparent) {
+            entries.retain(|e| e.name != newname);
+        }
+        if target_is_dir {
+            directories.remove(&target.ino);
+            inodes.remove(&target.ino);
+            self.free_ino(target.ino);
+            if let Some(parent_inode) = inodes.get_mut(&newparent) {
+                drop_subdir_link(parent_inode);
+            }
+        } else if let Some(inode) = inodes.get_mut(&target.ino) {
+            drop_link(inode);
+            if inode.nlink == 0 {
+                self.free_inode_blocks(inode);
+                inodes.remove(&target.ino);
+                self.free_ino(target.ino);
+            }
+        }
+        log_point!(self, format!(
+            "rename_target(): replaced '{}' (ino={}) in parent {}",
+            newname, target.ino, newparent
+        ));
+
+        Ok(true)
+    }
+
+    /// Read up to `size` bytes of a file starting at `offset`, never past
+    /// EOF. Holes (unallocated blocks) read back as zeros.
+    fn read_range(
+        &self,
+        inode: &INode,
+        storage: &BlockStorage,
+        offset: u64,
+        size: usize,
+    ) -> std::result::Result<Vec<u8>, libc::c_int> {
+        // Nunca leer más allá del final del archivo
+        let file_size = inode.size as usize;
+        let offset = offset as usize;
+        if offset >= file_size {
+            log_point!(self, "read -> offset past EOF");
+            return Ok(Vec::new());
+        }
+        let size = size.min(file_size - offset);
+
+        if inode.is_inline() {
+            log_point!(self, "read -> inline data");
+            let mut data = vec![0u8; size];
+            let stored = inode.inline_data.len().min(offset + size);
+            if offset < stored {
+                data[..stored - offset].copy_from_slice(&inode.inline_data[offset..stored]);
+            }
+            return Ok(data);
+        }
+
+        let block_size = storage.bytes_per_block();
+        log_point!(self, format!("read -> block_size={}", block_size));
+
+        let start_block = offset / block_size;
+        let end_block = (offset + size).div_ceil(block_size);
+
+        log_point!(self, format!(
+            "read -> start_block={} end_block={}",
+            start_block, end_block
+        ));
+
+        // El buffer se indexa por posición de bloque: cada bloque ocupa
+        // siempre su propio tramo, y los huecos (bloques sin asignar)
+        // quedan rellenos de ceros en lugar de desplazar a los siguientes.
+        let mut data = vec![0u8; (end_block - start_block) * block_size];
+
+        for block_idx in start_block..end_block {
+            let pos = (block_idx - start_block) * block_size;
+
+            if let Some(block_num) = self.file_block(inode, storage, block_idx as u32)? {
+                log_point!(self, format!(
+                    "read -> block {} mapped to physical {}",
+                    block_idx, block_num
+                ));
+                match storage.read_block(block_num) {
+                    Ok(block_data) => {
+                        let len = block_data.len().min(block_size);
+                        data[pos..pos + len].copy_from_slice(&block_data[..len]);
+                    }
+                    Err(e) => {
+                        log_point!(self, format!(
+                            "read -> error reading block {}: {}",
+                            block_num, e
+                        ));
+                        return Err(libc::EIO);
+                    }
+                }
+            } else {
+                log_point!(self, format!("read -> block {} not allocated (hole)", block_idx));
+            }
+        }
+
+        let start_offset = offset % block_size;
+        let end_offset = start_offset + size;
+
+        log_point!(self, format!(
+            "read -> slicing data from {} to {} (data.len={})",
+            start_offset,
+            end_offset,
+            data.len()
+        ));
+
+        data.truncate(end_offset);
+        data.drain(..start_offset);
+        Ok(data)
+    }
+
+    /// Write `data` at `offset` in the regular file `ino`, allocating blocks
+    /// as needed (small files stay inline). Returns the bytes written; on
+    /// error the blocks allocated by this call are released. Used by the
+    /// FUSE `write` handler and by `import_tar`.
+    pub fn write_data(&self, ino: u64, offset: u64, data: &[u8]) -> std::result::Result<usize, libc::c_int> {
+        log_point!(self, format!(
+            "ENTER write(): ino={}, offset={}, size={}",
+            ino,
+            offset,
+            data.len()
+        ));
+
+        {
+            let mut inodes = self.inodes.lock().unwrap();
+            let storage = self.storage.lock().unwrap();
+
+            // Obtener el inode
+            let inode = match inodes.get_mut(&ino) {
+                Some(inode) => inode,
+                None => {
+                    log_point!(self, "write() -> ENOENT");
+                    return Err(libc::ENOENT);
+                }
+            };
+
+            if let Err(errno) = require_file(inode) {
+                log_point!(self, format!("write() -> ERR {} (not a regular file)", errno));
+                return Err(errno);
+            }
+
+            let block_size = storage.bytes_per_block();
+            log_point!(self, format!("write() -> block_size={}", block_size));
+
+            let start_block = (offset as usize) / block_size;
+            let blocks_needed = (offset as usize + data.len()).div_ceil(block_size);
+            if blocks_needed as u64 > INode::max_blocks(block_size) {
+                log_point!(self, "write() -> EFBIG");
+                return Err(libc::EFBIG);
+            }
+
+            log_point!(self, format!(
+                "write() -> start_block={} blocks_needed={}",
+                start_block, blocks_needed
+            ));
+
+            // --------------------------------------------
+            // Archivo pequeño sin bloques: los datos van al inodo
+            // --------------------------------------------
+            let end = offset + data.len() as u64;
+            let new_size = end.max(inode.size);
+            if inode.allocated_blocks == 0 && new_size <= self.inline_limit(&storage) {
+                inode.inline_data.resize(new_size as usize, 0);
+                inode.inline_data[offset as usize..end as usize].copy_from_slice(data);
+                inode.size = new_size;
+                inode.mtime = SystemTime::now();
+                log_point!(self, format!("write() -> stored inline, new inode size={}", new_size));
+                drop(storage);
+                drop(inodes);
+                self.mark_dirty();
+                return Ok(data.len());
+            }
+
+            // Los bloques escritos desde aquí forman una operación del journal
+            self.journal.begin(ino);
+
+            // Los punteros indirectos se acumulan en memoria: cada bloque de
+            // índices se guarda una sola vez, al final de la escritura
+            storage.defer_pointer_blocks();
+            let result = (|| {
+                // Los datos inline pasan al primer bloque antes de crecer
+                if let Err(errno) = self.spill_inline(inode, &storage) {
+                    log_point!(self, format!("write() -> cannot spill inline data (errno {})", errno));
+                    return Err(errno);
+                }
+
+                // Escribir más allá del final deja un hueco: la cola del último
+                // bloque antiguo debe leerse como ceros
+                if offset > inode.size {
+                    if let Err(errno) = self.zero_file_tail(inode, &storage, inode.size) {
+                        log_point!(self, format!("write() -> cannot zero old tail (errno {})", errno));
+                        return Err(errno);
+                    }
+                }
+
+                // --------------------------------------------
+                // Asignar bloques faltantes (usa allocate_block → safe)
+                // --------------------------------------------
+                let mut allocated = Vec::new();
+                for block_idx in start_block..blocks_needed {
+                    let mapped = match self.file_block(inode, &storage, block_idx as u32) {
+                        Ok(mapped) => mapped,
+                        Err(errno) => {
+                            self.undo_allocations(inode, &storage, &allocated);
+                            return Err(errno);
+                        }
+                    };
+                    if mapped.is_none() {
+                        // Intentar asignar bloque
+                        if let Some(new_block) = self.allocate_block() {
+                            log_point!(self, format!(
+                                "write() -> allocating PHYSICAL block {}",
+                                new_block
+                            ));
+
+                            if let Err(errno) = self.map_file_block(inode, &storage, block_idx as u32, new_block) {
+                                log_point!(self, format!("write() -> cannot map block (errno {})", errno));
+                                self.free_block(new_block);
+                                self.undo_allocations(inode, &storage, &allocated);
+                                return Err(errno);
+                            }
+                            allocated.push((block_idx as u32, new_block));
+
+                            let _ = storage.init_block(new_block);
+                        } else {
+                            log_point!(self, "write() -> ENOSPC");
+                            self.undo_allocations(inode, &storage, &allocated);
+                            return Err(libc::ENOSPC);
+                        }
+                    }
+                }
+
+                // --------------------------------------------
+                // Escribir datos
+                // --------------------------------------------
+                let mut written = 0;
+
+                for block_idx in start_block..blocks_needed {
+                    let block_num = match self.file_block(inode, &storage, block_idx as u32) {
+                        Ok(Some(block_num)) => block_num,
+                        Ok(None) | Err(_) => {
+                            self.undo_allocations(inode, &storage, &allocated);
+                            return Err(libc::EIO);
+                        }
+                    };
+
+                    log_point!(self, format!("write() -> writing to block {}", block_num));
+
+                    let block_offset = if block_idx == start_block {
+                        (offset as usize) % block_size
+                    } else {
+                        0
+                    };
+
+                    let write_size = (block_size - block_offset).min(data.len() - written);
+
+                    // Un bloque recién asignado parte de ceros: lo que la
+                    // escritura no cubra es parte de un hueco
+                    let fresh = allocated.iter().any(|&(idx, _)| idx == block_idx as u32);
+                    let mut block_data = if fresh {
+                        vec![0; block_size]
+                    } else {
+                        storage.read_block(block_num).unwrap_or_else(|_| vec![0; block_size])
+                    };
+
+                    block_data[block_offset..block_offset + write_size]
+                        .copy_from_slice(&data[written..written + write_size]);
+
+                    if let Err(errno) = self.write_file_block(inode, block_idx as u32, &storage, &block_data) {
+                        // La imagen anterior sigue intacta (escritura atómica);
+                        // se devuelven los bloques asignados en esta llamada
+                        log_point!(self, format!("write() -> error writing block (errno {})", errno));
+                        self.undo_allocations(inode, &storage, &allocated);
+                        return Err(errno);
+                    }
+
+                    written += write_size;
+
+                    log_point!(self, format!(
+                        "write() -> wrote {} bytes into block {}",
+                        write_size, block_num
+                    ));
+                }
+                Ok(())
+            })();
+            let flushed = storage.flush_pointer_blocks().map_err(|e| {
+                log_point!(self, format!("write() -> cannot store index blocks -> {}", e));
+                storage_errno(&e)
+            });
+            result.and(flushed)?;
+
+            // --------------------------------------------
+            // Actualizar metadata del inode
+            // --------------------------------------------
+            let new_size = (offset + data.len() as u64).max(inode.size);
+            log_point!(self, format!("write() -> new inode size={}", new_size));
+
+            inode.size = new_size;
+            inode.mtime = SystemTime::now();
+
+            // La operación ya está completa en memoria; sin el commit, la
+            // escritura solo sobrevive a un crash si llega al checkpoint
+            if let Err(e) = self.journal.commit(ino, new_size) {
+                log::error!("[{}] cannot commit write to the journal: {:#}", self.instance_id, e);
+            }
+
+        } // <-- aquí se LIBERAN TODOS LOS LOCKS (inodes + storage)
+
+        // Marcar metadata como sucia; se sincronizará en fsync()/release()
+        self.mark_dirty();
+
+        log_point!(self, "write() -> EXIT OK (lazy metadata, fsync/release will persist)");
+        Ok(data.len())
+    }
+
+    /// Read the whole contents of a regular file
+    pub fn read_file(&self, ino: u64) -> Result<Vec<u8>> {
+        let inodes = self.inodes.lock().unwrap();
+        let storage = self.storage.lock().unwrap();
+
+        let inode = inodes
+            .get(&ino)
+            .ok_or_else(|| anyhow::anyhow!("Inode {} not found", ino))?;
+        if inode.is_dir() {
+            anyhow::bail!("Inode {} is a directory", ino);
+        }
+
+        self.read_range(inode, &storage, 0, inode.size as usize)
+            .map_err(|errno| anyhow::anyhow!("Failed to read inode {} (errno {})", ino, errno))
+    }
+
+    /// Block and inode totals as `statfs` reports them, from the bitmaps
+    pub fn fs_stats(&self) -> FsStats {
+        let block_size = self.storage.lock().unwrap().bytes_per_block() as u32;
+        let free_blocks = {
+            let block_bitmap = self.block_bitmap.lock().unwrap();
+            (0..self.config.total_blocks as usize)
+                .filter(|&i| !block_bitmap.is_set(i))
+                .count() as u64
+        };
+        // El bitmap es la fuente de verdad; la raíz no cuenta porque queda
+        // fuera del rango asignable
+        let used_inodes = self.inode_bitmap.lock().unwrap().count_set() as u64;
+
+        FsStats {
+            block_size,
+            total_blocks: self.config.total_blocks as u64,
+            free_blocks,
+            total_inodes: self.config.total_inodes as u64,
+            free_inodes: (self.config.total_inodes as u64).saturating_sub(used_inodes),
+            max_name_len: self.config.max_name_len,
+        }
+    }
+
+    /// Open file `ino` with `open(2)` `flags` and return its handle.
+    /// `O_TRUNC` empties the file first; opening a directory for writing
+    /// is `EISDIR` under `strict_posix`
+    pub fn open_file(&self, ino: u64, flags: i32) -> std::result::Result<u64, libc::c_int> {
+        let is_dir = self.inodes.lock().unwrap().get(&ino).ok_or(libc::ENOENT)?.is_dir();
+        // Un directorio no se puede abrir para escritura
+        if self.strict_posix && is_dir && flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(libc::EISDIR);
+        }
+        // O_TRUNC vacía el archivo antes de dar el handle (`>` en la shell)
+        if flags & libc::O_TRUNC != 0 {
+            if is_dir {
+                return Err(libc::EISDIR);
+            }
+            self.set_file_size(ino, 0)?;
+        }
+        let fh = self.allocate_fh();
+        self.open_files.lock().unwrap().insert(fh, OpenHandle::file(ino));
+        Ok(fh)
+    }
+
+    /// Close file handle `fh`, saving the metadata first if it is dirty
+    /// (the save is what can fail; the handle is kept in that case)
+    pub fn release_handle(&self, fh: u64) -> std::result::Result<(), libc::c_int> {
+        self.sync_on_close().map_err(|e| storage_errno(&e))?;
+        self.open_files.lock().unwrap().remove(&fh);
+        Ok(())
+    }
+
+    /// Open directory `ino` for listing: the entries are captured now and
+    /// the returned handle lists this snapshot until it is released
+    pub fn open_dir(&self, ino: u64) -> std::result::Result<u64, libc::c_int> {
+        let inodes = self.inodes.lock().unwrap();
+        require_dir(inodes.get(&ino).ok_or(libc::ENOENT)?)?;
+        let entries = self
+            .directories
+            .lock()
+            .unwrap()
+            .get(&ino)
+            .cloned()
+            .unwrap_or_default();
+        let fh = self.allocate_fh();
+        self.open_files.lock().unwrap().insert(
+            fh,
+            OpenHandle {
+                ino,
+                dir_entries: Some(entries),
+                opened_at: Instant::now(),
+            },
+        );
+        Ok(fh)
+    }
+
+    /// Entries `readdir` lists for directory `ino` through handle `fh`: the
+    /// snapshot taken by `open_dir`, or the live directory table when the
+    /// handle has none (e.g. an unknown fh)
+    pub fn dir_entries(&self, ino: u64, fh: u64) -> std::result::Result<Vec<DirEntry>, libc::c_int> {
+        let snapshot = self
+            .open_files
+            .lock()
+            .unwrap()
+            .get(&fh)
+            .filter(|handle| handle.ino == ino)
+            .and_then(|handle| handle.dir_entries.clone());
+        if let Some(entries) = snapshot {
+            return Ok(entries);
+        }
+
+        // Sin snapshot no hubo opendir() que validara el tipo
+        let inodes = self.inodes.lock().unwrap();
+        require_dir(inodes.get(&ino).ok_or(libc::ENOENT)?)?;
+        Ok(self.directories.lock().unwrap().get(&ino).cloned().unwrap_or_default())
+    }
+
+    /// Remove entry `name` from directory `parent`, as `unlink` does. The
+    /// inode and its blocks are freed with its last link. In strict POSIX
+    /// mode a directory is refused with EISDIR.
+    pub fn unlink_entry(&self, parent: u64, name: &str) -> std::result::Result<(), libc::c_int> {
+        let mut exit_code: Option<i32> = None; // None = OK, Some(errno) = error
+
+        {
+            // --------------------------------------------
+            // LOCK INODES + DIRS
+            // --------------------------------------------
+            let mut inodes = self.inodes.lock().unwrap();
+            let mut directories = self.directories.lock().unwrap();
+            log_point!(self, "unlink() -> locks acquired");
+
+            // --------------------------------------------
+            // Buscar entrada en el directorio padre
+            // --------------------------------------------
+            if let Some(entries) = directories.get_mut(&parent) {
+                if let Some(pos) = entries.iter().position(|e| e.name == name) {
+                    if self.strict_posix && entries[pos].file_type == FileType::Directory {
+                        log_point!(self, format!("unlink(): '{}' is a directory -> EISDIR", name));
+                        exit_code = Some(libc::EISDIR);
+                    } else {
+                        let entry = entries.remove(pos);
+                        log_point!(self, format!("unlink(): removed DirEntry for ino={}", entry.ino));
+
+                        // Reducir nlink
+                        if let Some(inode) = inodes.get_mut(&entry.ino) {
+                            let old = inode.nlink;
+                            drop_link(inode);
+                            log_point!(self, format!(
+                                "unlink(): inode {} nlink {} -> {}",
+                                entry.ino, old, inode.nlink
+                            ));
+
+                            if inode.nlink == 0 {
+                                log_point!(self, format!(
+                                    "unlink(): inode {} nlink=0 → freeing blocks",
+                                    entry.ino
+                                ));
+
+                                self.free_inode_blocks(inode);
+
+                                inodes.remove(&entry.ino);
+                                self.free_ino(entry.ino);
+                                log_point!(self, format!("unlink(): inode {} removed", entry.ino));
+                            }
+                        }
+                    }
+                } else {
+                    log_point!(self, format!(
+                        "unlink(): entry '{}' not found in parent {}",
+                        name, parent
+                    ));
+                    exit_code = Some(libc::ENOENT);
+                }
+            } else {
+                log_point!(self, format!("unlink(): parent directory {} not found", parent));
+                exit_code = Some(libc::ENOENT);
+            }
+        } // <---- Locks se liberan aquí
+
+        match exit_code {
+            None => {
+                // Directory tree cambió → metadata sucia
+                self.mark_dirty();
+                Ok(())
+            }
+            Some(errno) => Err(errno),
+        }
+    }
+
+    /// Remove the empty directory `name` from `parent`, as `rmdir` does. In
+    /// strict POSIX mode "." is EINVAL, ".." ENOTEMPTY and a non-directory
+    /// ENOTDIR (ENOENT otherwise).
+    pub fn remove_dir(&self, parent: u64, name: &str) -> std::result::Result<(), libc::c_int> {
+        // Variables de salida
+        let mut exit_code: Option<i32> = None; // None = OK, Some(errno) = error
+
+        {
+            // --------------------------------------------
+            // LOCK INODES + DIRECTORIES
+            // --------------------------------------------
+            let mut inodes = self.inodes.lock().unwrap();
+            let mut directories = self.directories.lock().unwrap();
+            log_point!(self, "rmdir() -> locks acquired");
+
+            // --------------------------------------------
+            // Buscar el directorio
+            // --------------------------------------------
+            let entry_opt = directories
+                .get(&parent)
+                .and_then(|entries| {
+                    entries
+                        .iter()
+                        .find(|e| e.name == name && e.file_type == FileType::Directory)
+                        .cloned()
+                });
+
+            if self.strict_posix && (name == "." || name == "..") {
+                // POSIX: rmdir(".") -> EINVAL; ".." nunca está vacío
+                let errno = if name == "." { libc::EINVAL } else { libc::ENOTEMPTY };
+                log_point!(self, format!("rmdir(): refusing to remove '{}' (errno {})", name, errno));
+                exit_code = Some(errno);
+            } else if let Some(entry) = entry_opt {
+
+                // --------------------------------------------
+                // Verificar vacío
+                // --------------------------------------------
+                if let Some(children) = directories.get(&entry.ino) {
+                    if children.len() > 2 {
+                        log_point!(self, format!(
+                            "rmdir(): directory {} NOT EMPTY ({} entries)",
+                            entry.ino,
+                            children.len()
+                        ));
+                        exit_code = Some(libc::ENOTEMPTY);
+                    }
+                }
+
+                // Si NO se ha puesto error → borrar
+                if exit_code.is_none() {
+                    log_point!(self, format!("rmdir(): removing inode {}", entry.ino));
+
+                    // Quitar del padre
+                    if let Some(parent_entries) = directories.get_mut(&parent) {
+                        parent_entries.retain(|e| e.ino != entry.ino);
+                    }
+
+                    directories.remove(&entry.ino);
+                    inodes.remove(&entry.ino);
+                    self.free_ino(entry.ino);
+
+                    // Reducir nlink del padre
+                    if let Some(parent_inode) = inodes.get_mut(&parent) {
+                        drop_subdir_link(parent_inode);
+                    }
+                }
+            } else if self.strict_posix
+                && directories
+                    .get(&parent)
+                    .map(|entries| entries.iter().any(|e| e.name == name))
+                    .unwrap_or(false)
+            {
+                log_point!(self, format!("rmdir(): '{}' is not a directory -> ENOTDIR", name));
+                exit_code = Some(libc::ENOTDIR);
+            } else {
+                log_point!(self, format!(
+                    "rmdir(): '{}' not found under parent {}",
+                    name, parent
+                ));
+                exit_code = Some(libc::ENOENT);
+            }
+
+            // Los locks se sueltan automáticamente aquí
+        }
+
+        // --------------------------------------------
+        // REPLY FINAL
+        // --------------------------------------------
+        match exit_code {
+            None => {
+                // Directory tree cambió → metadata sucia
+                self.mark_dirty();
+                Ok(())
+            }
+            Some(errno) => Err(errno),
+        }
+    }
+
+    /// Inode of entry `name` in directory `parent`, as `lookup` resolves it:
+    /// ENAMETOOLONG for an overlong name, ENOENT for a missing parent or
+    /// entry and ENOTDIR when `parent` is not a directory
+    pub fn lookup_entry(&self, parent: u64, name: &str) -> std::result::Result<INode, libc::c_int> {
+        if name.len() > self.config.max_name_len as usize {
+            return Err(libc::ENAMETOOLONG);
+        }
+
+        let directories = self.directories.lock().unwrap();
+        let inodes = self.inodes.lock().unwrap();
+
+        // Un componente intermedio que no es directorio -> ENOTDIR (POSIX)
+        match inodes.get(&parent) {
+            None => return Err(libc::ENOENT),
+            Some(parent_inode) if !parent_inode.is_dir() => return Err(libc::ENOTDIR),
+            Some(_) => {}
+        }
+
+        directories
+            .get(&parent)
+            .and_then(|entries| entries.iter().find(|e| e.name == name))
+            .and_then(|entry| inodes.get(&entry.ino))
+            .cloned()
+            .ok_or(libc::ENOENT)
+    }
+
+    /// Copy of inode `ino`, if it exists
+    pub fn inode(&self, ino: u64) -> Option<INode> {
+        self.inodes.lock().unwrap().get(&ino).cloned()
+    }
+
+    /// Walk the directory tree from the root and return every entry with its
+    /// absolute path, parents before children. `.` and `..` are skipped.
+    pub fn walk(&self) -> Vec<(String, INode)> {
+        let inodes = self.inodes.lock().unwrap();
+        let directories = self.directories.lock().unwrap();
+
+        let mut result = Vec::new();
+        let mut stack = vec![(String::new(), self.root_ino)];
+
+        while let Some((dir_path, dir_ino)) = stack.pop() {
+            let Some(entries) = directories.get(&dir_ino) else {
+                continue;
+            };
+            for entry in entries.iter().filter(|e| e.name != "." && e.name != "..") {
+                let path = format!("{}/{}", dir_path, entry.name);
+                if let Some(inode) = inodes.get(&entry.ino) {
+                    if inode.is_dir() {
+                        stack.push((path.clone(), entry.ino));
+                    }
+                    result.push((path, inode.clone()));
+                }
+            }
+        }
+
+        // Orden por ruta: determinista y con cada padre antes que sus hijos
+        result.sort_by(|a, b| a.0.cmp(&b.0));
+        result
+    }
+
+    /// Summarize space and inode usage from the in-memory metadata and the
+    /// bitmaps; no data block is read. Block counts per file are the blocks
+    /// the file owns (shared clone blocks count for each file).
+    pub fn usage_report(&self) -> UsageReport {
+        let block_size = self.storage.lock().unwrap().bytes_per_block();
+        let total_blocks = self.config.total_blocks as u64;
+        let free_blocks = {
+            let block_bitmap = self.block_bitmap.lock().unwrap();
+            (0..self.config.total_blocks as usize)
+                .filter(|&i| !block_bitmap.is_set(i))
+                .count() as u64
+        };
+        let used_inodes = self.inode_bitmap.lock().unwrap().count_set() as u64;
+
+        let mut report = UsageReport {
+            block_size,
+            total_blocks,
+            used_blocks: total_blocks - free_blocks,
+            free_blocks,
+            total_inodes: self.config.total_inodes as u64,
+            used_inodes,
+            ..Default::default()
+        };
+
+        // Se cuenta desde walk() para ignorar inodos inalcanzables
+        let mut seen = std::collections::HashSet::new();
+        let mut files = Vec::new();
+        for (path, inode) in self.walk() {
+            if !seen.insert(inode.ino) {
+                continue;
+            }
+            match inode.file_type {
+                FileType::RegularFile => {
+                    report.regular_files += 1;
+                    files.push((path, inode.allocated_blocks as u64, inode.size));
+                }
+                FileType::Directory => report.directories += 1,
+                FileType::Symlink => report.symlinks += 1,
+                _ => report.special_files += 1,
+            }
+        }
+        // La raíz no aparece en walk()
+        report.directories += 1;
+
+        files.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)).then(a.0.cmp(&b.0)));
+        files.truncate(USAGE_REPORT_LARGEST);
+        report.largest_files = files;
+        report
+    }
+
+    /// Cross-check the inode table, directories and block bitmap, returning
+    /// one line per problem (empty if the metadata is consistent).
+    ///
+    /// Checks that every entry points to an existing inode, every inode is
+    /// reachable from the root exactly as often as its link count says,
+    /// every data block is in range and allocated, no allocated block is
+    /// unreferenced, and the inode bitmap marks exactly the inodes in use.
+    /// Meant for checking a filesystem reloaded after a crash.
+    pub fn check_consistency(&self) -> Vec<String> {
+        let inodes = self.inodes.lock().unwrap();
+        let directories = self.directories.lock().unwrap();
+        let storage = self.storage.lock().unwrap();
+        let block_bitmap = self.block_bitmap.lock().unwrap();
+        let inode_bitmap = self.inode_bitmap.lock().unwrap();
+        let mut problems = Vec::new();
+
+        // Recorrido desde la raíz contando referencias a cada inodo
+        let mut links: HashMap<u64, u32> = HashMap::new();
+        let mut visited = std::collections::HashSet::from([self.root_ino]);
+        let mut stack = vec![self.root_ino];
+        while let Some(dir_ino) = stack.pop() {
+            let Some(entries) = directories.get(&dir_ino) else {
+                problems.push(format!("directory inode {} has no entry list", dir_ino));
+                continue;
+            };
+            for entry in entries.iter().filter(|e| e.name != "." && e.name != "..") {
+                let Some(inode) = inodes.get(&entry.ino) else {
+                    problems.push(format!(
+                        "entry '{}' in directory {} points to missing inode {}",
+                        entry.name, dir_ino, entry.ino
+                    ));
+                    continue;
+                };
+                *links.entry(entry.ino).or_insert(0) += 1;
+                if inode.is_dir() && !visited.insert(entry.ino) {
+                    problems.push(format!("directory inode {} is linked more than once", entry.ino));
+                } else if inode.is_dir() {
+                    stack.push(entry.ino);
+                }
+            }
+        }
+
+        // El área de metadata no pertenece a ningún inodo pero está en uso
+        let metadata_area = storage.metadata_area();
+        let mut referenced: std::collections::HashSet<u32> =
+            metadata_area.iter().flat_map(|area| area.blocks()).collect();
+        for (&ino, inode) in inodes.iter() {
+            if ino != self.root_ino && !links.contains_key(&ino) {
+                problems.push(format!("inode {} is not reachable from the root", ino));
+            }
+            if ino != self.root_ino {
+                match Self::ino_slot(&self.config, ino) {
+                    None => problems.push(format!("inode {} is outside the allocation range", ino)),
+                    Some(slot) if !inode_bitmap.is_set(slot) => {
+                        problems.push(format!("inode {} is marked free in the inode bitmap", ino))
+                    }
+                    Some(_) => {}
+                }
+            }
+            if inode.file_type == FileType::RegularFile
+                && links.get(&ino).copied().unwrap_or(0) != inode.nlink
+            {
+                problems.push(format!(
+                    "inode {} has nlink {} but {} directory entries",
+                    ino,
+                    inode.nlink,
+                    links.get(&ino).copied().unwrap_or(0)
+                ));
+            }
+            if inode.is_inline() && inode.allocated_blocks > 0 {
+                problems.push(format!("inode {} has inline data and blocks", ino));
+            }
+            for block_num in Self::owned_blocks(inode, &storage) {
+                if block_num == 0
+                    || block_num >= self.config.total_blocks
+                    || metadata_area.is_some_and(|area| area.blocks().contains(&block_num))
+                {
+                    problems.push(format!("inode {} points to invalid block {}", ino, block_num));
+                } else if !block_bitmap.is_set(block_num as usize) {
+                    problems.push(format!("inode {} uses block {} marked free", ino, block_num));
+                }
+                referenced.insert(block_num);
+            }
+        }
+
+        let leaked = (1..self.config.total_blocks)
+            .filter(|&block_num| block_bitmap.is_set(block_num as usize) && !referenced.contains(&block_num))
+            .count();
+        if leaked > 0 {
+            problems.push(format!("{} block(s) allocated but not used by any inode", leaked));
+        }
+
+        // Un bit sin inodo reduce el límite de total_inodes sin motivo
+        let stale_inos = (0..self.config.total_inodes as u64)
+            .filter(|&slot| {
+                inode_bitmap.is_set(slot as usize) && !inodes.contains_key(&(self.config.first_ino + slot))
+            })
+            .count();
+        if stale_inos > 0 {
+            problems.push(format!("{} inode number(s) marked used but without an inode", stale_inos));
+        }
+
+        problems
+    }
+
+    /// Rebuild the block bitmap from every inode's direct and indirect
+    /// blocks and compare it with the stored one.
+    ///
+    /// Blocks shared by clones are expected to be referenced as many times
+    /// as `block_refs` says; any other block referenced twice is reported as
+    /// doubly allocated. With `repair`, leaked blocks are freed, referenced
+    /// blocks are marked used and dangling directory entries are dropped;
+    /// doubly allocated blocks are only reported.
+    pub fn fsck(&self, repair: bool) -> Result<FsckReport> {
+        let mut report = FsckReport::default();
+        {
+            let mut inodes = self.inodes.lock().unwrap();
+            let mut directories = self.directories.lock().unwrap();
+            let storage = self.storage.lock().unwrap();
+            let block_refs = self.block_refs.lock().unwrap();
+            let mut block_bitmap = self.block_bitmap.lock().unwrap();
+
+            // Referencias esperadas a cada bloque según los inodos (y el
+            // área de metadata, que es de uso exclusivo)
+            let mut references: HashMap<u32, u32> = storage
+                .metadata_area()
+                .iter()
+                .flat_map(|area| area.blocks())
+                .map(|block_num| (block_num, 1))
+                .collect();
+            for inode in inodes.values() {
+                for block_num in Self::owned_blocks(inode, &storage) {
+                    if block_num != 0 && block_num < self.config.total_blocks {
+                        *references.entry(block_num).or_insert(0) += 1;
+                    }
+                }
+            }
+
+            for block_num in 1..self.config.total_blocks {
+                let used = block_bitmap.is_set(block_num as usize);
+                match references.get(&block_num).copied().unwrap_or(0) {
+                    0 if used => report.leaked_blocks.push(block_num),
+                    0 => {}
+                    count => {
+                        if !used {
+                            report.unmarked_blocks.push(block_num);
+                        }
+                        let expected = block_refs.get(&block_num).copied().unwrap_or(1);
+                        if count > expected {
+                            report.doubly_allocated_blocks.push(block_num);
+                        }
+                    }
+                }
+            }
+
+            let mut dir_inos: Vec<u64> = directories.keys().copied().collect();
+            dir_inos.sort_unstable();
+            for dir_ino in dir_inos {
+                for entry in directories[&dir_ino].iter() {
+                    if entry.name != "." && entry.name != ".." && !inodes.contains_key(&entry.ino) {
+                        report.dangling_entries.push((dir_ino, entry.name.clone(), entry.ino));
+                    }
+                }
+            }
+
+            if repair && !report.is_clean() {
+                for &block_num in &report.leaked_blocks {
+                    block_bitmap.clear(block_num as usize);
+                }
+                for &block_num in &report.unmarked_blocks {
+                    block_bitmap.set(block_num as usize);
+                }
+                for (dir_ino, name, _) in &report.dangling_entries {
+                    let Some(entries) = directories.get_mut(dir_ino) else {
+                        continue;
+                    };
+                    let Some(pos) = entries.iter().position(|e| &e.name == name) else {
+                        continue;
+                    };
+                    let entry = entries.remove(pos);
+                    // Un subdirectorio perdido ya no aporta su ".." al padre
+                    if let Some(parent) = inodes.get_mut(dir_ino) {
+                        if entry.file_type == FileType::Directory {
+                            drop_subdir_link(parent);
+                        }
+                        let now = SystemTime::now();
+                        parent.mtime = now;
+                        parent.ctime = now;
+                    }
+                }
+                report.repaired = true;
+            }
+        }
+
+        if report.repaired {
+            for (dir_ino, name, _) in &report.dangling_entries {
+                self.invalidate_entry(*dir_ino, name);
+            }
+            log_point!(self, format!("fsck(): repaired {:?}", report));
+            self.mark_dirty();
+            self.sync_if_dirty()?;
+        }
+        Ok(report)
+    }
+
+    /// SHA-256 over the directory tree and every file's contents.
+    ///
+    /// Entries are hashed in path order as (path, type, mode, size, data),
+    /// each field length-prefixed, plus the device number of device nodes,
+    /// so the digest depends only on what the
+    /// filesystem contains: block placement, inode numbers and timestamps
+    /// do not affect it.
+    pub fn content_digest(&self) -> Result<Digest> {
+        let mut hasher = crate::hash::Sha256::new();
+
+        for (path, inode) in self.walk() {
+            hasher.update(&(path.len() as u64).to_le_bytes());
+            hasher.update(path.as_bytes());
+            hasher.update(&[match inode.file_type {
+                FileType::RegularFile => 0,
+                FileType::Directory => 1,
+                FileType::Symlink => 2,
+                FileType::CharDevice => 3,
+                FileType::BlockDevice => 4,
+                FileType::NamedPipe => 5,
+                FileType::Socket => 6,
+            }]);
+            hasher.update(&inode.mode.to_le_bytes());
+            if inode.is_device() {
+                hasher.update(&inode.rdev.to_le_bytes());
+            }
+            if !inode.is_dir() {
+                let data = self.read_file(inode.ino)?;
+                hasher.update(&(data.len() as u64).to_le_bytes());
+                hasher.update(&data);
+            }
+        }
+
+        Ok(hasher.finalize())
+    }
+
+    /// Remove the file or directory at `path` (absolute, from the root) and
+    /// everything below it.
+    ///
+    /// The whole subtree is unlinked under one lock span: each entry drops
+    /// one link, inodes left without links have their blocks and inode
+    /// number freed (a file still linked elsewhere keeps its data), and
+    /// metadata is saved once at the end. Returns the number of entries
+    /// removed.
+    pub fn remove_tree(&self, path: &str) -> Result<usize> {
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let Some((&name, dirs)) = components.split_last() else {
+            anyhow::bail!("Refusing to remove the root directory");
+        };
+
+        let (parent, removed, removed_inos) = {
+            let mut inodes = self.inodes.lock().unwrap();
+            let mut directories = self.directories.lock().unwrap();
+
+            // Resolver el directorio padre componente a componente
+            let mut parent = self.root_ino;
+            for component in dirs {
+                let entry = directories
+                    .get(&parent)
+                    .and_then(|entries| entries.iter().find(|e| e.name == *component))
+                    .ok_or_else(|| anyhow::anyhow!("{}: no such file or directory", path))?;
+                if entry.file_type != FileType::Directory {
+                    anyhow::bail!("{}: '{}' is not a directory", path, component);
+                }
+                parent = entry.ino;
+            }
+
+            let entries = directories
+                .get_mut(&parent)
+                .ok_or_else(|| anyhow::anyhow!("{}: no such file or directory", path))?;
+            let pos = entries
+                .iter()
+                .position(|e| e.name == name && name != "." && name != "..")
+                .ok_or_else(|| anyhow::anyhow!("{}: no such file or directory", path))?;
+            let top = entries.remove(pos);
+            if top.file_type == FileType::Directory {
+                if let Some(parent_inode) = inodes.get_mut(&parent) {
+                    drop_subdir_link(parent_inode);
+                }
+            }
+
+            let mut removed = 0;
+            let mut freed_inos = Vec::new();
+            let mut stack = vec![top];
+            while let Some(entry) = stack.pop() {
+                removed += 1;
+                if let Some(children) = directories.remove(&entry.ino) {
+                    stack.extend(
+                        children
+                            .into_iter()
+                            .filter(|e| e.name != "." && e.name != ".."),
+                    );
+                }
+                let Some(inode) = inodes.get_mut(&entry.ino) else {
+                    continue;
+                };
+                // Como en unlink: un archivo con otro nombre (enlace duro
+                // fuera del subárbol) conserva sus datos
+                if !inode.is_dir() {
+                    drop_link(inode);
+                    if inode.nlink > 0 {
+                        continue;
+                    }
+                }
+                if let Some(inode) = inodes.remove(&entry.ino) {
+                    self.free_inode_blocks(&inode);
+                    freed_inos.push(entry.ino);
+                }
+            }
+
+            for &ino in &freed_inos {
+                self.free_ino(ino);
+            }
+            (parent, removed, freed_inos)
+        };
+
+        // Que el kernel olvide la entrada y los inodos borrados
+        self.invalidate_entry(parent, name);
+        for ino in removed_inos {
+            self.invalidate_inode(ino);
+        }
+
+        log_point!(self, format!("remove_tree(): removed {} entries under '{}'", removed, path));
+        self.mark_dirty();
+        self.sync_if_dirty()?;
+        Ok(removed)
+    }
+
+    /// Resize a regular file to exactly `new_size` bytes.
+    ///
+    /// Growing leaves a hole: no blocks are allocated, only the remainder
+    /// of the old last block is zeroed; shrinking frees the tail blocks,
+    /// clears their pointers and zeroes the remainder of the last partial
+    /// block. `size`, `mtime` and `ctime` are
+    /// updated and the filesystem is marked dirty. On failure the errno to
+    /// report is returned and the file is left unchanged.
+    pub fn set_file_size(&self, ino: u64, new_size: u64) -> std::result::Result<(), libc::c_int> {
+        log_point!(self, format!("set_file_size(): ino={}, new_size={}", ino, new_size));
+
+        {
+            let mut inodes = self.inodes.lock().unwrap();
+            let storage = self.storage.lock().unwrap();
+
+            let inode = inodes.get_mut(&ino).ok_or(libc::ENOENT)?;
+            require_file(inode)?;
+
+            let block_size = storage.bytes_per_block() as u64;
+            if new_size.div_ceil(block_size) > INode::max_blocks(block_size as usize) {
+                return Err(libc::EFBIG);
+            }
+            let old_size = inode.size;
+            let new_blocks = new_size.div_ceil(block_size) as u32;
+
+            if new_size > old_size {
+                // --------------------------------------------
+                // Crecer: el archivo queda disperso; los bloques nuevos no
+                // se asignan y `read` los devuelve como huecos (ceros)
+                // --------------------------------------------
+                if inode.allocated_blocks == 0 && new_size <= self.inline_limit(&storage) {
+                    if inode.is_inline() {
+                        inode.inline_data.resize(new_size as usize, 0);
+                    }
+                } else {
+                    self.spill_inline(inode, &storage)?;
+                    self.zero_file_tail(inode, &storage, old_size)?;
+                }
+            } else if new_size < old_size && inode.is_inline() {
+                inode.inline_data.truncate(new_size as usize);
+            } else if new_size < old_size {
+                // --------------------------------------------
+                // Encoger: liberar bloques de cola (también los reservados
+                // más allá del tamaño por create_with_size_hint)
+                // --------------------------------------------
+                let mapped = inode.mapped_blocks(&storage).map_err(|e| storage_errno(&e))?;
+                for (block_idx, block_num) in mapped.into_iter().filter(|&(i, _)| i >= new_blocks) {
+                    self.map_file_block(inode, &storage, block_idx, u32::MAX)?;
+                    self.free_block(block_num);
+                    log_point!(self, format!(
+                        "set_file_size(): freed block {} (index {})",
+                        block_num, block_idx
+                    ));
+                }
+                let pointer_blocks = inode
+                    .trim_pointer_blocks(new_blocks, &storage)
+                    .map_err(|e| storage_errno(&e))?;
+                for block_num in pointer_blocks {
+                    self.free_block(block_num);
+                }
+
+                // Poner a cero la cola del último bloque parcial
+                self.zero_file_tail(inode, &storage, new_size)?;
+
+                // Un archivo que vuelve a ser pequeño regresa al inodo
+                if new_size > 0 && new_size <= self.inline_limit(&storage) {
+                    inode.size = new_size;
+                    self.collapse_to_inline(inode, &storage)?;
+                }
+            }
+
+            let now = SystemTime::now();
+            inode.size = new_size;
+            inode.mtime = now;
+            inode.ctime = now;
+        }
+
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Offset of the next data (`SEEK_DATA`) or hole (`SEEK_HOLE`) at or
+    /// after `offset` in a regular file, at block granularity.
+    ///
+    /// Unmapped blocks are holes; the end of the file counts as a hole, so
+    /// `SEEK_HOLE` returns at most the size. Offsets at or past EOF give
+    /// ENXIO, as does `SEEK_DATA` with no data left. Inline files are all data.
+    pub fn seek_data_hole(&self, ino: u64, offset: u64, whence: i32) -> std::result::Result<u64, libc::c_int> {
+        let inodes = self.inodes.lock().unwrap();
+        let storage = self.storage.lock().unwrap();
+
+        let inode = inodes.get(&ino).ok_or(libc::ENOENT)?;
+        if !inode.is_file() {
+            return Err(libc::EINVAL);
+        }
+        if whence != libc::SEEK_DATA && whence != libc::SEEK_HOLE {
+            return Err(libc::EINVAL);
+        }
+        if offset >= inode.size {
+            return Err(libc::ENXIO);
+        }
+        if inode.is_inline() {
+            return Ok(if whence == libc::SEEK_DATA { offset } else { inode.size });
+        }
+
+        // mapped_blocks sale ordenado por índice; los reservados más allá
+        // del tamaño no cuentan
+        let block_size = storage.bytes_per_block() as u64;
+        let first = (offset / block_size) as u32;
+        let last = inode.size.div_ceil(block_size) as u32;
+        let mapped: std::collections::BTreeSet<u32> = inode
+            .mapped_blocks(&storage)
+            .map_err(|e| storage_errno(&e))?
+            .into_iter()
+            .map(|(block_idx, _)| block_idx)
+            .filter(|block_idx| (first..last).contains(block_idx))
+            .collect();
+
+        let block_start = |block_idx: u32| (block_idx as u64 * block_size).max(offset);
+        if whence == libc::SEEK_DATA {
+            mapped.first().map(|&block_idx| block_start(block_idx)).ok_or(libc::ENXIO)
+        } else {
+            Ok((first..last)
+                .find(|block_idx| !mapped.contains(block_idx))
+                .map_or(inode.size, |block_idx| block_start(block_idx).min(inode.size)))
+        }
+    }
+
+    /// Copy `len` bytes of regular file `src_ino` at `src_offset` into
+    /// `dest_ino` at `dest_offset` block by block, without decoding the
+    /// data into a caller's buffer. Returns the bytes copied, which stop at
+    /// the source's EOF.
+    ///
+    /// Both offsets must be block-aligned and the source must not be
+    /// inline (EOPNOTSUPP otherwise, so the kernel falls back to read and
+    /// write). The destination gets fresh blocks; holes in the source stay
+    /// holes. Overlapping ranges of the same file give EINVAL.
+    pub fn copy_range(
+        &self,
+        src_ino: u64,
+        src_offset: u64,
+        dest_ino: u64,
+        dest_offset: u64,
+        len: u64,
+    ) -> std::result::Result<u64, libc::c_int> {
+        log_point!(self, format!(
+            "copy_range(): {}@{} -> {}@{}, len={}",
+            src_ino, src_offset, dest_ino, dest_offset, len
+        ));
+
+        let copied = {
+            let mut inodes = self.inodes.lock().unwrap();
+            let storage = self.storage.lock().unwrap();
+
+            // Copia del origen: puede ser el mismo inodo que el destino
+            let src = inodes.get(&src_ino).ok_or(libc::ENOENT)?.clone();
+            let dest = inodes.get_mut(&dest_ino).ok_or(libc::ENOENT)?;
+            require_file(&src)?;
+            require_file(dest)?;
+
+            let len = len.min(src.size.saturating_sub(src_offset));
+            if len == 0 {
+                return Ok(0);
+            }
+            if src_ino == dest_ino
+                && src_offset < dest_offset.saturating_add(len)
+                && dest_offset < src_offset + len
+            {
+                return Err(libc::EINVAL);
+            }
+            let block_size = storage.bytes_per_block() as u64;
+            if !src_offset.is_multiple_of(block_size) || !dest_offset.is_multiple_of(block_size) || src.is_inline() {
+                return Err(libc::EOPNOTSUPP);
+            }
+            let dest_end = dest_offset.checked_add(len).ok_or(libc::EFBIG)?;
+            if dest_end.div_ceil(block_size) > INode::max_blocks(block_size as usize) {
+                return Err(libc::EFBIG);
+            }
+
+            self.journal.begin(dest_ino);
+            self.spill_inline(dest, &storage)?;
+            // Como en write: un hueco entre el final anterior y el destino
+            if dest_offset > dest.size {
+                self.zero_file_tail(dest, &storage, dest.size)?;
+            }
+
+            let mut allocated = Vec::new();
+            let result = (|| {
+                for i in 0..len.div_ceil(block_size) {
+                    let src_idx = (src_offset / block_size + i) as u32;
+                    let dest_idx = (dest_offset / block_size + i) as u32;
+                    let count = (len - i * block_size).min(block_size) as usize;
+
+                    let src_block = self.file_block(&src, &storage, src_idx)?;
+                    let dest_block = self.file_block(dest, &storage, dest_idx)?;
+
+                    // Un hueco completo en el origen deja un hueco en el destino
+                    if src_block.is_none() && count == block_size as usize {
+                        if let Some(block_num) = dest_block {
+                            self.map_file_block(dest, &storage, dest_idx, u32::MAX)?;
+                            self.free_block(block_num);
+                        }
+                        continue;
+                    }
+                    if src_block.is_none() && dest_block.is_none() {
+                        continue;
+                    }
+
+                    // El último bloque parcial conserva lo que el destino
+                    // tenga detrás del tramo copiado
+                    let mut data = match dest_block {
+                        Some(block_num) if count < block_size as usize => {
+                            storage.read_block(block_num).map_err(|e| storage_errno(&e))?
+                        }
+                        _ => vec![0; block_size as usize],
+                    };
+                    match src_block {
+                        Some(block_num) => {
+                            let src_data = storage.read_block(block_num).map_err(|e| storage_errno(&e))?;
+                            data[..count].copy_from_slice(&src_data[..count]);
+                        }
+                        None => data[..count].fill(0),
+                    }
+
+                    if dest_block.is_none() {
+                        let new_block = self.allocate_block().ok_or(libc::ENOSPC)?;
+                        if let Err(errno) = self.map_file_block(dest, &storage, dest_idx, new_block) {
+                            self.free_block(new_block);
+                            return Err(errno);
+                        }
+                        allocated.push((dest_idx, new_block));
+                    }
+                    self.write_file_block(dest, dest_idx, &storage, &data)?;
+                }
+                Ok(())
+            })();
+            if let Err(errno) = result {
+                self.undo_allocations(dest, &storage, &allocated);
+                return Err(errno);
+            }
+
+            let now = SystemTime::now();
+            dest.size = dest.size.max(dest_end);
+            dest.mtime = now;
+            dest.ctime = now;
+            if let Err(e) = self.journal.commit(dest_ino, dest.size) {
+                log::error!("[{}] cannot commit copy to the journal: {:#}", self.instance_id, e);
+            }
+            len
+        };
+
+        self.mark_dirty();
+        Ok(copied)
+    }
+
+    /// Deallocate the byte range `offset..offset + length` of a regular file
+    /// without changing its size.
+    ///
+    /// Blocks lying entirely inside the range are freed and their pointers
+    /// cleared, so they read back as holes; partially covered blocks at the
+    /// edges are zeroed in place. The range is clipped at EOF.
+    pub fn punch_hole(&self, ino: u64, offset: u64, length: u64) -> std::result::Result<(), libc::c_int> {
+        log_point!(self, format!("punch_hole(): ino={}, offset={}, length={}", ino, offset, length));
+
+        {
+            let mut inodes = self.inodes.lock().unwrap();
+            let storage = self.storage.lock().unwrap();
+
+            let inode = inodes.get_mut(&ino).ok_or(libc::ENOENT)?;
+            require_file(inode)?;
+
+            let end = offset.saturating_add(length).min(inode.size);
+            if offset >= end {
+                return Ok(());
+            }
+
+            if inode.is_inline() {
+                let stored = inode.inline_data.len() as u64;
+                inode.inline_data[offset.min(stored) as usize..end.min(stored) as usize].fill(0);
+            }
+
+            let block_size = storage.bytes_per_block() as u64;
+            for block_idx in offset / block_size..=(end - 1) / block_size {
+                let Some(block_num) = self.file_block(inode, &storage, block_idx as u32)? else {
+                    continue; // ya es un hueco
+                };
+                let block_start = block_idx * block_size;
+                let from = (offset.max(block_start) - block_start) as usize;
+                let to = (end.min(block_start + block_size) - block_start) as usize;
+
+                if from == 0 && to == block_size as usize {
+                    self.map_file_block(inode, &storage, block_idx as u32, u32::MAX)?;
+                    self.free_block(block_num);
+                    log_point!(self, format!(
+                        "punch_hole(): freed block {} (index {})",
+                        block_num, block_idx
+                    ));
+                } else {
+                    // Bloque de borde: se pone a cero solo el tramo afectado
+                    let mut data = storage.read_block(block_num).map_err(|_| libc::EIO)?;
+                    data[from..to].fill(0);
+                    self.write_file_block(inode, block_idx as u32, &storage, &data)?;
+                }
+            }
+
+            let now = SystemTime::now();
+            inode.mtime = now;
+            inode.ctime = now;
+        }
+
+        self.mark_dirty();
+        Ok(())
+    }
+}
+
+impl Drop for BWFS {
+    /// A filesystem dropped without `destroy` (offline tools, a failed
+    /// mount) still stops its checkpoint thread. Pending changes are not
+    /// saved here; callers sync explicitly.
+    fn drop(&mut self) {
+        self.stop_background_sync();
+    }
+}
+
+impl Filesystem for BWFS {
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), libc::c_int> {
+        log_enter!(self, "init()");
+        log_point!(self, "Initializing FS");
+        // O_TRUNC llega a `open` en vez de como un setattr aparte; un kernel
+        // sin la capacidad sigue usando setattr
+        if config.add_capabilities(fuser::consts::FUSE_ATOMIC_O_TRUNC).is_err() {
+            log_point!(self, "init(): kernel without FUSE_ATOMIC_O_TRUNC, O_TRUNC arrives as setattr");
+        }
+        log_exit!(self, "init()");
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        log_enter!(self, "destroy()");
+        self.stop_background_sync();
+        if let Err(e) = self.sync_if_dirty() {
+            log::error!("Failed to checkpoint metadata on unmount: {}", e);
+        }
+        log_exit!(self, "destroy()");
+    }
+
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &std::ffi::OsStr, reply: ReplyEntry) {
+        let parent = self.internal_ino(parent);
+        let name = name.to_string_lossy().to_string();
+        log_enter!(self, "lookup()");
+        log_point!(self, format!("lookup: parent={}, name={}", parent, name.clone()));
+
+        match self.lookup_entry(parent, &name) {
+            Ok(inode) => {
+                log_point!(self, "lookup match found");
+                let attr = self.inode_to_attr(&inode);
+                reply.entry(&TTL, &attr, inode.generation);
+            }
+            Err(errno) => {
+                log_point!(self, format!("lookup: error {}", errno));
+                reply.error(errno);
+            }
+        }
+        log_exit!(self, "lookup()");
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        let ino = self.internal_ino(ino);
+        log_enter!(self, "getattr()");
+        log_point!(self, format!("getattr ino={}", ino));
+
+        let inodes = self.inodes.lock().unwrap();
+
+        if let Some(inode) = inodes.get(&ino) {
+            let attr = self.inode_to_attr(inode);
+            reply.attr(&TTL, &attr);
+        } else {
+            log_point!(self, "getattr: NOENT");
+            reply.error(libc::ENOENT);
+        }
+        log_exit!(self, "getattr()");
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let ino = self.internal_ino(ino);
+        log_enter!(self, "setattr()");
+        log_point!(self, format!("setattr ino={} size={:?} fh={:?}", ino, size, fh));
+
+        if let Some(new_size) = size {
+            if let Err(errno) = self.set_file_size(ino, new_size) {
+                log_point!(self, format!("setattr: set_file_size failed (errno {})", errno));
+                reply.error(errno);
+                log_exit!(self, "setattr()");
+                return;
+            }
+        }
+
+        if let Err(errno) = self.set_mode_and_owner(ino, mode, uid, gid) {
+            log_point!(self, format!("setattr: set_mode_and_owner failed (errno {})", errno));
+            reply.error(errno);
+            log_exit!(self, "setattr()");
+            return;
+        }
+
+        if atime.is_some() || mtime.is_some() {
+            if let Err(errno) = self.set_times(ino, atime, mtime) {
+                log_point!(self, format!("setattr: set_times failed (errno {})", errno));
+                reply.error(errno);
+                log_exit!(self, "setattr()");
+                return;
+            }
+        }
+
+        let inodes = self.inodes.lock().unwrap();
+        match inodes.get(&ino) {
+            Some(inode) => reply.attr(&TTL, &self.inode_to_attr(inode)),
+            None => {
+                log_point!(self, "setattr: NOENT");
+                reply.error(libc::ENOENT);
+            }
+        }
+        log_exit!(self, "setattr()");
+    }
+
+    fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let ino = self.internal_ino(ino);
+        log_enter!(self, "open()");
+        log_point!(self, format!("open ino={} flags={}", ino, flags));
+
+        match self.open_file(ino, flags) {
+            Ok(fh) => {
+                log_point!(self, format!("open: fh={} assigned", fh));
+                reply.opened(fh, 0);
+            }
+            Err(errno) => {
+                log_point!(self, format!("open: ino={} failed (errno {})", ino, errno));
+                reply.error(errno);
+            }
+        }
+        log_exit!(self, "open()");
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!(
+            "read: ino={}, offset={}, size={}",
+            ino, offset, size
+        ));
+
+        let inodes = self.inodes.lock().unwrap();
+        let storage = self.storage.lock().unwrap();
+
+        if let Some(inode) = inodes.get(&ino) {
+            if let Err(errno) = require_file(inode) {
+                log_point!(self, format!("read -> ERR {} (not a regular file)", errno));
+                reply.error(errno);
+                return;
+            }
+
+            let data = match self.read_range(inode, &storage, offset.max(0) as u64, size as usize) {
+                Ok(data) => data,
+                Err(errno) => {
+                    reply.error(errno);
+                    return;
+                }
+            };
+            reply.data(&data);
+
+            drop(storage);
+            drop(inodes);
+            self.touch_atime(ino);
+            self.enforce_memory_limit();
+        } else {
+            log_point!(self, "read -> ENOENT");
+            reply.error(libc::ENOENT);
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let ino = self.internal_ino(ino);
+        match self.write_data(ino, offset.max(0) as u64, data) {
+            Ok(written) => reply.written(written as u32),
+            Err(errno) => reply.error(errno),
+        }
     }
 
     fn create(
@@ -570,21 +4489,28 @@ fn create(
         name: &std::ffi::OsStr,
         mode: u32,
         _umask: u32,
-        _flags: i32,
+        flags: i32,
         reply: ReplyCreate,
     ) {
+        let parent = self.internal_ino(parent);
         let name = name.to_string_lossy().to_string();
-        log_point!(format!(
-            "ENTER create(): parent={}, name='{}', mode={}",
-            parent, name, mode
+        log_point!(self, format!(
+            "ENTER create(): parent={}, name='{}', mode={}, flags={:#o}",
+            parent, name, mode, flags
         ));
 
+        if let Err(errno) = self.validate_name(&name) {
+            log_point!(self, format!("create() -> invalid name '{}' (errno {})", name, errno));
+            reply.error(errno);
+            return;
+        }
+
         // Vamos a devolver estos valores después del bloque de locks
-        let (ino, attr, fh) = {
-            log_point!("create() -> locking inodes and directories");
+        let (ino, attr, fh, generation) = {
+            log_point!(self, "create() -> locking inodes and directories");
             let mut inodes = self.inodes.lock().unwrap();
             let mut directories = self.directories.lock().unwrap();
-            log_point!("create() -> locks acquired");
+            log_point!(self, "create() -> locks acquired");
 
             // --------------------------------------------
             // VALIDATE PARENT
@@ -594,12 +4520,12 @@ fn create(
                 .map(|i| i.is_dir())
                 .unwrap_or(false)
             {
-                log_point!(format!(
+                log_point!(self, format!(
                     "create() -> ERROR: parent={} no es directorio",
                     parent
                 ));
                 reply.error(libc::ENOTDIR);
-                log_exit!("create() -> exit ENOTDIR");
+                log_exit!(self, "create() -> exit ENOTDIR");
                 return;
             }
 
@@ -608,12 +4534,12 @@ fn create(
             // --------------------------------------------
             if let Some(entries) = directories.get(&parent) {
                 if entries.iter().any(|e| e.name == name) {
-                    log_point!(format!(
+                    log_point!(self, format!(
                         "create() -> ERROR: file '{}' already exists in parent {}",
                         name, parent
                     ));
                     reply.error(libc::EEXIST);
-                    log_exit!("create() -> exit EEXIST");
+                    log_exit!(self, "create() -> exit EEXIST");
                     return;
                 }
             }
@@ -621,30 +4547,41 @@ fn create(
             // --------------------------------------------
             // ALLOCATE INODE
             // --------------------------------------------
-            let ino = self.allocate_ino();
-            log_point!(format!("create() -> allocated inode {}", ino));
+            let ino = match self.allocate_ino() {
+                Some(ino) => ino,
+                None => {
+                    log_point!(self, "create() -> ERROR: no free inodes");
+                    reply.error(libc::ENOSPC);
+                    log_exit!(self, "create() -> exit ENOSPC");
+                    return;
+                }
+            };
+            log_point!(self, format!("create() -> allocated inode {}", ino));
 
-            let inode = INode::new(
+            let mut inode = INode::new(
                 ino,
                 FileType::RegularFile,
                 mode as u16,
                 req.uid(),
                 req.gid(),
             );
+            inode.generation = self.allocate_generation();
             let attr = self.inode_to_attr(&inode);
+            let generation = inode.generation;
+            let dir_entry = DirEntry::for_inode(name.clone(), &inode);
 
             inodes.insert(ino, inode);
-            log_point!("create() -> inode inserted into inode table");
+            log_point!(self, "create() -> inode inserted into inode table");
 
             // --------------------------------------------
             // ADD ENTRY TO PARENT DIRECTORY
             // --------------------------------------------
             directories
                 .entry(parent)
-                .or_insert_with(Vec::new)
-                .push(DirEntry::new(ino, name.clone(), FileType::RegularFile));
+                .or_default()
+                .push(dir_entry);
 
-            log_point!(format!(
+            log_point!(self, format!(
                 "create() -> Added DirEntry '{}' (ino={}) to parent {}",
                 name, ino, parent
             ));
@@ -653,16 +4590,16 @@ fn create(
             // ALLOCATE FILE HANDLE
             // --------------------------------------------
             let fh = self.allocate_fh();
-            log_point!(format!("create() -> allocated file handle {}", fh));
+            log_point!(self, format!("create() -> allocated file handle {}", fh));
 
             let mut open_files = self.open_files.lock().unwrap();
-            open_files.insert(fh, ino);
-            log_point!(format!(
+            open_files.insert(fh, OpenHandle::file(ino));
+            log_point!(self, format!(
                 "create() -> open_files updated, fh={} -> ino={}",
                 fh, ino
             ));
 
-            (ino, attr, fh)
+            (ino, attr, fh, generation)
         };
 
         // Marcar metadata como sucia; se sincronizará en fsync()/release()
@@ -671,13 +4608,13 @@ fn create(
         // --------------------------------------------
         // SEND REPLY
         // --------------------------------------------
-        log_point!(format!(
+        log_point!(self, format!(
             "create() -> replying created file: ino={}, fh={}",
             ino, fh
         ));
-        reply.created(&TTL, &attr, 0, fh, 0);
+        reply.created(&TTL, &attr, generation, fh, 0);
 
-        log_exit!("create() -> EXIT OK");
+        log_exit!(self, "create() -> EXIT OK");
     }
 
     fn mkdir(
@@ -689,29 +4626,36 @@ fn mkdir(
         _umask: u32,
         reply: ReplyEntry,
     ) {
+        let parent = self.internal_ino(parent);
         let name = name.to_string_lossy().to_string();
-        log_point!(format!(
+        log_point!(self, format!(
             "ENTER mkdir(): parent={}, name='{}', mode={}",
             parent, name, mode
         ));
 
+        if let Err(errno) = self.validate_name(&name) {
+            log_point!(self, format!("mkdir() -> invalid name '{}' (errno {})", name, errno));
+            reply.error(errno);
+            return;
+        }
+
         // Vamos a construir estos valores mientras tenemos locks
-        let (ino, attr, success) = {
-            log_point!("mkdir() -> locking inodes and directories");
+        let (ino, attr, generation, success) = {
+            log_point!(self, "mkdir() -> locking inodes and directories");
             let mut inodes = self.inodes.lock().unwrap();
             let mut directories = self.directories.lock().unwrap();
-            log_point!("mkdir() -> locks acquired");
+            log_point!(self, "mkdir() -> locks acquired");
 
             // --------------------------------------------
             // VALIDATE PARENT DIRECTORY
             // --------------------------------------------
             if !inodes.get(&parent).map(|i| i.is_dir()).unwrap_or(false) {
-                log_point!(format!(
+                log_point!(self, format!(
                     "mkdir() -> ERROR: parent={} is not a directory",
                     parent
                 ));
                 reply.error(libc::ENOTDIR);
-                log_exit!("mkdir() -> exit ENOTDIR");
+                log_exit!(self, "mkdir() -> exit ENOTDIR");
                 return;
             }
 
@@ -720,12 +4664,12 @@ fn mkdir(
             // --------------------------------------------
             if let Some(entries) = directories.get(&parent) {
                 if entries.iter().any(|e| e.name == name) {
-                    log_point!(format!(
+                    log_point!(self, format!(
                         "mkdir() -> ERROR: directory '{}' already exists in parent {}",
                         name, parent
                     ));
                     reply.error(libc::EEXIST);
-                    log_exit!("mkdir() -> exit EEXIST");
+                    log_exit!(self, "mkdir() -> exit EEXIST");
                     return;
                 }
             }
@@ -733,8 +4677,16 @@ fn mkdir(
             // --------------------------------------------
             // ALLOCATE INODE FOR NEW DIRECTORY
             // --------------------------------------------
-            let ino = self.allocate_ino();
-            log_point!(format!("mkdir() -> allocated inode {}", ino));
+            let ino = match self.allocate_ino() {
+                Some(ino) => ino,
+                None => {
+                    log_point!(self, "mkdir() -> ERROR: no free inodes");
+                    reply.error(libc::ENOSPC);
+                    log_exit!(self, "mkdir() -> exit ENOSPC");
+                    return;
+                }
+            };
+            log_point!(self, format!("mkdir() -> allocated inode {}", ino));
 
             let mut inode = INode::new(
                 ino,
@@ -744,11 +4696,14 @@ fn mkdir(
                 req.gid(),
             );
             inode.nlink = 2;
+            inode.generation = self.allocate_generation();
 
             let attr = self.inode_to_attr(&inode);
+            let generation = inode.generation;
+            let dir_entry = DirEntry::for_inode(name.clone(), &inode);
 
             inodes.insert(ino, inode);
-            log_point!(format!("mkdir() -> inserted inode {} into inode table", ino));
+            log_point!(self, format!("mkdir() -> inserted inode {} into inode table", ino));
 
             // --------------------------------------------
             // INSERT '.' and '..'
@@ -761,7 +4716,7 @@ fn mkdir(
                 ],
             );
 
-            log_point!(format!(
+            log_point!(self, format!(
                 "mkdir() -> created '.' and '..' entries for directory {}",
                 ino
             ));
@@ -771,10 +4726,10 @@ fn mkdir(
             // --------------------------------------------
             directories
                 .entry(parent)
-                .or_insert_with(Vec::new)
-                .push(DirEntry::new(ino, name.clone(), FileType::Directory));
+                .or_default()
+                .push(dir_entry);
 
-            log_point!(format!(
+            log_point!(self, format!(
                 "mkdir() -> added '{}' (ino={}) to parent {}",
                 name, ino, parent
             ));
@@ -786,13 +4741,13 @@ fn mkdir(
                 let old = parent_inode.nlink;
                 parent_inode.nlink += 1;
 
-                log_point!(format!(
+                log_point!(self, format!(
                     "mkdir() -> parent {} nlink {} -> {}",
                     parent, old, parent_inode.nlink
                 ));
             }
 
-            (ino, attr, true)
+            (ino, attr, generation, true)
             // <-- locks se liberan aquí porque salimos del bloque
         };
 
@@ -803,238 +4758,216 @@ fn mkdir(
             // --------------------------------------------
             // SEND REPLY
             // --------------------------------------------
-            log_point!(format!("mkdir() -> replying entry: ino={}", ino));
-            reply.entry(&TTL, &attr, 0);
+            log_point!(self, format!("mkdir() -> replying entry: ino={}", ino));
+            reply.entry(&TTL, &attr, generation);
 
-            log_exit!("mkdir() -> EXIT OK");
+            log_exit!(self, "mkdir() -> EXIT OK");
         }
     }
 
-    fn readdir(
+    fn symlink(
         &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        mut reply: ReplyDirectory,
+        req: &Request,
+        parent: u64,
+        link_name: &std::ffi::OsStr,
+        target: &std::path::Path,
+        reply: ReplyEntry,
     ) {
-        log_point!(format!("ENTER readdir(): ino={}, offset={}", ino, offset));
+        use std::os::unix::ffi::OsStrExt;
 
-        // --------------------------------------------
-        // LOCK DIRECTORIES + INODES
-        // --------------------------------------------
-        log_point!("readdir() -> locking directories and inodes");
-        let directories = self.directories.lock().unwrap();
-        let _inodes = self.inodes.lock().unwrap();
-        log_point!("readdir() -> locks acquired");
-
-        // --------------------------------------------
-        // GET DIRECTORY ENTRIES
-        // --------------------------------------------
-        if let Some(entries) = directories.get(&ino) {
-            log_point!(format!(
-                "readdir() -> directory {} has {} entries",
-                ino,
-                entries.len()
-            ));
-
-            // Iterate entries starting at offset
-            for (i, entry) in entries.iter().enumerate().skip(offset as usize) {
-                log_point!(format!(
-                    "readdir() -> adding entry index={}, ino={}, name='{}'",
-                    i, entry.ino, entry.name
-                ));
-
-                let kind = match entry.file_type {
-                    FileType::RegularFile => FuseFileType::RegularFile,
-                    FileType::Directory => FuseFileType::Directory,
-                    FileType::Symlink => FuseFileType::Symlink,
-                };
-
-                let full = reply.add(entry.ino, (i + 1) as i64, kind, &entry.name);
+        let parent = self.internal_ino(parent);
+        let name = link_name.to_string_lossy().to_string();
+        log_point!(self, format!(
+            "ENTER symlink(): parent={}, name='{}', target={:?}",
+            parent, name, target
+        ));
 
-                if full {
-                    log_point!(format!(
-                        "readdir() -> reply buffer FULL after entry index={} (ino={})",
-                        i, entry.ino
-                    ));
-                    break;
-                }
+        match self.create_symlink(parent, &name, target.as_os_str().as_bytes(), req.uid(), req.gid()) {
+            Ok(inode) => {
+                reply.entry(&TTL, &self.inode_to_attr(&inode), inode.generation);
+                log_exit!(self, format!("symlink() -> EXIT OK ino={}", inode.ino));
+            }
+            Err(errno) => {
+                reply.error(errno);
+                log_exit!(self, format!("symlink() -> EXIT ERR {}", errno));
             }
-        } else {
-            log_point!(format!(
-                "readdir() -> directory {} NOT FOUND in directories table",
-                ino
-            ));
         }
-
-        // --------------------------------------------
-        // SEND OK REPLY
-        // --------------------------------------------
-        log_point!("readdir() -> sending reply.ok()");
-        reply.ok();
-
-        log_exit!("readdir()");
     }
 
-    fn unlink(&mut self, _req: &Request, parent: u64, name: &std::ffi::OsStr, reply: ReplyEmpty) {
+    fn mknod(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        _umask: u32,
+        rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        let parent = self.internal_ino(parent);
         let name = name.to_string_lossy().to_string();
-        log_point!(format!("ENTER unlink(): parent={}, name={}", parent, name));
-
-        let mut success = false;
-
-        {
-            // --------------------------------------------
-            // LOCK INODES + DIRS
-            // --------------------------------------------
-            let mut inodes = self.inodes.lock().unwrap();
-            let mut directories = self.directories.lock().unwrap();
-            log_point!("unlink() -> locks acquired");
-
-            // --------------------------------------------
-            // Buscar entrada en el directorio padre
-            // --------------------------------------------
-            if let Some(entries) = directories.get_mut(&parent) {
-                if let Some(pos) = entries.iter().position(|e| e.name == name) {
-                    let entry = entries.remove(pos);
-                    log_point!(format!("unlink(): removed DirEntry for ino={}", entry.ino));
-
-                    // Reducir nlink
-                    if let Some(inode) = inodes.get_mut(&entry.ino) {
-                        let old = inode.nlink;
-                        inode.nlink -= 1;
-                        log_point!(format!(
-                            "unlink(): inode {} nlink {} -> {}",
-                            entry.ino, old, inode.nlink
-                        ));
-
-                        if inode.nlink == 0 {
-                            log_point!(format!(
-                                "unlink(): inode {} nlink=0 → freeing blocks",
-                                entry.ino
-                            ));
-
-                            for i in 0..12 {
-                                if let Some(block_num) = inode.get_block_number(i) {
-                                    self.free_block(block_num);
-                                    log_point!(format!("unlink(): freed block {}", block_num));
-                                }
-                            }
-
-                            inodes.remove(&entry.ino);
-                            log_point!(format!("unlink(): inode {} removed", entry.ino));
-                        }
-                    }
+        log_point!(self, format!(
+            "ENTER mknod(): parent={}, name='{}', mode={:#o}, rdev={:#x}",
+            parent, name, mode, rdev
+        ));
 
-                    success = true;
-                } else {
-                    log_point!(format!(
-                        "unlink(): entry '{}' not found in parent {}",
-                        name, parent
-                    ));
-                }
-            } else {
-                log_point!(format!("unlink(): parent directory {} not found", parent));
+        // Tipo 0 equivale a S_IFREG, como en mknod(2)
+        let file_type = match mode & libc::S_IFMT {
+            0 | libc::S_IFREG => FileType::RegularFile,
+            libc::S_IFCHR => FileType::CharDevice,
+            libc::S_IFBLK => FileType::BlockDevice,
+            libc::S_IFIFO => FileType::NamedPipe,
+            libc::S_IFSOCK => FileType::Socket,
+            _ => {
+                reply.error(libc::EINVAL);
+                log_exit!(self, "mknod() -> EXIT ERR EINVAL");
+                return;
             }
-        } // <---- Locks se liberan aquí
+        };
 
-        if success {
-            // Directory tree cambió → metadata sucia
-            self.mark_dirty();
-            reply.ok();
-            log_exit!("unlink() -> EXIT OK");
-        } else {
-            reply.error(libc::ENOENT);
-            log_exit!("unlink() -> ENOENT");
+        match self.create_node(parent, &name, file_type, mode as u16, rdev, req.uid(), req.gid()) {
+            Ok(inode) => {
+                reply.entry(&TTL, &self.inode_to_attr(&inode), inode.generation);
+                log_exit!(self, format!("mknod() -> EXIT OK ino={}", inode.ino));
+            }
+            Err(errno) => {
+                reply.error(errno);
+                log_exit!(self, format!("mknod() -> EXIT ERR {}", errno));
+            }
         }
     }
 
-    fn rmdir(&mut self, _req: &Request, parent: u64, name: &std::ffi::OsStr, reply: ReplyEmpty) {
-        let name = name.to_string_lossy().to_string();
-        log_point!(format!("ENTER rmdir(): parent={}, name={}", parent, name));
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!("ENTER readlink(): ino={}", ino));
 
-        // Variables de salida
-        let mut exit_code: Option<i32> = None; // None = OK, Some(errno) = error
+        match self.read_symlink(ino) {
+            Ok(target) => reply.data(&target),
+            Err(errno) => {
+                log_point!(self, format!("readlink() -> errno {}", errno));
+                reply.error(errno);
+            }
+        }
+    }
 
-        {
-            // --------------------------------------------
-            // LOCK INODES + DIRECTORIES
-            // --------------------------------------------
-            let mut inodes = self.inodes.lock().unwrap();
-            let mut directories = self.directories.lock().unwrap();
-            log_point!("rmdir() -> locks acquired");
+    fn link(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        newparent: u64,
+        newname: &std::ffi::OsStr,
+        reply: ReplyEntry,
+    ) {
+        let ino = self.internal_ino(ino);
+        let newparent = self.internal_ino(newparent);
+        let newname = newname.to_string_lossy().to_string();
+        log_point!(self, format!(
+            "ENTER link(): ino={}, newparent={}, newname='{}'",
+            ino, newparent, newname
+        ));
 
-            // --------------------------------------------
-            // Buscar el directorio
-            // --------------------------------------------
-            let entry_opt = directories
-                .get(&parent)
-                .and_then(|entries| {
-                    entries
-                        .iter()
-                        .find(|e| e.name == name && e.file_type == FileType::Directory)
-                        .cloned()
-                });
+        match self.link_inode(ino, newparent, &newname) {
+            Ok(inode) => {
+                reply.entry(&TTL, &self.inode_to_attr(&inode), inode.generation);
+                log_exit!(self, "link() -> EXIT OK");
+            }
+            Err(errno) => {
+                reply.error(errno);
+                log_exit!(self, format!("link() -> EXIT ERR {}", errno));
+            }
+        }
+    }
 
-            if entry_opt.is_none() {
-                log_point!(format!(
-                    "rmdir(): '{}' not found under parent {}",
-                    name, parent
-                ));
-                exit_code = Some(libc::ENOENT);
-            } else {
-                let entry = entry_opt.unwrap();
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!("ENTER readdir(): ino={}, fh={}, offset={}", ino, fh, offset));
 
-                // --------------------------------------------
-                // Verificar vacío
-                // --------------------------------------------
-                if let Some(children) = directories.get(&entry.ino) {
-                    if children.len() > 2 {
-                        log_point!(format!(
-                            "rmdir(): directory {} NOT EMPTY ({} entries)",
-                            entry.ino,
-                            children.len()
-                        ));
-                        exit_code = Some(libc::ENOTEMPTY);
-                    }
-                }
+        // --------------------------------------------
+        // GET DIRECTORY ENTRIES
+        // --------------------------------------------
+        let entries = match self.dir_entries(ino, fh) {
+            Ok(entries) => entries,
+            Err(errno) => {
+                log_point!(self, format!("readdir() -> ERR {} for ino={}", errno, ino));
+                reply.error(errno);
+                log_exit!(self, "readdir()");
+                return;
+            }
+        };
 
-                // Si NO se ha puesto error → borrar
-                if exit_code.is_none() {
-                    log_point!(format!("rmdir(): removing inode {}", entry.ino));
+        log_point!(self, format!(
+            "readdir() -> directory {} has {} entries",
+            ino,
+            entries.len()
+        ));
 
-                    // Quitar del padre
-                    if let Some(parent_entries) = directories.get_mut(&parent) {
-                        parent_entries.retain(|e| e.ino != entry.ino);
-                    }
+        // Iterate entries starting at offset
+        for (i, entry) in entries.iter().enumerate().skip(offset as usize) {
+            log_point!(self, format!(
+                "readdir() -> adding entry index={}, ino={}, name='{}'",
+                i, entry.ino, entry.name
+            ));
 
-                    directories.remove(&entry.ino);
-                    inodes.remove(&entry.ino);
+            // El tipo sale del DirEntry: readdir nunca toma el lock de inodos
+            let kind = fuse_file_type(entry.file_type);
 
-                    // Reducir nlink del padre
-                    if let Some(parent_inode) = inodes.get_mut(&parent) {
-                        parent_inode.nlink -= 1;
-                    }
-                }
-            }
+            let full = reply.add(self.kernel_ino(entry.ino), (i + 1) as i64, kind, &entry.name);
 
-            // Los locks se sueltan automáticamente aquí
+            if full {
+                log_point!(self, format!(
+                    "readdir() -> reply buffer FULL after entry index={} (ino={})",
+                    i, entry.ino
+                ));
+                break;
+            }
         }
 
         // --------------------------------------------
-        // REPLY FINAL
+        // SEND OK REPLY
         // --------------------------------------------
-        match exit_code {
-            None => {
-                // Directory tree cambió → metadata sucia
-                self.mark_dirty();
+        log_point!(self, "readdir() -> sending reply.ok()");
+        reply.ok();
 
+        log_exit!(self, "readdir()");
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: u64, name: &std::ffi::OsStr, reply: ReplyEmpty) {
+        let parent = self.internal_ino(parent);
+        let name = name.to_string_lossy().to_string();
+        log_point!(self, format!("ENTER unlink(): parent={}, name={}", parent, name));
+
+        match self.unlink_entry(parent, &name) {
+            Ok(()) => {
+                reply.ok();
+                log_exit!(self, "unlink() -> EXIT OK");
+            }
+            Err(errno) => {
+                reply.error(errno);
+                log_exit!(self, format!("unlink() -> EXIT ERR {}", errno));
+            }
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: u64, name: &std::ffi::OsStr, reply: ReplyEmpty) {
+        let parent = self.internal_ino(parent);
+        let name = name.to_string_lossy().to_string();
+        log_point!(self, format!("ENTER rmdir(): parent={}, name={}", parent, name));
+
+        match self.remove_dir(parent, &name) {
+            Ok(()) => {
                 reply.ok();
-                log_exit!("rmdir() -> EXIT OK");
+                log_exit!(self, "rmdir() -> EXIT OK");
             }
-            Some(errno) => {
+            Err(errno) => {
                 reply.error(errno);
-                log_exit!(format!("rmdir() -> EXIT ERR {}", errno));
+                log_exit!(self, format!("rmdir() -> EXIT ERR {}", errno));
             }
         }
     }
@@ -1049,117 +4982,403 @@ fn rename(
         _flags: u32,
         reply: ReplyEmpty,
     ) {
+        let parent = self.internal_ino(parent);
+        let newparent = self.internal_ino(newparent);
         let name = name.to_string_lossy().to_string();
         let newname = newname.to_string_lossy().to_string();
 
-        log_point!(format!(
+        log_point!(self, format!(
             "ENTER rename(): parent={}, name='{}', newparent={}, newname='{}'",
             parent, name, newparent, newname
         ));
 
-        let mut exit_code: Option<i32> = None; // None = OK; Some(errno) = error
+        match self.rename_entry(parent, &name, newparent, &newname) {
+            Ok(()) => {
+                reply.ok();
+                log_exit!(self, "rename() -> EXIT OK");
+            }
+            Err(errno) => {
+                reply.error(errno);
+                log_exit!(self, format!("rename() -> EXIT ERR {}", errno));
+            }
+        }
+    }
 
-        {
-            log_point!("rename() -> locking directories");
-            let mut directories = self.directories.lock().unwrap();
-            log_point!("rename() -> locks acquired");
+    fn fallocate(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!(
+            "ENTER fallocate(): ino={}, fh={}, offset={}, length={}, mode={:#x}",
+            ino, fh, offset, length, mode
+        ));
 
-            // ----------------------------------------------------------
-            // Buscar entrada en el parent original
-            // ----------------------------------------------------------
-            let entry_info = directories
-                .get_mut(&parent)
-                .and_then(|entries| {
-                    entries
-                        .iter()
-                        .position(|e| e.name == name)
-                        .map(|pos| (pos, entries))
-                });
+        if offset < 0 || length <= 0 {
+            reply.error(libc::EINVAL);
+            log_exit!(self, "fallocate() -> EXIT ERR EINVAL");
+            return;
+        }
 
-            if entry_info.is_none() {
-                log_point!(format!(
-                    "rename(): entry '{}' not found in parent {}",
-                    name, parent
-                ));
-                exit_code = Some(libc::ENOENT);
-            } else {
-                let (pos, parent_entries) = entry_info.unwrap();
-                log_point!(format!(
-                    "rename(): found '{}' at pos {} in parent {}",
-                    name, pos, parent
-                ));
+        // Solo se soporta perforar huecos (que en Linux exige KEEP_SIZE)
+        let punch = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+        if mode != punch {
+            log_point!(self, "fallocate(): unsupported mode -> EOPNOTSUPP");
+            reply.error(libc::EOPNOTSUPP);
+            log_exit!(self, "fallocate() -> EXIT ERR EOPNOTSUPP");
+            return;
+        }
 
-                // ----------------------------------------------------------
-                // Quitar la entrada del directorio original
-                // ----------------------------------------------------------
-                let mut entry = parent_entries.remove(pos);
-                log_point!(format!(
-                    "rename(): removed old entry '{}' (ino={}) from parent {}",
-                    name, entry.ino, parent
-                ));
+        match self.punch_hole(ino, offset as u64, length as u64) {
+            Ok(()) => {
+                reply.ok();
+                log_exit!(self, "fallocate() -> EXIT OK");
+            }
+            Err(errno) => {
+                reply.error(errno);
+                log_exit!(self, format!("fallocate() -> EXIT ERR {}", errno));
+            }
+        }
+    }
 
-                // ----------------------------------------------------------
-                // Actualizar nombre
-                // ----------------------------------------------------------
-                entry.name = newname.clone();
-                log_point!(format!(
-                    "rename(): updated name '{}' -> '{}'",
-                    name, newname
-                ));
+    fn lseek(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!(
+            "ENTER lseek(): ino={}, fh={}, offset={}, whence={}",
+            ino, fh, offset, whence
+        ));
 
-                // ----------------------------------------------------------
-                // Insertar en el nuevo parent
-                // ----------------------------------------------------------
-                directories
-                    .entry(newparent)
-                    .or_insert_with(Vec::new)
-                    .push(entry);
+        // El kernel resuelve SEEK_SET/CUR/END; aquí solo llegan DATA y HOLE
+        if offset < 0 {
+            reply.error(libc::ENXIO);
+            log_exit!(self, "lseek() -> EXIT ERR ENXIO");
+            return;
+        }
 
-                log_point!(format!(
-                    "rename(): inserted updated entry into newparent {}",
-                    newparent
-                ));
+        match self.seek_data_hole(ino, offset as u64, whence) {
+            Ok(position) => {
+                reply.offset(position as i64);
+                log_exit!(self, format!("lseek() -> EXIT OK {}", position));
+            }
+            Err(errno) => {
+                reply.error(errno);
+                log_exit!(self, format!("lseek() -> EXIT ERR {}", errno));
+            }
+        }
+    }
+
+    fn copy_file_range(
+        &mut self,
+        _req: &Request,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let ino_in = self.internal_ino(ino_in);
+        let ino_out = self.internal_ino(ino_out);
+        log_point!(self, format!(
+            "ENTER copy_file_range(): {}(fh={})@{} -> {}(fh={})@{}, len={}, flags={:#x}",
+            ino_in, fh_in, offset_in, ino_out, fh_out, offset_out, len, flags
+        ));
+
+        if offset_in < 0 || offset_out < 0 || flags != 0 {
+            reply.error(libc::EINVAL);
+            log_exit!(self, "copy_file_range() -> EXIT ERR EINVAL");
+            return;
+        }
+
+        // La respuesta cuenta bytes en un u32
+        let len = len.min(u32::MAX as u64);
+        match self.copy_range(ino_in, offset_in as u64, ino_out, offset_out as u64, len) {
+            Ok(copied) => {
+                reply.written(copied as u32);
+                log_exit!(self, format!("copy_file_range() -> EXIT OK {}", copied));
+            }
+            Err(errno) => {
+                reply.error(errno);
+                log_exit!(self, format!("copy_file_range() -> EXIT ERR {}", errno));
+            }
+        }
+    }
+
+    fn flush(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        _lock_owner: u64,
+        reply: ReplyEmpty,
+    ) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!("ENTER flush(): ino={}, fh={}", ino, fh));
+
+        // Nota: flush no escribe metadata, solo notifica el cierre del descriptor.
+        // Usamos release() para decidir cuándo sincronizar metadata.
+        reply.ok();
+
+        log_exit!(self, format!("flush(): completed for ino={}, fh={}", ino, fh));
+    }
+
+    fn fsync(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!(
+            "ENTER fsync(): ino={}, fh={}, datasync={}",
+            ino, fh, datasync
+        ));
+
+        match self.sync_if_dirty() {
+            Ok(_) => {
+                log_point!(self, "fsync(): sync_if_dirty() completed OK");
+                reply.ok();
+            }
+            Err(e) => {
+                log_point!(self, format!("ERROR in fsync(): sync_if_dirty() failed -> {}", e));
+                reply.error(storage_errno(&e));
+            }
+        }
+
+        log_exit!(self, format!("EXIT fsync(): ino={}, fh={}", ino, fh));
+    }
+
+    fn access(&mut self, _req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!("ENTER access(): ino={}, mask={}", ino, mask));
+
+        let inodes = self.inodes.lock().unwrap();
+
+        if inodes.contains_key(&ino) {
+            log_point!(self, format!("access(): inode {} EXISTS -> granting access", ino));
+            reply.ok();
+        } else {
+            log_point!(self, format!("access(): inode {} NOT FOUND -> ENOENT", ino));
+            reply.error(libc::ENOENT);
+        }
+
+        log_exit!(self, format!("EXIT access(): ino={}", ino));
+    }
+
+    fn setxattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        name: &std::ffi::OsStr,
+        value: &[u8],
+        flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        let ino = self.internal_ino(ino);
+        match self.set_xattr(ino, &name.to_string_lossy(), value, flags) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        name: &std::ffi::OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        let ino = self.internal_ino(ino);
+        match self.get_xattr(ino, &name.to_string_lossy()) {
+            Ok(value) => reply_xattr(reply, size, &value),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        let ino = self.internal_ino(ino);
+        match self.list_xattrs(ino) {
+            Ok(list) => reply_xattr(reply, size, &list),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn removexattr(&mut self, _req: &Request, ino: u64, name: &std::ffi::OsStr, reply: ReplyEmpty) {
+        let ino = self.internal_ino(ino);
+        match self.remove_xattr(ino, &name.to_string_lossy()) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn ioctl(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        let ino = self.internal_ino(ino);
+        log_enter!(self, "ioctl()");
+        log_point!(self, format!("ioctl ino={} cmd={:#x} out_size={}", ino, cmd, out_size));
+
+        if cmd == IOCTL_CLONE_FROM {
+            let Ok(src) = <[u8; 8]>::try_from(in_data) else {
+                reply.error(libc::EINVAL);
+                log_exit!(self, "ioctl()");
+                return;
+            };
+            match self.clone_blocks_into(self.internal_ino(u64::from_le_bytes(src)), ino) {
+                Ok(()) => reply.ioctl(0, &[]),
+                Err(errno) => reply.error(errno),
+            }
+            log_exit!(self, "ioctl()");
+            return;
+        }
+
+        if cmd != IOCTL_LIST_HANDLES {
+            reply.error(libc::ENOTTY);
+            log_exit!(self, "ioctl()");
+            return;
+        }
+
+        // Se descartan entradas del final hasta que la respuesta quepa
+        let mut handles = self.open_handles();
+        let limit = (out_size as usize).min(LIST_HANDLES_MAX_BYTES);
+        let data = loop {
+            let data = serde_json::to_vec(&handles).unwrap_or_default();
+            if data.len() <= limit || handles.pop().is_none() {
+                break data;
             }
+        };
+        reply.ioctl(0, &data);
+        log_exit!(self, "ioctl()");
+    }
+
+    fn statfs(&mut self, _req: &Request, ino: u64, reply: ReplyStatfs) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!("ENTER statfs(): ino={}", ino));
+
+        let stats = self.fs_stats();
+        log_point!(self, format!("statfs(): {:?}", stats));
+
+        reply.statfs(
+            stats.total_blocks,                   // blocks
+            stats.free_blocks,                    // bfree
+            stats.free_blocks,                    // bavail
+            stats.total_inodes,                   // files
+            stats.free_inodes,                    // ffree
+            stats.block_size,                     // bsize
+            stats.max_name_len,                   // namelen
+            stats.block_size,                     // frsize
+        );
 
-            // Locks salen aquí
-        }
+        log_exit!(self, format!("EXIT statfs(): ino={}", ino));
+    }
 
-        // ----------------------------------------------------------
-        // REPLY FINAL
-        // ----------------------------------------------------------
-        match exit_code {
-            None => {
-                // Directory tree cambió → metadata sucia
-                self.mark_dirty();
+    fn opendir(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!("ENTER opendir(): ino={}, flags={}", ino, flags));
 
-                reply.ok();
-                log_exit!("rename() -> EXIT OK");
+        match self.open_dir(ino) {
+            Ok(fh) => {
+                log_point!(self, format!("opendir(): allocated fh={} for dir inode {}", fh, ino));
+                reply.opened(fh, 0);
             }
-            Some(errno) => {
+            Err(errno) => {
+                log_point!(self, format!("opendir(): inode {} -> error {}", ino, errno));
                 reply.error(errno);
-                log_exit!(format!("rename() -> EXIT ERR {}", errno));
             }
         }
+
+        log_exit!(self, format!("EXIT opendir(): ino={}", ino));
     }
 
-    fn flush(
+    fn release(
         &mut self,
         _req: &Request,
         ino: u64,
         fh: u64,
-        _lock_owner: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
         reply: ReplyEmpty,
     ) {
-        log_point!(format!("ENTER flush(): ino={}, fh={}", ino, fh));
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!("ENTER release(): ino={}, fh={}", ino, fh));
+
+        if let Err(errno) = self.release_handle(fh) {
+            log_point!(self, format!("release(): ERROR syncing metadata (errno {})", errno));
+            reply.error(errno);
+            log_exit!(self, format!("EXIT release(): ino={}, fh={} (ERROR)", ino, fh));
+            return;
+        }
+        log_point!(self, format!("release(): fh={} released", fh));
 
-        // Nota: flush no escribe metadata, solo notifica el cierre del descriptor.
-        // Usamos release() para decidir cuándo sincronizar metadata.
         reply.ok();
+        log_exit!(self, format!("EXIT release(): ino={}, fh={}", ino, fh));
+    }
+
+    fn releasedir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        _flags: i32,
+        reply: ReplyEmpty,
+    ) {
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!("ENTER releasedir(): ino={}, fh={}", ino, fh));
+
+        // También aquí sincronizamos si hay metadata sucia, para cubrir cambios
+        // que sólo afecten directorios (mkdir/rename/rmdir, etc.).
+        if let Err(e) = self.sync_on_close() {
+            log_point!(self, format!("releasedir(): ERROR syncing metadata -> {}", e));
+            reply.error(storage_errno(&e));
+            log_exit!(self, format!("EXIT releasedir(): ino={}, fh={} (ERROR)", ino, fh));
+            return;
+        }
+
+        let mut open_files = self.open_files.lock().unwrap();
+        if let Some(handle) = open_files.remove(&fh) {
+            log_point!(self, format!(
+                "releasedir(): removed fh={} for directory ino={} (snapshot freed)",
+                fh, handle.ino
+            ));
+        } else {
+            log_point!(self, format!("releasedir(): fh={} not found in open_files", fh));
+        }
 
-        log_exit!(format!("flush(): completed for ino={}, fh={}", ino, fh));
+        reply.ok();
+        log_exit!(self, format!("EXIT releasedir(): ino={}, fh={}", ino, fh));
     }
 
-    fn fsync(
+    fn fsyncdir(
         &mut self,
         _req: &Request,
         ino: u64,
@@ -1167,169 +5386,1332 @@ fn fsync(
         datasync: bool,
         reply: ReplyEmpty,
     ) {
-        log_point!(format!(
-            "ENTER fsync(): ino={}, fh={}, datasync={}",
+        let ino = self.internal_ino(ino);
+        log_point!(self, format!(
+            "ENTER fsyncdir(): ino={}, fh={}, datasync={}",
             ino, fh, datasync
         ));
 
+        // Las entradas de directorio viven en la metadata: hacerlas durables
+        // (create/unlink/rename) es un checkpoint, igual que en fsync
         match self.sync_if_dirty() {
             Ok(_) => {
-                log_point!("fsync(): sync_if_dirty() completed OK");
+                log_point!(self, "fsyncdir(): sync_if_dirty() completed OK");
                 reply.ok();
             }
             Err(e) => {
-                log_point!(format!("ERROR in fsync(): sync_if_dirty() failed -> {}", e));
-                reply.error(libc::EIO);
+                log_point!(self, format!("ERROR in fsyncdir(): sync_if_dirty() failed -> {}", e));
+                reply.error(storage_errno(&e));
             }
         }
 
-        log_exit!(format!("EXIT fsync(): ino={}, fh={}", ino, fh));
+        log_exit!(self, format!("EXIT fsyncdir(): ino={}, fh={}", ino, fh));
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_config, write_superblock, TempDir};
+
+    /// A fresh filesystem in `dir`
+    fn new_fs(dir: &TempDir) -> BWFS {
+        BWFS::new(test_config(dir.path())).unwrap()
+    }
+
+    /// Create a regular file under `parent` holding `data`
+    fn make_file(fs: &BWFS, parent: u64, name: &str, data: &[u8]) -> u64 {
+        let ino = fs.create_with_size_hint(parent, name, 0o644, 0).unwrap();
+        if !data.is_empty() {
+            fs.write_data(ino, 0, data).unwrap();
+        }
+        ino
     }
 
-    fn access(&mut self, _req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
-        log_point!(format!("ENTER access(): ino={}, mask={}", ino, mask));
+    /// Every block an inode points to can be read back, and the metadata
+    /// agrees with the bitmaps
+    fn assert_no_dangling_pointers(fs: &BWFS) {
+        let storage = fs.storage.lock().unwrap();
+        for (path, inode) in fs.walk() {
+            for (_, block_num) in inode.mapped_blocks(&storage).unwrap() {
+                assert!(storage.read_block(block_num).is_ok(), "{} points at unreadable block {}", path, block_num);
+            }
+        }
+        drop(storage);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
 
-        let inodes = self.inodes.lock().unwrap();
+    #[test]
+    fn remove_tree_keeps_files_linked_outside_the_subtree() {
+        let dir = TempDir::new("rmtree-link");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        let sub = fs.create_dir(root, "sub", 0o755, 0, 0).unwrap().ino;
+        let data = vec![7u8; 2000];
+        let ino = make_file(&fs, sub, "f", &data);
+        fs.link_inode(ino, root, "keep").unwrap();
+
+        assert_eq!(fs.remove_tree("/sub").unwrap(), 2);
+        assert_eq!(fs.read_file(ino).unwrap(), data);
+        assert_eq!(fs.inodes.lock().unwrap()[&ino].nlink, 1);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
 
-        if inodes.contains_key(&ino) {
-            log_point!(format!("access(): inode {} EXISTS -> granting access", ino));
-            reply.ok();
-        } else {
-            log_point!(format!("access(): inode {} NOT FOUND -> ENOENT", ino));
-            reply.error(libc::ENOENT);
+    #[test]
+    fn write_returns_eio_when_a_block_write_fails() {
+        let dir = TempDir::new("fault-write");
+        let fs = new_fs(&dir);
+        let ino = make_file(&fs, fs.root_ino(), "f", &[]);
+
+        fs.inject_faults(Some(crate::storage::FaultPlan {
+            fail_write: Some(1),
+            ..Default::default()
+        }));
+        assert_eq!(fs.write_data(ino, 0, &[1u8; 1500]), Err(libc::EIO));
+        fs.inject_faults(None);
+
+        assert_eq!(fs.inodes.lock().unwrap()[&ino].size, 0);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn journaled_write_survives_a_crash_before_the_checkpoint() {
+        let dir = TempDir::new("journal-crash");
+        let config = Config {
+            journal: true,
+            journal_sync: crate::config::JournalSync::Always,
+            ..test_config(dir.path())
+        };
+        let fs = BWFS::new(config.clone()).unwrap();
+        let ino = make_file(&fs, fs.root_ino(), "f", &[]);
+        fs.save().unwrap();
+        let data = vec![9u8; 1500];
+        fs.write_data(ino, 0, &data).unwrap();
+        // Crash: sin checkpoint
+        drop(fs);
+
+        let fs = BWFS::load(config).unwrap();
+        assert_eq!(fs.read_file(ino).unwrap(), data);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn write_stores_each_index_block_once() {
+        let dir = TempDir::new("indirect-once");
+        let fs = new_fs(&dir);
+        let ino = make_file(&fs, fs.root_ino(), "big", &[]);
+
+        // 12 bloques directos + 8 bajo el bloque indirecto
+        let data = vec![3u8; 20 * 512];
+        fs.write_data(ino, 0, &data).unwrap();
+        let indirect = fs.inodes.lock().unwrap()[&ino].indirect_block;
+        assert_ne!(indirect, u32::MAX);
+        assert_eq!(fs.storage.lock().unwrap().write_count(indirect), 1);
+
+        fs.write_data(ino, data.len() as u64, &[4u8; 4 * 512]).unwrap();
+        assert_eq!(fs.storage.lock().unwrap().write_count(indirect), 2);
+
+        let mut expected = data;
+        expected.extend_from_slice(&[4u8; 4 * 512]);
+        assert_eq!(fs.read_file(ino).unwrap(), expected);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn read_keeps_blocks_aligned_around_a_hole() {
+        let dir = TempDir::new("read-hole");
+        let fs = new_fs(&dir);
+        let ino = make_file(&fs, fs.root_ino(), "sparse", &[1u8; 512]);
+        fs.write_data(ino, 1024, &[3u8; 512]).unwrap();
+
+        let inode = fs.inode(ino).unwrap();
+        assert!(inode.get_block_number(0).is_some());
+        assert_eq!(inode.get_block_number(1), None);
+        assert!(inode.get_block_number(2).is_some());
+
+        let mut expected = vec![1u8; 512];
+        expected.extend_from_slice(&[0u8; 512]);
+        expected.extend_from_slice(&[3u8; 512]);
+        assert_eq!(fs.read_file(ino).unwrap(), expected);
+        let storage = fs.storage.lock().unwrap();
+        assert_eq!(fs.read_range(&inode, &storage, 1000, 100).unwrap(), [&[0u8; 24][..], &[3u8; 76][..]].concat());
+    }
+
+    #[test]
+    fn statfs_and_create_agree_on_a_short_name_limit() {
+        let dir = TempDir::new("namelen");
+        let fs = BWFS::new(Config { max_name_len: 8, ..test_config(dir.path()) }).unwrap();
+        assert_eq!(fs.fs_stats().max_name_len, 8);
+
+        let root = fs.root_ino();
+        assert!(fs.create_with_size_hint(root, "12345678", 0o644, 0).is_ok());
+        assert!(fs.create_with_size_hint(root, "123456789", 0o644, 0).is_err());
+        assert_eq!(fs.create_dir(root, "dir-too-long", 0o755, 0, 0).unwrap_err(), libc::ENAMETOOLONG);
+        assert_eq!(fs.create_symlink(root, "link-too-long", b"x", 0, 0).unwrap_err(), libc::ENAMETOOLONG);
+        let ino = fs.create_with_size_hint(root, "short", 0o644, 0).unwrap();
+        assert_eq!(fs.link_inode(ino, root, "hard-too-long").unwrap_err(), libc::ENAMETOOLONG);
+    }
+
+    #[test]
+    fn failed_metadata_write_leaves_no_dangling_pointer() {
+        let dir = TempDir::new("barrier");
+        let config = Config { metadata_blocks: 2, metadata_cache: false, ..test_config(dir.path()) };
+        let fs = BWFS::new(config.clone()).unwrap();
+        let ino = make_file(&fs, fs.root_ino(), "f", &[1u8; 1000]);
+        fs.save().unwrap();
+
+        // Los datos nuevos llegan al disco; falla la escritura de la metadata
+        fs.write_data(ino, 1000, &[2u8; 3000]).unwrap();
+        fs.inject_faults(Some(crate::storage::FaultPlan { fail_write: Some(1), ..Default::default() }));
+        assert!(fs.save().is_err());
+        drop(fs);
+
+        let fs = BWFS::load(config).unwrap();
+        assert_eq!(fs.read_file(ino).unwrap(), vec![1u8; 1000]);
+        assert_no_dangling_pointers(&fs);
+    }
+
+    #[test]
+    fn set_file_size_frees_and_grows_without_leaking_blocks() {
+        let dir = TempDir::new("resize");
+        let fs = new_fs(&dir);
+        let free_at_start = fs.fs_stats().free_blocks;
+        let data: Vec<u8> = (0..10 * 512u32).map(|i| i as u8).collect();
+        let ino = make_file(&fs, fs.root_ino(), "f", &data);
+        assert_eq!(fs.fs_stats().free_blocks, free_at_start - 10);
+
+        fs.set_file_size(ino, 1000).unwrap();
+        assert_eq!(fs.fs_stats().free_blocks, free_at_start - 2);
+        assert_eq!(fs.read_file(ino).unwrap(), &data[..1000]);
+
+        // Crecer deja un hueco: ningún bloque nuevo
+        fs.set_file_size(ino, 4000).unwrap();
+        assert_eq!(fs.fs_stats().free_blocks, free_at_start - 2);
+        let inode = fs.inode(ino).unwrap();
+        assert_eq!((inode.size, inode.allocated_blocks), (4000, 2));
+        let mut expected = data[..1000].to_vec();
+        expected.resize(4000, 0);
+        assert_eq!(fs.read_file(ino).unwrap(), expected);
+
+        fs.set_file_size(ino, 0).unwrap();
+        assert_eq!(fs.fs_stats().free_blocks, free_at_start);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn two_instances_work_concurrently_without_interfering() {
+        let dirs = [TempDir::new("multi-a"), TempDir::new("multi-b")];
+        let filesystems: Vec<BWFS> = dirs
+            .iter()
+            .zip(["a", "b"])
+            .map(|(dir, name)| BWFS::new(Config { name: name.to_string(), ..test_config(dir.path()) }).unwrap())
+            .collect();
+        assert_ne!(filesystems[0].instance_id(), filesystems[1].instance_id());
+
+        std::thread::scope(|scope| {
+            for (n, fs) in filesystems.iter().enumerate() {
+                scope.spawn(move || {
+                    for i in 0..5u8 {
+                        let name = format!("f{}", i);
+                        make_file(fs, fs.root_ino(), &name, &vec![n as u8 * 10 + i; 700]);
+                    }
+                    fs.save().unwrap();
+                });
+            }
+        });
+
+        for (n, fs) in filesystems.iter().enumerate() {
+            let files = fs.walk();
+            assert_eq!(files.len(), 5);
+            for (path, inode) in files {
+                let i: u8 = path.trim_start_matches("/f").parse().unwrap();
+                assert_eq!(fs.read_file(inode.ino).unwrap(), vec![n as u8 * 10 + i; 700]);
+            }
+            assert_eq!(fs.check_consistency(), Vec::<String>::new());
         }
+    }
 
-        log_exit!(format!("EXIT access(): ino={}", ino));
+    #[test]
+    fn exported_blocks_load_as_the_same_filesystem() {
+        let dir = TempDir::new("export-src");
+        let dest = TempDir::new("export-dest");
+        let fs = new_fs(&dir);
+        let sub = fs.create_dir(fs.root_ino(), "sub", 0o755, 0, 0).unwrap().ino;
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 249) as u8).collect();
+        let ino = make_file(&fs, sub, "f", &data);
+        fs.save().unwrap();
+
+        let copied = fs.export_blocks(dest.path(), true).unwrap();
+        // Los bloques de datos en uso: BWFS::new no escribe el superblock
+        assert_eq!(copied as u64, fs.fs_stats().total_blocks - fs.fs_stats().free_blocks - 1);
+
+        let copy = BWFS::load(test_config(dest.path())).unwrap();
+        assert_eq!(copy.read_file(ino).unwrap(), data);
+        assert_eq!(copy.walk().len(), 2);
+        assert_eq!(copy.check_consistency(), Vec::<String>::new());
     }
 
-    fn statfs(&mut self, _req: &Request, ino: u64, reply: ReplyStatfs) {
-        log_point!(format!("ENTER statfs(): ino={}", ino));
+    #[test]
+    fn lookup_under_a_regular_file_is_enotdir() {
+        let dir = TempDir::new("lookup-enotdir");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        let file = make_file(&fs, root, "file", b"data");
 
-        let block_bitmap = self.block_bitmap.lock().unwrap();
-        let _inode_bitmap = self.inode_bitmap.lock().unwrap();
+        assert_eq!(fs.lookup_entry(file, "child").unwrap_err(), libc::ENOTDIR);
+        assert_eq!(fs.lookup_entry(root, "missing").unwrap_err(), libc::ENOENT);
+        assert_eq!(fs.lookup_entry(root, "file").unwrap().ino, file);
+    }
 
-        let block_size = self.storage.lock().unwrap().bytes_per_block() as u32;
-        let total_blocks = self.config.total_blocks as u64;
+    #[test]
+    fn verify_tree_points_at_a_tampered_block() {
+        let dir = TempDir::new("merkle-tamper");
+        let fs = BWFS::new(Config { integrity_tree: true, ..test_config(dir.path()) }).unwrap();
+        let ino = make_file(&fs, fs.root_ino(), "f", &[0x5a; 2 * 512]);
+        let root = fs.merkle_root().unwrap();
+        assert_eq!(fs.verify_tree().unwrap(), Vec::<u32>::new());
+
+        // Reescribir el primer bloque por fuera del filesystem montado
+        let first = fs.inode(ino).unwrap().direct_blocks[0];
+        let config = test_config(dir.path());
+        let outside = BlockStorage::new(&config.storage_path, 64, 64, 64, config.fingerprint).unwrap();
+        outside.write_block(first, &[0xa5; 512]).unwrap();
+
+        assert_eq!(fs.verify_tree().unwrap(), vec![first]);
+        assert_eq!(fs.merkle_root().unwrap(), root);
+    }
 
-        // Count free blocks
-        let mut free_blocks = 0u64;
-        for i in 0..self.config.total_blocks as usize {
-            if !block_bitmap.is_set(i) {
-                free_blocks += 1;
+    #[test]
+    fn atime_policies_decide_when_reads_advance_atime() {
+        let dir = TempDir::new("atime");
+        let mut fs = new_fs(&dir);
+        let ino = make_file(&fs, fs.root_ino(), "f", b"data");
+        let hours = |n: u64| SystemTime::now() - Duration::from_secs(n * 60 * 60);
+        // Fija atime/mtime/ctime y devuelve si un read movió atime
+        let read_advances = |fs: &BWFS, atime: SystemTime, changed: SystemTime| {
+            if let Some(inode) = fs.inodes.lock().unwrap().get_mut(&ino) {
+                (inode.atime, inode.mtime, inode.ctime) = (atime, changed, changed);
             }
+            fs.touch_atime(ino);
+            fs.inode(ino).unwrap().atime != atime
+        };
+
+        fs.set_atime_policy(AtimePolicy::Strict);
+        assert!(read_advances(&fs, hours(1), hours(2)));
+
+        fs.set_atime_policy(AtimePolicy::Noatime);
+        assert!(!read_advances(&fs, hours(1), hours(2)));
+        assert!(!read_advances(&fs, hours(48), hours(2)));
+
+        fs.set_atime_policy(AtimePolicy::Relatime);
+        assert!(!read_advances(&fs, hours(1), hours(2)));
+        assert!(read_advances(&fs, hours(3), hours(2)));
+        assert!(read_advances(&fs, hours(25), hours(26)));
+    }
+
+    #[test]
+    fn writes_proceed_while_a_checkpoint_is_being_written() {
+        let dir = TempDir::new("checkpoint-bg");
+        let fs = new_fs(&dir);
+        let early = make_file(&fs, fs.root_ino(), "early", &[1; 700]);
+
+        // Instantánea tomada, escritura a disco todavía pendiente
+        let checkpoint = fs.prepare_checkpoint().unwrap();
+        let (done, finished) = std::sync::mpsc::channel();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                fs.write_data(early, 700, &[2; 700]).unwrap();
+                make_file(&fs, fs.root_ino(), "late", b"late");
+                done.send(()).unwrap();
+            });
+            finished
+                .recv_timeout(Duration::from_secs(10))
+                .expect("writes blocked by a pending checkpoint");
+        });
+        checkpoint.write().unwrap();
+        assert_eq!(fs.read_file(early).unwrap().len(), 1400);
+        drop(fs);
+
+        // El disco tiene la instantánea: ni la escritura ni el archivo posteriores
+        let fs = BWFS::load(test_config(dir.path())).unwrap();
+        let names: Vec<String> = fs.walk().into_iter().map(|(path, _)| path).collect();
+        assert_eq!(names, ["/early"]);
+        assert_eq!(fs.read_file(early).unwrap(), vec![1; 700]);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn directory_stream_lists_the_opendir_snapshot() {
+        let dir = TempDir::new("opendir-snapshot");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        make_file(&fs, root, "kept", b"k");
+        make_file(&fs, root, "removed", b"r");
+        let names = |entries: Vec<DirEntry>| entries.into_iter().map(|e| e.name).collect::<Vec<_>>();
+
+        let fh = fs.open_dir(root).unwrap();
+        make_file(&fs, root, "added", b"a");
+        fs.remove_tree("/removed").unwrap();
+
+        assert_eq!(names(fs.dir_entries(root, fh).unwrap()), [".", "..", "kept", "removed"]);
+        let fresh = fs.open_dir(root).unwrap();
+        assert_eq!(names(fs.dir_entries(root, fresh).unwrap()), [".", "..", "kept", "added"]);
+        assert_eq!(fs.open_dir(fs.lookup_entry(root, "kept").unwrap().ino).unwrap_err(), libc::ENOTDIR);
+    }
+
+    #[test]
+    fn strict_posix_reports_precise_errnos() {
+        let dir = TempDir::new("strict-posix");
+        let mut fs = new_fs(&dir);
+        let root = fs.root_ino();
+        let sub = fs.create_dir(root, "sub", 0o755, 0, 0).unwrap().ino;
+        make_file(&fs, root, "file", b"f");
+
+        // Por defecto se aproxima con ENOENT o se acepta la operación
+        assert_eq!(fs.remove_dir(root, "file").unwrap_err(), libc::ENOENT);
+
+        fs.set_strict_posix(true);
+        assert_eq!(fs.unlink_entry(root, "sub").unwrap_err(), libc::EISDIR);
+        assert_eq!(fs.remove_dir(root, "file").unwrap_err(), libc::ENOTDIR);
+        assert_eq!(fs.remove_dir(sub, ".").unwrap_err(), libc::EINVAL);
+        assert_eq!(fs.remove_dir(sub, "..").unwrap_err(), libc::ENOTEMPTY);
+        make_file(&fs, sub, "child", b"c");
+        assert_eq!(fs.remove_dir(root, "sub").unwrap_err(), libc::ENOTEMPTY);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn recreated_inode_number_gets_a_new_generation() {
+        let dir = TempDir::new("generation");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        let old = fs.inode(make_file(&fs, root, "a", b"old")).unwrap();
+        fs.unlink_entry(root, "a").unwrap();
+
+        let new = fs.inode(make_file(&fs, root, "b", b"new")).unwrap();
+        assert_eq!(new.ino, old.ino);
+        assert_ne!(new.generation, old.generation);
+        assert_eq!(fs.lookup_entry(root, "b").unwrap().generation, new.generation);
+    }
+
+    #[test]
+    fn lost_metadata_file_is_an_error_or_a_recovery_never_an_empty_fs() {
+        let dir = TempDir::new("lost-metadata");
+        let fs = new_fs(&dir);
+        make_file(&fs, fs.root_ino(), "f", &[3; 900]);
+        fs.save().unwrap();
+        drop(fs);
+        std::fs::remove_file(dir.path().join("metadata.json")).unwrap();
+        let Err(error) = BWFS::load(test_config(dir.path())) else {
+            panic!("loaded a filesystem without its metadata");
+        };
+        assert!(error.to_string().contains("metadata is missing"), "{}", error);
+
+        // Con área de metadata en las imágenes se reconstruye desde ella
+        let dir = TempDir::new("lost-metadata-area");
+        let config = Config { metadata_blocks: 2, ..test_config(dir.path()) };
+        let fs = BWFS::new(config.clone()).unwrap();
+        let ino = make_file(&fs, fs.root_ino(), "f", &[3; 900]);
+        fs.save().unwrap();
+        drop(fs);
+        std::fs::remove_file(dir.path().join("metadata.json")).unwrap();
+        let fs = BWFS::load(config).unwrap();
+        assert_eq!(fs.read_file(ino).unwrap(), vec![3; 900]);
+    }
+
+    #[test]
+    fn remove_tree_reclaims_a_deep_tree() {
+        let dir = TempDir::new("remove-deep");
+        let fs = new_fs(&dir);
+        let before = fs.fs_stats();
+
+        let mut parent = fs.root_ino();
+        for depth in 0..6u8 {
+            parent = fs.create_dir(parent, &format!("d{}", depth), 0o755, 0, 0).unwrap().ino;
+            make_file(&fs, parent, "data", &vec![depth; 700 * (depth as usize + 1)]);
+            fs.create_symlink(parent, "up", b"..", 0, 0).unwrap();
         }
+        assert!(fs.fs_stats().free_blocks < before.free_blocks);
 
-        let used_inodes = self.inodes.lock().unwrap().len() as u64;
-        let free_inodes = self.config.total_inodes as u64 - used_inodes;
+        assert_eq!(fs.remove_tree("/d0").unwrap(), 6 * 3);
+        let after = fs.fs_stats();
+        assert_eq!((after.free_blocks, after.free_inodes), (before.free_blocks, before.free_inodes));
+        assert!(fs.walk().is_empty());
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
 
-        log_point!(format!(
-            "statfs(): block_size={}, total_blocks={}, free_blocks={}, used_inodes={}, free_inodes={}",
-            block_size, total_blocks, free_blocks, used_inodes, free_inodes
-        ));
+    #[test]
+    fn new_fingerprint_verifies_and_the_old_one_does_not() {
+        let dir = TempDir::new("fingerprint");
+        let config = test_config(dir.path());
+        write_superblock(&config);
+        let mut fs = BWFS::new(config.clone()).unwrap();
+        fs.set_fingerprint("  REBRANDED ").unwrap();
+        drop(fs);
+
+        let with = |fingerprint: &str| {
+            BlockStorage::new(&config.storage_path, 64, 64, 64, fingerprint.to_string()).unwrap()
+        };
+        assert!(with("REBRANDED").verify_fingerprint().unwrap());
+        assert!(!with(&config.fingerprint).verify_fingerprint().unwrap());
+        // La geometría del superblock sobrevive al cambio
+        let recorded = Config::from_superblock(&config.storage_path).unwrap();
+        assert_eq!((recorded.fingerprint.as_str(), recorded.total_inodes), ("REBRANDED", 32));
+    }
 
-        reply.statfs(
-            total_blocks,                         // blocks
-            free_blocks,                          // bfree
-            free_blocks,                          // bavail
-            self.config.total_inodes as u64,      // files
-            free_inodes,                          // ffree
-            block_size,                           // bsize
-            255,                                  // namelen
-            block_size,                           // frsize
-        );
+    #[test]
+    fn readdir_types_come_from_the_entries_without_the_inodes_lock() {
+        let dir = TempDir::new("readdir-types");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        fs.create_dir(root, "dir", 0o755, 0, 0).unwrap();
+        make_file(&fs, root, "file", b"f");
+        fs.create_symlink(root, "link", b"file", 0, 0).unwrap();
+        fs.create_node(root, "fifo", FileType::NamedPipe, 0o644, 0, 0, 0).unwrap();
+        let fh = fs.open_dir(root).unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::scope(|scope| {
+            let _inodes = fs.inodes.lock().unwrap();
+            scope.spawn(|| sender.send(fs.dir_entries(root, fh)).unwrap());
+            let entries = receiver
+                .recv_timeout(Duration::from_secs(10))
+                .expect("readdir waited for the inodes lock")
+                .unwrap();
+            let types: Vec<(&str, FileType)> =
+                entries.iter().skip(2).map(|e| (e.name.as_str(), e.file_type)).collect();
+            assert_eq!(
+                types,
+                [
+                    ("dir", FileType::Directory),
+                    ("file", FileType::RegularFile),
+                    ("link", FileType::Symlink),
+                    ("fifo", FileType::NamedPipe),
+                ]
+            );
+        });
+    }
 
-        log_exit!(format!("EXIT statfs(): ino={}", ino));
+    #[test]
+    fn punched_hole_reads_as_zeros_and_frees_whole_blocks() {
+        let dir = TempDir::new("punch-hole");
+        let fs = new_fs(&dir);
+        let data: Vec<u8> = (0..4 * 512u32).map(|i| (i % 251) as u8 + 1).collect();
+        let ino = make_file(&fs, fs.root_ino(), "f", &data);
+        let free_before = fs.fs_stats().free_blocks;
+
+        // Bloques 1 y 2 enteros, más la cola del 0 y la cabeza del 3
+        fs.punch_hole(ino, 300, 3 * 512 - 100).unwrap();
+
+        let mut expected = data.clone();
+        expected[300..3 * 512 + 200].fill(0);
+        assert_eq!(fs.read_file(ino).unwrap(), expected);
+        assert_eq!(fs.inode(ino).unwrap().size, 4 * 512);
+        assert_eq!(fs.fs_stats().free_blocks, free_before + 2);
+        assert_no_dangling_pointers(&fs);
     }
 
-    fn opendir(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
-        log_point!(format!("ENTER opendir(): ino={}, flags={}", ino, flags));
+    /// Serves lookups from a `BWFS` the test can also change out of band
+    struct SharedFs(Arc<Mutex<BWFS>>);
 
-        let inodes = self.inodes.lock().unwrap();
+    impl Filesystem for SharedFs {
+        fn lookup(&mut self, req: &Request, parent: u64, name: &std::ffi::OsStr, reply: ReplyEntry) {
+            self.0.lock().unwrap().lookup(req, parent, name, reply)
+        }
 
-        if let Some(inode) = inodes.get(&ino) {
-            if inode.is_dir() {
-                let fh = self.allocate_fh();
-                log_point!(format!(
-                    "opendir(): allocated fh={} for dir inode {}",
-                    fh, ino
-                ));
-                reply.opened(fh, 0);
-            } else {
-                log_point!(format!("opendir(): inode {} is NOT a directory", ino));
-                reply.error(libc::ENOTDIR);
+        fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+            self.0.lock().unwrap().getattr(req, ino, reply)
+        }
+    }
+
+    #[test]
+    fn out_of_band_removal_reaches_the_kernel_before_the_ttl() {
+        let dir = TempDir::new("notify");
+        let mountpoint = TempDir::new("notify-mnt");
+        let fs = new_fs(&dir);
+        let sub = fs.create_dir(fs.root_ino(), "sub", 0o755, 0, 0).unwrap().ino;
+        make_file(&fs, sub, "f", &[1; 100]);
+        let notifier = fs.notifier_handle();
+        let shared = Arc::new(Mutex::new(fs));
+
+        // Sin FUSE (sin /dev/fuse o sin permisos para montar) no hay kernel que notificar
+        let session = match fuser::Session::new(SharedFs(Arc::clone(&shared)), mountpoint.path(), &[]) {
+            Ok(session) => session,
+            Err(e) => {
+                eprintln!("skipping: cannot mount FUSE ({})", e);
+                return;
             }
-        } else {
-            log_point!(format!("opendir(): inode {} NOT FOUND", ino));
-            reply.error(libc::ENOENT);
+        };
+        *notifier.lock().unwrap() = Some(session.notifier());
+        let background = session.spawn().unwrap();
+
+        let path = mountpoint.path().join("sub/f");
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 100);
+        let started = Instant::now();
+        shared.lock().unwrap().remove_tree("/sub/f").unwrap();
+        assert!(!path.exists());
+        assert!(started.elapsed() < TTL, "the kernel only noticed after its cache expired");
+        drop(background);
+    }
+
+    #[test]
+    fn binary_metadata_round_trips_like_json() {
+        let mut sizes = Vec::new();
+        for format in [MetadataFormat::Json, MetadataFormat::Binary] {
+            let dir = TempDir::new("metadata-format");
+            let config = Config { metadata_format: format, ..test_config(dir.path()) };
+            let fs = BWFS::new(config.clone()).unwrap();
+            let sub = fs.create_dir(fs.root_ino(), "sub", 0o700, 5, 6).unwrap().ino;
+            let ino = make_file(&fs, sub, "big", &[4; 2000]);
+            fs.set_xattr(ino, "user.k", b"v", 0).unwrap();
+            make_file(&fs, fs.root_ino(), "inline", b"tiny");
+            fs.create_symlink(sub, "link", b"big", 0, 0).unwrap();
+            let state = |fs: &BWFS| serde_json::to_value(fs.walk()).unwrap();
+            let before = state(&fs);
+            fs.save().unwrap();
+            drop(fs);
+
+            let fs = BWFS::load(config).unwrap();
+            assert_eq!(state(&fs), before, "{:?}", format);
+            assert_eq!(fs.read_file(ino).unwrap(), vec![4; 2000]);
+            sizes.push(std::fs::metadata(dir.path().join(format.file_name())).unwrap().len());
         }
+        assert!(sizes[1] < sizes[0], "metadata.bin {} bytes, metadata.json {}", sizes[1], sizes[0]);
+    }
 
-        log_exit!(format!("EXIT opendir(): ino={}", ino));
+    #[test]
+    fn export_progress_fires_once_per_block_image() {
+        let dir = TempDir::new("progress-src");
+        let dest = TempDir::new("progress-dest");
+        let fs = new_fs(&dir);
+        for name in ["a", "b", "c"] {
+            make_file(&fs, fs.root_ino(), name, &[7; 400]);
+        }
+        fs.save().unwrap();
+
+        let mut calls = Vec::new();
+        let copied = fs.export_blocks_with_progress(dest.path(), true, |p| calls.push(p.clone())).unwrap();
+        assert_eq!((copied, calls.len()), (3, 3));
+        let files_done: Vec<u64> = calls.iter().map(|p| p.files_done).collect();
+        assert_eq!(files_done, [1, 2, 3]);
+        let last = calls.last().unwrap();
+        assert_eq!((last.files_total, last.bytes_done), (3, last.bytes_total));
     }
 
-    fn release(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        fh: u64,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        _flush: bool,
-        reply: ReplyEmpty,
-    ) {
-        log_point!(format!("ENTER release(): ino={}, fh={}", ino, fh));
+    #[test]
+    fn content_digest_ignores_layout_but_not_content() {
+        let dir = TempDir::new("digest-src");
+        let copy_dir = TempDir::new("digest-copy");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        // Bloques intercalados con archivos borrados: disposición fragmentada
+        for i in 0..4u8 {
+            make_file(&fs, root, &format!("keep{}", i), &vec![i + 1; 900]);
+            make_file(&fs, root, &format!("gap{}", i), &[0xee; 600]);
+        }
+        for i in 0..4u8 {
+            fs.unlink_entry(root, &format!("gap{}", i)).unwrap();
+        }
+        let ino = fs.lookup_entry(root, "keep3").unwrap().ino;
+        fs.write_data(ino, 900, &[9; 900]).unwrap();
 
-        // Primero intentamos sincronizar metadata si está sucia.
-        if let Err(e) = self.sync_if_dirty() {
-            log_point!(format!("release(): ERROR syncing metadata -> {}", e));
-            reply.error(libc::EIO);
-            log_exit!(format!("EXIT release(): ino={}, fh={} (ERROR)", ino, fh));
-            return;
+        // La copia reescribe cada archivo en bloques contiguos
+        let (copy, _) = crate::convert::convert(&fs, test_config(copy_dir.path())).unwrap();
+        let copied = copy.lookup_entry(copy.root_ino(), "keep3").unwrap();
+        assert_ne!(copied.direct_blocks, fs.inode(ino).unwrap().direct_blocks);
+        assert_eq!(copy.content_digest().unwrap(), fs.content_digest().unwrap());
+
+        copy.write_data(copied.ino, 5, b"x").unwrap();
+        assert_ne!(copy.content_digest().unwrap(), fs.content_digest().unwrap());
+    }
+
+    #[test]
+    fn paranoid_reads_fail_on_a_tampered_block() {
+        let dir = TempDir::new("paranoid");
+        let config = Config { integrity_tree: true, paranoid: true, ..test_config(dir.path()) };
+        let fs = BWFS::new(config.clone()).unwrap();
+        let tampered = make_file(&fs, fs.root_ino(), "tampered", &[1; 700]);
+        let clean = make_file(&fs, fs.root_ino(), "clean", &[2; 700]);
+
+        let block = fs.inode(tampered).unwrap().direct_blocks[1];
+        let outside = BlockStorage::new(&config.storage_path, 64, 64, 64, config.fingerprint).unwrap();
+        let mut data = outside.read_block(block).unwrap();
+        data[0] ^= 0x01;
+        outside.write_block(block, &data).unwrap();
+
+        let error = fs.read_file(tampered).unwrap_err();
+        assert!(error.to_string().contains(&format!("errno {}", libc::EIO)), "{}", error);
+        assert_eq!(fs.read_file(clean).unwrap(), vec![2; 700]);
+    }
+
+    #[test]
+    fn duplicate_entries_are_repaired_on_load() {
+        let dir = TempDir::new("duplicate-entries");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        let old = make_file(&fs, root, "old", b"old data");
+        let x = make_file(&fs, root, "x", b"current");
+        make_file(&fs, root, "y", b"y data");
+        // Lo que dejaba el rename antiguo: el nombre repetido delante del bueno
+        {
+            let mut directories = fs.directories.lock().unwrap();
+            let entries = directories.get_mut(&root).unwrap();
+            entries.insert(2, DirEntry::new(old, "x".to_string(), FileType::RegularFile));
+            entries.insert(2, DirEntry::new(999, "y".to_string(), FileType::RegularFile));
         }
+        fs.save().unwrap();
+        drop(fs);
+
+        let fs = BWFS::load(test_config(dir.path())).unwrap();
+        assert_eq!(fs.lookup_entry(root, "x").unwrap().ino, x);
+        assert_eq!(fs.read_file(fs.lookup_entry(root, "y").unwrap().ino).unwrap(), b"y data");
+        let names: Vec<String> = fs.walk().into_iter().map(|(path, _)| path).collect();
+        assert_eq!(names, ["/old", "/x", "/y"]);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
 
-        let mut open_files = self.open_files.lock().unwrap();
-        if open_files.remove(&fh).is_some() {
-            log_point!(format!(
-                "release(): removed fh={} mapped to ino={}",
-                fh, ino
-            ));
-        } else {
-            log_point!(format!("release(): fh={} not found in open_files", fh));
+    #[test]
+    fn pinned_superblock_survives_a_large_sequential_read() {
+        let dir = TempDir::new("pinned-superblock");
+        let config = Config { cache_max_bytes: 4 * 512, ..test_config(dir.path()) };
+        write_superblock(&config);
+        let fs = BWFS::new(config).unwrap();
+        let storage = fs.storage_handle();
+        let superblock = storage.lock().unwrap().read_block(0).unwrap();
+
+        let data: Vec<u8> = (0..20 * 512u32).map(|i| (i % 253) as u8).collect();
+        let ino = make_file(&fs, fs.root_ino(), "big", &data);
+        storage.lock().unwrap().trim_cache();
+        storage.lock().unwrap().read_block(0).unwrap();
+        assert_eq!(fs.read_file(ino).unwrap(), data);
+
+        // Sin la imagen en disco, el bloque 0 solo puede venir de la caché
+        let path = storage.lock().unwrap().get_block_path(0);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(storage.lock().unwrap().read_block(0).unwrap(), superblock);
+        let first = fs.inode(ino).unwrap().direct_blocks[0];
+        std::fs::remove_file(storage.lock().unwrap().get_block_path(first)).unwrap();
+        assert_ne!(storage.lock().unwrap().read_block(first).unwrap(), data[..512]);
+    }
+
+    #[test]
+    fn block_zero_pointer_is_relocated() {
+        let dir = TempDir::new("relocate-zero");
+        let fs = new_fs(&dir);
+        let data: Vec<u8> = (0..1024u32).map(|i| (i % 241) as u8 + 1).collect();
+        let ino = make_file(&fs, fs.root_ino(), "f", &data);
+        // Imagen antigua: el primer bloque del archivo vivía en el bloque 0
+        let first = fs.inode(ino).unwrap().direct_blocks[0];
+        fs.storage.lock().unwrap().write_block(0, &data[..512]).unwrap();
+        fs.inodes.lock().unwrap().get_mut(&ino).unwrap().direct_blocks[0] = 0;
+        fs.free_block(first);
+        fs.save().unwrap();
+        drop(fs);
+
+        let fs = BWFS::load(test_config(dir.path())).unwrap();
+        assert_eq!(fs.relocate_reserved_blocks().unwrap(), 1);
+        assert_ne!(fs.inode(ino).unwrap().direct_blocks[0], 0);
+        assert_eq!(fs.read_file(ino).unwrap(), data);
+        assert_eq!(fs.relocate_reserved_blocks().unwrap(), 0);
+        drop(fs);
+
+        let fs = BWFS::load(test_config(dir.path())).unwrap();
+        assert_ne!(fs.inode(ino).unwrap().direct_blocks[0], 0);
+        assert_eq!(fs.read_file(ino).unwrap(), data);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn size_hint_reserves_a_contiguous_run() {
+        let dir = TempDir::new("size-hint");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        // Huecos de un bloque al principio del disco
+        for i in 0..6 {
+            make_file(&fs, root, &format!("keep{}", i), &[1; 300]);
+            make_file(&fs, root, &format!("gap{}", i), &[2; 300]);
+        }
+        for i in 0..6 {
+            fs.unlink_entry(root, &format!("gap{}", i)).unwrap();
         }
 
-        reply.ok();
-        log_exit!(format!("EXIT release(): ino={}, fh={}", ino, fh));
+        let ino = fs.create_with_size_hint(root, "hinted", 0o644, 5 * 512).unwrap();
+        let data: Vec<u8> = (0..5 * 512u32).map(|i| (i % 251) as u8).collect();
+        fs.write_data(ino, 0, &data).unwrap();
+
+        let blocks = &fs.inode(ino).unwrap().direct_blocks[..5];
+        assert!(blocks.windows(2).all(|pair| pair[1] == pair[0] + 1), "{:?}", blocks);
+        assert_eq!(fs.read_file(ino).unwrap(), data);
+
+        // Sin pista, el mismo archivo reutiliza los huecos sueltos
+        let plain = make_file(&fs, root, "plain", &data);
+        let blocks = &fs.inode(plain).unwrap().direct_blocks[..5];
+        assert!(!blocks.windows(2).all(|pair| pair[1] == pair[0] + 1), "{:?}", blocks);
     }
 
-    fn releasedir(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        fh: u64,
-        _flags: i32,
-        reply: ReplyEmpty,
-    ) {
-        log_point!(format!("ENTER releasedir(): ino={}, fh={}", ino, fh));
+    #[test]
+    fn size_hint_past_the_direct_blocks_reserves_the_whole_run() {
+        let dir = TempDir::new("size-hint-indirect");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        let free_before = fs.fs_stats().free_blocks;
+
+        let ino = fs.create_with_size_hint(root, "big", 0o644, 16 * 512).unwrap();
+        // 16 bloques de datos y el indirecto
+        assert_eq!(fs.fs_stats().free_blocks, free_before - 17);
+        let blocks: Vec<u32> = {
+            let inodes = fs.inodes.lock().unwrap();
+            let storage = fs.storage.lock().unwrap();
+            (0..16).map(|idx| fs.file_block(&inodes[&ino], &storage, idx).unwrap().unwrap()).collect()
+        };
+        assert!(blocks.windows(2).all(|pair| pair[1] == pair[0] + 1), "{:?}", blocks);
+
+        let data: Vec<u8> = (0..16 * 512u32).map(|i| (i % 251) as u8).collect();
+        fs.write_data(ino, 0, &data).unwrap();
+        assert_eq!(fs.fs_stats().free_blocks, free_before - 17);
+        assert_eq!(fs.read_file(ino).unwrap(), data);
+
+        // Una pista que no cabe crea el archivo sin reservar nada
+        let free_before = fs.fs_stats().free_blocks;
+        let huge = fs.create_with_size_hint(root, "huge", 0o644, 100 * 512).unwrap();
+        assert_eq!(fs.fs_stats().free_blocks, free_before);
+        assert_eq!(fs.inode(huge).unwrap().indirect_block, u32::MAX);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
 
-        // También aquí sincronizamos si hay metadata sucia, para cubrir cambios
-        // que sólo afecten directorios (mkdir/rename/rmdir, etc.).
-        if let Err(e) = self.sync_if_dirty() {
-            log_point!(format!("releasedir(): ERROR syncing metadata -> {}", e));
-            reply.error(libc::EIO);
-            log_exit!(format!("EXIT releasedir(): ino={}, fh={} (ERROR)", ino, fh));
-            return;
+    #[test]
+    fn set_times_follows_utime_omit_and_now() {
+        let dir = TempDir::new("utimens");
+        let fs = new_fs(&dir);
+        let ino = make_file(&fs, fs.root_ino(), "f", b"data");
+        let past = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        fs.set_times(ino, Some(TimeOrNow::SpecificTime(past(1000))), Some(TimeOrNow::SpecificTime(past(2000))))
+            .unwrap();
+        let before = fs.inode(ino).unwrap();
+
+        // Solo atime; mtime omitido
+        fs.set_times(ino, Some(TimeOrNow::SpecificTime(past(3000))), None).unwrap();
+        let inode = fs.inode(ino).unwrap();
+        assert_eq!((inode.atime, inode.mtime), (past(3000), past(2000)));
+        assert!(inode.ctime >= before.ctime);
+
+        // mtime a "ahora"; atime omitido
+        let start = SystemTime::now();
+        fs.set_times(ino, None, Some(TimeOrNow::Now)).unwrap();
+        let inode = fs.inode(ino).unwrap();
+        assert_eq!(inode.atime, past(3000));
+        assert!(inode.mtime >= start && inode.mtime == inode.ctime);
+
+        // Ambos omitidos: nada cambia, tampoco ctime
+        fs.set_times(ino, None, None).unwrap();
+        let after = fs.inode(ino).unwrap();
+        assert_eq!((after.atime, after.mtime, after.ctime), (inode.atime, inode.mtime, inode.ctime));
+    }
+
+    #[test]
+    fn memory_limit_trims_the_cache_under_the_ceiling() {
+        let dir = TempDir::new("memory-limit");
+        let mut fs = BWFS::new(Config { cache_max_bytes: 64 * 512, ..test_config(dir.path()) }).unwrap();
+        let ino = make_file(&fs, fs.root_ino(), "big", &[6; 30 * 512]);
+        fs.read_file(ino).unwrap();
+        let usage = fs.memory_usage();
+        let ceiling = usage.metadata_bytes + 4 * 512;
+        assert!(usage.total_bytes > ceiling, "{:?}", usage);
+
+        // Se comprueba cada MEMORY_CHECK_INTERVAL cambios
+        fs.config.max_memory_bytes = ceiling;
+        for _ in 0..MEMORY_CHECK_INTERVAL {
+            fs.set_times(ino, Some(TimeOrNow::Now), None).unwrap();
         }
+        let usage = fs.memory_usage();
+        assert!(usage.total_bytes <= ceiling, "{:?} over {}", usage, ceiling);
+        assert_eq!(fs.read_file(ino).unwrap(), vec![6; 30 * 512]);
+    }
 
-        let mut open_files = self.open_files.lock().unwrap();
-        if open_files.remove(&fh).is_some() {
-            log_point!(format!(
-                "releasedir(): removed fh={} for directory ino={}",
-                fh, ino
-            ));
-        } else {
-            log_point!(format!("releasedir(): fh={} not found in open_files", fh));
+    #[test]
+    fn open_handles_lists_open_files_until_they_are_released() {
+        let dir = TempDir::new("open-handles");
+        let fs = new_fs(&dir);
+        let a = make_file(&fs, fs.root_ino(), "a", b"a");
+        let b = make_file(&fs, fs.root_ino(), "b", b"b");
+        let fh_a = fs.open_file(a, libc::O_RDONLY).unwrap();
+        let fh_b = fs.open_file(b, libc::O_RDWR).unwrap();
+
+        let listed: Vec<(u64, u64, Option<String>)> =
+            fs.open_handles().into_iter().map(|h| (h.fh, h.ino, h.path)).collect();
+        assert_eq!(
+            listed,
+            vec![(fh_a, a, Some("/a".to_string())), (fh_b, b, Some("/b".to_string()))]
+        );
+
+        fs.release_handle(fh_a).unwrap();
+        assert_eq!(fs.open_handles().iter().map(|h| h.fh).collect::<Vec<_>>(), vec![fh_b]);
+        fs.release_handle(fh_b).unwrap();
+        assert!(fs.open_handles().is_empty());
+    }
+
+    #[test]
+    fn clone_shares_blocks_until_written() {
+        let dir = TempDir::new("clone");
+        let fs = new_fs(&dir);
+        let data: Vec<u8> = (0..3 * 512).map(|i| (i / 512) as u8 + 1).collect();
+        let src = make_file(&fs, fs.root_ino(), "src", &data);
+        let copy = fs.clone_file(src, fs.root_ino(), "copy").unwrap();
+        let free_after_clone = fs.fs_stats().free_blocks;
+
+        fs.write_data(copy, 512, &[9; 512]).unwrap();
+        assert_eq!(fs.read_file(src).unwrap(), data);
+        let mut expected = data.clone();
+        expected[512..1024].fill(9);
+        assert_eq!(fs.read_file(copy).unwrap(), expected);
+        // Sólo el bloque escrito se copió
+        assert_eq!(fs.fs_stats().free_blocks, free_after_clone - 1);
+
+        let blocks = |ino: u64| -> Vec<Option<u32>> {
+            let inodes = fs.inodes.lock().unwrap();
+            let storage = fs.storage.lock().unwrap();
+            (0..3).map(|idx| fs.file_block(&inodes[&ino], &storage, idx).unwrap()).collect()
+        };
+        let (src_blocks, copy_blocks) = (blocks(src), blocks(copy));
+        assert_eq!(src_blocks[0], copy_blocks[0]);
+        assert_ne!(src_blocks[1], copy_blocks[1]);
+        assert_eq!(src_blocks[2], copy_blocks[2]);
+        assert_no_dangling_pointers(&fs);
+    }
+
+    #[test]
+    fn full_disk_on_save_is_enospc_and_keeps_the_state() {
+        let dir = TempDir::new("full-disk");
+        // Con el área de metadata el checkpoint pasa por las imágenes de bloque
+        let config = Config { metadata_blocks: 2, metadata_cache: false, ..test_config(dir.path()) };
+        let fs = BWFS::new(config.clone()).unwrap();
+        let a = make_file(&fs, fs.root_ino(), "a", &[1; 1024]);
+        fs.save().unwrap();
+        let b = make_file(&fs, fs.root_ino(), "b", &[2; 600]);
+        let fh = fs.open_file(b, libc::O_RDWR).unwrap();
+
+        fs.inject_faults(Some(crate::storage::FaultPlan { no_space: true, ..Default::default() }));
+        assert_eq!(fs.release_handle(fh), Err(libc::ENOSPC));
+        assert!(*fs.dirty.lock().unwrap());
+        fs.inject_faults(None);
+        // Hasta que un checkpoint funcione no se asignan bloques nuevos
+        assert_eq!(fs.write_data(b, 1024, &[3; 512]), Err(libc::ENOSPC));
+        assert_eq!(fs.read_file(b).unwrap(), vec![2; 600]);
+
+        fs.release_handle(fh).unwrap();
+        assert!(!*fs.dirty.lock().unwrap());
+        fs.write_data(b, 600, &[3; 512]).unwrap();
+        fs.save().unwrap();
+        drop(fs);
+
+        let fs = BWFS::load(config).unwrap();
+        assert_eq!(fs.read_file(a).unwrap(), vec![1; 1024]);
+        let mut expected = vec![2; 600];
+        expected.extend_from_slice(&[3; 512]);
+        assert_eq!(fs.read_file(b).unwrap(), expected);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn lookups_resolve_against_a_custom_root_inode() {
+        let dir = TempDir::new("custom-root");
+        let mut config = Config { root_ino: 1000, first_ino: 2000, ..test_config(dir.path()) };
+        config.normalize().unwrap();
+        let mut colliding = Config { root_ino: 2005, ..config.clone() };
+        assert!(colliding.normalize().is_err());
+
+        let fs = BWFS::new(config.clone()).unwrap();
+        assert_eq!(fs.root_ino(), 1000);
+        let sub = fs.create_dir(1000, "sub", 0o755, 0, 0).unwrap();
+        let file = make_file(&fs, sub.ino, "f", b"data");
+        assert!(sub.ino >= 2000 && file >= 2000);
+        assert_eq!(fs.lookup_entry(1000, "sub").unwrap().ino, sub.ino);
+        assert_eq!(fs.lookup_entry(sub.ino, "..").unwrap().ino, 1000);
+        // El kernel sigue viendo la raíz como 1
+        assert_eq!(fs.kernel_ino(1000), FUSE_ROOT_INO);
+        assert_eq!(fs.internal_ino(FUSE_ROOT_INO), 1000);
+        fs.save().unwrap();
+        drop(fs);
+
+        let fs = BWFS::load(config).unwrap();
+        assert_eq!(fs.lookup_entry(fs.root_ino(), "sub").unwrap().ino, sub.ino);
+        assert_eq!(fs.lookup_entry(sub.ino, "f").unwrap().ino, file);
+    }
+
+    #[test]
+    fn release_debounce_coalesces_saves() {
+        let dir = TempDir::new("debounce");
+        let fs = BWFS::new(Config { save_debounce_ms: 60_000, ..test_config(dir.path()) }).unwrap();
+        let saves = || *fs.checkpoint_seq.lock().unwrap();
+        let before = saves();
+        for i in 0..20 {
+            let ino = make_file(&fs, fs.root_ino(), &format!("f{}", i), b"x");
+            let fh = fs.open_file(ino, libc::O_RDONLY).unwrap();
+            fs.release_handle(fh).unwrap();
         }
+        assert_eq!(saves(), before + 1);
+        assert!(*fs.dirty.lock().unwrap());
 
-        reply.ok();
-        log_exit!(format!("EXIT releasedir(): ino={}, fh={}", ino, fh));
+        // fsync no espera a la ventana
+        fs.sync_if_dirty().unwrap();
+        assert_eq!(saves(), before + 2);
+        assert!(!*fs.dirty.lock().unwrap());
+    }
+
+    #[test]
+    fn link_names_an_existing_inode() {
+        let dir = TempDir::new("link");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        let a = make_file(&fs, root, "a", &[4; 700]);
+        assert_eq!(fs.link_inode(a, root, "b").unwrap().nlink, 2);
+        assert_eq!(fs.lookup_entry(root, "b").unwrap().ino, a);
+        assert_eq!(fs.read_file(fs.lookup_entry(root, "b").unwrap().ino).unwrap(), vec![4; 700]);
+        let sub = fs.create_dir(root, "sub", 0o755, 0, 0).unwrap();
+        assert_eq!(fs.link_inode(sub.ino, root, "sub2").unwrap_err(), libc::EPERM);
+        fs.save().unwrap();
+        drop(fs);
+
+        let fs = BWFS::load(test_config(dir.path())).unwrap();
+        assert_eq!(fs.inode(a).unwrap().nlink, 2);
+        assert_eq!(fs.lookup_entry(root, "b").unwrap().ino, a);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rename_over_a_file_frees_the_replaced_inode() {
+        let dir = TempDir::new("rename-over");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        let src = make_file(&fs, root, "src", &[1; 1024]);
+        let old = make_file(&fs, root, "dst", &[2; 3 * 512]);
+        let free_before = fs.fs_stats().free_blocks;
+
+        fs.rename_entry(root, "src", root, "dst").unwrap();
+        assert_eq!(fs.lookup_entry(root, "dst").unwrap().ino, src);
+        assert_eq!(fs.lookup_entry(root, "src").unwrap_err(), libc::ENOENT);
+        assert!(fs.inode(old).is_none());
+        assert_eq!(fs.fs_stats().free_blocks, free_before + 3);
+        assert_eq!(fs.read_file(src).unwrap(), vec![1; 1024]);
+        let names: Vec<String> = fs.dir_entries(root, 0).unwrap().into_iter().map(|e| e.name).collect();
+        assert_eq!(names.iter().filter(|n| *n == "dst").count(), 1);
+
+        let full = fs.create_dir(root, "full", 0o755, 0, 0).unwrap();
+        make_file(&fs, full.ino, "x", b"x");
+        let empty = fs.create_dir(root, "empty", 0o755, 0, 0).unwrap();
+        assert_eq!(fs.rename_entry(root, "empty", root, "full"), Err(libc::ENOTEMPTY));
+        assert_eq!(fs.rename_entry(root, "dst", root, "empty"), Err(libc::EISDIR));
+        assert_eq!(fs.rename_entry(root, "empty", root, "dst"), Err(libc::ENOTDIR));
+        assert!(fs.inode(empty.ino).is_some());
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn partial_metadata_write_keeps_the_previous_checkpoint() {
+        let dir = TempDir::new("partial-metadata");
+        let fs = new_fs(&dir);
+        let a = make_file(&fs, fs.root_ino(), "a", &[1; 700]);
+        fs.save().unwrap();
+        let metadata_path = dir.path().join(fs.config().metadata_format.file_name());
+        let tmp_path = dir.path().join(format!("{}.tmp", fs.config().metadata_format.file_name()));
+        let good = std::fs::read(&metadata_path).unwrap();
+
+        // Crash a mitad de escribir el temporal del siguiente checkpoint
+        make_file(&fs, fs.root_ino(), "b", b"b");
+        drop(fs);
+        std::fs::write(&tmp_path, &good[..good.len() / 2]).unwrap();
+        let fs = BWFS::load(test_config(dir.path())).unwrap();
+        assert_eq!(fs.read_file(a).unwrap(), vec![1; 700]);
+        assert_eq!(fs.lookup_entry(fs.root_ino(), "b").unwrap_err(), libc::ENOENT);
+        drop(fs);
+
+        // Crash entre el temporal completo y el rename
+        std::fs::remove_file(&tmp_path).unwrap();
+        std::fs::rename(&metadata_path, &tmp_path).unwrap();
+        let fs = BWFS::load(test_config(dir.path())).unwrap();
+        assert_eq!(fs.read_file(a).unwrap(), vec![1; 700]);
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn full_inode_table_makes_create_fail() {
+        let dir = TempDir::new("inode-limit");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        // La raíz queda fuera del rango asignable (first_ino..)
+        for i in 0..fs.config().total_inodes {
+            fs.create_node(root, &format!("f{}", i), FileType::RegularFile, 0o644, 0, 0, 0).unwrap();
+        }
+
+        // create y mkdir comparten allocate_ino
+        assert_eq!(fs.allocate_ino(), None);
+        assert_eq!(
+            fs.create_node(root, "extra", FileType::RegularFile, 0o644, 0, 0, 0).unwrap_err(),
+            libc::ENOSPC
+        );
+        assert_eq!(fs.create_dir(root, "extra", 0o755, 0, 0).unwrap_err(), libc::ENOSPC);
+        assert_eq!(fs.fs_stats().free_inodes, 0);
+
+        fs.unlink_entry(root, "f1").unwrap();
+        fs.create_dir(root, "extra", 0o755, 0, 0).unwrap();
+        assert_eq!(fs.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn char_device_rdev_survives_a_remount() {
+        let dir = TempDir::new("rdev");
+        let fs = new_fs(&dir);
+        let rdev = libc::makedev(4, 64) as u32;
+        let dev = fs
+            .create_node(fs.root_ino(), "tty", FileType::CharDevice, 0o620, rdev, 0, 0)
+            .unwrap();
+        assert_eq!(fs.inode_to_attr(&dev).rdev, rdev);
+        fs.save().unwrap();
+        drop(fs);
+
+        let fs = BWFS::load(test_config(dir.path())).unwrap();
+        let attr = fs.inode_to_attr(&fs.lookup_entry(fs.root_ino(), "tty").unwrap());
+        assert_eq!(attr.kind, FuseFileType::CharDevice);
+        assert_eq!((libc::major(attr.rdev as u64), libc::minor(attr.rdev as u64)), (4, 64));
+    }
+
+    #[test]
+    fn moving_a_directory_never_drops_the_old_parent_below_two_links() {
+        let dir = TempDir::new("rename-dir-nlink");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        let from = fs.create_dir(root, "from", 0o755, 0, 0).unwrap().ino;
+        let to = fs.create_dir(root, "to", 0o755, 0, 0).unwrap().ino;
+        fs.create_dir(from, "sub", 0o755, 0, 0).unwrap();
+        assert_eq!(fs.inode(from).unwrap().nlink, 3);
+
+        fs.rename_entry(from, "sub", to, "sub").unwrap();
+        assert_eq!(fs.inode(from).unwrap().nlink, 2);
+        assert_eq!(fs.inode(to).unwrap().nlink, 3);
+
+        // nlink corrupto que no cuenta el subdirectorio: se queda en 2
+        fs.inodes.lock().unwrap().get_mut(&to).unwrap().nlink = 2;
+        fs.rename_entry(to, "sub", from, "sub").unwrap();
+        assert_eq!(fs.inode(to).unwrap().nlink, 2);
+    }
+
+    #[test]
+    fn unlinking_twice_leaves_a_sane_nlink() {
+        let dir = TempDir::new("double-unlink");
+        let fs = new_fs(&dir);
+        let root = fs.root_ino();
+        let ino = make_file(&fs, root, "a", &[7; 600]);
+        fs.link_inode(ino, root, "b").unwrap();
+
+        fs.unlink_entry(root, "a").unwrap();
+        assert_eq!(fs.unlink_entry(root, "a"), Err(libc::ENOENT));
+        assert_eq!(fs.inode(ino).unwrap().nlink, 1);
+
+        // Metadata corrupta: el último nombre con nlink ya en 0
+        fs.inodes.lock().unwrap().get_mut(&ino).unwrap().nlink = 0;
+        fs.unlink_entry(root, "b").unwrap();
+        assert!(fs.inode(ino).is_none());
+
+        // rmdir con un padre cuyo nlink no cuenta el subdirectorio
+        let sub = fs.create_dir(root, "sub", 0o755, 0, 0).unwrap();
+        fs.inodes.lock().unwrap().get_mut(&root).unwrap().nlink = 2;
+        fs.remove_dir(root, "sub").unwrap();
+        assert!(fs.inode(sub.ino).is_none());
+        assert_eq!(fs.inode(root).unwrap().nlink, 2);
+    }
+
+    #[test]
+    fn read_spans_the_direct_to_indirect_boundary() {
+        let dir = TempDir::new("indirect-read");
+        let fs = new_fs(&dir);
+        let data: Vec<u8> = (0..14 * 512).map(|i| (i / 512) as u8 + 1).collect();
+        let ino = make_file(&fs, fs.root_ino(), "f", &data);
+        let inode = fs.inode(ino).unwrap();
+        assert_ne!(inode.indirect_block, u32::MAX);
+
+        let storage = fs.storage.lock().unwrap();
+        // Últimos bytes del índice 11 y primeros del 12
+        let start = 11 * 512 + 500;
+        let read = fs.read_range(&inode, &storage, start as u64, 24).unwrap();
+        assert_eq!(read, data[start..start + 24]);
+        assert_eq!(&read[..12], &[12; 12]);
+        assert_eq!(&read[12..], &[13; 12]);
+        // Desde el bloque 11 hasta el final, pidiendo de más
+        let read = fs.read_range(&inode, &storage, 11 * 512, 4096).unwrap();
+        assert_eq!(read, data[11 * 512..]);
+    }
+
+    /// Inode at `path`, resolved one component at a time like the kernel does
+    fn resolve(fs: &BWFS, path: &str) -> u64 {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .fold(fs.root_ino(), |parent, name| fs.lookup_entry(parent, name).unwrap().ino)
+    }
+
+    /// A named step of the crash test workload
+    type CrashOp = (&'static str, fn(&BWFS));
+
+    /// Operations replayed by the crash test, in order; the checkpoints
+    /// are the saves `release` does when the metadata is dirty
+    fn crash_workload() -> Vec<CrashOp> {
+        vec![
+            ("mkdir /docs", |fs| {
+                fs.create_dir(fs.root_ino(), "docs", 0o755, 0, 0).unwrap();
+            }),
+            ("write /docs/a", |fs| {
+                make_file(fs, resolve(fs, "/docs"), "a", &[1; 3 * 512]);
+            }),
+            ("release /docs/a", |fs| {
+                let fh = fs.open_file(resolve(fs, "/docs/a"), libc::O_RDONLY).unwrap();
+                fs.release_handle(fh).unwrap();
+            }),
+            ("grow /docs/a past the direct blocks", |fs| {
+                fs.write_data(resolve(fs, "/docs/a"), 3 * 512, &[2; 11 * 512]).unwrap();
+            }),
+            ("link /docs/a to /a2", |fs| {
+                fs.link_inode(resolve(fs, "/docs/a"), fs.root_ino(), "a2").unwrap();
+            }),
+            ("rename /docs/a to /docs/b", |fs| {
+                let docs = resolve(fs, "/docs");
+                fs.rename_entry(docs, "a", docs, "b").unwrap();
+            }),
+            ("write and truncate /c", |fs| {
+                let ino = make_file(fs, fs.root_ino(), "c", &[3; 1200]);
+                fs.set_file_size(ino, 100).unwrap();
+            }),
+            ("release /c", |fs| {
+                let fh = fs.open_file(resolve(fs, "/c"), libc::O_RDONLY).unwrap();
+                fs.release_handle(fh).unwrap();
+            }),
+            ("unlink /a2", |fs| fs.unlink_entry(fs.root_ino(), "a2").unwrap()),
+            ("mkdir and rmdir /tmp", |fs| {
+                fs.create_dir(fs.root_ino(), "tmp", 0o755, 0, 0).unwrap();
+                fs.remove_dir(fs.root_ino(), "tmp").unwrap();
+            }),
+            ("unlink /docs/b", |fs| fs.unlink_entry(resolve(fs, "/docs"), "b").unwrap()),
+            ("overwrite /c", |fs| {
+                fs.write_data(resolve(fs, "/c"), 0, &[4; 900]).unwrap();
+            }),
+        ]
+    }
+
+    /// Where the crash test drops the filesystem
+    #[derive(Debug, Clone, Copy)]
+    enum CrashPoint {
+        /// After the first N operations of the workload
+        AfterOps(usize),
+        /// In the checkpoint started by operation N, at its Kth block write
+        DuringCheckpoint(usize, u64),
+    }
+
+    /// A filesystem reloaded after a crash may have lost recent changes,
+    /// but it must be consistent and every file in it must be readable
+    fn assert_recovered(config: &Config, crash: CrashPoint) {
+        let fs = BWFS::load(config.clone()).unwrap_or_else(|e| panic!("{:?}: load failed: {:#}", crash, e));
+        let problems = fs.check_consistency();
+        assert!(problems.is_empty(), "{:?}: {:?}", crash, problems);
+        assert_no_dangling_pointers(&fs);
+        for (path, inode) in fs.walk() {
+            if inode.is_dir() {
+                fs.dir_entries(inode.ino, 0).unwrap_or_else(|e| panic!("{:?}: {} (errno {})", crash, path, e));
+            } else {
+                let data = fs.read_file(inode.ino).unwrap_or_else(|e| panic!("{:?}: {}: {:#}", crash, path, e));
+                assert_eq!(data.len() as u64, inode.size, "{:?}: {}", crash, path);
+            }
+        }
+    }
+
+    /// Run the workload up to `crash`, drop the filesystem without a clean
+    /// shutdown and check what a reload finds
+    fn crash_and_reload(config: &Config, crash: CrashPoint) {
+        let _ = std::fs::remove_dir_all(&config.storage_path);
+        std::fs::create_dir_all(&config.storage_path).unwrap();
+        let fs = BWFS::new(config.clone()).unwrap();
+        fs.save().unwrap();
+
+        let workload = crash_workload();
+        match crash {
+            CrashPoint::AfterOps(n) => {
+                for (_, op) in &workload[..n] {
+                    op(&fs);
+                }
+            }
+            CrashPoint::DuringCheckpoint(n, write) => {
+                for (_, op) in &workload[..n] {
+                    op(&fs);
+                }
+                fs.inject_faults(Some(crate::storage::FaultPlan {
+                    fail_write: Some(write),
+                    ..Default::default()
+                }));
+                // El fallo corta el checkpoint donde lo haría un crash
+                let _ = fs.save();
+            }
+        }
+        drop(fs);
+        assert_recovered(config, crash);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn crash_and_reload_at_every_point_stays_consistent() {
+        let dir = TempDir::new("crash-reload");
+        let base = test_config(&dir.path().join("fs"));
+        let configs = [
+            ("metadata file", base.clone()),
+            ("journal", Config { journal: true, ..base.clone() }),
+            ("metadata area", Config { metadata_blocks: 2, metadata_cache: false, ..base.clone() }),
+            ("area and file", Config { metadata_blocks: 2, ..base.clone() }),
+        ];
+        let names: Vec<&str> = crash_workload().iter().map(|(name, _)| *name).collect();
+        let ops = names.len();
+
+        for (label, config) in &configs {
+            for n in 0..=ops {
+                log::info!("crash test ({}): after {:?}", label, &names[..n].last());
+                crash_and_reload(config, CrashPoint::AfterOps(n));
+            }
+            if config.metadata_blocks > 0 {
+                // Escrituras del área y del registro en el superblock
+                for n in [3, 8, ops] {
+                    for write in 1..=4 {
+                        crash_and_reload(config, CrashPoint::DuringCheckpoint(n, write));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn geometry_that_disagrees_with_mkfs_refuses_to_load() {
+        let dir = TempDir::new("geometry");
+        let fs = new_fs(&dir);
+        make_file(&fs, fs.root_ino(), "f", &[1; 1024]);
+        fs.save().unwrap();
+        drop(fs);
+        let load_error = |config: Config| match BWFS::load(config) {
+            Ok(_) => panic!("loaded with a geometry that does not match"),
+            Err(e) => e.to_string(),
+        };
+
+        assert_eq!(
+            load_error(Config { total_blocks: 32, ..test_config(dir.path()) }),
+            "config says total_blocks = 32 but the filesystem metadata tracks 64 blocks; \
+             restore the value used by mkfs.bwfs"
+        );
+        assert_eq!(
+            load_error(Config { total_inodes: 16, ..test_config(dir.path()) }),
+            "config says total_inodes = 16 but the filesystem metadata tracks 32 inodes; \
+             restore the value used by mkfs.bwfs"
+        );
+
+        // Una imagen fuera de rango aunque la metadata coincida
+        let stray = dir.path().join("block_00000070.png");
+        std::fs::copy(dir.path().join("block_00000001.png"), &stray).unwrap();
+        assert_eq!(
+            load_error(test_config(dir.path())),
+            "storage holds block_00000070.png but total_blocks = 64; \
+             the configuration does not match this filesystem"
+        );
+        std::fs::remove_file(&stray).unwrap();
+        BWFS::load(test_config(dir.path())).unwrap();
+    }
+}
@@ -1,12 +1,37 @@
+use crate::storage::BlockStorage;
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::time::SystemTime;
 
+/// Number of block pointers stored directly in the inode
+pub const DIRECT_BLOCKS: u32 = 12;
+
+/// Bytes of one pointer inside an indirect block
+pub(crate) const POINTER_BYTES: usize = 4;
+
+/// Where the pointer for a file block index is stored
+enum BlockSlot {
+    Direct,
+    /// Entry of the single indirect block
+    Indirect(usize),
+    /// (entry of the double indirect block, entry of the block it points to)
+    DoubleIndirect(usize, usize),
+}
+
 /// File types supported by BWFS
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FileType {
     RegularFile,
     Directory,
     Symlink,
+    /// Character device; its device number is in `rdev`
+    CharDevice,
+    /// Block device; its device number is in `rdev`
+    BlockDevice,
+    /// FIFO (named pipe)
+    NamedPipe,
+    Socket,
 }
 
 /// INode structure for BWFS
@@ -50,6 +75,31 @@ pub struct INode {
     
     /// Double indirect block pointer
     pub double_indirect_block: u32,
+    
+    /// Generation number; differs between inodes that share a number over
+    /// time so stale handles to a removed inode can be told apart
+    #[serde(default)]
+    pub generation: u64,
+    
+    /// Blocks this inode points to, data and indirect, counted once per
+    /// pointer (holes excluded). Kept up to date by the block mapping
+    /// methods and recomputed on load.
+    #[serde(default)]
+    pub allocated_blocks: u32,
+    
+    /// Contents of a small file (or symlink target) kept in the inode
+    /// instead of a block; empty when the data lives in blocks
+    #[serde(default)]
+    pub inline_data: Vec<u8>,
+    
+    /// Extended attributes (`user.*`, `security.*`, ...) by full name
+    #[serde(default)]
+    pub xattrs: HashMap<String, Vec<u8>>,
+    
+    /// Device number of a character or block device, as `makedev` packs
+    /// major and minor; 0 for every other type
+    #[serde(default)]
+    pub rdev: u32,
 }
 
 impl INode {
@@ -71,6 +121,11 @@ pub fn new(ino: u64, file_type: FileType, mode: u16, uid: u32, gid: u32) -> Self
             direct_blocks: [u32::MAX; 12],
             indirect_block: u32::MAX,
             double_indirect_block: u32::MAX,
+            generation: 0,
+            allocated_blocks: 0,
+            inline_data: Vec::new(),
+            xattrs: HashMap::new(),
+            rdev: 0,
         }
     }
     
@@ -84,9 +139,20 @@ pub fn is_file(&self) -> bool {
         self.file_type == FileType::RegularFile
     }
     
-    /// Get block number for a given file offset
+    /// Check if this is a character or block device
+    pub fn is_device(&self) -> bool {
+        matches!(self.file_type, FileType::CharDevice | FileType::BlockDevice)
+    }
+    
+    /// Check if the data is stored inline in the inode
+    pub fn is_inline(&self) -> bool {
+        !self.inline_data.is_empty()
+    }
+    
+    /// Get block number for a given file offset. Only direct blocks: use
+    /// `get_block_number_with_storage` for indices past `DIRECT_BLOCKS`.
     pub fn get_block_number(&self, block_index: u32) -> Option<u32> {
-        if block_index < 12 {
+        if block_index < DIRECT_BLOCKS {
             let block = self.direct_blocks[block_index as usize];
             if block != u32::MAX {
                 Some(block)
@@ -94,21 +160,265 @@ pub fn get_block_number(&self, block_index: u32) -> Option<u32> {
                 None
             }
         } else {
-            // TODO: Implement indirect block logic
             None
         }
     }
     
-    /// Set block number for a given file offset
+    /// Set block number for a given file offset. Only direct blocks: use
+    /// `set_block_number_with_storage` for indices past `DIRECT_BLOCKS`.
     pub fn set_block_number(&mut self, block_index: u32, block_num: u32) -> bool {
-        if block_index < 12 {
-            self.direct_blocks[block_index as usize] = block_num;
+        if block_index < DIRECT_BLOCKS {
+            let slot = &mut self.direct_blocks[block_index as usize];
+            match (*slot == u32::MAX, block_num == u32::MAX) {
+                (true, false) => self.allocated_blocks += 1,
+                (false, true) => self.allocated_blocks = self.allocated_blocks.saturating_sub(1),
+                _ => {}
+            }
+            *slot = block_num;
             true
         } else {
-            // TODO: Implement indirect block logic
             false
         }
     }
+    
+    /// Largest number of blocks a file can map with blocks of
+    /// `bytes_per_block` bytes (direct + single + double indirect)
+    pub fn max_blocks(bytes_per_block: usize) -> u64 {
+        let per_block = (bytes_per_block / POINTER_BYTES) as u64;
+        DIRECT_BLOCKS as u64 + per_block + per_block * per_block
+    }
+    
+    /// Locate the pointer for `block_index`; `None` past the double
+    /// indirect range
+    fn slot(block_index: u32, storage: &BlockStorage) -> Option<BlockSlot> {
+        let per_block = (storage.bytes_per_block() / POINTER_BYTES) as u64;
+        let index = block_index as u64;
+        if index < DIRECT_BLOCKS as u64 {
+            return Some(BlockSlot::Direct);
+        }
+        let index = index - DIRECT_BLOCKS as u64;
+        if index < per_block {
+            return Some(BlockSlot::Indirect(index as usize));
+        }
+        let index = index - per_block;
+        if index < per_block * per_block {
+            return Some(BlockSlot::DoubleIndirect(
+                (index / per_block) as usize,
+                (index % per_block) as usize,
+            ));
+        }
+        None
+    }
+    
+    /// Get block number for a given file offset, reading the indirect
+    /// blocks from `storage` when needed. Unallocated slots (including
+    /// those under a missing indirect block) are `None`.
+    pub fn get_block_number_with_storage(
+        &self,
+        block_index: u32,
+        storage: &BlockStorage,
+    ) -> Result<Option<u32>> {
+        match Self::slot(block_index, storage) {
+            None => Ok(None),
+            Some(BlockSlot::Direct) => Ok(self.get_block_number(block_index)),
+            Some(BlockSlot::Indirect(entry)) => read_pointer(storage, self.indirect_block, entry),
+            Some(BlockSlot::DoubleIndirect(outer, inner)) => {
+                match read_pointer(storage, self.double_indirect_block, outer)? {
+                    Some(level2) => read_pointer(storage, level2, inner),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+    
+    /// Set block number for a given file offset, storing it in an indirect
+    /// block when needed. Missing indirect blocks are taken from `allocate`
+    /// (they start with every slot empty); clearing a slot (`u32::MAX`)
+    /// never allocates. Returns `false` if the index is beyond
+    /// `max_blocks`. Fails with ENOSPC if `allocate` runs out.
+    pub fn set_block_number_with_storage(
+        &mut self,
+        block_index: u32,
+        block_num: u32,
+        storage: &BlockStorage,
+        allocate: &mut dyn FnMut() -> Option<u32>,
+    ) -> Result<bool> {
+        let slot = Self::slot(block_index, storage);
+        if matches!(slot, None | Some(BlockSlot::Direct)) {
+            return Ok(self.set_block_number(block_index, block_num));
+        }
+        
+        let previous = self.get_block_number_with_storage(block_index, storage)?;
+        let mut new_pointers = 0;
+        let mut counting = || {
+            let block_num = allocate();
+            new_pointers += u32::from(block_num.is_some());
+            block_num
+        };
+        let result = self.set_indirect_slot(slot, block_num, storage, &mut counting);
+        
+        // Los bloques indirectos creados ya cuelgan del inodo aunque falle
+        self.allocated_blocks += new_pointers;
+        if let Ok(true) = result {
+            match (previous.is_none(), block_num == u32::MAX) {
+                (true, false) => self.allocated_blocks += 1,
+                (false, true) => self.allocated_blocks = self.allocated_blocks.saturating_sub(1),
+                _ => {}
+            }
+        }
+        result
+    }
+    
+    /// Store `block_num` in an indirect `slot` for
+    /// `set_block_number_with_storage`
+    fn set_indirect_slot(
+        &mut self,
+        slot: Option<BlockSlot>,
+        block_num: u32,
+        storage: &BlockStorage,
+        allocate: &mut dyn FnMut() -> Option<u32>,
+    ) -> Result<bool> {
+        let clearing = block_num == u32::MAX;
+        match slot {
+            // Los directos los resuelve el llamador sin leer el almacenamiento
+            None | Some(BlockSlot::Direct) => Ok(false),
+            Some(BlockSlot::Indirect(entry)) => {
+                let Some(indirect) = pointer_block(storage, &mut self.indirect_block, clearing, allocate)? else {
+                    return Ok(true);
+                };
+                write_pointer(storage, indirect, entry, block_num)?;
+                Ok(true)
+            }
+            Some(BlockSlot::DoubleIndirect(outer, inner)) => {
+                let Some(double) = pointer_block(storage, &mut self.double_indirect_block, clearing, allocate)? else {
+                    return Ok(true);
+                };
+                let mut level2 = read_pointer(storage, double, outer)?.unwrap_or(u32::MAX);
+                let created = level2 == u32::MAX;
+                let Some(level2) = pointer_block(storage, &mut level2, clearing, allocate)? else {
+                    return Ok(true);
+                };
+                if created {
+                    write_pointer(storage, double, outer, level2)?;
+                }
+                write_pointer(storage, level2, inner, block_num)?;
+                Ok(true)
+            }
+        }
+    }
+    
+    /// Every mapped data block as (file block index, block number), in
+    /// index order, reading each indirect block once
+    pub fn mapped_blocks(&self, storage: &BlockStorage) -> Result<Vec<(u32, u32)>> {
+        let mut blocks: Vec<(u32, u32)> = (0..DIRECT_BLOCKS)
+            .filter_map(|i| self.get_block_number(i).map(|b| (i, b)))
+            .collect();
+        
+        let per_block = (storage.bytes_per_block() / POINTER_BYTES) as u32;
+        if self.indirect_block != u32::MAX {
+            for (entry, block_num) in read_pointers(storage, self.indirect_block)? {
+                blocks.push((DIRECT_BLOCKS + entry, block_num));
+            }
+        }
+        if self.double_indirect_block != u32::MAX {
+            let base = DIRECT_BLOCKS + per_block;
+            for (outer, level2) in read_pointers(storage, self.double_indirect_block)? {
+                for (inner, block_num) in read_pointers(storage, level2)? {
+                    blocks.push((base + outer * per_block + inner, block_num));
+                }
+            }
+        }
+        Ok(blocks)
+    }
+    
+    /// Blocks holding this inode's pointers (not file data)
+    pub fn pointer_blocks(&self, storage: &BlockStorage) -> Result<Vec<u32>> {
+        let mut blocks = Vec::new();
+        if self.indirect_block != u32::MAX {
+            blocks.push(self.indirect_block);
+        }
+        if self.double_indirect_block != u32::MAX {
+            blocks.push(self.double_indirect_block);
+            for (_, level2) in read_pointers(storage, self.double_indirect_block)? {
+                blocks.push(level2);
+            }
+        }
+        Ok(blocks)
+    }
+    
+    /// Detach the indirect blocks a file of `block_count` blocks no longer
+    /// needs and return them (with the blocks under them) for freeing. The
+    /// data blocks they mapped must already have been released.
+    pub fn trim_pointer_blocks(&mut self, block_count: u32, storage: &BlockStorage) -> Result<Vec<u32>> {
+        let per_block = (storage.bytes_per_block() / POINTER_BYTES) as u32;
+        let mut freed = Vec::new();
+        if block_count <= DIRECT_BLOCKS.saturating_add(per_block) && self.double_indirect_block != u32::MAX {
+            freed.push(self.double_indirect_block);
+            for (_, level2) in read_pointers(storage, self.double_indirect_block)? {
+                freed.push(level2);
+            }
+            self.double_indirect_block = u32::MAX;
+        }
+        if block_count <= DIRECT_BLOCKS && self.indirect_block != u32::MAX {
+            freed.push(self.indirect_block);
+            self.indirect_block = u32::MAX;
+        }
+        self.allocated_blocks = self.allocated_blocks.saturating_sub(freed.len() as u32);
+        Ok(freed)
+    }
+}
+
+/// Pointer `entry` of indirect block `block_num`, or `None` if either is
+/// unallocated. 0 also counts as empty: it is the reserved superblock.
+fn read_pointer(storage: &BlockStorage, block_num: u32, entry: usize) -> Result<Option<u32>> {
+    if block_num == u32::MAX {
+        return Ok(None);
+    }
+    let data = storage.read_block(block_num)?;
+    let Some(bytes) = data.get(entry * POINTER_BYTES..(entry + 1) * POINTER_BYTES) else {
+        return Ok(None);
+    };
+    let pointer = u32::from_le_bytes(bytes.try_into().unwrap());
+    Ok((pointer != u32::MAX && pointer != 0).then_some(pointer))
+}
+
+/// Every allocated (entry, pointer) of indirect block `block_num`
+fn read_pointers(storage: &BlockStorage, block_num: u32) -> Result<Vec<(u32, u32)>> {
+    let data = storage.read_block(block_num)?;
+    Ok(data
+        .chunks_exact(POINTER_BYTES)
+        .enumerate()
+        .map(|(entry, bytes)| (entry as u32, u32::from_le_bytes(bytes.try_into().unwrap())))
+        .filter(|&(_, pointer)| pointer != u32::MAX && pointer != 0)
+        .collect())
+}
+
+/// Store `pointer` as entry `entry` of indirect block `block_num`
+fn write_pointer(storage: &BlockStorage, block_num: u32, entry: usize, pointer: u32) -> Result<()> {
+    let mut data = storage.read_block(block_num)?;
+    data[entry * POINTER_BYTES..(entry + 1) * POINTER_BYTES].copy_from_slice(&pointer.to_le_bytes());
+    storage.write_pointer_block(block_num, &data)
+}
+
+/// The indirect block in `*slot`, allocating an empty one if missing.
+/// `None` if it is missing and `clearing` (nothing to clear under it).
+fn pointer_block(
+    storage: &BlockStorage,
+    slot: &mut u32,
+    clearing: bool,
+    allocate: &mut dyn FnMut() -> Option<u32>,
+) -> Result<Option<u32>> {
+    if *slot != u32::MAX {
+        return Ok(Some(*slot));
+    }
+    if clearing {
+        return Ok(None);
+    }
+    let block_num = allocate().ok_or_else(|| std::io::Error::from_raw_os_error(libc::ENOSPC))?;
+    // Todas las entradas a u32::MAX: ningún bloque asignado
+    storage.write_pointer_block(block_num, &vec![0xFF; storage.bytes_per_block()])?;
+    *slot = block_num;
+    Ok(Some(block_num))
 }
 
 /// Directory entry
@@ -120,7 +430,9 @@ pub struct DirEntry {
     /// File name
     pub name: String,
     
-    /// File type
+    /// File type. Authoritative copy of the inode's type: `readdir` reports
+    /// it without looking the inode up, so it must be kept in sync whenever
+    /// the entry is (re)pointed at an inode.
     pub file_type: FileType,
 }
 
@@ -132,4 +444,9 @@ pub fn new(ino: u64, name: String, file_type: FileType) -> Self {
             file_type,
         }
     }
+    
+    /// Entry named `name` for `inode`, taking the type from the inode
+    pub fn for_inode(name: String, inode: &INode) -> Self {
+        Self::new(inode.ino, name, inode.file_type)
+    }
 }
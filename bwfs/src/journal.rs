@@ -0,0 +1,352 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::config::JournalSync;
+
+/// Name of the journal file inside the storage path
+pub const JOURNAL_FILE_NAME: &str = "journal.log";
+
+/// One line of the journal.
+///
+/// A data operation (a `write` or a `copy_file_range`) appends a `Block`
+/// record before each block image it writes and a `Commit` record once the
+/// inode has been updated in memory. Blocks written outside an operation
+/// carry op 0, which never commits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum JournalRecord {
+    /// File block `index` of `ino` is about to be written to `block`; `crc`
+    /// is the CRC32 of the full block contents
+    Block { op: u64, ino: u64, index: u32, block: u32, crc: u32 },
+    /// Operation `op` finished and left `ino` with `size` bytes
+    Commit { op: u64, ino: u64, size: u64 },
+}
+
+/// Append-only journal of data block writes, replayed by
+/// `BWFS::replay_journal` on load and cut back after every checkpoint.
+///
+/// Positions are absolute byte offsets since the journal was created, so a
+/// checkpoint can drop exactly what its snapshot covers even if records
+/// were appended while it was being written.
+pub struct Journal {
+    path: PathBuf,
+    enabled: bool,
+    sync: JournalSync,
+    /// Least time between two syncs under `JournalSync::Interval`
+    sync_interval: Duration,
+    state: Mutex<JournalState>,
+}
+
+struct JournalState {
+    /// Open file; `None` when disabled and no journal is left to remove
+    file: Option<File>,
+    /// Absolute position of the first byte in the file
+    base: u64,
+    /// Bytes currently in the file
+    len: u64,
+    /// Bytes of the file known to be on disk
+    synced: u64,
+    /// When the file was last synced
+    last_sync: Option<Instant>,
+    next_op: u64,
+    /// Operation in progress for each inode (between `begin` and `commit`)
+    active: HashMap<u64, u64>,
+}
+
+impl Journal {
+    /// Open the journal of an existing filesystem. Records already in the
+    /// file are kept until the next checkpoint; when journaling is disabled
+    /// nothing is appended and the file is removed by that checkpoint.
+    pub fn open(storage_dir: &Path, enabled: bool) -> Result<Self> {
+        let path = storage_dir.join(JOURNAL_FILE_NAME);
+        let (records, valid_len) = Self::read_valid(&path)?;
+        // Los números de operación nuevos no deben coincidir con los que
+        // quedan en el archivo, o un commit nuevo confirmaría uno viejo
+        let last_op = records
+            .iter()
+            .map(|record| match record {
+                JournalRecord::Block { op, .. } | JournalRecord::Commit { op, .. } => *op,
+            })
+            .max()
+            .unwrap_or(0);
+
+        let file = if enabled || path.exists() {
+            Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("cannot open journal {:?}", path))?,
+            )
+        } else {
+            None
+        };
+        let mut len = match &file {
+            Some(file) => file.metadata()?.len(),
+            None => 0,
+        };
+        // Una línea cortada por un crash se quita: lo que se agregue detrás
+        // quedaría en la misma línea y la repetición lo ignoraría
+        if enabled && len > valid_len {
+            file.as_ref().unwrap().set_len(valid_len)?;
+            len = valid_len;
+        }
+
+        Ok(Self {
+            path,
+            enabled,
+            sync: JournalSync::default(),
+            sync_interval: Duration::ZERO,
+            state: Mutex::new(JournalState {
+                file,
+                base: 0,
+                len,
+                synced: len,
+                last_sync: None,
+                next_op: last_op + 1,
+                active: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Start the journal of a new filesystem, discarding any left by a
+    /// previous one in the same storage path
+    pub fn create(storage_dir: &Path, enabled: bool) -> Result<Self> {
+        let path = storage_dir.join(JOURNAL_FILE_NAME);
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        Self::open(storage_dir, enabled)
+    }
+
+    /// Sync records according to `mode`; `interval` is the least time
+    /// between two syncs under `JournalSync::Interval`
+    pub fn with_sync(mut self, mode: JournalSync, interval: Duration) -> Self {
+        self.sync = mode;
+        self.sync_interval = interval;
+        self
+    }
+    
+    /// Start a data operation on `ino`; the blocks it writes are recorded
+    /// under a new operation number until `commit`
+    pub fn begin(&self, ino: u64) {
+        if !self.enabled {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let op = state.next_op;
+        state.next_op += 1;
+        state.active.insert(ino, op);
+    }
+
+    /// Record that file block `index` of `ino` is about to be written to
+    /// `block`. Synced only under `JournalSync::Always`; otherwise a record
+    /// whose block never reached the disk fails its CRC on replay and is
+    /// skipped.
+    pub fn record_block(&self, ino: u64, index: u32, block: u32, data: &[u8]) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        let op = state.active.get(&ino).copied().unwrap_or(0);
+        let record = JournalRecord::Block { op, ino, index, block, crc: crc32fast::hash(data) };
+        state.append(&record, self.sync == JournalSync::Always)
+    }
+
+    /// Finish the operation in progress on `ino`, leaving it `size` bytes
+    /// long. Once synced (see `JournalSync`) the operation survives a crash
+    /// together with every record before it.
+    pub fn commit(&self, ino: u64, size: u64) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        let Some(op) = state.active.remove(&ino) else {
+            return Ok(());
+        };
+        let sync = match self.sync {
+            JournalSync::Always => true,
+            JournalSync::Interval => state
+                .last_sync
+                .is_none_or(|at| at.elapsed() >= self.sync_interval),
+            JournalSync::Never => false,
+        };
+        state.append(&JournalRecord::Commit { op, ino, size }, sync)
+    }
+
+    /// Absolute position of the end of the journal
+    pub fn position(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.base + state.len
+    }
+
+    /// Drop the records before `position`, once a checkpoint covering them
+    /// is on disk. Later records are kept (rewritten through a temporary
+    /// file so a crash never loses them).
+    pub fn discard_through(&self, position: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.file.is_none() {
+            return Ok(());
+        }
+        if !self.enabled {
+            // Nada nuevo se agrega: el checkpoint cubre todo el archivo
+            state.file = None;
+            fs::remove_file(&self.path)?;
+            log::info!("journal {:?} replayed and checkpointed; removed", self.path);
+            return Ok(());
+        }
+        if position <= state.base {
+            return Ok(());
+        }
+        let keep_from = (position - state.base).min(state.len);
+
+        if keep_from == state.len {
+            let file = state.file.as_ref().unwrap();
+            file.set_len(0)?;
+            file.sync_all()?;
+        } else {
+            let data = fs::read(&self.path)?;
+            let tail = &data[keep_from as usize..];
+            let tmp_path = self.path.with_extension("log.tmp");
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(tail)?;
+            tmp.sync_all()?;
+            drop(tmp);
+            fs::rename(&tmp_path, &self.path)?;
+            if let Some(dir) = self.path.parent() {
+                crate::storage::sync_dir(dir)?;
+            }
+            state.file = Some(OpenOptions::new().append(true).open(&self.path)?);
+        }
+        state.len -= keep_from;
+        state.base += keep_from;
+        // Lo que queda se acaba de sincronizar
+        state.synced = state.len;
+        Ok(())
+    }
+
+    /// Read the records of the journal in `storage_dir`, oldest first. A
+    /// missing file is an empty journal; reading stops at the first line
+    /// that does not parse (a record torn by a crash).
+    pub fn read_records(storage_dir: &Path) -> Result<Vec<JournalRecord>> {
+        Ok(Self::read_valid(&storage_dir.join(JOURNAL_FILE_NAME))?.0)
+    }
+
+    /// Records of the journal at `path` and the length of the prefix of
+    /// complete lines they come from
+    fn read_valid(path: &Path) -> Result<(Vec<JournalRecord>, u64)> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+            Err(e) => return Err(e).with_context(|| format!("cannot read journal {:?}", path)),
+        };
+
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        for (line_no, line) in data.split_inclusive(|&b| b == b'\n').enumerate() {
+            let parsed = line
+                .strip_suffix(b"\n")
+                .ok_or_else(|| "missing newline".to_string())
+                .and_then(|line| serde_json::from_slice(line).map_err(|e| e.to_string()));
+            match parsed {
+                Ok(record) => {
+                    records.push(record);
+                    valid_len += line.len() as u64;
+                }
+                Err(e) => {
+                    log::warn!(
+                        "journal {:?}: ignoring line {} and everything after it ({})",
+                        path, line_no + 1, e
+                    );
+                    break;
+                }
+            }
+        }
+        Ok((records, valid_len))
+    }
+}
+
+impl JournalState {
+    /// Append one record as a JSON line
+    fn append(&mut self, record: &JournalRecord, sync: bool) -> Result<()> {
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        self.len += line.len() as u64;
+        if sync {
+            file.sync_data()?;
+            self.synced = self.len;
+            self.last_sync = Some(Instant::now());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// Cut the journal in `dir` back to what `journal` synced, as a power
+    /// loss would, and return the records that survive
+    fn power_loss(dir: &Path, journal: Journal) -> Vec<JournalRecord> {
+        let synced = journal.state.lock().unwrap().synced;
+        drop(journal);
+        OpenOptions::new()
+            .write(true)
+            .open(dir.join(JOURNAL_FILE_NAME))
+            .unwrap()
+            .set_len(synced)
+            .unwrap();
+        Journal::read_records(dir).unwrap()
+    }
+
+    fn record_one_block(dir: &Path, mode: JournalSync) -> Vec<JournalRecord> {
+        let journal = Journal::create(dir, true).unwrap().with_sync(mode, Duration::ZERO);
+        journal.begin(5);
+        journal.record_block(5, 0, 7, &[1, 2, 3]).unwrap();
+        power_loss(dir, journal)
+    }
+
+    #[test]
+    fn always_syncs_a_block_record_before_the_commit() {
+        let dir = TempDir::new("journal-always");
+        let records = record_one_block(dir.path(), JournalSync::Always);
+        assert_eq!(
+            records,
+            vec![JournalRecord::Block { op: 1, ino: 5, index: 0, block: 7, crc: crc32fast::hash(&[1, 2, 3]) }]
+        );
+    }
+
+    #[test]
+    fn interval_and_never_leave_uncommitted_records_unsynced() {
+        let dir = TempDir::new("journal-lazy");
+        assert!(record_one_block(dir.path(), JournalSync::Interval).is_empty());
+        assert!(record_one_block(dir.path(), JournalSync::Never).is_empty());
+    }
+
+    #[test]
+    fn interval_syncs_commits_at_most_once_per_interval() {
+        let dir = TempDir::new("journal-interval");
+        let journal = Journal::create(dir.path(), true)
+            .unwrap()
+            .with_sync(JournalSync::Interval, Duration::from_secs(3600));
+        for ino in [1, 2] {
+            journal.begin(ino);
+            journal.record_block(ino, 0, ino as u32 + 10, &[0]).unwrap();
+            journal.commit(ino, 1).unwrap();
+        }
+        // El primer commit sincroniza; el segundo cae dentro del intervalo
+        let records = power_loss(dir.path(), journal);
+        assert_eq!(records.len(), 2);
+        assert!(matches!(records[1], JournalRecord::Commit { ino: 1, .. }));
+    }
+}
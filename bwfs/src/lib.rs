@@ -1,8 +1,18 @@
 pub mod fs;
 pub mod storage;
+pub mod cache;
 pub mod inode;
 pub mod config;
 pub mod network;
+pub mod hash;
+pub mod merkle;
+pub mod diff;
+pub mod tar;
+pub mod journal;
+pub mod convert;
+
+#[cfg(test)]
+mod test_util;
 
 pub use fs::BWFS;
 pub use config::Config;
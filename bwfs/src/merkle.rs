@@ -0,0 +1,111 @@
+use crate::hash::{self, Digest, Sha256};
+use serde::{Deserialize, Serialize};
+
+/// Merkle tree over the data blocks of the filesystem.
+///
+/// `levels[0]` holds one leaf per block; every upper level hashes pairs of
+/// the level below (an unpaired node is carried up unchanged), and the last
+/// level holds the root. Leaves and inner nodes use different prefixes so a
+/// leaf can never be confused with an inner node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct MerkleTree {
+    levels: Vec<Vec<Digest>>,
+}
+
+impl MerkleTree {
+    /// Build a tree from its leaves
+    pub fn from_leaves(leaves: Vec<Digest>) -> Self {
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let below = levels.last().unwrap();
+            let above = below
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => Self::node_hash(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(above);
+        }
+        Self { levels }
+    }
+
+    /// Hash of a block's contents as stored in a leaf
+    pub fn leaf_hash(data: &[u8]) -> Digest {
+        let mut hasher = Sha256::new();
+        hasher.update(&[0x00]);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    fn node_hash(left: &Digest, right: &Digest) -> Digest {
+        let mut hasher = Sha256::new();
+        hasher.update(&[0x01]);
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize()
+    }
+
+    /// Replace one leaf and recompute only the path up to the root
+    pub fn update(&mut self, index: usize, leaf: Digest) {
+        if index >= self.levels[0].len() {
+            return;
+        }
+        self.levels[0][index] = leaf;
+
+        let mut idx = index;
+        for level in 1..self.levels.len() {
+            let below = &self.levels[level - 1];
+            let left = idx & !1;
+            let value = match below.get(left + 1) {
+                Some(right) => Self::node_hash(&below[left], right),
+                None => below[left],
+            };
+            idx /= 2;
+            self.levels[level][idx] = value;
+        }
+    }
+
+    /// Current root of the tree
+    pub fn root(&self) -> Digest {
+        self.levels
+            .last()
+            .and_then(|level| level.first().copied())
+            .unwrap_or([0; 32])
+    }
+
+    /// Stored leaf for a block
+    pub fn leaf(&self, index: usize) -> Option<Digest> {
+        self.levels[0].get(index).copied()
+    }
+
+    /// Number of leaves (blocks) covered by the tree
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+}
+
+// Persisted as the hex-encoded list of leaves; inner levels are rebuilt.
+impl From<MerkleTree> for Vec<String> {
+    fn from(tree: MerkleTree) -> Self {
+        tree.levels[0].iter().map(hash::to_hex).collect()
+    }
+}
+
+impl TryFrom<Vec<String>> for MerkleTree {
+    type Error = String;
+
+    fn try_from(leaves: Vec<String>) -> Result<Self, Self::Error> {
+        let leaves = leaves
+            .iter()
+            .map(|s| hash::from_hex(s).ok_or_else(|| format!("invalid leaf hash '{}'", s)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_leaves(leaves))
+    }
+}
@@ -2,11 +2,45 @@
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::storage::BlockStorage;
+
+/// Largest message accepted on the wire (a 1000x1000 block plus JSON overhead)
+const MAX_MESSAGE_LEN: usize = 8 * 1024 * 1024;
+
+/// Default cap on the block payload of one response. JSON spends up to four
+/// bytes per data byte, so this keeps responses well under `MAX_MESSAGE_LEN`.
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+
+/// Default time `NetworkClient::ping` waits for a node, connection included
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Address the block server listens on unless told otherwise: loopback only,
+/// so a single-host setup does not expose its blocks to the network
+pub const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1";
+
+/// Idle connections kept open per node; more concurrent requests than this
+/// still work, but the extra connections are closed when they finish
+const MAX_IDLE_PER_NODE: usize = 4;
 
 /// Network request types
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Request {
+    /// First message on every connection: the filesystem fingerprint as a
+    /// shared secret. The server answers `Success`, or `Error` (EACCES) and
+    /// closes the connection on a mismatch; any other request before it is
+    /// refused the same way.
+    Auth { fingerprint: String },
     ReadBlock { block_num: u32 },
+    /// Read `count` consecutive blocks starting at `start`
+    ReadBlocks { start: u32, count: u32 },
+    /// Read arbitrary blocks; a failing block does not fail the others
+    ReadBlockList { block_nums: Vec<u32> },
+    /// Store a block image as is. It bypasses the allocation of a mounted
+    /// filesystem (bitmap, journal, kernel notifications); only block 0 and
+    /// the metadata area are refused.
     WriteBlock { block_num: u32, data: Vec<u8> },
     Ping,
 }
@@ -15,25 +49,135 @@ pub enum Request {
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
     BlockData { data: Vec<u8> },
+    Blocks { blocks: Vec<Vec<u8>> },
+    /// Answer to `ReadBlockList`: the blocks that could be read, and
+    /// (block, errno, message) for those that could not
+    BlocksData {
+        blocks: Vec<(u32, Vec<u8>)>,
+        errors: Vec<(u32, i32, String)>,
+    },
     Success,
-    Error { message: String },
+    /// `errno` says what kind of failure it was (EINVAL for a bad request,
+    /// ENOSPC when the remote disk is full, EIO otherwise)
+    Error {
+        message: String,
+        #[serde(default = "default_errno")]
+        errno: i32,
+    },
     Pong,
 }
 
+/// errno assumed for errors from peers that predate the field
+fn default_errno() -> i32 {
+    libc::EIO
+}
+
+impl Response {
+    fn error(errno: i32, message: impl Into<String>) -> Self {
+        Response::Error { message: message.into(), errno }
+    }
+    
+    /// Error response for a failed storage operation
+    fn storage_error(error: anyhow::Error) -> Self {
+        let errno = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<std::io::Error>()?.raw_os_error())
+            .filter(|&errno| errno == libc::ENOSPC)
+            .unwrap_or(libc::EIO);
+        // {:#} incluye la cadena de contexto ("Failed to save block image ...: ...")
+        Self::error(errno, format!("{:#}", error))
+    }
+}
+
+/// Turn a remote error into a local one carrying its errno as an
+/// `std::io::Error`, so callers map it like a local storage failure
+fn remote_error(addr: &str, message: String, errno: i32) -> anyhow::Error {
+    anyhow::Error::new(std::io::Error::from_raw_os_error(errno))
+        .context(format!("{}: {}", addr, message))
+}
+
+/// Send one length-prefixed JSON message: a 4-byte big-endian length
+/// followed by the JSON body. Requests and responses use the same framing.
+/// Both go out in a single write: two small writes on a kept-alive
+/// connection stall on Nagle and delayed ACKs (~40 ms per message).
+async fn write_message<T: Serialize>(stream: &mut TcpStream, message: &T) -> Result<()> {
+    let mut frame = vec![0u8; 4];
+    serde_json::to_writer(&mut frame, message)?;
+    let len = (frame.len() - 4) as u32;
+    frame[..4].copy_from_slice(&len.to_be_bytes());
+    stream.write_all(&frame).await?;
+    Ok(())
+}
+
+/// Receive one length-prefixed JSON message; `None` when the peer closed.
+/// The body is read with `read_exact`, so frames split across several TCP
+/// segments are reassembled.
+async fn read_message<T: for<'de> Deserialize<'de>>(stream: &mut TcpStream) -> Result<Option<T>> {
+    let len = match stream.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_MESSAGE_LEN {
+        anyhow::bail!("Message of {} bytes exceeds the {} byte limit", len, MAX_MESSAGE_LEN);
+    }
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+    Ok(Some(serde_json::from_slice(&buf)?))
+}
+
 /// Network server for distributed BWFS
 pub struct NetworkServer {
     port: u16,
+    
+    /// IP address the listener binds to (`0.0.0.0` for every interface)
+    bind_address: String,
+    
+    /// Block storage served to peers. When shared with a mounted `BWFS`
+    /// (see `BWFS::storage_handle`), remote and local block writes are
+    /// serialized by the same lock.
+    storage: Arc<Mutex<BlockStorage>>,
+    
+    /// Largest block payload a single response may carry
+    max_response_bytes: usize,
 }
 
 impl NetworkServer {
-    pub fn new(port: u16) -> Self {
-        Self { port }
+    /// Serve the blocks of `storage` on `port`, on the loopback interface
+    /// unless `with_bind_address` says otherwise
+    pub fn new(port: u16, storage: Arc<Mutex<BlockStorage>>) -> Self {
+        Self {
+            port,
+            bind_address: DEFAULT_BIND_ADDRESS.to_string(),
+            storage,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+        }
+    }
+    
+    /// Reject requests whose response would carry more than `bytes` of
+    /// block data (defaults to `DEFAULT_MAX_RESPONSE_BYTES`)
+    pub fn with_max_response_bytes(mut self, bytes: usize) -> Self {
+        self.max_response_bytes = bytes;
+        self
+    }
+    
+    /// Listen on `address` (an IPv4 or IPv6 address, e.g. `0.0.0.0` to
+    /// accept peers on every interface)
+    pub fn with_bind_address(mut self, address: impl Into<String>) -> Self {
+        self.bind_address = address.into();
+        self
     }
     
     /// Start the network server
     pub async fn start(&self) -> Result<()> {
-        let addr = format!("0.0.0.0:{}", self.port);
-        let listener = TcpListener::bind(&addr).await?;
+        let ip: std::net::IpAddr = self.bind_address.trim().parse().map_err(|_| {
+            anyhow::anyhow!("Invalid bind address '{}' (expected an IP address)", self.bind_address)
+        })?;
+        // SocketAddr pone los corchetes de IPv6
+        let addr = std::net::SocketAddr::new(ip, self.port);
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("cannot listen on {}: {}", addr, e))?;
         
         log::info!("BWFS network server listening on {}", addr);
         
@@ -41,8 +185,10 @@ pub async fn start(&self) -> Result<()> {
             let (socket, addr) = listener.accept().await?;
             log::debug!("New connection from {}", addr);
             
+            let storage = self.storage.clone();
+            let max_response_bytes = self.max_response_bytes;
             tokio::spawn(async move {
-                if let Err(e) = handle_connection(socket).await {
+                if let Err(e) = handle_connection(socket, storage, max_response_bytes).await {
                     log::error!("Connection error: {}", e);
                 }
             });
@@ -50,47 +196,302 @@ pub async fn start(&self) -> Result<()> {
     }
 }
 
-async fn handle_connection(mut socket: TcpStream) -> Result<()> {
-    let mut buf = vec![0u8; 8192];
-    
-    loop {
-        let n = socket.read(&mut buf).await?;
-        if n == 0 {
-            break;
+/// Compare fingerprints without stopping at the first differing byte, so
+/// response timing does not reveal how much of a guess was right
+fn fingerprint_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn handle_connection(
+    mut socket: TcpStream,
+    storage: Arc<Mutex<BlockStorage>>,
+    max_response_bytes: usize,
+) -> Result<()> {
+    // Ninguna operación de bloque antes de autenticarse
+    let peer = socket.peer_addr()?;
+    let response = match read_message::<Request>(&mut socket).await? {
+        None => return Ok(()),
+        Some(Request::Auth { fingerprint }) => {
+            if fingerprint_matches(&fingerprint, storage.lock().unwrap().fingerprint()) {
+                Response::Success
+            } else {
+                log::warn!("Rejected connection from {}: fingerprint mismatch", peer);
+                Response::error(libc::EACCES, "Fingerprint mismatch")
+            }
         }
-        
-        let request: Request = serde_json::from_slice(&buf[..n])?;
-        let response = process_request(request).await;
-        
-        let response_data = serde_json::to_vec(&response)?;
-        socket.write_all(&response_data).await?;
+        Some(_) => {
+            log::warn!("Rejected connection from {}: request before authentication", peer);
+            Response::error(libc::EACCES, "Authentication required")
+        }
+    };
+    let authenticated = matches!(response, Response::Success);
+    write_message(&mut socket, &response).await?;
+    if !authenticated {
+        return Ok(());
+    }
+    
+    while let Some(request) = read_message::<Request>(&mut socket).await? {
+        let storage = storage.clone();
+        // Las operaciones de bloque bloquean (PNG + mutex): fuera del runtime
+        let response = tokio::task::spawn_blocking(move || {
+            process_request(request, storage, max_response_bytes)
+        })
+        .await?;
+        write_message(&mut socket, &response).await?;
     }
     
     Ok(())
 }
 
-async fn process_request(request: Request) -> Response {
+fn process_request(
+    request: Request,
+    storage: Arc<Mutex<BlockStorage>>,
+    max_response_bytes: usize,
+) -> Response {
     match request {
         Request::Ping => Response::Pong,
-        Request::ReadBlock { block_num: _ } => {
-            // TODO: Implement actual block reading
-            Response::BlockData { data: vec![0; 1024] }
+        Request::Auth { .. } => Response::error(libc::EINVAL, "Already authenticated"),
+        Request::ReadBlock { block_num } => {
+            let storage = storage.lock().unwrap();
+            if let Some(error) = out_of_range(&storage, block_num, 1) {
+                return error;
+            }
+            if storage.bytes_per_block() > max_response_bytes {
+                return Response::error(
+                    libc::EINVAL,
+                    format!("Block size exceeds the {} byte response limit", max_response_bytes),
+                );
+            }
+            match storage.read_block(block_num) {
+                Ok(data) => Response::BlockData { data },
+                Err(e) => Response::storage_error(e),
+            }
+        }
+        Request::ReadBlocks { start, count } => {
+            let storage = storage.lock().unwrap();
+            if let Some(error) = out_of_range(&storage, start, count) {
+                return error;
+            }
+            // Se rechaza antes de leer nada: el tamaño se conoce de antemano
+            let max_count = max_response_bytes / storage.bytes_per_block().max(1);
+            if count as usize > max_count {
+                return Response::error(
+                    libc::EINVAL,
+                    format!(
+                        "Requested {} blocks but at most {} fit in the {} byte response limit",
+                        count, max_count, max_response_bytes
+                    ),
+                );
+            }
+            let mut blocks = Vec::with_capacity(count as usize);
+            for block_num in start..start + count {
+                match storage.read_block(block_num) {
+                    Ok(data) => blocks.push(data),
+                    Err(e) => return Response::storage_error(e),
+                }
+            }
+            Response::Blocks { blocks }
+        }
+        Request::ReadBlockList { block_nums } => {
+            let storage = storage.lock().unwrap();
+            let max_count = max_response_bytes / storage.bytes_per_block().max(1);
+            if block_nums.len() > max_count {
+                return Response::error(
+                    libc::EINVAL,
+                    format!(
+                        "Requested {} blocks but at most {} fit in the {} byte response limit",
+                        block_nums.len(), max_count, max_response_bytes
+                    ),
+                );
+            }
+            let mut blocks = Vec::with_capacity(block_nums.len());
+            let mut errors = Vec::new();
+            for block_num in block_nums {
+                // Cada bloque responde por sí mismo: un fallo no anula el lote
+                if let Some(Response::Error { message, errno }) = out_of_range(&storage, block_num, 1) {
+                    errors.push((block_num, errno, message));
+                    continue;
+                }
+                match storage.read_block(block_num) {
+                    Ok(data) => blocks.push((block_num, data)),
+                    Err(e) => {
+                        if let Response::Error { message, errno } = Response::storage_error(e) {
+                            errors.push((block_num, errno, message));
+                        }
+                    }
+                }
+            }
+            Response::BlocksData { blocks, errors }
+        }
+        Request::WriteBlock { block_num, data } => {
+            // El bloque 0 guarda el superblock/fingerprint
+            if block_num == 0 {
+                return Response::error(libc::EINVAL, "Block 0 is reserved");
+            }
+            // El lock de storage es el mismo que usa write() del montaje local,
+            // así que una escritura remota nunca se intercala con una local
+            let storage = storage.lock().unwrap();
+            if let Some(error) = out_of_range(&storage, block_num, 1) {
+                return error;
+            }
+            // Pisar el área de metadata corrompería el próximo montaje
+            if storage.metadata_area().is_some_and(|area| area.blocks().contains(&block_num)) {
+                return Response::error(
+                    libc::EINVAL,
+                    format!("Block {} belongs to the metadata area", block_num),
+                );
+            }
+            match storage.write_block(block_num, &data) {
+                Ok(()) => Response::Success,
+                Err(e) => Response::storage_error(e),
+            }
+        }
+    }
+}
+
+/// EINVAL response if `count` blocks from `start` run past the end of the
+/// filesystem
+fn out_of_range(storage: &BlockStorage, start: u32, count: u32) -> Option<Response> {
+    let end = start as u64 + count as u64;
+    (end > storage.total_blocks() as u64).then(|| {
+        Response::error(
+            libc::EINVAL,
+            format!(
+                "Blocks {}..{} are out of range ({} blocks)",
+                start,
+                end,
+                storage.total_blocks()
+            ),
+        )
+    })
+}
+
+/// Persistent connections to the nodes, reused across requests so each
+/// block access does not pay a TCP handshake (nor an `Auth`). The server
+/// answers any number of requests on one connection.
+struct ConnectionPool {
+    fingerprint: String,
+    idle: Mutex<HashMap<String, Vec<TcpStream>>>,
+}
+
+impl ConnectionPool {
+    fn new(fingerprint: String) -> Self {
+        Self { fingerprint, idle: Mutex::new(HashMap::new()) }
+    }
+    
+    /// Open an authenticated connection to `addr`
+    async fn connect(&self, addr: &str) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect(addr)
+            .await
+            .map_err(|e| anyhow::Error::new(e).context(addr.to_string()))?;
+        let auth = Request::Auth { fingerprint: self.fingerprint.clone() };
+        match exchange(&mut stream, addr, &auth).await? {
+            Response::Success => Ok(stream),
+            Response::Error { message, errno } => Err(remote_error(addr, message, errno)),
+            _ => anyhow::bail!("Unexpected response"),
         }
-        Request::WriteBlock { block_num: _, data: _ } => {
-            // TODO: Implement actual block writing
-            Response::Success
+    }
+    
+    /// Send `request` to `addr` and wait for the response, on an idle
+    /// connection if there is one. A pooled connection that fails (the node
+    /// restarted or closed it) is dropped and the request is sent once more
+    /// on a new connection; every request is safe to repeat.
+    async fn request(&self, addr: &str, request: &Request) -> Result<Response> {
+        let pooled = self.idle.lock().unwrap().get_mut(addr).and_then(Vec::pop);
+        if let Some(mut stream) = pooled {
+            match exchange(&mut stream, addr, request).await {
+                Ok(response) => {
+                    self.release(addr, stream);
+                    return Ok(response);
+                }
+                Err(e) => log::debug!("{}: pooled connection failed ({:#}); reconnecting", addr, e),
+            }
+        }
+        
+        let mut stream = self.connect(addr).await?;
+        let response = exchange(&mut stream, addr, request).await?;
+        self.release(addr, stream);
+        Ok(response)
+    }
+    
+    /// Keep `stream` for the next request to `addr`, unless enough are idle
+    fn release(&self, addr: &str, stream: TcpStream) {
+        let mut idle = self.idle.lock().unwrap();
+        let streams = idle.entry(addr.to_string()).or_default();
+        if streams.len() < MAX_IDLE_PER_NODE {
+            streams.push(stream);
         }
     }
 }
 
+/// One request/response round trip on `stream`
+async fn exchange(stream: &mut TcpStream, addr: &str, request: &Request) -> Result<Response> {
+    write_message(stream, request).await?;
+    read_message(stream)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Connection closed by {}", addr))
+}
+
 /// Network client for accessing remote blocks
 pub struct NetworkClient {
     nodes: Vec<String>,
+    ping_timeout: Duration,
+    pool: Arc<ConnectionPool>,
 }
 
 impl NetworkClient {
-    pub fn new(nodes: Vec<String>) -> Self {
-        Self { nodes }
+    /// Client for `nodes`, authenticating with the filesystem `fingerprint`
+    /// (the nodes must serve a filesystem with the same one)
+    pub fn new(nodes: Vec<String>, fingerprint: String) -> Self {
+        Self {
+            nodes,
+            ping_timeout: DEFAULT_PING_TIMEOUT,
+            pool: Arc::new(ConnectionPool::new(fingerprint)),
+        }
+    }
+    
+    /// Give up on a `ping` after `timeout` instead of `DEFAULT_PING_TIMEOUT`
+    pub fn with_ping_timeout(mut self, timeout: Duration) -> Self {
+        self.ping_timeout = timeout;
+        self
+    }
+    
+    /// Configured node addresses, in node index order
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+    
+    /// Send `Ping` to a node and return the round-trip time, connection
+    /// setup and authentication included: a ping always opens a new
+    /// connection instead of using the pool, so a fingerprint mismatch
+    /// shows up as an EACCES error. Fails if the node does not answer
+    /// `Pong` within the ping timeout, so a dead node never hangs the
+    /// caller.
+    pub async fn ping(&self, node_idx: usize) -> Result<Duration> {
+        if node_idx >= self.nodes.len() {
+            anyhow::bail!("Invalid node index");
+        }
+        
+        let addr = &self.nodes[node_idx];
+        let start = Instant::now();
+        let round_trip = async {
+            let mut stream = self.pool.connect(addr).await?;
+            exchange(&mut stream, addr, &Request::Ping).await
+        };
+        let response = tokio::time::timeout(self.ping_timeout, round_trip)
+            .await
+            .map_err(|_| anyhow::anyhow!("{}: no answer within {:?}", addr, self.ping_timeout))??;
+        
+        match response {
+            Response::Pong => Ok(start.elapsed()),
+            Response::Error { message, errno } => Err(remote_error(addr, message, errno)),
+            _ => anyhow::bail!("Unexpected response"),
+        }
     }
     
     /// Read a block from a remote node
@@ -100,21 +501,73 @@ pub async fn read_block(&self, node_idx: usize, block_num: u32) -> Result<Vec<u8
         }
         
         let addr = &self.nodes[node_idx];
-        let mut stream = TcpStream::connect(addr).await?;
+        let response = self.pool.request(addr, &Request::ReadBlock { block_num }).await?;
         
-        let request = Request::ReadBlock { block_num };
-        let request_data = serde_json::to_vec(&request)?;
+        match response {
+            Response::BlockData { data } => Ok(data),
+            Response::Error { message, errno } => Err(remote_error(addr, message, errno)),
+            _ => anyhow::bail!("Unexpected response"),
+        }
+    }
+    
+    /// Read `count` consecutive blocks starting at `start` from a remote node
+    pub async fn read_blocks(&self, node_idx: usize, start: u32, count: u32) -> Result<Vec<Vec<u8>>> {
+        if node_idx >= self.nodes.len() {
+            anyhow::bail!("Invalid node index");
+        }
         
-        stream.write_all(&request_data).await?;
+        let addr = &self.nodes[node_idx];
+        let response = self.pool.request(addr, &Request::ReadBlocks { start, count }).await?;
         
-        let mut buf = vec![0u8; 8192];
-        let n = stream.read(&mut buf).await?;
+        match response {
+            Response::Blocks { blocks } => Ok(blocks),
+            Response::Error { message, errno } => Err(remote_error(addr, message, errno)),
+            _ => anyhow::bail!("Unexpected response"),
+        }
+    }
+    
+    /// Read the listed blocks from a remote node in one round trip. Each
+    /// block gets its own result, in the order requested.
+    pub async fn read_block_list(
+        &self,
+        node_idx: usize,
+        block_nums: Vec<u32>,
+    ) -> Result<Vec<(u32, Result<Vec<u8>>)>> {
+        if node_idx >= self.nodes.len() {
+            anyhow::bail!("Invalid node index");
+        }
         
-        let response: Response = serde_json::from_slice(&buf[..n])?;
+        let addr = &self.nodes[node_idx];
+        let request = Request::ReadBlockList { block_nums: block_nums.clone() };
+        let response = self.pool.request(addr, &request).await?;
         
         match response {
-            Response::BlockData { data } => Ok(data),
-            Response::Error { message } => anyhow::bail!(message),
+            Response::BlocksData { blocks, errors } => {
+                // Una cola por bloque: un número repetido recibe una respuesta por aparición
+                let mut results: HashMap<u32, VecDeque<Result<Vec<u8>>>> = HashMap::new();
+                for (block_num, data) in blocks {
+                    results.entry(block_num).or_default().push_back(Ok(data));
+                }
+                for (block_num, errno, message) in errors {
+                    results
+                        .entry(block_num)
+                        .or_default()
+                        .push_back(Err(remote_error(addr, message, errno)));
+                }
+                Ok(block_nums
+                    .into_iter()
+                    .map(|block_num| {
+                        let result = results
+                            .get_mut(&block_num)
+                            .and_then(VecDeque::pop_front)
+                            .unwrap_or_else(|| {
+                                Err(anyhow::anyhow!("{}: no reply for block {}", addr, block_num))
+                            });
+                        (block_num, result)
+                    })
+                    .collect())
+            }
+            Response::Error { message, errno } => Err(remote_error(addr, message, errno)),
             _ => anyhow::bail!("Unexpected response"),
         }
     }
@@ -125,23 +578,175 @@ pub async fn write_block(&self, node_idx: usize, block_num: u32, data: Vec<u8>)
             anyhow::bail!("Invalid node index");
         }
         
-        let addr = &self.nodes[node_idx];
-        let mut stream = TcpStream::connect(addr).await?;
-        
-        let request = Request::WriteBlock { block_num, data };
-        let request_data = serde_json::to_vec(&request)?;
-        
-        stream.write_all(&request_data).await?;
+        write_block_to(&self.pool, &self.nodes[node_idx], block_num, data).await
+    }
+    
+    /// Write a block to the first `replicas` nodes (all of them if there
+    /// are fewer) in parallel. Succeeds once a majority of those replicas
+    /// acknowledged; otherwise returns the first failure.
+    pub async fn write_block_replicated(&self, block_num: u32, data: Vec<u8>, replicas: usize) -> Result<()> {
+        let targets = &self.nodes[..replicas.min(self.nodes.len())];
+        if targets.is_empty() {
+            anyhow::bail!("No nodes to replicate block {} to", block_num);
+        }
+        let quorum = targets.len() / 2 + 1;
         
-        let mut buf = vec![0u8; 8192];
-        let n = stream.read(&mut buf).await?;
+        let mut writes = tokio::task::JoinSet::new();
+        for addr in targets {
+            let addr = addr.clone();
+            let data = data.clone();
+            let pool = Arc::clone(&self.pool);
+            writes.spawn(async move { write_block_to(&pool, &addr, block_num, data).await });
+        }
         
-        let response: Response = serde_json::from_slice(&buf[..n])?;
+        let mut acks = 0;
+        let mut first_error = None;
+        while let Some(result) = writes.join_next().await {
+            match result.map_err(anyhow::Error::from).and_then(|r| r) {
+                Ok(()) => acks += 1,
+                Err(e) => {
+                    log::warn!("Replica write of block {} failed: {:#}", block_num, e);
+                    first_error.get_or_insert(e);
+                }
+            }
+            // El resto de réplicas sigue escribiéndose en segundo plano
+            if acks >= quorum {
+                writes.detach_all();
+                return Ok(());
+            }
+        }
         
-        match response {
-            Response::Success => Ok(()),
-            Response::Error { message } => anyhow::bail!(message),
-            _ => anyhow::bail!("Unexpected response"),
+        let error = first_error.unwrap_or_else(|| anyhow::anyhow!("no replica answered"));
+        Err(error.context(format!(
+            "block {} reached {} of {} replicas (quorum {})",
+            block_num, acks, targets.len(), quorum
+        )))
+    }
+    
+    /// Read a block from the first node that returns it, trying nodes in
+    /// order. Fails with the last node's error if none does.
+    pub async fn read_block_any(&self, block_num: u32) -> Result<Vec<u8>> {
+        let mut last_error = anyhow::anyhow!("No nodes configured");
+        for node_idx in 0..self.nodes.len() {
+            match self.read_block(node_idx, block_num).await {
+                Ok(data) => return Ok(data),
+                Err(e) => {
+                    log::debug!("read_block_any({}): {:#}", block_num, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// Send one `WriteBlock` to `addr` and wait for its acknowledgement
+async fn write_block_to(pool: &ConnectionPool, addr: &str, block_num: u32, data: Vec<u8>) -> Result<()> {
+    let response = pool.request(addr, &Request::WriteBlock { block_num, data }).await?;
+    
+    match response {
+        Response::Success => Ok(()),
+        Response::Error { message, errno } => Err(remote_error(addr, message, errno)),
+        _ => anyhow::bail!("Unexpected response"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_config, TempDir};
+    use crate::{Config, BWFS};
+
+    /// Start a server on a free loopback port and return its address
+    async fn serve(storage: Arc<Mutex<BlockStorage>>) -> String {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        tokio::spawn(async move {
+            let _ = NetworkServer::new(port, storage).start().await;
+        });
+        let addr = format!("127.0.0.1:{}", port);
+        for _ in 0..100 {
+            if TcpStream::connect(&addr).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
+        addr
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn local_writes_are_visible_to_network_reads() {
+        let dir = TempDir::new("net-shared");
+        let config = test_config(dir.path());
+        let fingerprint = config.fingerprint.clone();
+        let fs = BWFS::new(config).unwrap();
+        let data: Vec<u8> = (0..700u32).map(|i| (i % 251) as u8).collect();
+        let ino = fs.create_with_size_hint(fs.root_ino(), "f", 0o644, 0).unwrap();
+        fs.write_data(ino, 0, &data).unwrap();
+        let (_, inode) = fs.walk().into_iter().find(|(_, inode)| inode.ino == ino).unwrap();
+        let block = inode.direct_blocks[0];
+
+        let addr = serve(fs.storage_handle()).await;
+        let client = NetworkClient::new(vec![addr], fingerprint);
+        let remote = client.read_block(0, block).await.unwrap();
+        assert_eq!(&remote[..512], &data[..512]);
+    }
+
+    #[test]
+    fn write_block_refuses_the_metadata_area() {
+        let dir = TempDir::new("net-metadata");
+        let fs = BWFS::new(Config { metadata_blocks: 2, ..test_config(dir.path()) }).unwrap();
+        let storage = fs.storage_handle();
+        let area = storage.lock().unwrap().metadata_area().unwrap();
+        let bytes = storage.lock().unwrap().bytes_per_block();
+
+        for block_num in [0, area.first_block] {
+            let request = Request::WriteBlock { block_num, data: vec![0; bytes] };
+            let response = process_request(request, storage.clone(), DEFAULT_MAX_RESPONSE_BYTES);
+            assert!(matches!(response, Response::Error { errno: libc::EINVAL, .. }));
+        }
+        let free = area.blocks().end;
+        let request = Request::WriteBlock { block_num: free, data: vec![0; bytes] };
+        let response = process_request(request, storage.clone(), DEFAULT_MAX_RESPONSE_BYTES);
+        assert!(matches!(response, Response::Success));
+    }
+
+    #[test]
+    fn oversized_batches_are_rejected() {
+        let dir = TempDir::new("net-batch");
+        let fs = BWFS::new(test_config(dir.path())).unwrap();
+        let storage = fs.storage_handle();
+        let limit = 4 * 512;
+
+        let response = process_request(Request::ReadBlocks { start: 1, count: 5 }, storage.clone(), limit);
+        assert!(matches!(response, Response::Error { errno: libc::EINVAL, .. }), "{:?}", response);
+        let request = Request::ReadBlockList { block_nums: (1..6).collect() };
+        let response = process_request(request, storage.clone(), limit);
+        assert!(matches!(response, Response::Error { errno: libc::EINVAL, .. }), "{:?}", response);
+
+        let response = process_request(Request::ReadBlocks { start: 1, count: 4 }, storage.clone(), limit);
+        assert!(matches!(response, Response::Blocks { ref blocks } if blocks.len() == 4), "{:?}", response);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn remote_out_of_range_read_maps_to_eio() {
+        let dir = TempDir::new("net-range");
+        let config = test_config(dir.path());
+        let fingerprint = config.fingerprint.clone();
+        let fs = BWFS::new(config).unwrap();
+        let addr = serve(fs.storage_handle()).await;
+        let client = NetworkClient::new(vec![addr], fingerprint);
+
+        let error = client.read_block(0, 1000).await.unwrap_err();
+        // El errno remoto llega intacto y se distingue de un fallo de E/S
+        let remote_errno = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<std::io::Error>()?.raw_os_error());
+        assert_eq!(remote_errno, Some(libc::EINVAL));
+        assert!(error.to_string().contains("out of range"), "{:#}", error);
+        assert_eq!(crate::fs::storage_errno(&error), libc::EIO);
     }
 }
@@ -1,10 +1,256 @@
-use image::{ImageBuffer, Luma};
-use std::path::PathBuf;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{ColorType, ImageEncoder};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::sync::Mutex;
+use std::time::Duration;
 use anyhow::Result;
+use crate::hash::Digest;
+use crate::merkle::MerkleTree;
+use crate::cache::{BlockCache, CacheStats};
+use crate::config::{Encoding, PngCompression};
+
+/// Offset inside the superblock (block 0) where the Merkle root is stored
+const SUPERBLOCK_MERKLE_OFFSET: usize = 256;
+
+/// Marker preceding the Merkle root in the superblock
+const SUPERBLOCK_MERKLE_MAGIC: &[u8; 4] = b"MRKL";
+
+/// Minimum block size (bytes) able to hold the Merkle root in the superblock
+pub const MERKLE_SUPERBLOCK_BYTES: usize = SUPERBLOCK_MERKLE_OFFSET + SUPERBLOCK_MERKLE_MAGIC.len() + 32;
+
+/// Offset inside the superblock where the filesystem geometry is stored
+const SUPERBLOCK_GEOMETRY_OFFSET: usize = 192;
+
+/// Marker preceding the geometry record in the superblock
+const SUPERBLOCK_GEOMETRY_MAGIC: &[u8; 4] = b"GEOM";
+
+/// Geometry record: magic, width, height, total blocks, total inodes,
+/// fingerprint length, bits per pixel (0 in records written before it
+/// existed, meaning 1), encoding (0 = grayscale, 1 = RGB)
+const SUPERBLOCK_GEOMETRY_LEN: usize = SUPERBLOCK_GEOMETRY_MAGIC.len() + 4 * 4 + 2 + 1 + 1;
+
+/// Offset inside the superblock where the metadata area record is stored
+const SUPERBLOCK_METADATA_OFFSET: usize = 320;
+
+/// Marker preceding the metadata area record in the superblock
+const SUPERBLOCK_METADATA_MAGIC: &[u8; 4] = b"META";
+
+/// Metadata area record: magic, first block, blocks per copy, active copy,
+/// checkpoint sequence, payload length, SHA-256 of the payload
+const SUPERBLOCK_METADATA_LEN: usize = SUPERBLOCK_METADATA_MAGIC.len() + 4 + 4 + 1 + 8 + 8 + 32;
+
+/// Minimum block size (bytes) able to hold the metadata area record in the superblock
+pub const METADATA_SUPERBLOCK_BYTES: usize = SUPERBLOCK_METADATA_OFFSET + SUPERBLOCK_METADATA_LEN;
+
+/// 1-bit samples brighter than this read as 1
+pub const DEFAULT_PIXEL_THRESHOLD: u8 = 127;
+
+/// Marks a data block whose payload is zlib-compressed. The header is
+/// the magic, the uncompressed length and its CRC32 (both little endian).
+const BLOCK_COMPRESSION_MAGIC: &[u8; 4] = b"BWZ1";
+const BLOCK_COMPRESSION_HEADER: usize = 12;
+
+/// Supported data-block encodings, in bits stored per pixel
+pub const SUPPORTED_BITS_PER_PIXEL: [u8; 4] = [1, 2, 4, 8];
+
+/// Filesystem geometry recorded in the superblock by mkfs.bwfs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Geometry {
+    pub block_width: u32,
+    pub block_height: u32,
+    pub total_blocks: u32,
+    pub total_inodes: u32,
+    pub fingerprint: String,
+    pub bits_per_pixel: u8,
+    pub encoding: Encoding,
+}
+
+/// Superblock records kept by `BlockStorage::rewrite_superblock`, i.e. the
+/// ones that could still be read from the damaged block 0
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SuperblockRecovery {
+    pub geometry: bool,
+    pub merkle_root: bool,
+    pub metadata_record: bool,
+}
+
+/// Blocks reserved by mkfs.bwfs for the metadata checkpoint: two copies of
+/// `blocks_per_copy` blocks from `first_block`, written alternately so an
+/// interrupted checkpoint leaves the previous one intact
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataArea {
+    pub first_block: u32,
+    pub blocks_per_copy: u32,
+}
+
+impl MetadataArea {
+    /// Every block of both copies
+    pub fn blocks(&self) -> std::ops::Range<u32> {
+        self.first_block..self.first_block + 2 * self.blocks_per_copy
+    }
+    
+    /// Blocks of copy 0 or 1
+    fn copy(&self, index: u8) -> std::ops::Range<u32> {
+        let start = self.first_block + index as u32 * self.blocks_per_copy;
+        start..start + self.blocks_per_copy
+    }
+}
+
+/// Metadata area record kept in the superblock: where the area is and
+/// which checkpoint its active copy holds (`len` 0 = none written yet)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataRecord {
+    pub area: MetadataArea,
+    pub active: u8,
+    pub seq: u64,
+    pub len: u64,
+    pub digest: Digest,
+}
+
+/// Lock file created in the storage directory while `mount.bwfs` serves
+/// it; holds the PID of the mounting process
+pub const MOUNT_MARKER: &str = ".bwfs.lock";
+
+/// Whether a running process holds the storage at `base_path` mounted.
+/// Markers left behind by a process that no longer exists are ignored.
+pub fn is_mounted(base_path: &Path) -> bool {
+    mount_holder(base_path).is_some()
+}
+
+/// PID recorded in the lock file at `base_path` if that process is still
+/// alive (0 for a lock file that cannot be parsed)
+fn mount_holder(base_path: &Path) -> Option<u32> {
+    let pid = fs::read_to_string(base_path.join(MOUNT_MARKER)).ok()?;
+    match pid.trim().parse::<u32>() {
+        Ok(pid) => Path::new("/proc").join(pid.to_string()).exists().then_some(pid),
+        // Marcador ilegible: mejor asumir que está montado
+        Err(_) => Some(0),
+    }
+}
+
+/// Exclusive hold on a storage directory for one mount, released on drop.
+/// Two processes serving the same storage would race on the metadata file
+/// and the block images.
+#[derive(Debug)]
+pub struct MountLock {
+    path: PathBuf,
+}
+
+impl MountLock {
+    /// Create the lock file at `base_path` with this process's PID. Fails
+    /// if a live process holds it; a lock left by a dead process is
+    /// replaced.
+    pub fn acquire(base_path: &Path) -> Result<Self> {
+        let path = base_path.join(MOUNT_MARKER);
+        loop {
+            // create_new: dos montajes simultáneos no pueden ganar los dos
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    use std::io::Write;
+                    file.write_all(std::process::id().to_string().as_bytes())?;
+                    file.sync_all()?;
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+            
+            match mount_holder(base_path) {
+                Some(0) => anyhow::bail!(
+                    "{:?} exists but holds no PID; if no mount.bwfs is running, remove it",
+                    path
+                ),
+                Some(pid) => anyhow::bail!(
+                    "{} is already mounted by process {} (lock file {:?})",
+                    base_path.display(),
+                    pid,
+                    path
+                ),
+                None => {
+                    log::warn!("Removing stale lock file {:?} left by a process that no longer runs", path);
+                    match fs::remove_file(&path) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for MountLock {
+    fn drop(&mut self) {
+        // Solo se borra si sigue siendo nuestro
+        let ours = fs::read_to_string(&self.path)
+            .is_ok_and(|pid| pid.trim() == std::process::id().to_string());
+        if ours {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// fsync a directory so that entries created/renamed inside it are durable
+pub(crate) fn sync_dir(path: &Path) -> Result<()> {
+    fs::File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+/// fsync the given block images and then their directory. Needs no lock on
+/// the storage. On failure, returns the blocks that are still not durable.
+pub(crate) fn sync_block_files(
+    blocks: &[(u32, PathBuf)],
+    dir: &Path,
+) -> std::result::Result<(), (Vec<u32>, anyhow::Error)> {
+    for (i, (block_num, path)) in blocks.iter().enumerate() {
+        if let Err(e) = fs::File::open(path).and_then(|f| f.sync_all()) {
+            let failed = blocks[i..].iter().map(|(b, _)| *b).collect();
+            return Err((failed, anyhow::anyhow!("Failed to sync block {}: {}", block_num, e)));
+        }
+    }
+    if !blocks.is_empty() {
+        if let Err(e) = sync_dir(dir) {
+            return Err((blocks.iter().map(|(b, _)| *b).collect(), e));
+        }
+    }
+    Ok(())
+}
+
+/// Deterministic failures injected into a `BlockStorage` to drive the
+/// filesystem's error paths in tests (see `BlockStorage::set_faults`)
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct FaultPlan {
+    /// Fail the Nth block read (1-based) with an I/O error
+    pub fail_read: Option<u64>,
+    
+    /// Fail the Nth block write (1-based) with an I/O error
+    pub fail_write: Option<u64>,
+    
+    /// Invert every byte returned by reads
+    pub corrupt_reads: bool,
+    
+    /// Fail every write with ENOSPC
+    pub no_space: bool,
+}
+
+/// A fault plan plus the reads/writes counted since it was installed
+#[cfg(test)]
+#[derive(Debug)]
+struct FaultState {
+    plan: FaultPlan,
+    reads: u64,
+    writes: u64,
+}
 
 /// Block storage using black and white images
-/// Each pixel can store 1 bit of information (black=0, white=1)
+/// Each pixel stores 1 bit of information (black=0, white=1), or with
+/// `with_bits_per_pixel` several bits as evenly spaced gray levels. With
+/// `Encoding::Rgb` each of the three channels carries that many bits. The
+/// superblock is always grayscale at 1 bit per pixel so it can be read
+/// before the encoding is known.
 pub struct BlockStorage {
     /// Base path for storing images
     base_path: PathBuf,
@@ -13,14 +259,60 @@ pub struct BlockStorage {
     block_width: u32,
     block_height: u32,
     
-    /// Bytes per block (width * height / 8)
+    /// Bytes per data block (width * height * bits_per_pixel / 8)
     bytes_per_block: usize,
     
+    /// Bits stored in each pixel (each channel, in RGB) of a data block
+    bits_per_pixel: u8,
+    
+    /// Grayscale or RGB data blocks
+    encoding: Encoding,
+    
+    /// 1-bit samples above this value read as 1
+    pixel_threshold: u8,
+    
     /// Total number of blocks
     total_blocks: u32,
     
     /// Filesystem fingerprint
     fingerprint: String,
+    
+    /// Blocks written since the last `sync_blocks()` (PNG not yet fsynced)
+    unsynced: Mutex<HashSet<u32>>,
+    
+    /// Merkle tree over the data blocks (only in integrity mode)
+    merkle: Option<Mutex<MerkleTree>>,
+    
+    /// Retries for transient read failures and the initial backoff
+    read_retries: u32,
+    retry_backoff: Duration,
+    
+    /// zlib level used when encoding block images
+    compression: CompressionType,
+    
+    /// Injected failures (unit tests only)
+    #[cfg(test)]
+    faults: Option<Mutex<FaultState>>,
+    
+    /// Decoded blocks, to skip PNG decoding on repeated reads
+    cache: Option<Mutex<BlockCache>>,
+    
+    /// Check every read against its Merkle leaf (bypasses the cache)
+    paranoid: bool,
+    
+    /// Blocks holding the metadata checkpoint (outside the Merkle tree)
+    metadata_area: Option<MetadataArea>,
+    
+    /// zlib-compress data block payloads before packing them into pixels
+    block_compression: bool,
+    
+    /// Index (pointer) blocks held in memory while an operation updates
+    /// them, by block number; `None` when writes are not being deferred
+    deferred_pointers: Mutex<Option<HashMap<u32, Vec<u8>>>>,
+    
+    /// Images written per block (unit tests only)
+    #[cfg(test)]
+    write_counts: Mutex<HashMap<u32, u32>>,
 }
 
 impl BlockStorage {
@@ -42,13 +334,265 @@ pub fn new(
             block_width,
             block_height,
             bytes_per_block,
+            bits_per_pixel: 1,
+            encoding: Encoding::Grayscale,
+            pixel_threshold: DEFAULT_PIXEL_THRESHOLD,
             total_blocks,
             fingerprint,
+            unsynced: Mutex::new(HashSet::new()),
+            merkle: None,
+            read_retries: 0,
+            retry_backoff: Duration::from_millis(0),
+            compression: CompressionType::Default,
+            #[cfg(test)]
+            faults: None,
+            cache: None,
+            paranoid: false,
+            metadata_area: None,
+            block_compression: false,
+            deferred_pointers: Mutex::new(None),
+            #[cfg(test)]
+            write_counts: Mutex::new(HashMap::new()),
         })
     }
     
+    /// Store `bits_per_pixel` bits (1, 2, 4 or 8) in each pixel of the data
+    /// blocks, multiplying their capacity. Must match the value the
+    /// filesystem was created with.
+    pub fn with_bits_per_pixel(mut self, bits_per_pixel: u8) -> Result<Self> {
+        if !SUPPORTED_BITS_PER_PIXEL.contains(&bits_per_pixel) {
+            anyhow::bail!("bits_per_pixel must be 1, 2, 4 or 8 (got {})", bits_per_pixel);
+        }
+        self.bits_per_pixel = bits_per_pixel;
+        self.bytes_per_block = self.data_block_bytes();
+        Ok(self)
+    }
+    
+    /// Store data blocks as grayscale or RGB images; RGB carries three
+    /// times the data per image. Must match the value the filesystem was
+    /// created with.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self.bytes_per_block = self.data_block_bytes();
+        self
+    }
+    
+    /// Read 1-bit samples brighter than `threshold` as 1 (default 127).
+    /// Writes still produce pure black and white; a different midpoint
+    /// helps when images were brightened or darkened after being written.
+    /// Multi-bit samples always round to the nearest gray level.
+    pub fn with_pixel_threshold(mut self, threshold: u8) -> Self {
+        self.pixel_threshold = threshold;
+        self
+    }
+    
+    /// Capacity of a data block under the current encoding
+    fn data_block_bytes(&self) -> usize {
+        self.block_width as usize
+            * self.block_height as usize
+            * self.bits_per_pixel as usize
+            * self.encoding.channels()
+            / 8
+    }
+    
+    /// Retry transient read failures up to `retries` times, waiting
+    /// `backoff_ms` before the first retry and doubling after each one
+    pub fn with_read_retry(mut self, retries: u32, backoff_ms: u64) -> Self {
+        self.read_retries = retries;
+        self.retry_backoff = Duration::from_millis(backoff_ms);
+        self
+    }
+    
+    /// Encode block images with the given zlib effort
+    pub fn with_png_compression(mut self, compression: PngCompression) -> Self {
+        self.compression = match compression {
+            PngCompression::Fast => CompressionType::Fast,
+            PngCompression::Default => CompressionType::Default,
+            PngCompression::Best => CompressionType::Best,
+        };
+        self
+    }
+    
+    /// zlib-compress the bytes of each data block before packing them into
+    /// pixels, when that makes them smaller. Blocks that do not shrink are
+    /// stored raw. Compressed blocks are recognized on read whatever this
+    /// setting, so it can change at any time. The superblock is never
+    /// compressed.
+    pub fn with_block_compression(mut self, enabled: bool) -> Self {
+        self.block_compression = enabled;
+        self
+    }
+    
+    /// Cache decoded blocks in memory, using at most `max_bytes` (0 disables
+    /// the cache)
+    pub fn with_cache(mut self, max_bytes: usize) -> Self {
+        self.cache = (max_bytes > 0).then(|| Mutex::new(BlockCache::new(max_bytes)));
+        self
+    }
+    
+    /// Cache up to `blocks` decoded data blocks (0 disables the cache).
+    /// Sized from the current block capacity, so call it after
+    /// `with_bits_per_pixel`.
+    pub fn with_cache_capacity(self, blocks: usize) -> Self {
+        let max_bytes = blocks.saturating_mul(self.bytes_per_block);
+        self.with_cache(max_bytes)
+    }
+    
+    /// Verify every block read from disk against its Merkle leaf. Only has
+    /// an effect once integrity mode is enabled.
+    pub fn with_paranoid(mut self, paranoid: bool) -> Self {
+        self.paranoid = paranoid;
+        self
+    }
+    
+    /// Counters of the block cache, if enabled
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| cache.lock().unwrap().stats())
+    }
+    
+    /// Keep these blocks in the cache regardless of LRU pressure (no effect
+    /// without a cache)
+    pub fn with_pinned_blocks(self, blocks: &[u32]) -> Self {
+        if let Some(cache) = &self.cache {
+            let mut cache = cache.lock().unwrap();
+            for &block_num in blocks {
+                cache.pin(block_num);
+            }
+        }
+        self
+    }
+    
+    /// Evict cached blocks until the cache holds at most `bytes`
+    pub fn shrink_cache(&self, bytes: usize) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().shrink_to(bytes);
+        }
+    }
+    
+    /// Drop every cached block and reset the cache to its initial capacity
+    pub fn trim_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().trim();
+        }
+    }
+    
+    /// Forget a block's cached contents before it changes on disk
+    fn invalidate_cached(&self, block_num: u32) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().remove(block_num);
+        }
+    }
+    
+    /// Install (or with `None`, remove) a fault plan. Read and write
+    /// counters restart from zero.
+    #[cfg(test)]
+    pub fn set_faults(&mut self, plan: Option<FaultPlan>) {
+        self.faults = plan.map(|plan| Mutex::new(FaultState { plan, reads: 0, writes: 0 }));
+    }
+    
+    /// Count a read and fail it if the fault plan says so
+    #[cfg(test)]
+    fn inject_read_fault(&self, block_num: u32) -> Result<()> {
+        if let Some(faults) = &self.faults {
+            let mut state = faults.lock().unwrap();
+            state.reads += 1;
+            if state.plan.fail_read == Some(state.reads) {
+                return Err(std::io::Error::other(format!(
+                    "injected fault on read #{} (block {})",
+                    state.reads, block_num
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+    
+    /// Count a write and fail it if the fault plan says so
+    #[cfg(test)]
+    fn inject_write_fault(&self, block_num: u32) -> Result<()> {
+        if let Some(faults) = &self.faults {
+            let mut state = faults.lock().unwrap();
+            state.writes += 1;
+            if state.plan.no_space {
+                return Err(std::io::Error::from_raw_os_error(libc::ENOSPC).into());
+            }
+            if state.plan.fail_write == Some(state.writes) {
+                return Err(std::io::Error::other(format!(
+                    "injected fault on write #{} (block {})",
+                    state.writes, block_num
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+    
+    /// Whether the fault plan asks for reads to return corrupt data
+    #[cfg(test)]
+    fn corrupt_reads(&self) -> bool {
+        self.faults
+            .as_ref()
+            .map(|faults| faults.lock().unwrap().plan.corrupt_reads)
+            .unwrap_or(false)
+    }
+    
+    /// Whether a fault plan is installed; the cache is bypassed so every
+    /// access reaches the injection points
+    #[cfg(test)]
+    fn faults_active(&self) -> bool {
+        self.faults.is_some()
+    }
+    
+    // Fuera de los tests no hay plan de fallos: los puntos de inyección no
+    // hacen nada
+    #[cfg(not(test))]
+    fn inject_read_fault(&self, _block_num: u32) -> Result<()> {
+        Ok(())
+    }
+    
+    #[cfg(not(test))]
+    fn inject_write_fault(&self, _block_num: u32) -> Result<()> {
+        Ok(())
+    }
+    
+    #[cfg(not(test))]
+    fn corrupt_reads(&self) -> bool {
+        false
+    }
+    
+    #[cfg(not(test))]
+    fn faults_active(&self) -> bool {
+        false
+    }
+    
+    /// Encode a block image as PNG at the configured compression level.
+    ///
+    /// The image is written to `<block>.png.tmp` and renamed over the block
+    /// file, so a failed save (disk full, permissions) never leaves a
+    /// partial image behind: the block keeps its previous contents.
+    fn save_image(&self, samples: &[u8], color: ColorType, path: &Path) -> Result<()> {
+        use std::io::Write;
+        
+        let tmp_path = path.with_extension("png.tmp");
+        let result = (|| -> Result<()> {
+            let mut file = std::io::BufWriter::new(fs::File::create(&tmp_path)?);
+            PngEncoder::new_with_quality(&mut file, self.compression, FilterType::Adaptive)
+                .write_image(samples, self.block_width, self.block_height, color)?;
+            file.flush()?;
+            drop(file);
+            fs::rename(&tmp_path, path)?;
+            Ok(())
+        })();
+        
+        if let Err(e) = result {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.context(format!("Failed to save block image {:?}", path)));
+        }
+        Ok(())
+    }
+    
     /// Get the image path for a block number
-    fn get_block_path(&self, block_num: u32) -> PathBuf {
+    pub fn get_block_path(&self, block_num: u32) -> PathBuf {
         self.base_path.join(format!("block_{:08}.png", block_num))
     }
     
@@ -59,88 +603,786 @@ pub fn init_block(&self, block_num: u32) -> Result<()> {
         }
         
         // Create a white image (all bits set to 1 = empty)
-        let img = ImageBuffer::from_pixel(
-            self.block_width,
-            self.block_height,
-            Luma([255u8])
-        );
+        let samples = vec![255u8; self.samples_per_block(block_num)];
         
         let path = self.get_block_path(block_num);
-        img.save(&path)?;
+        self.invalidate_cached(block_num);
+        self.save_image(&samples, self.color_for(block_num), &path)?;
+        self.unsynced.lock().unwrap().insert(block_num);
+        self.update_merkle_leaf(block_num, &[]);
         
         Ok(())
     }
     
+    /// Hold index block writes (`write_pointer_block`) in memory until
+    /// `flush_pointer_blocks`, so an operation that updates many pointers
+    /// stores each index block once instead of once per pointer
+    pub fn defer_pointer_blocks(&self) {
+        let mut deferred = self.deferred_pointers.lock().unwrap();
+        if deferred.is_none() {
+            *deferred = Some(HashMap::new());
+        }
+    }
+    
+    /// Store every index block held since `defer_pointer_blocks` and stop
+    /// deferring. On error the remaining blocks are still attempted.
+    pub fn flush_pointer_blocks(&self) -> Result<()> {
+        let Some(deferred) = self.deferred_pointers.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let mut blocks: Vec<_> = deferred.into_iter().collect();
+        blocks.sort_unstable_by_key(|(block_num, _)| *block_num);
+        let mut result = Ok(());
+        for (block_num, data) in blocks {
+            if let Err(e) = self.write_block(block_num, &data) {
+                log::error!("cannot store index block {}: {:#}", block_num, e);
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+    
+    /// Write an index block, or keep it in memory while writes are deferred
+    pub fn write_pointer_block(&self, block_num: u32, data: &[u8]) -> Result<()> {
+        if let Some(deferred) = self.deferred_pointers.lock().unwrap().as_mut() {
+            if block_num >= self.total_blocks {
+                anyhow::bail!("Block number {} exceeds total blocks", block_num);
+            }
+            deferred.insert(block_num, data.to_vec());
+            return Ok(());
+        }
+        self.write_block(block_num, data)
+    }
+    
+    /// Deferred contents of `block_num`, if it is a held index block
+    fn deferred_pointer_block(&self, block_num: u32) -> Option<Vec<u8>> {
+        self.deferred_pointers
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|deferred| deferred.get(&block_num).cloned())
+    }
+    
+    /// Number of images written to `block_num` so far
+    #[cfg(test)]
+    pub fn write_count(&self, block_num: u32) -> u32 {
+        self.write_counts.lock().unwrap().get(&block_num).copied().unwrap_or(0)
+    }
+    
     /// Read data from a block
+    ///
+    /// Transient I/O failures are retried according to `with_read_retry`;
+    /// permanent errors (out of range, undecodable image) fail immediately.
+    /// Served from the block cache when possible; the cache is bypassed
+    /// while a fault plan is installed and in paranoid mode, where every
+    /// read is decoded from disk and checked against its Merkle leaf.
     pub fn read_block(&self, block_num: u32) -> Result<Vec<u8>> {
+        if let Some(data) = self.deferred_pointer_block(block_num) {
+            return Ok(data);
+        }
+        if self.paranoid {
+            let data = self.read_block_uncached(block_num)?;
+            self.check_merkle_leaf(block_num, &data)?;
+            return Ok(data);
+        }
+        
+        let cache = self.cache.as_ref().filter(|_| !self.faults_active());
+        if let Some(cache) = cache {
+            if let Some(data) = cache.lock().unwrap().get(block_num) {
+                return Ok(data);
+            }
+        }
+        
+        let data = self.read_block_uncached(block_num)?;
+        if let Some(cache) = cache {
+            cache.lock().unwrap().insert(block_num, data.clone());
+        }
+        Ok(data)
+    }
+    
+    /// Read a block from its image on disk, ignoring the cache
+    fn read_block_uncached(&self, block_num: u32) -> Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            match self.read_block_once(block_num) {
+                Ok(data) => return Ok(data),
+                Err(e) if attempt < self.read_retries && Self::is_transient(&e) => {
+                    let delay = self.retry_backoff * 2u32.saturating_pow(attempt);
+                    attempt += 1;
+                    log::warn!(
+                        "read_block({}): transient error '{}', retry {}/{} in {:?}",
+                        block_num, e, attempt, self.read_retries, delay
+                    );
+                    std::thread::sleep(delay);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    
+    /// Whether a read error is worth retrying (I/O) or permanent (bad data)
+    fn is_transient(error: &anyhow::Error) -> bool {
+        if let Some(e) = error.downcast_ref::<image::ImageError>() {
+            return matches!(e, image::ImageError::IoError(_));
+        }
+        error.downcast_ref::<std::io::Error>().is_some()
+    }
+    
+    fn read_block_once(&self, block_num: u32) -> Result<Vec<u8>> {
         if block_num >= self.total_blocks {
             anyhow::bail!("Block number {} exceeds total blocks", block_num);
         }
+        self.inject_read_fault(block_num)?;
         
         let path = self.get_block_path(block_num);
         if !path.exists() {
             // Return empty block if doesn't exist
-            return Ok(vec![0; self.bytes_per_block]);
+            return Ok(vec![0; self.capacity(block_num)]);
         }
         
-        let img = image::open(&path)?.to_luma8();
+        let img = image::open(&path)?;
+        // Nunca decodificar con el formato equivocado: RGB y gris no se mezclan
+        let is_rgb = img.color().channel_count() >= 3;
+        if is_rgb != (self.color_for(block_num) == ColorType::Rgb8) {
+            anyhow::bail!(
+                "Block {} is stored as {} but the filesystem expects {}",
+                block_num,
+                if is_rgb { "RGB" } else { "grayscale" },
+                if is_rgb { "grayscale" } else { "RGB" }
+            );
+        }
+        let samples = if is_rgb { img.to_rgb8().into_raw() } else { img.to_luma8().into_raw() };
+        let mut data = self.decompress_block(block_num, self.decode_pixels(block_num, &samples));
         
-        // Convert pixels to bytes
-        let mut data = Vec::with_capacity(self.bytes_per_block);
-        let pixels = img.as_raw();
+        if self.corrupt_reads() {
+            data.iter_mut().for_each(|byte| *byte = !*byte);
+        }
+        
+        Ok(data)
+    }
+    
+    /// Convert samples (pixels, or R, G, B channels in turn) to bytes: each
+    /// sample holds `bits` bits, most significant first. With 1 bit, white
+    /// (255) = 1 and black (0) = 0.
+    fn decode_pixels(&self, block_num: u32, pixels: &[u8]) -> Vec<u8> {
+        let bits = self.bits_for(block_num);
+        let pixels_per_byte = (8 / bits) as usize;
+        let step = Self::gray_step(bits);
+        let mut data = Vec::with_capacity(self.capacity(block_num));
         
-        for chunk in pixels.chunks(8) {
+        for chunk in pixels.chunks(pixels_per_byte) {
             let mut byte = 0u8;
             for (i, &pixel) in chunk.iter().enumerate() {
-                // White (255) = 1, Black (0) = 0
-                if pixel > 127 {
-                    byte |= 1 << (7 - i);
-                }
+                // Con 1 bit decide el umbral; si no, el nivel de gris más
+                // cercano (redondeo), inverso exacto de write_block
+                let level = if bits == 1 {
+                    u16::from(pixel > self.pixel_threshold)
+                } else {
+                    (pixel as u16 + step / 2) / step
+                };
+                let shift = 8 - bits as usize * (i + 1);
+                byte |= (level as u8) << shift;
             }
             data.push(byte);
         }
+        data
+    }
+    
+    /// Compressed payload for `data` with its header, if block compression
+    /// is on and the result is smaller than the data
+    fn compress_block(&self, block_num: u32, data: &[u8]) -> Option<Vec<u8>> {
+        use std::io::Write;
         
-        Ok(data)
+        if !self.block_compression || block_num == 0 {
+            return None;
+        }
+        let mut payload = Vec::with_capacity(data.len());
+        payload.extend_from_slice(BLOCK_COMPRESSION_MAGIC);
+        payload.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+        let mut encoder = flate2::write::ZlibEncoder::new(payload, flate2::Compression::default());
+        encoder.write_all(data).ok()?;
+        let payload = encoder.finish().ok()?;
+        (payload.len() < data.len()).then_some(payload)
+    }
+    
+    /// Undo `compress_block` on a block's raw bytes. Anything that is not a
+    /// well-formed compressed payload (bad header, stream or checksum) is
+    /// raw data and comes back unchanged. Decompressed data is padded with
+    /// 0xFF to the block capacity, as a short raw write reads back.
+    fn decompress_block(&self, block_num: u32, raw: Vec<u8>) -> Vec<u8> {
+        use std::io::Read;
+        
+        if block_num == 0 || !raw.starts_with(BLOCK_COMPRESSION_MAGIC) {
+            return raw;
+        }
+        let field = |i: usize| u32::from_le_bytes(raw[i..i + 4].try_into().unwrap());
+        let (len, crc) = (field(4) as usize, field(8));
+        let capacity = self.capacity(block_num);
+        if len > capacity {
+            return raw;
+        }
+        
+        // Como mucho len + 1 bytes: un flujo más largo no es nuestro
+        let mut data = Vec::with_capacity(capacity);
+        let decoded = flate2::read::ZlibDecoder::new(&raw[BLOCK_COMPRESSION_HEADER..])
+            .take(len as u64 + 1)
+            .read_to_end(&mut data);
+        if decoded.is_err() || data.len() != len || crc32fast::hash(&data) != crc {
+            return raw;
+        }
+        data.resize(capacity, 0xFF);
+        data
     }
     
     /// Write data to a block
+    ///
+    /// The PNG is written immediately; with a cache the block's new
+    /// contents (as they will read back, padding included) replace the
+    /// cached copy once the image is saved, so a following read is a hit.
+    /// With block compression the bytes are compressed first when that
+    /// saves space.
     pub fn write_block(&self, block_num: u32, data: &[u8]) -> Result<()> {
         if block_num >= self.total_blocks {
             anyhow::bail!("Block number {} exceeds total blocks", block_num);
         }
         
-        if data.len() > self.bytes_per_block {
+        if data.len() > self.capacity(block_num) {
             anyhow::bail!("Data size exceeds block capacity");
         }
+        self.inject_write_fault(block_num)?;
+        // Una escritura directa reemplaza la copia diferida (el bloque de
+        // índices se liberó y se reutilizó)
+        if let Some(deferred) = self.deferred_pointers.lock().unwrap().as_mut() {
+            deferred.remove(&block_num);
+        }
+        let compressed = self.compress_block(block_num, data);
         
-        // Convert bytes to pixels
-        let mut pixels = Vec::with_capacity((self.block_width * self.block_height) as usize);
+        // Convert bytes to pixels (channel samples, in RGB)
+        let bits = self.bits_for(block_num);
+        let mask = (1u16 << bits) - 1;
+        let step = Self::gray_step(bits);
+        let sample_count = self.samples_per_block(block_num);
+        let mut pixels = Vec::with_capacity(sample_count);
         
-        for &byte in data {
-            for i in 0..8 {
-                let bit = (byte >> (7 - i)) & 1;
-                // 1 = white (255), 0 = black (0)
-                pixels.push(if bit == 1 { 255u8 } else { 0u8 });
+        for &byte in compressed.as_deref().unwrap_or(data) {
+            for i in 0..8 / bits as usize {
+                let shift = 8 - bits as usize * (i + 1);
+                let level = (byte as u16 >> shift) & mask;
+                // Con 1 bit: 1 = white (255), 0 = black (0)
+                pixels.push((level * step) as u8);
             }
         }
         
         // Pad with white pixels if needed
-        while pixels.len() < (self.block_width * self.block_height) as usize {
-            pixels.push(255);
+        pixels.resize(sample_count, 255);
+        
+        let path = self.get_block_path(block_num);
+        // Si el guardado falla, la caché no debe conservar la versión vieja
+        self.invalidate_cached(block_num);
+        self.save_image(&pixels, self.color_for(block_num), &path)?;
+        if let Some(cache) = self.cache.as_ref().filter(|_| !self.faults_active()) {
+            let decoded = self.decompress_block(block_num, self.decode_pixels(block_num, &pixels));
+            cache.lock().unwrap().insert(block_num, decoded);
+        }
+        self.unsynced.lock().unwrap().insert(block_num);
+        self.update_merkle_leaf(block_num, data);
+        #[cfg(test)]
+        {
+            *self.write_counts.lock().unwrap().entry(block_num).or_default() += 1;
+        }
+        
+        Ok(())
+    }
+    
+    /// Flush every block written since the last call to stable storage.
+    ///
+    /// Each PNG is fsynced, followed by the storage directory so newly
+    /// created block files are durable too. Blocks that could not be synced
+    /// stay pending and are retried on the next call.
+    pub fn sync_blocks(&self) -> Result<()> {
+        let pending = self.take_unsynced();
+        if let Err((failed, e)) = sync_block_files(&pending, &self.base_path) {
+            self.requeue_unsynced(&failed);
+            return Err(e);
+        }
+        Ok(())
+    }
+    
+    /// Take the list of blocks written since the last sync, with their paths.
+    /// The caller becomes responsible for syncing them (see `sync_block_files`)
+    /// or handing them back with `requeue_unsynced`.
+    pub fn take_unsynced(&self) -> Vec<(u32, PathBuf)> {
+        let mut pending: Vec<u32> = self.unsynced.lock().unwrap().drain().collect();
+        pending.sort_unstable();
+        pending
+            .into_iter()
+            .map(|block_num| (block_num, self.get_block_path(block_num)))
+            .collect()
+    }
+    
+    /// Mark blocks as still needing a sync
+    pub fn requeue_unsynced(&self, blocks: &[u32]) {
+        self.unsynced.lock().unwrap().extend(blocks);
+    }
+    
+    /// Number of blocks in the filesystem
+    pub fn total_blocks(&self) -> u32 {
+        self.total_blocks
+    }
+    
+    /// Filesystem fingerprint, also the shared secret of the network protocol
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+    
+    /// Storage directory holding the block images
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+    
+    /// Leaf hash of a block as it reads back: short writes are padded with
+    /// white pixels, which decode as 0xFF bytes.
+    fn block_leaf_hash(&self, data: &[u8]) -> Digest {
+        let mut block = data[..data.len().min(self.bytes_per_block)].to_vec();
+        block.resize(self.bytes_per_block, 0xFF);
+        MerkleTree::leaf_hash(&block)
+    }
+    
+    /// Whether `block_num` has no Merkle leaf: the superblock stores the
+    /// root, and the metadata area (which holds the tree) is covered by the
+    /// hash in its superblock record instead
+    fn outside_tree(&self, block_num: u32) -> bool {
+        block_num == 0 || self.metadata_area.is_some_and(|area| area.blocks().contains(&block_num))
+    }
+    
+    fn update_merkle_leaf(&self, block_num: u32, data: &[u8]) {
+        if self.outside_tree(block_num) {
+            return;
+        }
+        if let Some(tree) = &self.merkle {
+            let leaf = self.block_leaf_hash(data);
+            tree.lock().unwrap().update(block_num as usize, leaf);
+        }
+    }
+    
+    /// Fail if a block's contents do not match its Merkle leaf. Passes when
+    /// integrity mode is off and for blocks without a leaf.
+    fn check_merkle_leaf(&self, block_num: u32, data: &[u8]) -> Result<()> {
+        if self.outside_tree(block_num) {
+            return Ok(());
+        }
+        if let Some(tree) = &self.merkle {
+            if tree.lock().unwrap().leaf(block_num as usize) != Some(self.block_leaf_hash(data)) {
+                anyhow::bail!("Block {} does not match its stored hash", block_num);
+            }
+        }
+        Ok(())
+    }
+    
+    /// Build a Merkle tree by hashing every block currently on disk
+    pub fn build_merkle_tree(&self) -> Result<MerkleTree> {
+        let mut leaves = Vec::with_capacity(self.total_blocks as usize);
+        for block_num in 0..self.total_blocks {
+            if self.outside_tree(block_num) {
+                leaves.push([0u8; 32]);
+                continue;
+            }
+            let data = self.read_block(block_num)?;
+            leaves.push(self.block_leaf_hash(&data));
+        }
+        Ok(MerkleTree::from_leaves(leaves))
+    }
+    
+    /// Enable integrity mode with the given tree
+    pub fn enable_merkle(&mut self, tree: MerkleTree) {
+        self.merkle = Some(Mutex::new(tree));
+    }
+    
+    /// Snapshot of the Merkle tree, if integrity mode is enabled
+    pub fn merkle_tree(&self) -> Option<MerkleTree> {
+        self.merkle.as_ref().map(|t| t.lock().unwrap().clone())
+    }
+    
+    /// Current Merkle root, if integrity mode is enabled
+    pub fn merkle_root(&self) -> Option<Digest> {
+        self.merkle.as_ref().map(|t| t.lock().unwrap().root())
+    }
+    
+    /// Recompute every block's hash from disk and return the blocks whose
+    /// contents no longer match the tree
+    pub fn verify_merkle(&self) -> Result<Vec<u32>> {
+        let tree = self
+            .merkle_tree()
+            .ok_or_else(|| anyhow::anyhow!("Integrity mode is not enabled"))?;
+        
+        let mut divergent = Vec::new();
+        for block_num in (1..self.total_blocks).filter(|&b| !self.outside_tree(b)) {
+            let data = self.read_block_uncached(block_num)?;
+            if tree.leaf(block_num as usize) != Some(self.block_leaf_hash(&data)) {
+                divergent.push(block_num);
+            }
+        }
+        Ok(divergent)
+    }
+    
+    /// Read the Merkle root recorded in the superblock
+    pub fn stored_merkle_root(&self) -> Result<Option<Digest>> {
+        let end = SUPERBLOCK_MERKLE_OFFSET + SUPERBLOCK_MERKLE_MAGIC.len() + 32;
+        if end > self.superblock_bytes() {
+            return Ok(None);
+        }
+        let data = self.read_block(0)?;
+        let magic_end = SUPERBLOCK_MERKLE_OFFSET + SUPERBLOCK_MERKLE_MAGIC.len();
+        if &data[SUPERBLOCK_MERKLE_OFFSET..magic_end] != SUPERBLOCK_MERKLE_MAGIC {
+            return Ok(None);
+        }
+        let mut root = [0u8; 32];
+        root.copy_from_slice(&data[magic_end..end]);
+        Ok(Some(root))
+    }
+    
+    /// Record the Merkle root in the superblock, keeping the fingerprint
+    pub fn write_merkle_root(&self, root: &Digest) -> Result<()> {
+        let magic_end = SUPERBLOCK_MERKLE_OFFSET + SUPERBLOCK_MERKLE_MAGIC.len();
+        let end = magic_end + 32;
+        if end > self.superblock_bytes() {
+            anyhow::bail!("Block too small to hold the Merkle root in the superblock");
+        }
+        let mut data = self.read_block(0)?;
+        data.resize(self.superblock_bytes(), 0);
+        data[SUPERBLOCK_MERKLE_OFFSET..magic_end].copy_from_slice(SUPERBLOCK_MERKLE_MAGIC);
+        data[magic_end..end].copy_from_slice(root);
+        self.write_block(0, &data)
+    }
+    
+    /// Record the filesystem geometry in the superblock, after the
+    /// fingerprint and before the Merkle root, so `read_geometry` can
+    /// configure a mount without `config.ini`
+    pub fn write_geometry(&self, total_inodes: u32) -> Result<()> {
+        let end = SUPERBLOCK_GEOMETRY_OFFSET + SUPERBLOCK_GEOMETRY_LEN;
+        if end > self.superblock_bytes() {
+            anyhow::bail!("Block too small to hold the geometry in the superblock");
+        }
+        let fingerprint = self.fingerprint.as_bytes();
+        if fingerprint.len() > SUPERBLOCK_GEOMETRY_OFFSET {
+            anyhow::bail!(
+                "Fingerprint longer than {} bytes overlaps the geometry record",
+                SUPERBLOCK_GEOMETRY_OFFSET
+            );
         }
         
-        let img: ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::from_vec(
-            self.block_width,
-            self.block_height,
-            pixels
-        ).ok_or_else(|| anyhow::anyhow!("Failed to create image from pixels"))?;
+        let mut record = Vec::with_capacity(SUPERBLOCK_GEOMETRY_LEN);
+        record.extend_from_slice(SUPERBLOCK_GEOMETRY_MAGIC);
+        record.extend_from_slice(&self.block_width.to_le_bytes());
+        record.extend_from_slice(&self.block_height.to_le_bytes());
+        record.extend_from_slice(&self.total_blocks.to_le_bytes());
+        record.extend_from_slice(&total_inodes.to_le_bytes());
+        record.extend_from_slice(&(fingerprint.len() as u16).to_le_bytes());
+        record.push(self.bits_per_pixel);
+        record.push(match self.encoding {
+            Encoding::Grayscale => 0,
+            Encoding::Rgb => 1,
+        });
         
-        let path = self.get_block_path(block_num);
-        img.save(&path)?;
+        let mut data = self.read_block(0)?;
+        data.resize(self.superblock_bytes(), 0);
+        data[SUPERBLOCK_GEOMETRY_OFFSET..end].copy_from_slice(&record);
+        self.write_block(0, &data)
+    }
+    
+    /// Read the geometry recorded in the superblock of the filesystem stored
+    /// at `base_path`. The block dimensions are taken from the superblock
+    /// image itself and must agree with the record.
+    pub fn read_geometry(base_path: &str) -> Result<Geometry> {
+        let storage = Self::open_superblock(base_path)?;
+        let (width, height) = (storage.block_width, storage.block_height);
+        let end = SUPERBLOCK_GEOMETRY_OFFSET + SUPERBLOCK_GEOMETRY_LEN;
+        if end > storage.superblock_bytes() {
+            anyhow::bail!("Superblock too small to hold a geometry record");
+        }
+        let data = storage.read_block(0)?;
+        let record = &data[SUPERBLOCK_GEOMETRY_OFFSET..end];
+        let (magic, fields) = record.split_at(SUPERBLOCK_GEOMETRY_MAGIC.len());
+        if magic != SUPERBLOCK_GEOMETRY_MAGIC {
+            anyhow::bail!("Superblock has no geometry record (created by an older mkfs.bwfs?)");
+        }
+        
+        let field = |i: usize| u32::from_le_bytes(fields[i * 4..i * 4 + 4].try_into().unwrap());
+        let fingerprint_len = u16::from_le_bytes(fields[16..18].try_into().unwrap()) as usize;
+        if fingerprint_len == 0 || fingerprint_len > SUPERBLOCK_GEOMETRY_OFFSET {
+            anyhow::bail!("Superblock records an invalid fingerprint length {}", fingerprint_len);
+        }
+        let geometry = Geometry {
+            block_width: field(0),
+            block_height: field(1),
+            total_blocks: field(2),
+            total_inodes: field(3),
+            fingerprint: String::from_utf8(data[..fingerprint_len].to_vec())
+                .map_err(|_| anyhow::anyhow!("Superblock fingerprint is not valid UTF-8"))?,
+            bits_per_pixel: match fields[18] {
+                0 => 1,
+                bits => bits,
+            },
+            encoding: match fields[19] {
+                0 => Encoding::Grayscale,
+                1 => Encoding::Rgb,
+                other => anyhow::bail!("Superblock records unknown block encoding {}", other),
+            },
+        };
+        
+        if (geometry.block_width, geometry.block_height) != (width, height) {
+            anyhow::bail!(
+                "Superblock records {}x{} blocks but the image is {}x{}",
+                geometry.block_width, geometry.block_height, width, height
+            );
+        }
+        if geometry.total_blocks == 0 || geometry.total_inodes == 0 {
+            anyhow::bail!("Superblock records an empty filesystem geometry");
+        }
+        if !SUPPORTED_BITS_PER_PIXEL.contains(&geometry.bits_per_pixel) {
+            anyhow::bail!("Superblock records unsupported {} bits per pixel", geometry.bits_per_pixel);
+        }
+        
+        Ok(geometry)
+    }
+    
+    /// Storage able to read only the superblock at `base_path`, sized from
+    /// the superblock image itself
+    fn open_superblock(base_path: &str) -> Result<Self> {
+        let path = Path::new(base_path).join(format!("block_{:08}.png", 0));
+        if !path.exists() {
+            anyhow::bail!("No superblock found at {:?}. Did you run mkfs.bwfs?", path);
+        }
+        
+        let (width, height) = image::image_dimensions(&path)?;
+        if width == 0 || height == 0 || width > 1000 || height > 1000 {
+            anyhow::bail!("Superblock image has invalid dimensions {}x{}", width, height);
+        }
+        
+        Self::new(base_path, width, height, 1, String::new())
+    }
+    
+    /// Reserve `area` for the metadata checkpoint; its blocks leave the
+    /// Merkle tree. Set before building or enabling the tree.
+    pub fn set_metadata_area(&mut self, area: Option<MetadataArea>) {
+        self.metadata_area = area;
+    }
+    
+    /// Blocks reserved for the metadata checkpoint, if any
+    pub fn metadata_area(&self) -> Option<MetadataArea> {
+        self.metadata_area
+    }
+    
+    /// Read the metadata area record from the superblock
+    pub fn read_metadata_record(&self) -> Result<Option<MetadataRecord>> {
+        if METADATA_SUPERBLOCK_BYTES > self.superblock_bytes() {
+            return Ok(None);
+        }
+        let data = self.read_block(0)?;
+        let record = &data[SUPERBLOCK_METADATA_OFFSET..METADATA_SUPERBLOCK_BYTES];
+        let (magic, fields) = record.split_at(SUPERBLOCK_METADATA_MAGIC.len());
+        if magic != SUPERBLOCK_METADATA_MAGIC {
+            return Ok(None);
+        }
+        
+        let u32_at = |i: usize| u32::from_le_bytes(fields[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(fields[i..i + 8].try_into().unwrap());
+        let area = MetadataArea {
+            first_block: u32_at(0),
+            blocks_per_copy: u32_at(4),
+        };
+        let active = fields[8];
+        if area.first_block == 0 || area.blocks_per_copy == 0 || area.blocks().end > self.total_blocks || active > 1 {
+            anyhow::bail!("Superblock has an invalid metadata area record");
+        }
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&fields[25..57]);
+        
+        Ok(Some(MetadataRecord {
+            area,
+            active,
+            seq: u64_at(9),
+            len: u64_at(17),
+            digest,
+        }))
+    }
+    
+    /// Whether the filesystem at `base_path` has a metadata checkpoint in
+    /// its metadata area
+    pub fn has_metadata_record(base_path: &str) -> bool {
+        // Sin total_blocks: solo se lee el superblock, el área no se comprueba
+        Self::open_superblock(base_path)
+            .map(|storage| Self { total_blocks: u32::MAX, ..storage })
+            .and_then(|storage| storage.read_metadata_record())
+            .is_ok_and(|record| record.is_some_and(|record| record.len > 0))
+    }
+    
+    /// Record the metadata area in the superblock, keeping everything else
+    fn write_metadata_record(&self, record: &MetadataRecord) -> Result<()> {
+        if METADATA_SUPERBLOCK_BYTES > self.superblock_bytes() {
+            anyhow::bail!("Block too small to hold the metadata area record in the superblock");
+        }
+        
+        let mut bytes = Vec::with_capacity(SUPERBLOCK_METADATA_LEN);
+        bytes.extend_from_slice(SUPERBLOCK_METADATA_MAGIC);
+        bytes.extend_from_slice(&record.area.first_block.to_le_bytes());
+        bytes.extend_from_slice(&record.area.blocks_per_copy.to_le_bytes());
+        bytes.push(record.active);
+        bytes.extend_from_slice(&record.seq.to_le_bytes());
+        bytes.extend_from_slice(&record.len.to_le_bytes());
+        bytes.extend_from_slice(&record.digest);
+        
+        let mut data = self.read_block(0)?;
+        data.resize(self.superblock_bytes(), 0);
+        data[SUPERBLOCK_METADATA_OFFSET..METADATA_SUPERBLOCK_BYTES].copy_from_slice(&bytes);
+        self.write_block(0, &data)
+    }
+    
+    /// Write a metadata checkpoint to the inactive copy of the metadata
+    /// area, make it durable, then point the superblock record at it. Until
+    /// the record is replaced the previous copy stays the active one.
+    pub fn write_metadata_payload(&self, payload: &[u8], seq: u64) -> Result<()> {
+        let area = self
+            .metadata_area
+            .ok_or_else(|| anyhow::anyhow!("No metadata area is reserved"))?;
+        let capacity = area.blocks_per_copy as usize * self.bytes_per_block;
+        if payload.len() > capacity {
+            anyhow::bail!(
+                "Metadata ({} bytes) does not fit in the {}-byte metadata area; \
+                 the metadata cache file is the only copy",
+                payload.len(),
+                capacity
+            );
+        }
+        
+        let active = match self.read_metadata_record()? {
+            Some(record) if record.len > 0 => 1 - record.active,
+            _ => 0,
+        };
+        let mut written = Vec::new();
+        for (block_num, chunk) in area.copy(active).zip(payload.chunks(self.bytes_per_block)) {
+            self.write_block(block_num, chunk)?;
+            // Solo se releen al montar: no ocupan la caché
+            self.invalidate_cached(block_num);
+            written.push((block_num, self.get_block_path(block_num)));
+        }
+        sync_block_files(&written, &self.base_path).map_err(|(_, e)| e)?;
+        
+        self.write_metadata_record(&MetadataRecord {
+            area,
+            active,
+            seq,
+            len: payload.len() as u64,
+            digest: crate::hash::sha256(payload),
+        })?;
+        let superblock = [(0, self.get_block_path(0))];
+        sync_block_files(&superblock, &self.base_path).map_err(|(_, e)| e)?;
         
+        let mut unsynced = self.unsynced.lock().unwrap();
+        for (block_num, _) in written.iter().chain(&superblock) {
+            unsynced.remove(block_num);
+        }
         Ok(())
     }
     
+    /// Read the checkpoint `record` points at, checked against its hash
+    pub fn read_metadata_payload(&self, record: &MetadataRecord) -> Result<Vec<u8>> {
+        let len = record.len as usize;
+        if len == 0 {
+            anyhow::bail!("The metadata area holds no checkpoint yet");
+        }
+        if len > record.area.blocks_per_copy as usize * self.bytes_per_block {
+            anyhow::bail!("Metadata area record claims {} bytes, more than the area holds", len);
+        }
+        
+        let mut payload = Vec::with_capacity(len);
+        for block_num in record.area.copy(record.active) {
+            if payload.len() >= len {
+                break;
+            }
+            payload.extend(self.read_block_uncached(block_num)?);
+        }
+        payload.truncate(len);
+        if crate::hash::sha256(&payload) != record.digest {
+            anyhow::bail!(
+                "Metadata area copy {} does not match the hash in the superblock",
+                record.active
+            );
+        }
+        Ok(payload)
+    }
+    
+    /// Bits per pixel used to encode `block_num` (the superblock is always 1)
+    fn bits_for(&self, block_num: u32) -> u8 {
+        if block_num == 0 {
+            1
+        } else {
+            self.bits_per_pixel
+        }
+    }
+    
+    /// Image color type of `block_num` (the superblock is always grayscale)
+    fn color_for(&self, block_num: u32) -> ColorType {
+        match self.encoding {
+            Encoding::Rgb if block_num != 0 => ColorType::Rgb8,
+            _ => ColorType::L8,
+        }
+    }
+    
+    /// Samples (pixels times channels) in the image of `block_num`
+    fn samples_per_block(&self, block_num: u32) -> usize {
+        let channels = self.color_for(block_num).channel_count() as usize;
+        self.block_width as usize * self.block_height as usize * channels
+    }
+    
+    /// Bytes stored in `block_num`
+    fn capacity(&self, block_num: u32) -> usize {
+        if block_num == 0 {
+            self.superblock_bytes()
+        } else {
+            self.bytes_per_block
+        }
+    }
+    
+    /// Distance between consecutive gray levels with `bits` bits per pixel
+    /// (255, 85, 17 or 1): every level maps to an exact Luma value
+    fn gray_step(bits: u8) -> u16 {
+        255 / ((1u16 << bits) - 1)
+    }
+    
+    /// Bytes stored in the superblock (block 0), always 1 bit per pixel
+    pub fn superblock_bytes(&self) -> usize {
+        (self.block_width as usize * self.block_height as usize) / 8
+    }
+    
+    /// Bits stored in each pixel of a data block
+    pub fn bits_per_pixel(&self) -> u8 {
+        self.bits_per_pixel
+    }
+    
+    /// Grayscale or RGB data blocks
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+    
+    /// Highest block number with an image in the storage directory,
+    /// including images past `total_blocks`
+    pub fn highest_block_file(&self) -> Result<Option<u32>> {
+        let mut highest = None;
+        for entry in fs::read_dir(&self.base_path)? {
+            let name = entry?.file_name();
+            let block_num = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("block_")?.strip_suffix(".png"))
+                .and_then(|digits| digits.parse::<u32>().ok());
+            if let Some(block_num) = block_num {
+                highest = highest.max(Some(block_num));
+            }
+        }
+        Ok(highest)
+    }
+    
     /// Check if a block exists
     pub fn block_exists(&self, block_num: u32) -> bool {
         self.get_block_path(block_num).exists()
@@ -153,15 +1395,80 @@ pub fn bytes_per_block(&self) -> usize {
     
     /// Write fingerprint to block 0 (superblock)
     pub fn write_fingerprint(&self) -> Result<()> {
-        let mut data = vec![0u8; self.bytes_per_block];
+        let mut data = vec![0u8; self.superblock_bytes()];
         let fingerprint_bytes = self.fingerprint.as_bytes();
-        let len = fingerprint_bytes.len().min(self.bytes_per_block);
+        let len = fingerprint_bytes.len().min(data.len());
         data[..len].copy_from_slice(&fingerprint_bytes[..len]);
         
         self.write_block(0, &data)?;
         Ok(())
     }
     
+    /// Replace the fingerprint in block 0 via `write_fingerprint`, keeping
+    /// the geometry record, Merkle root and metadata area record stored
+    /// after it
+    pub fn set_fingerprint(&mut self, fingerprint: String) -> Result<()> {
+        if fingerprint.is_empty() {
+            anyhow::bail!("Fingerprint must not be empty");
+        }
+        if fingerprint.len() > self.superblock_bytes() {
+            anyhow::bail!(
+                "Fingerprint ({} bytes) does not fit in a {}-byte superblock",
+                fingerprint.len(),
+                self.superblock_bytes()
+            );
+        }
+        
+        let geometry = Self::read_geometry(&self.base_path.to_string_lossy()).ok();
+        if geometry.is_some() && fingerprint.len() > SUPERBLOCK_GEOMETRY_OFFSET {
+            anyhow::bail!(
+                "Fingerprint longer than {} bytes overlaps the geometry record",
+                SUPERBLOCK_GEOMETRY_OFFSET
+            );
+        }
+        let merkle_root = self.stored_merkle_root()?;
+        let metadata_record = self.read_metadata_record()?;
+        
+        self.fingerprint = fingerprint;
+        self.write_fingerprint()?;
+        if let Some(geometry) = geometry {
+            self.write_geometry(geometry.total_inodes)?;
+        }
+        if let Some(root) = merkle_root {
+            self.write_merkle_root(&root)?;
+        }
+        if let Some(record) = metadata_record {
+            self.write_metadata_record(&record)?;
+        }
+        Ok(())
+    }
+    
+    /// Rewrite block 0 after it was overwritten: the fingerprint goes back
+    /// via `write_fingerprint`, the geometry is recorded again from this
+    /// storage and `total_inodes`, and the Merkle root and metadata area
+    /// record are restored when they can still be read. No other block is
+    /// touched.
+    pub fn rewrite_superblock(&self, total_inodes: u32) -> Result<SuperblockRecovery> {
+        // Un bloque 0 ilegible se trata como vacío
+        let merkle_root = self.stored_merkle_root().ok().flatten();
+        let metadata_record = self.read_metadata_record().ok().flatten();
+        
+        self.write_fingerprint()?;
+        let mut recovery = SuperblockRecovery {
+            geometry: self.write_geometry(total_inodes).is_ok(),
+            ..SuperblockRecovery::default()
+        };
+        if let Some(root) = merkle_root {
+            self.write_merkle_root(&root)?;
+            recovery.merkle_root = true;
+        }
+        if let Some(record) = metadata_record {
+            self.write_metadata_record(&record)?;
+            recovery.metadata_record = true;
+        }
+        Ok(recovery)
+    }
+    
     /// Read and verify fingerprint from block 0
     pub fn verify_fingerprint(&self) -> Result<bool> {
         let data = self.read_block(0)?;
@@ -181,13 +1488,27 @@ pub struct Bitmap {
 impl Bitmap {
     /// Create a new bitmap with all bits set to free (1)
     pub fn new(size: usize) -> Self {
-        let byte_size = (size + 7) / 8;
+        let byte_size = size.div_ceil(8);
         Self {
             bits: vec![0x00; byte_size],
             size,
         }
     }
     
+    /// Number of bits tracked
+    pub fn len(&self) -> usize {
+        self.size
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+    
+    /// Number of bits set (allocated)
+    pub fn count_set(&self) -> usize {
+        self.bits.iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+    
     /// Check if a bit is set (allocated)
     pub fn is_set(&self, index: usize) -> bool {
         if index >= self.size {
@@ -229,6 +1550,24 @@ pub fn allocate(&mut self) -> Option<usize> {
         None
     }
     
+    /// Find the first run of `count` free bits, allocate it and return its
+    /// first index
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<usize> {
+        if count == 0 {
+            return None;
+        }
+        let mut run_start = 0;
+        for i in 0..self.size {
+            if self.is_set(i) {
+                run_start = i + 1;
+            } else if i + 1 - run_start == count {
+                (run_start..=i).for_each(|j| self.set(j));
+                return Some(run_start);
+            }
+        }
+        None
+    }
+    
     /// Deallocate a bit
     pub fn deallocate(&mut self, index: usize) {
         self.clear(index);
@@ -242,9 +1581,67 @@ pub fn as_bytes(&self) -> &[u8] {
     /// Load bitmap from bytes
     pub fn from_bytes(data: &[u8], size: usize) -> Self {
         let mut bits = data.to_vec();
-        let required_bytes = (size + 7) / 8;
+        let required_bytes = size.div_ceil(8);
         bits.resize(required_bytes, 0xFF);
         
         Self { bits, size }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// 64x64 pixel blocks (512 bytes) in `dir`
+    fn new_storage(dir: &TempDir) -> BlockStorage {
+        BlockStorage::new(&dir.path().to_string_lossy(), 64, 64, 64, "BWFS_TEST".to_string()).unwrap()
+    }
+
+    #[test]
+    fn failed_read_is_retried() {
+        let dir = TempDir::new("read-retry");
+        let mut storage = new_storage(&dir).with_read_retry(2, 0);
+        storage.write_block(5, &[7; 512]).unwrap();
+
+        storage.set_faults(Some(FaultPlan { fail_read: Some(1), ..FaultPlan::default() }));
+        assert_eq!(storage.read_block(5).unwrap(), vec![7; 512]);
+
+        // Sin reintentos el mismo fallo llega al llamador
+        let mut storage = storage.with_read_retry(0, 0);
+        storage.set_faults(Some(FaultPlan { fail_read: Some(1), ..FaultPlan::default() }));
+        assert!(storage.read_block(5).is_err());
+    }
+
+    #[test]
+    fn png_compression_levels_change_the_size_not_the_data() {
+        let (fast_dir, best_dir) = (TempDir::new("png-fast"), TempDir::new("png-best"));
+        let fast = new_storage(&fast_dir).with_png_compression(PngCompression::Fast);
+        let best = new_storage(&best_dir).with_png_compression(PngCompression::Best);
+        let data: Vec<u8> = (0..512u32).map(|i| (i / 3 % 7) as u8 * 31).collect();
+        fast.write_block(3, &data).unwrap();
+        best.write_block(3, &data).unwrap();
+
+        // Instancias nuevas: los datos salen de decodificar el PNG
+        assert_eq!(new_storage(&fast_dir).read_block(3).unwrap(), data);
+        assert_eq!(new_storage(&best_dir).read_block(3).unwrap(), data);
+        let size = |storage: &BlockStorage| fs::metadata(storage.get_block_path(3)).unwrap().len();
+        assert_ne!(size(&fast), size(&best));
+    }
+
+    #[test]
+    fn failed_image_save_keeps_the_previous_block() {
+        let dir = TempDir::new("save-fails");
+        let storage = new_storage(&dir);
+        storage.write_block(3, &[1; 512]).unwrap();
+
+        // Un directorio en la ruta del temporal hace fallar la creación
+        let tmp_path = storage.get_block_path(3).with_extension("png.tmp");
+        fs::create_dir(&tmp_path).unwrap();
+        let error = storage.write_block(3, &[2; 512]).unwrap_err();
+        assert!(format!("{:#}", error).contains("block_00000003.png"), "{:#}", error);
+
+        assert_eq!(storage.read_block(3).unwrap(), vec![1; 512]);
+        assert_eq!(new_storage(&dir).read_block(3).unwrap(), vec![1; 512]);
+    }
+}
@@ -0,0 +1,564 @@
+use crate::fs::BWFS;
+use crate::inode::FileType;
+use anyhow::{Context, Result};
+use fuser::TimeOrNow;
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Size of a tar header and of the padding unit for entry data
+const BLOCK: usize = 512;
+
+/// Name GNU tar gives the pseudo-entries carrying long names
+const LONG_LINK_NAME: &str = "././@LongLink";
+
+/// Largest size the 11 octal digits of a ustar header can hold
+const MAX_OCTAL_SIZE: u64 = 0o77777777777;
+
+/// Type of a tar entry, from the header's typeflag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    File,
+    HardLink,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Directory,
+    Fifo,
+    /// GNU long name for the next entry
+    LongName,
+    /// GNU long link target for the next entry
+    LongLink,
+    /// POSIX extended header for the next entry
+    Pax,
+    /// Anything else (global pax headers, sparse files, ...): skipped
+    Other(u8),
+}
+
+impl EntryKind {
+    fn from_flag(flag: u8) -> Self {
+        match flag {
+            b'0' | 0 | b'7' => EntryKind::File,
+            b'1' => EntryKind::HardLink,
+            b'2' => EntryKind::Symlink,
+            b'3' => EntryKind::CharDevice,
+            b'4' => EntryKind::BlockDevice,
+            b'5' => EntryKind::Directory,
+            b'6' => EntryKind::Fifo,
+            b'L' => EntryKind::LongName,
+            b'K' => EntryKind::LongLink,
+            b'x' => EntryKind::Pax,
+            other => EntryKind::Other(other),
+        }
+    }
+
+    fn flag(self) -> u8 {
+        match self {
+            EntryKind::File => b'0',
+            EntryKind::HardLink => b'1',
+            EntryKind::Symlink => b'2',
+            EntryKind::CharDevice => b'3',
+            EntryKind::BlockDevice => b'4',
+            EntryKind::Directory => b'5',
+            EntryKind::Fifo => b'6',
+            EntryKind::LongName => b'L',
+            EntryKind::LongLink => b'K',
+            EntryKind::Pax => b'x',
+            EntryKind::Other(flag) => flag,
+        }
+    }
+}
+
+/// One tar header, with long names already resolved
+#[derive(Debug, Clone)]
+struct Header {
+    path: String,
+    kind: EntryKind,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    mtime: u64,
+    /// Symlink target or hard link source
+    link: String,
+    rdev: u32,
+}
+
+impl Header {
+    fn new(path: String, kind: EntryKind) -> Self {
+        Header {
+            path,
+            kind,
+            mode: 0,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            mtime: 0,
+            link: String::new(),
+            rdev: 0,
+        }
+    }
+}
+
+fn put_str(field: &mut [u8], value: &str) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value.as_bytes()[..len]);
+}
+
+/// Octal number, zero-padded to the field minus its NUL terminator
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    put_str(field, &format!("{:0width$o}", value, width = digits));
+}
+
+/// Split a path into the ustar (prefix, name) fields, if it fits
+fn split_ustar_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    // El prefijo termina en un '/' que no se guarda
+    path.char_indices()
+        .filter(|&(i, c)| c == '/' && i <= 155 && path.len() - i - 1 <= 100 && i > 0)
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .next()
+}
+
+fn encode_header(header: &Header) -> [u8; BLOCK] {
+    let mut block = [0u8; BLOCK];
+    let (prefix, name) = split_ustar_path(&header.path).unwrap_or(("", &header.path));
+    put_str(&mut block[0..100], name);
+    put_octal(&mut block[100..108], header.mode as u64 & 0o7777);
+    put_octal(&mut block[108..116], header.uid as u64);
+    put_octal(&mut block[116..124], header.gid as u64);
+    put_octal(&mut block[124..136], header.size);
+    put_octal(&mut block[136..148], header.mtime);
+    block[156] = header.kind.flag();
+    put_str(&mut block[157..257], &header.link);
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    if matches!(header.kind, EntryKind::CharDevice | EntryKind::BlockDevice) {
+        put_octal(&mut block[329..337], libc::major(header.rdev as libc::dev_t) as u64);
+        put_octal(&mut block[337..345], libc::minor(header.rdev as libc::dev_t) as u64);
+    }
+    put_str(&mut block[345..500], prefix);
+
+    // El checksum se calcula con su propio campo lleno de espacios
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|&b| b as u32).sum();
+    put_str(&mut block[148..155], &format!("{:06o}\0", checksum));
+    block
+}
+
+/// Zero padding that completes `len` bytes of data to a whole block
+fn padding(len: u64) -> usize {
+    (BLOCK - (len as usize % BLOCK)) % BLOCK
+}
+
+fn write_data(out: &mut impl Write, data: &[u8]) -> Result<()> {
+    out.write_all(data)?;
+    out.write_all(&[0u8; BLOCK][..padding(data.len() as u64)])?;
+    Ok(())
+}
+
+/// Write `header` and `data`, preceded by GNU long name/link entries when
+/// the path or link target does not fit the ustar fields
+fn write_entry(out: &mut impl Write, header: &Header, data: &[u8]) -> Result<()> {
+    if header.size > MAX_OCTAL_SIZE {
+        anyhow::bail!("{}: {} bytes is too large for a tar entry", header.path, header.size);
+    }
+    if header.link.len() > 100 {
+        let mut long = Header::new(LONG_LINK_NAME.to_string(), EntryKind::LongLink);
+        long.size = header.link.len() as u64 + 1;
+        out.write_all(&encode_header(&long))?;
+        write_data(out, format!("{}\0", header.link).as_bytes())?;
+    }
+    if split_ustar_path(&header.path).is_none() {
+        let mut long = Header::new(LONG_LINK_NAME.to_string(), EntryKind::LongName);
+        long.size = header.path.len() as u64 + 1;
+        out.write_all(&encode_header(&long))?;
+        write_data(out, format!("{}\0", header.path).as_bytes())?;
+    }
+    out.write_all(&encode_header(header))?;
+    write_data(out, data)
+}
+
+fn get_str(field: &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+/// Octal number, or GNU base-256 when the high bit of the first byte is set
+fn get_number(field: &[u8]) -> Result<u64> {
+    if field.first().is_some_and(|&b| b & 0x80 != 0) {
+        let first = (field[0] & 0x7f) as u64;
+        return Ok(field[1..].iter().fold(first, |acc, &b| (acc << 8) | b as u64));
+    }
+    let text = get_str(field);
+    let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).with_context(|| format!("bad octal field {:?}", text))
+}
+
+fn decode_header(block: &[u8; BLOCK]) -> Result<Header> {
+    let stored = get_number(&block[148..156])?;
+    let checksum: u64 = block
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+        .sum();
+    if stored != checksum {
+        anyhow::bail!("tar header checksum mismatch (stored {}, computed {})", stored, checksum);
+    }
+
+    let name = get_str(&block[0..100]);
+    let prefix = if &block[257..262] == b"ustar" { get_str(&block[345..500]) } else { String::new() };
+    let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+    let mut header = Header::new(path, EntryKind::from_flag(block[156]));
+    header.mode = get_number(&block[100..108])? as u32;
+    header.uid = get_number(&block[108..116])? as u32;
+    header.gid = get_number(&block[116..124])? as u32;
+    header.size = get_number(&block[124..136])?;
+    header.mtime = get_number(&block[136..148])?;
+    header.link = get_str(&block[157..257]);
+    if matches!(header.kind, EntryKind::CharDevice | EntryKind::BlockDevice) {
+        let major = get_number(&block[329..337])? as u32;
+        let minor = get_number(&block[337..345])? as u32;
+        header.rdev = libc::makedev(major, minor) as u32;
+    }
+    Ok(header)
+}
+
+/// Values of the `path` and `linkpath` records of a pax extended header
+fn parse_pax(data: &[u8]) -> (Option<String>, Option<String>) {
+    let mut path = None;
+    let mut link = None;
+    let mut rest = data;
+    // Cada registro es "<longitud> <clave>=<valor>\n", longitud incluida
+    while let Some(space) = rest.iter().position(|&b| b == b' ') {
+        let Some(len) = std::str::from_utf8(&rest[..space]).ok().and_then(|s| s.parse::<usize>().ok()) else {
+            break;
+        };
+        if len <= space || len > rest.len() {
+            break;
+        }
+        let record = String::from_utf8_lossy(&rest[space + 1..len]);
+        let record = record.trim_end_matches('\n');
+        if let Some((key, value)) = record.split_once('=') {
+            match key {
+                "path" => path = Some(value.to_string()),
+                "linkpath" => link = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        rest = &rest[len..];
+    }
+    (path, link)
+}
+
+/// Sequential reader of the entries of a tar archive
+struct TarReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> TarReader<R> {
+    fn read_data(&mut self, size: u64) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; size as usize];
+        self.inner.read_exact(&mut data)?;
+        let mut pad = [0u8; BLOCK];
+        self.inner.read_exact(&mut pad[..padding(size)])?;
+        Ok(data)
+    }
+
+    /// Next entry with its data, or `None` at the end of the archive
+    fn next_entry(&mut self) -> Result<Option<(Header, Vec<u8>)>> {
+        let mut long_name = None;
+        let mut long_link = None;
+        loop {
+            let mut block = [0u8; BLOCK];
+            match self.inner.read_exact(&mut block) {
+                Ok(()) => {}
+                // Archivo truncado justo tras una entrada: se trata como el final
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+            if block.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+
+            let mut header = decode_header(&block)?;
+            let data = self.read_data(header.size)?;
+            match header.kind {
+                EntryKind::LongName => long_name = Some(get_str(&data)),
+                EntryKind::LongLink => long_link = Some(get_str(&data)),
+                EntryKind::Pax => {
+                    let (path, link) = parse_pax(&data);
+                    long_name = path.or(long_name);
+                    long_link = link.or(long_link);
+                }
+                _ => {
+                    if let Some(path) = long_name.take() {
+                        header.path = path;
+                    }
+                    if let Some(link) = long_link.take() {
+                        header.link = link;
+                    }
+                    return Ok(Some((header, data)));
+                }
+            }
+        }
+    }
+}
+
+/// Normalize an archive path to its components: leading `/` and `./` are
+/// dropped; `..` is rejected so an archive cannot escape the root
+fn archive_components(path: &str) -> Result<Vec<&str>> {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => anyhow::bail!("{}: '..' is not allowed in archive paths", path),
+            name => components.push(name),
+        }
+    }
+    Ok(components)
+}
+
+fn to_unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn errno_error(what: &str, errno: libc::c_int) -> anyhow::Error {
+    anyhow::anyhow!("{}: {}", what, std::io::Error::from_raw_os_error(errno))
+}
+
+impl BWFS {
+    /// Write every file, directory, symlink, device and FIFO to a ustar
+    /// archive at `path`, with modes, owners and modification times.
+    ///
+    /// Paths are relative to the root; names too long for ustar use GNU
+    /// long-name entries. The second and later names of a hard-linked file
+    /// are stored as hard links to the first. Sockets cannot be archived
+    /// and are skipped with a warning. Returns the number of entries
+    /// written.
+    pub fn export_tar(&self, path: &Path) -> Result<usize> {
+        let file = std::fs::File::create(path).with_context(|| format!("cannot create {:?}", path))?;
+        let mut out = BufWriter::new(file);
+        let mut first_paths: HashMap<u64, String> = HashMap::new();
+        let mut count = 0;
+
+        for (entry_path, inode) in self.walk() {
+            let mut rel = entry_path.trim_start_matches('/').to_string();
+            let kind = match inode.file_type {
+                FileType::RegularFile => EntryKind::File,
+                FileType::Directory => EntryKind::Directory,
+                FileType::Symlink => EntryKind::Symlink,
+                FileType::CharDevice => EntryKind::CharDevice,
+                FileType::BlockDevice => EntryKind::BlockDevice,
+                FileType::NamedPipe => EntryKind::Fifo,
+                FileType::Socket => {
+                    log::warn!("Skipping socket {} (tar cannot store sockets)", entry_path);
+                    continue;
+                }
+            };
+            if kind == EntryKind::Directory {
+                rel.push('/');
+            }
+
+            let mut header = Header::new(rel.clone(), kind);
+            header.mode = inode.mode as u32;
+            header.uid = inode.uid;
+            header.gid = inode.gid;
+            header.mtime = to_unix_secs(inode.mtime);
+            header.rdev = inode.rdev;
+
+            let mut data = Vec::new();
+            if kind != EntryKind::Directory && inode.nlink > 1 {
+                if let Some(first) = first_paths.get(&inode.ino) {
+                    header.kind = EntryKind::HardLink;
+                    header.link = first.clone();
+                    write_entry(&mut out, &header, &data)?;
+                    count += 1;
+                    continue;
+                }
+                first_paths.insert(inode.ino, rel.clone());
+            }
+            match kind {
+                EntryKind::File => {
+                    data = self.read_file(inode.ino)?;
+                    header.size = data.len() as u64;
+                }
+                EntryKind::Symlink => {
+                    let target = self
+                        .read_symlink(inode.ino)
+                        .map_err(|errno| errno_error(&entry_path, errno))?;
+                    header.link = String::from_utf8_lossy(&target).into_owned();
+                }
+                _ => {}
+            }
+
+            write_entry(&mut out, &header, &data)?;
+            count += 1;
+        }
+
+        // Fin del archivo: dos bloques de ceros
+        out.write_all(&[0u8; 2 * BLOCK])?;
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        Ok(count)
+    }
+
+    /// Populate an empty filesystem from the tar archive at `path`.
+    ///
+    /// Regular files, directories, symlinks, hard links, devices and FIFOs
+    /// are created with their modes, owners and modification times.
+    /// Directories missing from the archive, and entries whose header has
+    /// no permission bits, get `default_dir_mode` or `default_file_mode`.
+    /// Refuses to run if the root already has entries. Metadata is saved
+    /// at the end. Returns the number of entries imported.
+    pub fn import_tar(&self, path: &Path) -> Result<usize> {
+        let root = self.root_ino();
+        if !self.walk().is_empty() {
+            anyhow::bail!("the filesystem is not empty; import_tar needs a freshly created one");
+        }
+
+        let file = std::fs::File::open(path).with_context(|| format!("cannot open {:?}", path))?;
+        let mut reader = TarReader { inner: BufReader::new(file) };
+        let mut inos: HashMap<String, u64> = HashMap::from([(String::new(), root)]);
+        // Los directorios se fechan al final: crear sus hijos cambia mtime
+        let mut dir_times = Vec::new();
+        let mut count = 0;
+
+        while let Some((header, data)) = reader.next_entry()? {
+            let components = archive_components(&header.path)?;
+            let Some((&name, dirs)) = components.split_last() else {
+                continue;
+            };
+
+            // Padre, creando los directorios intermedios que falten
+            let mut parent = root;
+            let mut parent_path = String::new();
+            for &dir in dirs {
+                let dir_path = if parent_path.is_empty() { dir.to_string() } else { format!("{}/{}", parent_path, dir) };
+                parent = match inos.get(&dir_path) {
+                    Some(&ino) => ino,
+                    None => {
+                        let inode = self
+                            .create_dir(parent, dir, self.config().default_dir_mode, 0, 0)
+                            .map_err(|errno| errno_error(&dir_path, errno))?;
+                        inos.insert(dir_path.clone(), inode.ino);
+                        inode.ino
+                    }
+                };
+                parent_path = dir_path;
+            }
+            let full_path = components.join("/");
+            let mode = match (header.mode & 0o7777) as u16 {
+                // Sin permisos en la cabecera: los por defecto de la configuración
+                0 if header.kind == EntryKind::Directory => self.config().default_dir_mode,
+                0 => self.config().default_file_mode,
+                mode => mode,
+            };
+            let mtime = UNIX_EPOCH + Duration::from_secs(header.mtime);
+            let fail = |errno| errno_error(&full_path, errno);
+
+            let ino = match header.kind {
+                EntryKind::Directory => match inos.get(&full_path) {
+                    // Ya creado como intermedio: solo se corrigen sus atributos
+                    Some(&ino) => {
+                        self.set_mode_and_owner(ino, Some(mode as u32), Some(header.uid), Some(header.gid))
+                            .map_err(fail)?;
+                        ino
+                    }
+                    None => self.create_dir(parent, name, mode, header.uid, header.gid).map_err(fail)?.ino,
+                },
+                EntryKind::File => {
+                    let ino = self.create_with_size_hint(parent, name, mode, header.size)?;
+                    if !data.is_empty() {
+                        self.write_data(ino, 0, &data).map_err(fail)?;
+                    }
+                    self.set_mode_and_owner(ino, None, Some(header.uid), Some(header.gid))
+                        .map_err(fail)?;
+                    ino
+                }
+                EntryKind::Symlink => {
+                    self.create_symlink(parent, name, header.link.as_bytes(), header.uid, header.gid)
+                        .map_err(fail)?
+                        .ino
+                }
+                EntryKind::HardLink => {
+                    let source = archive_components(&header.link)?.join("/");
+                    let &source_ino = inos
+                        .get(&source)
+                        .ok_or_else(|| anyhow::anyhow!("{}: hard link to unknown {}", full_path, source))?;
+                    self.link_inode(source_ino, parent, name).map_err(fail)?;
+                    inos.insert(full_path, source_ino);
+                    count += 1;
+                    continue;
+                }
+                EntryKind::CharDevice | EntryKind::BlockDevice | EntryKind::Fifo => {
+                    let file_type = match header.kind {
+                        EntryKind::CharDevice => FileType::CharDevice,
+                        EntryKind::BlockDevice => FileType::BlockDevice,
+                        _ => FileType::NamedPipe,
+                    };
+                    self.create_node(parent, name, file_type, mode, header.rdev, header.uid, header.gid)
+                        .map_err(fail)?
+                        .ino
+                }
+                kind => {
+                    log::warn!("Skipping {} (unsupported tar entry type {:?})", full_path, kind);
+                    continue;
+                }
+            };
+
+            if header.kind == EntryKind::Directory {
+                dir_times.push((ino, mtime));
+            } else {
+                self.set_times(ino, Some(TimeOrNow::SpecificTime(mtime)), Some(TimeOrNow::SpecificTime(mtime)))
+                    .map_err(fail)?;
+            }
+            inos.insert(full_path, ino);
+            count += 1;
+        }
+
+        for (ino, mtime) in dir_times {
+            self.set_times(ino, Some(TimeOrNow::SpecificTime(mtime)), Some(TimeOrNow::SpecificTime(mtime)))
+                .map_err(|errno| errno_error("directory times", errno))?;
+        }
+        self.save()?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_config, TempDir};
+    use crate::Config;
+
+    #[test]
+    fn imported_entries_without_a_mode_get_the_configured_defaults() {
+        let dir = TempDir::new("tar-modes");
+        let archive = dir.path().join("in.tar");
+        let mut out = Vec::new();
+        // "docs/" no está en el archivo; "readme" no trae permisos
+        write_entry(&mut out, &Header { size: 2, ..Header::new("docs/readme".into(), EntryKind::File) }, b"hi").unwrap();
+        let explicit = Header { mode: 0o640, size: 1, ..Header::new("docs/kept".into(), EntryKind::File) };
+        write_entry(&mut out, &explicit, b"k").unwrap();
+        out.extend_from_slice(&[0u8; 2 * BLOCK]);
+        std::fs::write(&archive, out).unwrap();
+
+        let storage = TempDir::new("tar-modes-fs");
+        let config = Config { default_file_mode: 0o600, default_dir_mode: 0o750, ..test_config(storage.path()) };
+        let fs = BWFS::new(config).unwrap();
+        assert_eq!(fs.import_tar(&archive).unwrap(), 2);
+
+        let modes: HashMap<String, u16> = fs.walk().into_iter().map(|(path, inode)| (path, inode.mode)).collect();
+        assert_eq!(modes["/docs"], 0o750);
+        assert_eq!(modes["/docs/readme"], 0o600);
+        assert_eq!(modes["/docs/kept"], 0o640);
+    }
+}
@@ -0,0 +1,63 @@
+//! Helpers shared by the unit tests
+
+use crate::Config;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// Scratch directory under the system temp dir, removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(tag: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "bwfs-test-{}-{}-{}",
+            tag,
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A small filesystem in `dir`: 64x64 pixel blocks (512 bytes each), so
+/// block images encode quickly and indirect blocks appear after 6 KiB
+pub fn test_config(dir: &Path) -> Config {
+    Config {
+        name: "test".to_string(),
+        storage_path: dir.to_string_lossy().into_owned(),
+        block_width: 64,
+        block_height: 64,
+        total_blocks: 64,
+        total_inodes: 32,
+        ..Config::default()
+    }
+}
+
+/// Write the superblock (fingerprint and geometry) of `config` the way
+/// `mkfs.bwfs` does before creating the filesystem
+pub fn write_superblock(config: &Config) {
+    let storage = crate::storage::BlockStorage::new(
+        &config.storage_path,
+        config.block_width,
+        config.block_height,
+        config.total_blocks,
+        config.fingerprint.clone(),
+    )
+    .unwrap();
+    storage.write_fingerprint().unwrap();
+    storage.write_geometry(config.total_inodes).unwrap();
+}
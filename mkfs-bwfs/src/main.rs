@@ -10,6 +10,11 @@ struct Args {
     /// Path to configuration file
     #[arg(short = 'c', long = "config")]
     config: String,
+
+    /// Create the image of every block now instead of on first write
+    /// (e.g. to copy the storage to read-only media)
+    #[arg(long = "preallocate")]
+    preallocate: bool,
 }
 
 fn main() -> Result<()> {
@@ -22,7 +27,7 @@ fn main() -> Result<()> {
     
     // Load configuration
     println!("Loading configuration from: {}", args.config);
-    let config = Config::from_ini(&args.config)?;
+    let config = Config::from_file(&args.config)?;
     
     // Validate configuration
     println!("Validating configuration...");
@@ -36,26 +41,34 @@ fn main() -> Result<()> {
     println!("Fingerprint: {}", config.fingerprint);
     
     // Calculate filesystem capacity
-    let bytes_per_block = (config.block_width * config.block_height / 8) as u64;
+    let bytes_per_block = config.bytes_per_block() as u64;
     let total_capacity = bytes_per_block * config.total_blocks as u64;
     let capacity_mb = total_capacity as f64 / (1024.0 * 1024.0);
     
+    println!("Encoding: {:?}, {} bit(s) per sample", config.encoding, config.bits_per_pixel);
     println!("Bytes per block: {}", bytes_per_block);
     println!("Total capacity: {:.2} MB", capacity_mb);
-    
-    // Create the filesystem
-    println!("\nCreating filesystem structure...");
-    let fs = BWFS::new(config.clone())?;
+    if config.metadata_blocks > 0 {
+        println!(
+            "Metadata area: blocks 1-{} (2 copies of {} blocks)",
+            2 * config.metadata_blocks,
+            config.metadata_blocks
+        );
+    }
     
     // Initialize storage
-    println!("Initializing block storage...");
+    println!("\nInitializing block storage...");
     let storage = bwfs::storage::BlockStorage::new(
         &config.storage_path,
         config.block_width,
         config.block_height,
         config.total_blocks,
         config.fingerprint.clone(),
-    )?;
+    )?
+    .with_bits_per_pixel(config.bits_per_pixel)?
+    .with_encoding(config.encoding)
+    .with_pixel_threshold(config.pixel_threshold)
+    .with_png_compression(config.png_compression);
     
     // Initialize first few blocks
     println!("Initializing system blocks...");
@@ -68,10 +81,35 @@ fn main() -> Result<()> {
     }
     println!();
     
+    if args.preallocate {
+        // El bloque 0 ya existe y se reescribe abajo con el fingerprint
+        println!("Preallocating all blocks...");
+        let total = config.total_blocks.saturating_sub(1);
+        let step = (total / 100).max(1);
+        for i in 10.min(config.total_blocks)..config.total_blocks {
+            storage.init_block(i)?;
+            if i % step == 0 || i + 1 == config.total_blocks {
+                print!("\r  {}/{} blocks", i, total);
+                std::io::Write::flush(&mut std::io::stdout())?;
+            }
+        }
+        println!();
+    }
+    
     // Write fingerprint to superblock (block 0) - AFTER initializing
     println!("Writing fingerprint to superblock...");
     storage.write_fingerprint()?;
     
+    // Geometría en el superblock para `mount.bwfs --auto`
+    if let Err(e) = storage.write_geometry(config.total_inodes) {
+        println!("Warning: geometry not recorded in superblock ({}); --auto mount unavailable", e);
+    }
+    
+    // Create the filesystem once the system blocks exist, so integrity mode
+    // hashes their final contents
+    println!("Creating filesystem structure...");
+    let fs = BWFS::new(config.clone())?;
+    
     // Save filesystem metadata
     println!("Saving filesystem metadata...");
     fs.save()?;
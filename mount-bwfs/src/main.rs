@@ -1,5 +1,7 @@
 use clap::Parser;
 use bwfs::{Config, BWFS};
+use bwfs::config::AtimePolicy;
+use bwfs::network::NetworkServer;
 use anyhow::Result;
 use fuser::MountOption;
 use std::path::Path;
@@ -9,9 +11,18 @@
 #[command(name = "mount.bwfs")]
 #[command(about = "Mount a BWFS (Black and White FileSystem)", long_about = None)]
 struct Args {
-    /// Path to configuration file
-    #[arg(short = 'c', long = "config")]
-    config: String,
+    /// Path to configuration file (optional with --auto)
+    #[arg(short = 'c', long = "config", required_unless_present = "auto")]
+    config: Option<String>,
+    
+    /// Read block geometry and fingerprint from the superblock instead of
+    /// trusting the configuration file
+    #[arg(long = "auto")]
+    auto: bool,
+    
+    /// Storage path holding the block images (overrides the configuration)
+    #[arg(short = 's', long = "storage", required_unless_present = "config")]
+    storage: Option<String>,
     
     /// Mount point directory
     #[arg(value_name = "MOUNTPOINT")]
@@ -24,6 +35,32 @@ struct Args {
     /// Run in foreground
     #[arg(short = 'f', long = "foreground")]
     foreground: bool,
+    
+    /// Never update access times on read
+    #[arg(long = "noatime", conflicts_with_all = ["relatime", "strictatime"])]
+    noatime: bool,
+    
+    /// Update access times only when older than mtime/ctime or 24h (default
+    /// unless atime_mode says otherwise)
+    #[arg(long = "relatime", conflicts_with = "strictatime")]
+    relatime: bool,
+    
+    /// Update access times on every read
+    #[arg(long = "strictatime")]
+    strictatime: bool,
+    
+    /// Reply with precise POSIX error codes (EISDIR, EINVAL)
+    #[arg(long = "strict-posix")]
+    strict_posix: bool,
+    
+    /// Also serve this filesystem's blocks to peers on the given TCP port
+    #[arg(long = "serve-port", value_name = "PORT")]
+    serve_port: Option<u16>,
+    
+    /// Mount even if the mount point is not empty (its files stay hidden
+    /// until unmount)
+    #[arg(long = "force")]
+    force: bool,
 }
 
 fn main() -> Result<()> {
@@ -35,11 +72,26 @@ fn main() -> Result<()> {
     println!("=================================================");
     
     // Load configuration
-    println!("Loading configuration from: {}", args.config);
-    let mut config = Config::from_ini(&args.config)?;
-    
-    // Trim fingerprint (avoid mismatch caused by trailing spaces/newlines)
-    config.fingerprint = config.fingerprint.trim().to_string();
+    let config = match &args.config {
+        Some(path) => {
+            println!("Loading configuration from: {}", path);
+            let mut config = Config::from_file(path)?;
+            if let Some(storage) = &args.storage {
+                config.storage_path = storage.clone();
+            }
+            if args.auto {
+                println!("Taking geometry and fingerprint from the superblock");
+                config.apply_superblock()?;
+            }
+            config
+        }
+        None => {
+            // clap garantiza --storage cuando no hay --config
+            let storage = args.storage.as_deref().unwrap();
+            println!("Reading configuration from the superblock in: {}", storage);
+            Config::from_superblock(storage)?
+        }
+    };
     
     // Validate configuration
     config.validate()?;
@@ -48,12 +100,24 @@ fn main() -> Result<()> {
     println!("Storage path: {}", config.storage_path);
     println!("Mount point: {}", args.mountpoint);
     
+    // Montar encima de un directorio con archivos los oculta hasta desmontar
+    if check_mountpoint(&args.mountpoint, args.force)? {
+        println!(
+            "Warning: mount point {} is not empty; its contents are hidden until unmount (--force)",
+            args.mountpoint
+        );
+    }
+    
     // Check if storage path exists
     let storage_path = Path::new(&config.storage_path);
     if !storage_path.exists() {
         anyhow::bail!("Storage path does not exist. Did you run mkfs.bwfs?");
     }
     
+    // Un segundo montaje del mismo almacenamiento corrompería la metadata;
+    // el lock también avisa a las herramientas offline (bwfs-tune)
+    let _mount_lock = bwfs::storage::MountLock::acquire(storage_path)?;
+    
     // Verify fingerprint
     println!("Verifying filesystem fingerprint...");
     let storage = bwfs::storage::BlockStorage::new(
@@ -62,7 +126,10 @@ fn main() -> Result<()> {
         config.block_height,
         config.total_blocks,
         config.fingerprint.clone(),
-    )?;
+    )?
+    .with_bits_per_pixel(config.bits_per_pixel)?
+    .with_encoding(config.encoding)
+    .with_pixel_threshold(config.pixel_threshold);
     
     // ==================================================================
     // DEBUG: LEER LA PRIMERA PARTE DEL BLOQUE 0 PARA VER EL FINGERPRINT
@@ -106,11 +173,39 @@ fn main() -> Result<()> {
     
     // Load or create filesystem
     println!("Loading filesystem...");
-    let fs = BWFS::load(config.clone())
-        .or_else(|_| {
-            println!("Creating new filesystem instance...");
-            BWFS::new(config.clone())
-        })?;
+    // Sin fallback a BWFS::new: una metadata ilegible no debe esconder los datos
+    let mut fs = BWFS::load(config.clone())?;
+    
+    // Las opciones de montaje mandan sobre atime_mode del config
+    let atime_policy = if args.noatime {
+        AtimePolicy::Noatime
+    } else if args.strictatime {
+        AtimePolicy::Strict
+    } else if args.relatime {
+        AtimePolicy::Relatime
+    } else {
+        config.atime_mode
+    };
+    println!("Access time policy: {:?}", atime_policy);
+    fs.set_atime_policy(atime_policy);
+    
+    if args.strict_posix {
+        println!("Strict POSIX error codes enabled");
+    }
+    fs.set_strict_posix(args.strict_posix);
+    
+    // Servidor de bloques embebido: comparte el BlockStorage del montaje
+    if let Some(port) = args.serve_port {
+        let server = NetworkServer::new(port, fs.storage_handle())
+            .with_bind_address(config.bind_address.clone());
+        let runtime = tokio::runtime::Runtime::new()?;
+        std::thread::spawn(move || {
+            if let Err(e) = runtime.block_on(server.start()) {
+                log::error!("Embedded network server stopped: {}", e);
+            }
+        });
+        println!("Serving blocks to peers on {} port {}", config.bind_address, port);
+    }
     
     // Prepare mount options
     let mut options = vec![
@@ -122,6 +217,10 @@ fn main() -> Result<()> {
         options.push(MountOption::AllowOther);
     }
     
+    if atime_policy == AtimePolicy::Noatime {
+        options.push(MountOption::NoAtime);
+    }
+    
     if !args.foreground {
         println!("\nMounting filesystem in background...");
         println!("To unmount, use: fusermount -u {}", args.mountpoint);
@@ -132,7 +231,51 @@ fn main() -> Result<()> {
     
     // Mount the filesystem
     println!("✓ Mounting at {}", args.mountpoint);
-    fuser::mount2(fs, args.mountpoint, &options)?;
+    // Sesión explícita (en lugar de mount2) para entregar el Notifier al FS
+    let notifier = fs.notifier_handle();
+    let result = fuser::Session::new(fs, Path::new(&args.mountpoint), &options).and_then(|mut session| {
+        *notifier.lock().unwrap() = Some(session.notifier());
+        session.run()
+    });
+    result?;
     
     Ok(())
 }
+
+/// Refuse a non-empty mount point unless `force` is set. Returns whether
+/// it is non-empty, so the caller can warn that its files will be hidden.
+fn check_mountpoint(mountpoint: &str, force: bool) -> Result<bool> {
+    let mut entries = std::fs::read_dir(mountpoint)
+        .map_err(|e| anyhow::anyhow!("Cannot open mount point {}: {}", mountpoint, e))?;
+    if entries.next().is_none() {
+        return Ok(false);
+    }
+    if !force {
+        anyhow::bail!(
+            "Mount point {} is not empty; its contents would be hidden while mounted. \
+             Use an empty directory or pass --force",
+            mountpoint
+        );
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_empty_mountpoint_is_refused_without_force() {
+        let dir = std::env::temp_dir().join(format!("bwfs-test-mountpoint-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mountpoint = dir.to_string_lossy().into_owned();
+        assert!(!check_mountpoint(&mountpoint, false).unwrap());
+
+        std::fs::write(dir.join("existing"), b"hidden while mounted").unwrap();
+        let error = check_mountpoint(&mountpoint, false).unwrap_err();
+        assert!(error.to_string().contains("not empty"), "{}", error);
+        assert!(check_mountpoint(&mountpoint, true).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}